@@ -0,0 +1,237 @@
+//! Directories stored as a single zstd compressed tar archive
+//!
+//! Directory `name` is stored as `name.tar.zst` in data dir. When it is accessed
+//! the archive is extracted to a staging directory in the inode cache where each member
+//! is stored as a regular compressed file. The archive is packed again
+//! whenever the content of the staging directory changes.
+
+use std::{
+    fs::{self, File, Permissions},
+    io::{self, BufReader, BufWriter, Read, Write},
+    os::unix::{self, fs::PermissionsExt},
+    path::{Component, Path, PathBuf},
+};
+
+use filetime::FileTime;
+use log::{debug, warn};
+use xattr::FileExt;
+use zstd::zstd_safe::CParameter;
+
+use crate::{
+    cdc, codec,
+    dict::Dictionaries,
+    meta::{self, Meta},
+    params,
+};
+
+pub const SUFFIX: &str = ".tar.zst";
+/// Extracted members are temporary so they are compressed fast
+pub const STAGING_LEVEL: i32 = 1;
+
+/// Name of the directory which is stored in the archive
+pub fn dir_name(file_name: &str) -> Option<&str> {
+    file_name.strip_suffix(SUFFIX).filter(|e| !e.is_empty())
+}
+
+fn mtime(path: &Path) -> io::Result<u64> {
+    Ok(FileTime::from_last_modification_time(&fs::symlink_metadata(path)?).unix_seconds() as u64)
+}
+
+fn append_dir<W: Write>(
+    builder: &mut tar::Builder<W>,
+    dir: &Path,
+    relative: &Path,
+    dicts: &Dictionaries,
+) -> io::Result<usize> {
+    let mut entries = fs::read_dir(dir)?
+        .map(|e| e.map(|e| e.path()))
+        .collect::<io::Result<Vec<_>>>()?;
+    entries.sort();
+
+    let mut members = 0;
+    for path in entries {
+        let metadata = fs::symlink_metadata(&path)?;
+        let file_name = path.file_name().unwrap().to_string_lossy().to_string();
+        let mut header = tar::Header::new_gnu();
+        header.set_mode(metadata.permissions().mode() & 0o7777);
+        header.set_mtime(mtime(&path)?);
+
+        if metadata.is_dir() {
+            let member = relative.join(&file_name);
+            header.set_entry_type(tar::EntryType::Directory);
+            header.set_size(0);
+            builder.append_data(&mut header, &member, io::empty())?;
+            members += 1 + append_dir(builder, &path, &member, dicts)?;
+        } else if metadata.file_type().is_symlink() {
+            header.set_entry_type(tar::EntryType::Symlink);
+            header.set_size(0);
+            builder.append_link(
+                &mut header,
+                relative.join(&file_name),
+                fs::read_link(&path)?,
+            )?;
+            members += 1;
+        } else if file_name.ends_with(cdc::SUFFIX) {
+            // chunks are not packed, the file would be lost with the directory
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                format!("Chunked file '{}' can't be archived", path.display()),
+            ));
+        } else if let Some(name) = file_name.strip_suffix(".zst") {
+            let mut file = File::open(&path)?;
+            let mut data = vec![];
+            if meta::is_raw(&file)? {
+                file.read_to_end(&mut data)?;
+            } else {
+                let dict = dicts.for_file(&file)?.unwrap_or_default();
+                zstd::stream::Decoder::with_dictionary(BufReader::new(file), dict)?
+                    .read_to_end(&mut data)?;
+            }
+            header.set_entry_type(tar::EntryType::Regular);
+            header.set_size(data.len() as u64);
+            builder.append_data(&mut header, relative.join(name), &data[..])?;
+            members += 1;
+        } else {
+            debug!("Skipping '{}' in archive", path.display());
+        }
+    }
+    Ok(members)
+}
+
+/// Packs compressed files of the directory to a single archive
+/// (`dict` is used to compress the archive, `dicts` to read the files)
+///
+/// Returns the number of members
+pub fn pack<W: Write>(
+    dir: &Path,
+    target: W,
+    dicts: &Dictionaries,
+    dict: Option<&[u8]>,
+    level: i32,
+    params: &[CParameter],
+) -> io::Result<usize> {
+    let mut encoder =
+        zstd::stream::Encoder::with_dictionary(target, level, dict.unwrap_or_default())?;
+    encoder.include_checksum(true)?;
+    params::apply(&mut encoder, params)?;
+    let mut builder = tar::Builder::new(encoder);
+    let members = append_dir(&mut builder, dir, Path::new(""), dicts)?;
+    builder.into_inner()?.finish()?.flush()?;
+    Ok(members)
+}
+
+/// Extracts members of the archive as compressed files
+///
+/// Returns the number of members
+pub fn extract(
+    archive: &Path,
+    target: &Path,
+    dicts: &Dictionaries,
+    level: i32,
+) -> io::Result<usize> {
+    let file = File::open(archive)?;
+    let dict = dicts.for_file(&file)?.unwrap_or_default();
+    let decoder = zstd::stream::Decoder::with_dictionary(BufReader::new(file), dict)?;
+    let mut archive = tar::Archive::new(decoder);
+    fs::create_dir_all(target)?;
+
+    let mut members = 0;
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let member = entry.path()?.into_owned();
+        // members can't be extracted outside of the target
+        if member
+            .components()
+            .any(|e| !matches!(e, Component::Normal(_) | Component::CurDir))
+        {
+            warn!("Skipping archive member '{}'", member.display());
+            continue;
+        }
+        let path = target.join(&member);
+        let mode = entry.header().mode()? & 0o7777;
+        let mtime = FileTime::from_unix_time(entry.header().mtime()? as i64, 0);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        match entry.header().entry_type() {
+            tar::EntryType::Directory => {
+                fs::create_dir_all(&path)?;
+                fs::set_permissions(&path, Permissions::from_mode(mode))?;
+            }
+            tar::EntryType::Regular | tar::EntryType::Continuous => {
+                let mut data = vec![];
+                entry.read_to_end(&mut data)?;
+                let mut file_path = path.into_os_string();
+                file_path.push(".zst");
+                let file = File::create(&file_path)?;
+                codec::compress(
+                    &data[..],
+                    BufWriter::new(&file),
+                    Some(data.len() as u64),
+                    level,
+                    &[],
+                    None,
+                    None,
+                )?;
+                Meta::new(level, data.len() as u64).write(&file)?;
+                file.set_permissions(Permissions::from_mode(mode))?;
+                filetime::set_file_handle_times(&file, None, Some(mtime))?;
+            }
+            tar::EntryType::Symlink => {
+                if let Some(link) = entry.link_name()? {
+                    unix::fs::symlink(link, &path)?;
+                }
+            }
+            other => {
+                debug!(
+                    "Skipping archive member '{}' ({:?})",
+                    member.display(),
+                    other
+                );
+                continue;
+            }
+        }
+        members += 1;
+    }
+    Ok(members)
+}
+
+/// Replaces the directory in data dir by an archive
+pub fn create(
+    dir: &Path,
+    dicts: &Dictionaries,
+    dict: Option<&[u8]>,
+    level: i32,
+    params: &[CParameter],
+) -> io::Result<PathBuf> {
+    let mut archive_path = dir.as_os_str().to_owned();
+    archive_path.push(SUFFIX);
+    let archive_path = PathBuf::from(archive_path);
+    let parent = dir
+        .parent()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "Wrong directory"))?;
+
+    let tmp_file = tempfile::NamedTempFile::new_in(parent)?;
+    let members = pack(
+        dir,
+        BufWriter::new(tmp_file.reopen()?),
+        dicts,
+        dict,
+        level,
+        params,
+    )?;
+    // directory keeps its inode
+    if let Some(ino) = xattr::get(dir, "user.ino")? {
+        tmp_file.as_file().set_xattr("user.ino", &ino)?;
+    }
+    tmp_file.as_file().sync_all()?;
+    tmp_file.persist_noclobber(&archive_path)?;
+    fs::remove_dir_all(dir)?;
+    debug!(
+        "Directory '{}' archived ({} members)",
+        dir.display(),
+        members
+    );
+    Ok(archive_path)
+}
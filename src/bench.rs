@@ -0,0 +1,142 @@
+//! Measures compression ratio and throughput of data dir content
+//! so that a suitable compression level can be chosen
+
+use std::{
+    fs,
+    io::{self, Read},
+    path::Path,
+    time::{Duration, Instant},
+};
+
+use log::debug;
+use zstd::zstd_safe::CParameter;
+
+use crate::{codec, dict::Dictionaries};
+
+/// Max amount of decompressed data used for measurement
+const MAX_SAMPLES_SIZE: u64 = 64 * 1024 * 1024;
+
+#[derive(Debug, Clone)]
+pub struct LevelResult {
+    pub level: i32,
+    pub input: u64,
+    pub output: u64,
+    pub elapsed: Duration,
+}
+
+impl LevelResult {
+    pub fn ratio(&self) -> f64 {
+        if self.input > 0 {
+            self.output as f64 / self.input as f64
+        } else {
+            0.0
+        }
+    }
+
+    /// Throughput in MB/s of uncompressed data
+    pub fn throughput(&self) -> f64 {
+        self.input as f64 / 1_000_000.0 / self.elapsed.as_secs_f64().max(f64::EPSILON)
+    }
+}
+
+/// Reads files from data dir (compressed ones are decompressed)
+fn collect_samples(
+    dir: &Path,
+    dicts: &Dictionaries,
+    samples: &mut Vec<Vec<u8>>,
+    size: &mut u64,
+) -> io::Result<()> {
+    let mut entries = fs::read_dir(dir)?
+        .map(|e| e.map(|e| e.path()))
+        .collect::<io::Result<Vec<_>>>()?;
+    entries.sort();
+
+    for path in entries {
+        if *size >= MAX_SAMPLES_SIZE {
+            break;
+        }
+        // files used by fuse-zstd itself
+        if path
+            .file_name()
+            .map(|e| e.to_string_lossy().starts_with(".fuse-zstd"))
+            .unwrap_or(false)
+        {
+            continue;
+        }
+        let file_type = fs::symlink_metadata(&path)?.file_type();
+        if file_type.is_dir() {
+            collect_samples(&path, dicts, samples, size)?;
+        } else if file_type.is_file() {
+            let file = fs::File::open(&path)?;
+            let limit = MAX_SAMPLES_SIZE - *size;
+            let mut data = vec![];
+            if path.extension().map(|e| e == "zst").unwrap_or(false) {
+                let dict = dicts.for_file(&file)?.unwrap_or_default();
+                let decoder =
+                    zstd::stream::Decoder::with_dictionary(io::BufReader::new(file), dict)?;
+                if let Err(err) = decoder.take(limit).read_to_end(&mut data) {
+                    debug!("Skipping '{}' ({})", path.display(), err);
+                    continue;
+                }
+            } else {
+                file.take(limit).read_to_end(&mut data)?;
+            }
+            *size += data.len() as u64;
+            samples.push(data);
+        }
+    }
+    Ok(())
+}
+
+/// Compresses files from data dir using each level
+pub fn bench(
+    data_dir: &Path,
+    levels: &[i32],
+    params: &[CParameter],
+    index_interval: Option<usize>,
+) -> io::Result<Vec<LevelResult>> {
+    let dicts = Dictionaries::load(data_dir)?;
+    let mut samples = vec![];
+    let mut size = 0;
+    collect_samples(data_dir, &dicts, &mut samples, &mut size)?;
+    debug!("Benchmarking {} files ({} bytes)", samples.len(), size);
+
+    let mut res = vec![];
+    for level in levels {
+        let mut output = 0;
+        let started = Instant::now();
+        for sample in &samples {
+            let mut compressed = Vec::with_capacity(sample.len());
+            codec::compress(
+                &sample[..],
+                &mut compressed,
+                Some(sample.len() as u64),
+                *level,
+                params,
+                dicts.current(),
+                index_interval,
+            )?;
+            output += compressed.len() as u64;
+        }
+        res.push(LevelResult {
+            level: *level,
+            input: size,
+            output,
+            elapsed: started.elapsed(),
+        });
+    }
+    Ok(res)
+}
+
+/// Prints results as a table
+pub fn print(results: &[LevelResult]) {
+    println!("{:>5} {:>8} {:>10}", "level", "ratio", "MB/s");
+    for result in results {
+        println!(
+            "{:>5} {:>8.3} {:>10.1}",
+            result.level,
+            result.ratio(),
+            result.throughput()
+        );
+    }
+}
@@ -1,31 +1,210 @@
-use std::path::Path;
+use std::{
+    collections::HashMap,
+    fs, io,
+    os::linux::fs::MetadataExt,
+    path::{Path, PathBuf},
+};
 
+use log::warn;
 use sled;
 use tempfile::TempDir;
 
 use crate::errors::{convert_io_error, convert_sled_error};
-use crate::Inode;
+use crate::meta::Meta;
+use crate::{xattrs, Inode};
+
+/// Cache is rebuilt from inodes stored in data dir when this file
+/// in the root of mount point is written
+pub const REBUILD_NAME: &str = ".fuse-zstd-rebuild-cache";
+/// Cached metadata are persisted to this file in data dir when it lacks xattrs
+pub const METAS_NAME: &str = ".fuse-zstd-metas";
+/// Size, mtime and mtime nanoseconds of compressed file which the metadata belong to
+const META_VERSION_SIZE: usize = 24;
+/// Marks value which contains parent inode and name instead of a path
+const PARENT_TAG: u8 = 0;
+/// Limits the lookup of parents of broken entries (e.g. in a cycle)
+const MAX_DEPTH: usize = 4096;
+
+/// Value of cached entry
+enum Entry {
+    Path(String),
+    Child(Inode, String),
+}
 
 pub struct InodeCache {
+    /// Paths are stored relative to this directory (data dir)
+    /// to keep them short and valid when data dir is moved
+    root: PathBuf,
     inode_dir: TempDir,
     inode_db: sled::Db,
+    /// Inodes of symlinks by inodes in data dir
+    /// (symlinks can't have extended attributes)
+    symlinks: sled::Tree,
+    /// Metadata of compressed files by inodes in data dir, compressed sizes and mtimes
+    /// (`--network-backing`)
+    metas: sled::Tree,
+    /// Metadata were changed since they were persisted
+    metas_dirty: bool,
+    /// Inodes of listed entries by inodes in data dir which are not stored
+    /// in extended attributes until the entry is looked up
+    pending: sled::Tree,
+    /// Number of references which kernel holds for each inode
+    lookups: HashMap<Inode, u64>,
+    /// Database failed (e.g. its filesystem is full), entries are kept in memory
+    degraded: bool,
+    memory: HashMap<Inode, String>,
+    memory_symlinks: HashMap<u64, Inode>,
+    memory_metas: HashMap<u64, Vec<u8>>,
+    memory_pending: HashMap<u64, Inode>,
+    /// Entries are stored as parent inode and name when the parent is cached
+    /// (`--cache-compression`), so renamed directory updates only its own entry
+    tree: bool,
+    /// Number of entries in database which are stored as paths
+    path_entries: usize,
 }
 
 impl InodeCache {
-    pub fn new<P>(data_dir: P) -> Result<Self, libc::c_int>
+    pub fn new<P1, P2>(cache_root: P1, root: P2, tree: bool) -> Result<Self, libc::c_int>
     where
-        P: AsRef<Path>,
+        P1: AsRef<Path>,
+        P2: AsRef<Path>,
     {
-        let inode_dir = TempDir::new_in(data_dir).map_err(convert_io_error)?;
+        // database has to be inside of cache root which is hidden from the mount point
+        let inode_dir = TempDir::new_in(cache_root).map_err(convert_io_error)?;
         let inode_db = sled::open(&inode_dir).map_err(convert_sled_error)?;
+        let symlinks = inode_db.open_tree("symlinks").map_err(convert_sled_error)?;
+        let metas = inode_db.open_tree("metas").map_err(convert_sled_error)?;
+        let pending = inode_db.open_tree("pending").map_err(convert_sled_error)?;
         Ok(Self {
+            root: root.as_ref().to_path_buf(),
             inode_dir,
             inode_db,
+            symlinks,
+            metas,
+            metas_dirty: false,
+            pending,
+            lookups: HashMap::new(),
+            degraded: false,
+            memory: HashMap::new(),
+            memory_symlinks: HashMap::new(),
+            memory_metas: HashMap::new(),
+            memory_pending: HashMap::new(),
+            tree,
+            path_entries: 0,
         })
     }
 
-    fn extract_data(data: &[u8]) -> String {
-        String::from_utf8_lossy(&data[8..]).to_string()
+    /// Recreates the database when its directory was removed (e.g. directly in data dir)
+    ///
+    /// Returns `true` when the cache was recreated
+    pub fn ensure_exists<P>(&mut self, cache_root: P) -> Result<bool, libc::c_int>
+    where
+        P: AsRef<Path>,
+    {
+        if self.inode_dir.path().exists() {
+            return Ok(false);
+        }
+        self.reopen(cache_root)?;
+        Ok(true)
+    }
+
+    /// Copies the entries to a new database, so the space of removed entries is reclaimed
+    ///
+    /// Returns the size of the new database
+    pub fn compact<P>(&mut self, cache_root: P) -> Result<u64, libc::c_int>
+    where
+        P: AsRef<Path>,
+    {
+        if self.degraded {
+            return Ok(self.size_on_disk());
+        }
+        self.reopen(cache_root)?;
+        self.inode_db.flush().map_err(convert_sled_error)?;
+        Ok(self.size_on_disk())
+    }
+
+    /// Removes paths of all inodes (other entries can't be restored from data dir)
+    pub fn clear_paths(&mut self) -> Result<(), libc::c_int> {
+        self.memory.clear();
+        self.path_entries = 0;
+        if let Err(err) = self.inode_db.clear() {
+            self.failed(err);
+        }
+        Ok(())
+    }
+
+    /// Size of database files
+    pub fn size_on_disk(&self) -> u64 {
+        self.inode_db.size_on_disk().unwrap_or_default()
+    }
+
+    /// Creates a new database in `cache_root` with the entries of the current one
+    fn reopen<P>(&mut self, cache_root: P) -> Result<(), libc::c_int>
+    where
+        P: AsRef<Path>,
+    {
+        fs::create_dir_all(cache_root.as_ref()).map_err(convert_io_error)?;
+        let inode_dir = TempDir::new_in(cache_root).map_err(convert_io_error)?;
+        let inode_db = sled::open(&inode_dir).map_err(convert_sled_error)?;
+        let symlinks = inode_db.open_tree("symlinks").map_err(convert_sled_error)?;
+        let metas = inode_db.open_tree("metas").map_err(convert_sled_error)?;
+        let pending = inode_db.open_tree("pending").map_err(convert_sled_error)?;
+
+        // database is still opened (even when its directory was removed) so its entries can be copied
+        for (source, target) in [
+            (&*self.inode_db, &*inode_db),
+            (&self.symlinks, &symlinks),
+            (&self.metas, &metas),
+            (&self.pending, &pending),
+        ] {
+            for (key, value) in source.iter().flatten() {
+                target.insert(key, value).map_err(convert_sled_error)?;
+            }
+        }
+        self.inode_db = inode_db;
+        self.symlinks = symlinks;
+        self.metas = metas;
+        self.pending = pending;
+        // previous directory is removed once its database is closed
+        self.inode_dir = inode_dir;
+        Ok(())
+    }
+
+    /// Cache is only an optimization so its failures are not reported,
+    /// inodes which are not found are searched in data dir instead
+    fn failed(&mut self, err: sled::Error) {
+        if !self.degraded {
+            warn!(
+                "Inode cache failed, keeping entries in memory (err={})",
+                convert_sled_error(err)
+            );
+            self.degraded = true;
+        }
+    }
+
+    pub fn is_degraded(&self) -> bool {
+        self.degraded
+    }
+
+    fn extract_data(data: &[u8]) -> Entry {
+        match &data[8..] {
+            [PARENT_TAG, rest @ ..] if rest.len() >= 8 => Entry::Child(
+                Inode::from_be_bytes(rest[..8].try_into().unwrap()),
+                String::from_utf8_lossy(&rest[8..]).to_string(),
+            ),
+            value => Entry::Path(String::from_utf8_lossy(value).to_string()),
+        }
+    }
+
+    /// Keeps the number of entries stored as paths
+    fn count_path_entry(&mut self, data: Option<&[u8]>, added: bool) {
+        if let Some(Entry::Path(_)) = data.map(Self::extract_data) {
+            if added {
+                self.path_entries += 1;
+            } else {
+                self.path_entries = self.path_entries.saturating_sub(1);
+            }
+        }
     }
 
     fn make_data(inode: Inode, value: &[u8]) -> Vec<u8> {
@@ -37,15 +216,30 @@ impl InodeCache {
     }
 
     pub fn get_inode_path(&mut self, ino: Inode) -> Result<String, libc::c_int> {
-        let data = self
-            .inode_db
-            .get(&ino.to_be_bytes())
-            .map(|e| e.to_owned())
-            .map_err(convert_sled_error)?;
+        let relative = self.relative_path(ino, 0)?;
+        let path = self.root.join(relative);
+        Ok(path.to_string_lossy().to_string())
+    }
+
+    /// Path relative to root composed from the paths of parents
+    fn relative_path(&mut self, ino: Inode, depth: usize) -> Result<String, libc::c_int> {
+        if depth > MAX_DEPTH {
+            return Err(libc::EIO);
+        }
+        let data = match self.memory.get(&ino) {
+            Some(data) => Some(Entry::Path(data.clone())),
+            None => match self.inode_db.get(ino.to_be_bytes()) {
+                Ok(data) => data.map(|e| Self::extract_data(&e)),
+                Err(err) => {
+                    self.failed(err);
+                    None
+                }
+            },
+        };
         match data {
-            Some(data) => {
-                let path = Self::extract_data(&data);
-                Ok(path)
+            Some(Entry::Path(path)) => Ok(path),
+            Some(Entry::Child(parent, name)) => {
+                Self::make_path_str(self.relative_path(parent, depth + 1)?, name)
             }
             None => Err(libc::ENOENT),
         }
@@ -53,12 +247,31 @@ impl InodeCache {
 
     pub fn del_inode_path(&mut self, ino: Inode) -> Result<(), libc::c_int> {
         // remove inode - best effort
-        self.inode_db
-            .remove(&ino.to_be_bytes())
-            .map_err(convert_sled_error)?;
+        self.memory.remove(&ino);
+        match self.inode_db.remove(ino.to_be_bytes()) {
+            Ok(previous) => self.count_path_entry(previous.as_deref(), false),
+            Err(err) => self.failed(err),
+        }
         Ok(())
     }
 
+    /// Inode of cached parent directory
+    fn cached_parent(&self, ino: Inode, path: &Path) -> Option<Inode> {
+        if !self.tree || self.relative(path).as_os_str().is_empty() {
+            return None;
+        }
+        let parent = xattr::get(path, "user.ino")
+            .ok()
+            .flatten()
+            .and_then(|e| xattrs::parse_number(&e))?;
+        (parent != ino && matches!(self.inode_db.contains_key(parent.to_be_bytes()), Ok(true)))
+            .then_some(parent)
+    }
+
+    fn relative<'a>(&self, path: &'a Path) -> &'a Path {
+        path.strip_prefix(&self.root).unwrap_or(path)
+    }
+
     fn make_path_str<P, N>(path: P, name: N) -> Result<String, libc::c_int>
     where
         P: AsRef<Path>,
@@ -87,16 +300,326 @@ impl InodeCache {
         P: AsRef<Path>,
         N: ToString,
     {
-        let path_data = Self::make_path_str(path, name)?.as_bytes().to_vec();
-        let data = Self::make_data(ino, &path_data);
-        Ok(self
-            .inode_db
-            .insert(ino.to_be_bytes(), data)
-            .map_err(convert_sled_error)?
-            .is_some())
+        let path_str = Self::make_path_str(self.relative(path.as_ref()), name.to_string())?;
+        if self.degraded {
+            return Ok(self.memory.insert(ino, path_str).is_some());
+        }
+        let data = match self.cached_parent(ino, path.as_ref()) {
+            Some(parent) => {
+                let mut value = vec![PARENT_TAG];
+                value.extend(parent.to_be_bytes());
+                value.extend(name.to_string().as_bytes());
+                Self::make_data(ino, &value)
+            }
+            None => Self::make_data(ino, path_str.as_bytes()),
+        };
+        match self.inode_db.insert(ino.to_be_bytes(), data.as_slice()) {
+            Ok(previous) => {
+                self.count_path_entry(previous.as_deref(), false);
+                self.count_path_entry(Some(&data), true);
+                Ok(previous.is_some())
+            }
+            Err(err) => {
+                self.failed(err);
+                Ok(self.memory.insert(ino, path_str).is_some())
+            }
+        }
+    }
+
+    pub fn get_symlink_inode(&mut self, source_ino: u64) -> Result<Option<Inode>, libc::c_int> {
+        if let Some(ino) = self.memory_symlinks.get(&source_ino) {
+            return Ok(Some(*ino));
+        }
+        match self.symlinks.get(source_ino.to_be_bytes()) {
+            Ok(data) => Ok(data.and_then(|e| e.as_ref().try_into().ok().map(Inode::from_be_bytes))),
+            Err(err) => {
+                self.failed(err);
+                Ok(None)
+            }
+        }
+    }
+
+    pub fn set_symlink_inode(&mut self, source_ino: u64, ino: Inode) -> Result<(), libc::c_int> {
+        if !self.degraded {
+            match self
+                .symlinks
+                .insert(source_ino.to_be_bytes(), &ino.to_be_bytes())
+            {
+                Ok(_) => return Ok(()),
+                Err(err) => self.failed(err),
+            }
+        }
+        self.memory_symlinks.insert(source_ino, ino);
+        Ok(())
+    }
+
+    pub fn del_symlink_inode(&mut self, source_ino: u64) -> Result<(), libc::c_int> {
+        self.memory_symlinks.remove(&source_ino);
+        if let Err(err) = self.symlinks.remove(source_ino.to_be_bytes()) {
+            self.failed(err);
+        }
+        Ok(())
+    }
+
+    pub fn get_pending_inode(&mut self, source_ino: u64) -> Result<Option<Inode>, libc::c_int> {
+        if let Some(ino) = self.memory_pending.get(&source_ino) {
+            return Ok(Some(*ino));
+        }
+        match self.pending.get(source_ino.to_be_bytes()) {
+            Ok(data) => Ok(data.and_then(|e| e.as_ref().try_into().ok().map(Inode::from_be_bytes))),
+            Err(err) => {
+                self.failed(err);
+                Ok(None)
+            }
+        }
+    }
+
+    pub fn set_pending_inode(&mut self, source_ino: u64, ino: Inode) -> Result<(), libc::c_int> {
+        if !self.degraded {
+            match self
+                .pending
+                .insert(source_ino.to_be_bytes(), &ino.to_be_bytes())
+            {
+                Ok(_) => return Ok(()),
+                Err(err) => self.failed(err),
+            }
+        }
+        self.memory_pending.insert(source_ino, ino);
+        Ok(())
+    }
+
+    pub fn del_pending_inode(&mut self, source_ino: u64) -> Result<(), libc::c_int> {
+        self.memory_pending.remove(&source_ino);
+        if let Err(err) = self.pending.remove(source_ino.to_be_bytes()) {
+            self.failed(err);
+        }
+        Ok(())
+    }
+
+    /// Compressed file which the cached metadata belong to
+    fn meta_version(metadata: &fs::Metadata) -> [u8; META_VERSION_SIZE] {
+        let mut res = [0; META_VERSION_SIZE];
+        res[..8].copy_from_slice(&metadata.st_size().to_be_bytes());
+        res[8..16].copy_from_slice(&metadata.st_mtime().to_be_bytes());
+        res[16..].copy_from_slice(&metadata.st_mtime_nsec().to_be_bytes());
+        res
+    }
+
+    /// Cached metadata of a compressed file
+    /// (entry of a file changed in the meantime is ignored, e.g. inode reused by NFS)
+    pub fn get_meta(&mut self, metadata: &fs::Metadata) -> Result<Option<Meta>, libc::c_int> {
+        let source_ino = metadata.st_ino();
+        let data = match self.memory_metas.get(&source_ino) {
+            Some(data) => Some(data.clone()),
+            None => match self.metas.get(source_ino.to_be_bytes()) {
+                Ok(data) => data.map(|e| e.to_vec()),
+                Err(err) => {
+                    self.failed(err);
+                    None
+                }
+            },
+        };
+        let version = Self::meta_version(metadata);
+        Ok(data
+            .filter(|e| e.len() > META_VERSION_SIZE && e[..META_VERSION_SIZE] == version)
+            .and_then(|e| Meta::decode(&e[META_VERSION_SIZE..]).ok()))
+    }
+
+    pub fn set_meta(&mut self, metadata: &fs::Metadata, meta: &Meta) -> Result<(), libc::c_int> {
+        let data: Vec<u8> = Self::meta_version(metadata)
+            .into_iter()
+            .chain(meta.encode())
+            .collect();
+        self.insert_meta(metadata.st_ino(), data);
+        self.metas_dirty = true;
+        Ok(())
+    }
+
+    fn insert_meta(&mut self, source_ino: u64, data: Vec<u8>) {
+        if !self.degraded {
+            match self.metas.insert(source_ino.to_be_bytes(), data.as_slice()) {
+                Ok(_) => return,
+                Err(err) => self.failed(err),
+            }
+        }
+        self.memory_metas.insert(source_ino, data);
+    }
+
+    /// Reads metadata persisted by a previous mount
+    ///
+    /// Records are an inode, the length of the value and the value itself
+    ///
+    /// Returns the number of loaded entries
+    pub fn load_metas(&mut self, path: &Path) -> io::Result<usize> {
+        let content = match fs::read(path) {
+            Ok(content) => content,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(0),
+            Err(err) => return Err(err),
+        };
+        let mut loaded = 0;
+        let mut rest = content.as_slice();
+        while !rest.is_empty() {
+            let Some((header, data)) = rest.split_at_checked(9) else {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "Metadata file is truncated",
+                ));
+            };
+            let size = header[8] as usize;
+            let Some((value, next)) = data.split_at_checked(size) else {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "Metadata file is truncated",
+                ));
+            };
+            let source_ino = u64::from_be_bytes(header[..8].try_into().unwrap());
+            self.insert_meta(source_ino, value.to_vec());
+            loaded += 1;
+            rest = next;
+        }
+        Ok(loaded)
+    }
+
+    /// Persists cached metadata when they changed
+    pub fn save_metas(&mut self, path: &Path) -> io::Result<()> {
+        if !self.metas_dirty {
+            return Ok(());
+        }
+        let mut entries: HashMap<u64, Vec<u8>> = self
+            .metas
+            .iter()
+            .flatten()
+            .filter_map(|(key, value)| {
+                let source_ino = u64::from_be_bytes(key.as_ref().try_into().ok()?);
+                Some((source_ino, value.to_vec()))
+            })
+            .collect();
+        entries.extend(self.memory_metas.clone());
+        let mut content = vec![];
+        for (source_ino, value) in entries.into_iter().filter(|(_, e)| e.len() < 256) {
+            content.extend(source_ino.to_be_bytes());
+            content.push(value.len() as u8);
+            content.extend(value);
+        }
+        let dir = path.parent().unwrap_or(Path::new("."));
+        let tmp_file = tempfile::NamedTempFile::new_in(dir)?;
+        fs::write(tmp_file.path(), content)?;
+        tmp_file.persist(path)?;
+        self.metas_dirty = false;
+        Ok(())
+    }
+
+    /// Updates paths of all cached entries inside renamed directory
+    pub fn rename_dir<P1, P2>(&mut self, from: P1, to: P2) -> Result<usize, libc::c_int>
+    where
+        P1: AsRef<Path>,
+        P2: AsRef<Path>,
+    {
+        let from = self.relative(from.as_ref()).to_path_buf();
+        let to = self.relative(to.as_ref()).to_path_buf();
+        let moved = |path: &str| -> Result<Option<String>, libc::c_int> {
+            match Path::new(path).strip_prefix(&from) {
+                Ok(rest) if !rest.as_os_str().is_empty() => {
+                    Self::make_path_str(&to, rest.display()).map(Some)
+                }
+                _ => Ok(None),
+            }
+        };
+
+        let mut updated = 0;
+        for path in self.memory.values_mut() {
+            if let Some(new_path) = moved(path)? {
+                *path = new_path;
+                updated += 1;
+            }
+        }
+        // entries refer to the renamed directory by its inode
+        if self.tree && self.path_entries == 0 {
+            return Ok(updated);
+        }
+        let mut renamed = vec![];
+        for item in self.inode_db.iter() {
+            let (key, data) = match item {
+                Ok(item) => item,
+                Err(err) => {
+                    // entries which are not updated are searched again
+                    self.failed(err);
+                    break;
+                }
+            };
+            let Entry::Path(path) = Self::extract_data(&data) else {
+                continue;
+            };
+            if let Some(new_path) = moved(&path)? {
+                let ino = Inode::from_be_bytes(key.as_ref().try_into().map_err(|_| libc::EIO)?);
+                renamed.push((ino, new_path));
+            }
+        }
+        for (ino, new_path) in renamed {
+            self.set_inode_path(ino, "", new_path)?;
+            updated += 1;
+        }
+        Ok(updated)
+    }
+
+    /// Kernel obtained a new reference to the inode
+    pub fn lookup(&mut self, ino: Inode) {
+        *self.lookups.entry(ino).or_default() += 1;
+    }
+
+    /// Kernel dropped `nlookup` references to the inode
+    ///
+    /// Returns `true` when no references remain
+    pub fn forget(&mut self, ino: Inode, nlookup: u64) -> bool {
+        match self.lookups.get_mut(&ino) {
+            Some(count) if *count > nlookup => {
+                *count -= nlookup;
+                false
+            }
+            _ => {
+                self.lookups.remove(&ino);
+                true
+            }
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.inode_db.len() + self.memory.len()
     }
 
     pub fn cache_data_dir(&self) -> &tempfile::TempDir {
         &self.inode_dir
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn persisted_metas() {
+        let root = tempfile::tempdir().unwrap();
+        let file = root.path().join("file.txt.zst");
+        fs::write(&file, b"DATA").unwrap();
+        let metadata = fs::metadata(&file).unwrap();
+        let meta = Meta::new(3, 1000);
+
+        let path = root.path().join(METAS_NAME);
+        for name in ["first", "second"] {
+            fs::create_dir(root.path().join(name)).unwrap();
+        }
+        let mut cache = InodeCache::new(root.path().join("first"), root.path(), false).unwrap();
+        cache.set_meta(&metadata, &meta).unwrap();
+        cache.save_metas(&path).unwrap();
+
+        let mut cache = InodeCache::new(root.path().join("second"), root.path(), false).unwrap();
+        assert_eq!(cache.load_metas(&path).unwrap(), 1);
+        assert_eq!(cache.get_meta(&metadata).unwrap(), Some(meta));
+
+        // same sized file was written in place
+        fs::write(&file, b"ATAD").unwrap();
+        filetime::set_file_mtime(&file, filetime::FileTime::from_unix_time(1000, 0)).unwrap();
+        let changed = fs::metadata(&file).unwrap();
+        assert_eq!(cache.get_meta(&changed).unwrap(), None);
+    }
+}
@@ -0,0 +1,309 @@
+//! Files split to chunks at content defined boundaries
+//!
+//! Each chunk is compressed and stored only once in chunk directory.
+//! Compressed file contains only a skippable frame with the list of its chunks
+//! and it is stored with [`SUFFIX`] instead of `.zst`.
+
+use std::{
+    collections::HashSet,
+    fs::{self, File},
+    io::{self, Read, Write},
+    os::unix::fs::FileExt,
+    path::Path,
+};
+
+use log::debug;
+use sha2::{Digest, Sha256};
+use zstd::zstd_safe::CParameter;
+
+use crate::params;
+
+/// Magic number of skippable frame containing the list of chunks
+const MAGIC: u32 = 0x184D2A5F;
+
+pub const CHUNKS_DIR: &str = ".fuse-zstd-chunks";
+/// Chunked files have their own suffix, other zstd tools can't decompress them
+pub const SUFFIX: &str = ".zstc";
+pub const EXTENSION: &str = "zstc";
+
+const MIN_SIZE: usize = 16 * 1024;
+/// Cut points are found when masked bits of the hash are zero (64KiB on average)
+const MASK: u64 = (1 << 16) - 1;
+const MAX_SIZE: usize = 256 * 1024;
+
+const fn gear_table() -> [u64; 256] {
+    // splitmix64
+    let mut table = [0; 256];
+    let mut state: u64 = 0x9E3779B97F4A7C15;
+    let mut i = 0;
+    while i < 256 {
+        state = state.wrapping_add(0x9E3779B97F4A7C15);
+        let mut z = state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D049BB133111EB);
+        table[i] = z ^ (z >> 31);
+        i += 1;
+    }
+    table
+}
+
+const GEAR: [u64; 256] = gear_table();
+
+#[derive(Debug, Clone)]
+pub struct Chunk {
+    /// Size of decompressed data
+    pub size: u64,
+    /// Path relative to chunk directory
+    pub name: String,
+}
+
+/// Size of the first chunk in data
+fn cut_point(data: &[u8]) -> usize {
+    if data.len() <= MIN_SIZE {
+        return data.len();
+    }
+    let mut hash: u64 = 0;
+    for (i, byte) in data.iter().enumerate().take(MAX_SIZE).skip(MIN_SIZE) {
+        hash = (hash << 1).wrapping_add(GEAR[*byte as usize]);
+        if hash & MASK == 0 {
+            return i + 1;
+        }
+    }
+    data.len().min(MAX_SIZE)
+}
+
+/// Hex encoded hash of the data (it has to be stable across builds, names are stored in files)
+fn chunk_hash(data: &[u8]) -> String {
+    Sha256::digest(data)
+        .iter()
+        .map(|e| format!("{:02x}", e))
+        .collect()
+}
+
+/// Name is `<prefix>/<hash>` or `<prefix>/<hash>-<suffix>`, the hash starts with the prefix
+fn valid_name(name: &str) -> bool {
+    let is_hex = |e: &str| !e.is_empty() && e.bytes().all(|e| e.is_ascii_hexdigit());
+    let Some((prefix, file_name)) = name.split_once('/') else {
+        return false;
+    };
+    let (hash, suffix) = file_name.split_once('-').unwrap_or((file_name, "0"));
+    prefix.len() == 2
+        && is_hex(prefix)
+        && is_hex(hash)
+        && hash.starts_with(prefix)
+        && !suffix.is_empty()
+        && suffix.bytes().all(|e| e.is_ascii_digit())
+}
+
+/// Stores the chunk unless the same chunk is already stored
+fn store_chunk(
+    chunks_dir: &Path,
+    data: &[u8],
+    level: i32,
+    params: &[CParameter],
+) -> io::Result<String> {
+    let hash = chunk_hash(data);
+    let dir = chunks_dir.join(&hash[..2]);
+    fs::create_dir_all(&dir)?;
+
+    // different chunks with the same hash get a suffix
+    for suffix in 0.. {
+        let name = if suffix == 0 {
+            format!("{}/{}", &hash[..2], hash)
+        } else {
+            format!("{}/{}-{}", &hash[..2], hash, suffix)
+        };
+        let path = chunks_dir.join(&name);
+        if path.exists() {
+            if zstd::decode_all(File::open(&path)?)? == data {
+                debug!("Chunk '{}' reused", name);
+                return Ok(name);
+            }
+            continue;
+        }
+
+        let mut encoder = zstd::stream::Encoder::new(vec![], level)?;
+        encoder.set_pledged_src_size(Some(data.len() as u64))?;
+        encoder.include_checksum(true)?;
+        params::apply(&mut encoder, params)?;
+        encoder.write_all(data)?;
+        let tmp_file = tempfile::NamedTempFile::new_in(&dir)?;
+        tmp_file.as_file().write_all(&encoder.finish()?)?;
+        tmp_file.as_file().sync_all()?;
+        match tmp_file.persist_noclobber(&path) {
+            Ok(_) => return Ok(name),
+            // stored by someone else in the meantime
+            Err(err) if err.error.kind() == io::ErrorKind::AlreadyExists => continue,
+            Err(err) => return Err(err.error),
+        }
+    }
+    unreachable!()
+}
+
+fn fill<R: Read>(source: &mut R, buffer: &mut Vec<u8>) -> io::Result<()> {
+    let mut start = buffer.len();
+    buffer.resize(MAX_SIZE, 0);
+    while start < buffer.len() {
+        match source.read(&mut buffer[start..]) {
+            Ok(0) => break,
+            Ok(read) => start += read,
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+            Err(err) => return Err(err),
+        }
+    }
+    buffer.truncate(start);
+    Ok(())
+}
+
+/// Splits the data to chunks, stores them and writes the list of chunks to target
+pub fn write_chunked<R, W>(
+    mut source: R,
+    mut target: W,
+    chunks_dir: &Path,
+    level: i32,
+    params: &[CParameter],
+) -> io::Result<()>
+where
+    R: Read,
+    W: Write,
+{
+    let mut payload = vec![];
+    let mut buffer = Vec::with_capacity(MAX_SIZE);
+    loop {
+        fill(&mut source, &mut buffer)?;
+        if buffer.is_empty() {
+            break;
+        }
+        let size = cut_point(&buffer);
+        let name = store_chunk(chunks_dir, &buffer[..size], level, params)?;
+        payload.extend((size as u64).to_le_bytes());
+        payload.push(name.len() as u8);
+        payload.extend(name.as_bytes());
+        buffer.drain(..size);
+    }
+
+    target.write_all(&MAGIC.to_le_bytes())?;
+    target.write_all(&(payload.len() as u32).to_le_bytes())?;
+    target.write_all(&payload)?;
+    target.flush()
+}
+
+/// Reads the list of chunks
+///
+/// Returns `None` when the file is not chunked
+pub fn read_chunks(file: &File) -> io::Result<Option<Vec<Chunk>>> {
+    let mut header = [0; 8];
+    if file.read_exact_at(&mut header, 0).is_err() {
+        return Ok(None);
+    }
+    let magic = u32::from_le_bytes(header[0..4].try_into().unwrap());
+    if magic != MAGIC {
+        return Ok(None);
+    }
+    let payload_size = u32::from_le_bytes(header[4..8].try_into().unwrap());
+    if u64::from(payload_size) + 8 > file.metadata()?.len() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "List of chunks is truncated",
+        ));
+    }
+    let mut payload = vec![0; payload_size as usize];
+    file.read_exact_at(&mut payload, 8)?;
+
+    let invalid = || io::Error::new(io::ErrorKind::InvalidData, "Wrong list of chunks");
+    let mut res = vec![];
+    let mut rest = &payload[..];
+    while !rest.is_empty() {
+        if rest.len() < 9 {
+            return Err(invalid());
+        }
+        let size = u64::from_le_bytes(rest[0..8].try_into().unwrap());
+        let name_len = rest[8] as usize;
+        let name = rest.get(9..9 + name_len).ok_or_else(invalid)?;
+        // names must not point outside of chunk directory
+        let name = std::str::from_utf8(name)
+            .ok()
+            .filter(|e| valid_name(e))
+            .ok_or_else(invalid)?;
+        res.push(Chunk {
+            size,
+            name: name.to_string(),
+        });
+        rest = &rest[9 + name_len..];
+    }
+    Ok(Some(res))
+}
+
+/// Writes decompressed chunks to target
+pub fn decompress_chunks<W: Write>(
+    chunks_dir: &Path,
+    chunks: &[Chunk],
+    mut target: W,
+) -> io::Result<()> {
+    for chunk in chunks {
+        let data = zstd::decode_all(File::open(chunks_dir.join(&chunk.name))?)?;
+        if data.len() as u64 != chunk.size {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Wrong size of chunk '{}'", chunk.name),
+            ));
+        }
+        target.write_all(&data)?;
+    }
+    target.flush()
+}
+
+fn collect_references(dir: &Path, skip: &Path, res: &mut HashSet<String>) -> io::Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let path = entry.path();
+        let file_type = entry.file_type()?;
+        if path == skip {
+            continue;
+        } else if file_type.is_dir() {
+            collect_references(&path, skip, res)?;
+        } else if file_type.is_file() {
+            // chunks of a file which can't be read may be still referenced
+            let chunks = read_chunks(&File::open(&path)?).map_err(|err| {
+                io::Error::new(err.kind(), format!("'{}': {}", path.display(), err))
+            })?;
+            if let Some(chunks) = chunks {
+                res.extend(chunks.into_iter().map(|e| e.name));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Removes chunks which are not referenced by any file in data dir
+///
+/// Should be called only when the data dir is not mounted
+pub fn collect_garbage(data_dir: &Path) -> io::Result<usize> {
+    let chunks_dir = data_dir.join(CHUNKS_DIR);
+    if !chunks_dir.exists() {
+        return Ok(0);
+    }
+    let mut references = HashSet::new();
+    collect_references(data_dir, &chunks_dir, &mut references)?;
+
+    let mut removed = 0;
+    for dir in fs::read_dir(&chunks_dir)? {
+        let dir = dir?;
+        if !dir.file_type()?.is_dir() {
+            continue;
+        }
+        for entry in fs::read_dir(dir.path())? {
+            let entry = entry?;
+            let name = format!(
+                "{}/{}",
+                dir.file_name().to_string_lossy(),
+                entry.file_name().to_string_lossy()
+            );
+            if !references.contains(&name) {
+                fs::remove_file(entry.path())?;
+                removed += 1;
+            }
+        }
+    }
+    Ok(removed)
+}
@@ -0,0 +1,101 @@
+//! Compression of file data shared by the filesystem and command line tools
+//!
+//! Allocation of a compression context (and loading of a dictionary into it)
+//! costs more than compression of a small file, so the context of the last
+//! compressed file is kept per thread and reused when the settings match.
+
+use std::{
+    cell::RefCell,
+    fs,
+    io::{self, Read, Write},
+    num::NonZeroU32,
+    os::unix::fs::FileExt,
+};
+
+use zstd::{
+    stream::{
+        raw::{self, Operation},
+        zio,
+    },
+    zstd_safe::CParameter,
+};
+
+use crate::index;
+
+/// Compresses the data to a single zstd frame
+/// or to indexed frames when `index_interval` is set
+pub fn compress<R, W>(
+    mut source: R,
+    target: W,
+    size: Option<u64>,
+    level: i32,
+    params: &[CParameter],
+    dict: Option<&[u8]>,
+    index_interval: Option<usize>,
+) -> io::Result<()>
+where
+    R: Read,
+    W: Write,
+{
+    if let Some(interval) = index_interval {
+        return index::write_indexed(source, target, level, interval, params, dict);
+    }
+    let settings = Settings {
+        level,
+        params: params.to_vec(),
+        dict_id: dict.map(zstd::zstd_safe::get_dict_id_from_dict),
+    };
+    // dictionaries without id (raw content) can't be told apart
+    let poolable = settings.dict_id != Some(None);
+    let mut encoder = match POOL.with(|e| e.borrow_mut().take()) {
+        Some((pooled, mut encoder)) if poolable && pooled == settings => {
+            // parameters and dictionary are kept
+            encoder.reinit()?;
+            encoder
+        }
+        _ => new_encoder(&settings, dict)?,
+    };
+    encoder.set_pledged_src_size(size)?;
+
+    let mut writer = zio::Writer::new(target, encoder);
+    io::copy(&mut source, &mut writer)?;
+    writer.finish()?;
+    let (mut target, encoder) = writer.into_inner();
+    // context of a failed compression is dropped
+    POOL.with(|e| *e.borrow_mut() = Some((settings, encoder)));
+    target.flush()
+}
+
+/// Settings of pooled compression context
+#[derive(PartialEq)]
+struct Settings {
+    level: i32,
+    params: Vec<CParameter>,
+    /// Dictionaries are compared by their id
+    dict_id: Option<Option<NonZeroU32>>,
+}
+
+thread_local! {
+    static POOL: RefCell<Option<(Settings, raw::Encoder<'static>)>> = const { RefCell::new(None) };
+}
+
+fn new_encoder(settings: &Settings, dict: Option<&[u8]>) -> io::Result<raw::Encoder<'static>> {
+    let mut encoder = raw::Encoder::with_dictionary(settings.level, dict.unwrap_or_default())?;
+    encoder.set_parameter(CParameter::ChecksumFlag(true))?;
+    for param in &settings.params {
+        encoder.set_parameter(*param)?;
+    }
+    Ok(encoder)
+}
+
+/// Max size of zstd frame header (`ZSTD_FRAMEHEADERSIZE_MAX`)
+const FRAME_HEADER_SIZE_MAX: usize = 18;
+
+/// Decompressed size stored in the header of the first frame (if present)
+pub fn frame_content_size(file: &fs::File) -> io::Result<Option<u64>> {
+    let mut header = [0; FRAME_HEADER_SIZE_MAX];
+    let size = file.read_at(&mut header, 0)?;
+    Ok(zstd::zstd_safe::get_frame_content_size(&header[..size])
+        .ok()
+        .flatten())
+}
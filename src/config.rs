@@ -0,0 +1,120 @@
+use std::{path::PathBuf, time::Duration};
+
+use zstd::zstd_safe::CParameter;
+
+/// When changes of opened files are compressed to data dir
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum SyncOn {
+    /// Each flush (close of any file descriptor), release and fsync
+    #[default]
+    Flush,
+    /// Last close of the file and fsync
+    Release,
+    /// Only fsync (changes which were not synced are dropped on release)
+    Fsync,
+}
+
+/// Which file is served when both `name` and `name.zst` exist in non-convert mode
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum OverlapPolicy {
+    /// Compressed file is served, the plain one is hidden
+    #[default]
+    PreferCompressed,
+    /// Plain file replaces the compressed one when it is looked up
+    PreferPlain,
+    /// Listing and lookup of the name fail
+    Error,
+}
+
+/// Options which affect the behavior of mounted filesystem
+#[derive(Debug, Default, Clone)]
+pub struct Config {
+    pub compression_level: i32,
+    /// Convert uncompressed data from original directory
+    /// to compressed files
+    pub convert: bool,
+    /// Keep mtime set via setattr when the file is compressed again
+    pub preserve_mtime: bool,
+    /// Subdirectory of data dir which is presented as mount root
+    pub subdir: Option<PathBuf>,
+    /// Check that stored real size matches decompressed data
+    pub verify_size: bool,
+    /// Extended attributes larger than this size are stored compressed
+    pub compress_xattrs: Option<usize>,
+    /// Store uncompressed copy of each file next to the compressed one
+    pub keep_plain: bool,
+    /// Permissions of new files (permissions of files are shown when set)
+    pub file_mode: Option<u32>,
+    /// Permissions of new directories (permissions of directories are shown when set)
+    pub dir_mode: Option<u32>,
+    /// Files are compressed to frames of this size with offsets stored in skippable frames
+    pub index_interval: Option<usize>,
+    /// Advanced zstd parameters set on each encoder
+    pub zstd_params: Vec<CParameter>,
+    /// Bytes of indexed files which are decompressed ahead of sequential reads
+    pub readahead: Option<u64>,
+    /// Where inode cache is stored when it can't be created in data dir
+    pub cache_dir: Option<PathBuf>,
+    /// Where decompressed data of opened files are stored
+    pub work_dir: Option<PathBuf>,
+    /// Files are opened with direct io (kernel page cache is not used)
+    pub direct_io: bool,
+    /// Files are split to deduplicated chunks at content defined boundaries
+    pub cdc: bool,
+    /// Existing data can't be modified or removed
+    pub append_only: bool,
+    /// Max number of decompressions running at the same time
+    pub max_concurrent_decompress: Option<usize>,
+    /// Convert only logs what would be done
+    pub convert_dry_run: bool,
+    /// Archives in data dir are presented as directories
+    pub archive_dirs: bool,
+    /// Sockets, FIFOs and device nodes are not hidden
+    pub show_special: bool,
+    /// When changes of opened files are compressed
+    pub sync_on: SyncOn,
+    /// Files which failed to be stored on release are kept opened
+    pub strict_close: bool,
+    /// Blocks are computed from decompressed size instead of compressed allocation
+    pub report_logical_blocks: bool,
+    /// Files are stored uncompressed (marked as raw in metadata)
+    pub no_compress: bool,
+    /// Files of these MIME types (`type/*` matches all subtypes) are stored uncompressed
+    pub skip_mime: Vec<String>,
+    /// Sizes of files created outside of fuse-zstd are stored when listed
+    pub fill_size_on_readdir: bool,
+    /// Data dir can be mounted by more processes at the same time
+    pub allow_shared: bool,
+    /// Changes of opened files are stored at least once per interval
+    pub flush_interval: Option<Duration>,
+    /// Deleted files are removed by a background thread
+    pub async_unlink: bool,
+    /// Files decompressed to more bytes can't be opened
+    pub max_decompressed_size: Option<u64>,
+    /// Inode cache stores parent inodes and names instead of paths
+    pub cache_compression: bool,
+    /// Inodes are `st_ino` of files in data dir instead of assigned ones
+    pub mirror_inodes: bool,
+    /// Data dir is on a network filesystem, metadata are cached in inode cache
+    pub network_backing: bool,
+    /// Data dir doesn't support extended attributes, metadata are kept only in inode cache
+    pub xattrs_unsupported: bool,
+    /// Compressed files are presented under names from manifests of their directories
+    pub name_manifests: bool,
+    /// Inode cache is checked once per interval and rebuilt when it grew
+    pub compact_cache_interval: Option<Duration>,
+    /// External dictionaries named by their ids
+    pub dictionary_dir: Option<PathBuf>,
+    /// Compressed files are verified in background
+    pub scrub: bool,
+    /// Limit of data read by scrubbing (bytes per second)
+    pub scrub_rate: Option<u64>,
+    /// Which file wins when plain and compressed files have the same name
+    pub overlap_policy: OverlapPolicy,
+    /// Durations of hot paths are measured and logged on unmount
+    pub profile: bool,
+    /// Opened files are not stored over compressed files changed outside of fuse-zstd
+    pub strict_external: bool,
+    /// New files are compressed with the dictionary of data dir
+    pub auto_dictionary: bool,
+}
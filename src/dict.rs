@@ -0,0 +1,245 @@
+//! Compression dictionaries stored in data dir
+//!
+//! Current dictionary is stored in `.fuse-zstd-dict` and it is used for new files.
+//! When it is trained again the previous one is kept as `.fuse-zstd-dict.<id>`
+//! so that files which were compressed using it remain readable.
+//!
+//! Dictionaries used by other systems can be loaded from an external directory
+//! (`--dictionary-dir`) where each file is named by the id of its dictionary.
+
+use std::{
+    collections::HashMap,
+    fs,
+    io::{self, Read},
+    os::unix::fs::FileExt,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use log::{debug, info};
+
+const DICT_NAME: &str = ".fuse-zstd-dict";
+
+/// Max size of trained dictionary
+const DICT_SIZE: usize = 112640;
+/// Only files smaller than this are used for training
+const SAMPLE_SIZE: u64 = 128 * 1024;
+const MAX_SAMPLES: usize = 10000;
+
+const SKIPPABLE_MAGIC: u32 = 0x184D2A50;
+const SKIPPABLE_MASK: u32 = 0xFFFFFFF0;
+
+#[derive(Debug, Default, Clone)]
+pub struct Dictionaries {
+    current: Option<u32>,
+    dicts: HashMap<u32, Arc<Vec<u8>>>,
+    /// Directory of external dictionaries
+    external_dir: Option<PathBuf>,
+}
+
+pub fn is_dict_path(data_dir: &Path, path: &Path) -> bool {
+    path.parent() == Some(data_dir)
+        && path
+            .file_name()
+            .map(|e| e.to_string_lossy().starts_with(DICT_NAME))
+            .unwrap_or(false)
+}
+
+fn dict_path(data_dir: &Path) -> PathBuf {
+    data_dir.join(DICT_NAME)
+}
+
+impl Dictionaries {
+    /// Loads current and previous dictionaries from data dir
+    pub fn load(data_dir: &Path) -> io::Result<Self> {
+        let mut res = Self::default();
+        let entries = match fs::read_dir(data_dir) {
+            Ok(entries) => entries,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(res),
+            Err(err) => return Err(err),
+        };
+        for entry in entries {
+            let path = entry?.path();
+            if !is_dict_path(data_dir, &path) || !path.is_file() {
+                continue;
+            }
+            let data = fs::read(&path)?;
+            let id = zstd::zstd_safe::get_dict_id_from_dict(&data).map(|e| e.get());
+            let id = if let Some(id) = id {
+                id
+            } else {
+                log::warn!("Dictionary '{}' is not valid, skipping", path.display());
+                continue;
+            };
+            debug!("Dictionary '{}' loaded (id={})", path.display(), id);
+            if path == dict_path(data_dir) {
+                res.current = Some(id);
+            }
+            res.dicts.insert(id, Arc::new(data));
+        }
+        Ok(res)
+    }
+
+    /// Loads dictionaries named by their ids from an external directory
+    ///
+    /// Returns the number of loaded dictionaries
+    pub fn load_external(&mut self, dir: &Path) -> io::Result<usize> {
+        let mut loaded = 0;
+        for entry in fs::read_dir(dir)? {
+            let path = entry?.path();
+            let Some(id) = path
+                .file_name()
+                .and_then(|e| e.to_str()?.parse::<u32>().ok())
+                .filter(|e| *e != 0)
+            else {
+                debug!("Skipping '{}' (not named by dictionary id)", path.display());
+                continue;
+            };
+            if !path.is_file() {
+                continue;
+            }
+            let data = fs::read(&path)?;
+            // raw content dictionaries have no id
+            match zstd::zstd_safe::get_dict_id_from_dict(&data).map(|e| e.get()) {
+                Some(dict_id) if dict_id != id => {
+                    log::warn!(
+                        "Dictionary '{}' has id {}, skipping",
+                        path.display(),
+                        dict_id
+                    );
+                    continue;
+                }
+                _ => {}
+            }
+            debug!(
+                "External dictionary '{}' loaded (id={})",
+                path.display(),
+                id
+            );
+            self.dicts.entry(id).or_insert_with(|| Arc::new(data));
+            loaded += 1;
+        }
+        self.external_dir = Some(dir.to_path_buf());
+        Ok(loaded)
+    }
+
+    /// Dictionary which should be used to compress new data
+    pub fn current(&self) -> Option<&[u8]> {
+        self.current
+            .and_then(|id| self.dicts.get(&id))
+            .map(|e| e.as_slice())
+    }
+
+    /// Dictionary which was used to compress the frame
+    pub fn for_frame(&self, frame: &[u8]) -> io::Result<Option<&[u8]>> {
+        match zstd::zstd_safe::get_dict_id_from_frame(frame) {
+            Some(id) => match self.dicts.get(&id.get()) {
+                Some(dict) => Ok(Some(dict.as_slice())),
+                None => Err(io::Error::new(
+                    io::ErrorKind::NotFound,
+                    match self.external_dir.as_ref() {
+                        Some(dir) => format!(
+                            "Dictionary {} not found (expected '{}')",
+                            id,
+                            dir.join(id.to_string()).display()
+                        ),
+                        None => format!(
+                            "Dictionary {} not found (external dictionaries are loaded with --dictionary-dir)",
+                            id
+                        ),
+                    },
+                )),
+            },
+            None => Ok(None),
+        }
+    }
+
+    /// Dictionary which was used to compress the file
+    pub fn for_file(&self, file: &fs::File) -> io::Result<Option<&[u8]>> {
+        // frame header has at most 18 bytes
+        let mut header = [0; 18];
+        let mut position = 0;
+        loop {
+            let size = file.read_at(&mut header, position)?;
+            let magic = u32::from_le_bytes(header[0..4].try_into().unwrap());
+            if size >= 8 && magic & SKIPPABLE_MASK == SKIPPABLE_MAGIC {
+                // skip frames with metadata
+                let frame_size = u32::from_le_bytes(header[4..8].try_into().unwrap());
+                position += 8 + frame_size as u64;
+            } else {
+                return self.for_frame(&header[..size]);
+            }
+        }
+    }
+}
+
+fn collect_samples(
+    dir: &Path,
+    skip: &Path,
+    dicts: &Dictionaries,
+    samples: &mut Vec<Vec<u8>>,
+) -> io::Result<()> {
+    for entry in fs::read_dir(dir)? {
+        if samples.len() >= MAX_SAMPLES {
+            break;
+        }
+        let entry = entry?;
+        let path = entry.path();
+        let file_type = entry.file_type()?;
+        if path == skip {
+            continue;
+        } else if file_type.is_dir() {
+            collect_samples(&path, skip, dicts, samples)?;
+        } else if file_type.is_file()
+            && path.extension().map(|e| e == "zst").unwrap_or(false)
+            && entry.metadata()?.len() < SAMPLE_SIZE
+        {
+            // files which can't be decompressed are not used
+            let file = fs::File::open(&path)?;
+            let dict = match dicts.for_file(&file) {
+                Ok(dict) => dict.unwrap_or_default(),
+                Err(_) => continue,
+            };
+            let decoder =
+                match zstd::stream::Decoder::with_dictionary(io::BufReader::new(file), dict) {
+                    Ok(decoder) => decoder,
+                    Err(_) => continue,
+                };
+            let mut data = vec![];
+            if decoder.take(SAMPLE_SIZE).read_to_end(&mut data).is_ok() && !data.is_empty() {
+                samples.push(data);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Trains a new dictionary from small files in data dir
+///
+/// The previous dictionary is kept so that older files can be decompressed.
+pub fn train(data_dir: &Path, cache_path: &Path) -> io::Result<u32> {
+    let dicts = Dictionaries::load(data_dir)?;
+    let mut samples = vec![];
+    collect_samples(data_dir, cache_path, &dicts, &mut samples)?;
+    info!("Training dictionary from {} files", samples.len());
+
+    let dict = zstd::dict::from_samples(&samples, DICT_SIZE)?;
+    let id = zstd::zstd_safe::get_dict_id_from_dict(&dict)
+        .map(|e| e.get())
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "Wrong dictionary trained"))?;
+
+    let path = dict_path(data_dir);
+    if let Ok(previous) = fs::read(&path) {
+        if let Some(previous_id) = zstd::zstd_safe::get_dict_id_from_dict(&previous) {
+            fs::write(
+                data_dir.join(format!("{}.{}", DICT_NAME, previous_id)),
+                previous,
+            )?;
+        }
+    }
+
+    let tmp_file = tempfile::NamedTempFile::new_in(data_dir)?;
+    fs::write(tmp_file.path(), &dict)?;
+    tmp_file.persist(&path)?;
+    Ok(id)
+}
@@ -15,3 +15,18 @@ pub fn convert_sled_error(err: sled::Error) -> libc::c_int {
         _ => libc::EIO,
     }
 }
+
+/// Errors caused by full filesystem or exceeded quota
+pub fn is_out_of_space(err: libc::c_int) -> bool {
+    err == libc::ENOSPC || err == libc::EDQUOT
+}
+
+/// Extended attributes are not supported (e.g. by a network filesystem)
+pub fn is_unsupported(err: &io::Error) -> bool {
+    err.raw_os_error() == Some(libc::ENOTSUP)
+}
+
+/// Errors of decompression, corrupted data are reported as `EFAULT`
+pub fn convert_decode_error(err: io::Error) -> libc::c_int {
+    err.raw_os_error().unwrap_or(libc::EFAULT)
+}
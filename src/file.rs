@@ -1,16 +1,57 @@
 use std::{
     collections::{HashMap, HashSet},
-    fs::File,
+    fs::{File, Metadata},
     io,
-    path::PathBuf,
+    os::linux::fs::MetadataExt,
+    path::{Path, PathBuf},
+    time::SystemTime,
 };
 
-use crate::Inode;
+use crate::{index, Inode};
 
 #[derive(Debug)]
 pub struct OpenedFiles {
     mount_point_inode_mapping: HashMap<u64, HashSet<u64>>,
+    /// Handles which read the compressed file of the inode using its index
+    indexed_mapping: HashMap<Inode, HashSet<u64>>,
     handlers: HashMap<u64, FileHandler>,
+    /// Size of data in opened files which may not be stored yet
+    logical_sizes: HashMap<Inode, u64>,
+    /// Compressed files from which opened files were decompressed
+    sources: HashMap<Inode, SourceVersion>,
+    /// Opened files which were not decompressed yet
+    pending: HashMap<Inode, Pending>,
+}
+
+/// Decompression postponed till the data are needed
+///
+/// Opened file contains only data written from its beginning,
+/// the rest is decompressed when it is needed (unless it is overwritten).
+#[derive(Debug)]
+pub struct Pending {
+    pub source: File,
+    /// Decompressed size of the source
+    pub real_size: u64,
+    /// Size of data written from the beginning of the file
+    pub written: u64,
+}
+
+/// Identifies the content of a compressed file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SourceVersion {
+    ino: u64,
+    size: u64,
+    mtime: Option<SystemTime>,
+}
+
+impl SourceVersion {
+    pub fn new(metadata: &Metadata) -> Self {
+        Self {
+            ino: metadata.st_ino(),
+            size: metadata.st_size(),
+            mtime: metadata.modified().ok(),
+        }
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -25,13 +66,27 @@ pub struct FileHandler {
     pub needs_sync: bool,
     pub file: File,
     pub refs: Option<References>,
+    /// mtime which should be set to source file after sync
+    pub mtime: Option<SystemTime>,
+    /// Compressed file is read directly using its index
+    pub index: Option<Vec<index::Entry>>,
+    /// Frames of indexed file which are decompressed in advance
+    pub readahead: Option<index::ReadAhead>,
+    /// Last frame of indexed file which was read
+    pub last_frame: index::LastFrame,
+    /// Compressed file which was indexed
+    pub indexed_source: Option<SourceVersion>,
 }
 
 impl OpenedFiles {
     pub fn new() -> Self {
         Self {
             mount_point_inode_mapping: HashMap::new(),
+            indexed_mapping: HashMap::new(),
             handlers: HashMap::new(),
+            logical_sizes: HashMap::new(),
+            sources: HashMap::new(),
+            pending: HashMap::new(),
         }
     }
 
@@ -54,6 +109,11 @@ impl OpenedFiles {
                 flags,
                 needs_sync: false,
                 refs: Some(References { inode, path }),
+                mtime: None,
+                index: None,
+                readahead: None,
+                last_frame: None,
+                indexed_source: None,
             },
         );
         self.mount_point_inode_mapping
@@ -64,6 +124,65 @@ impl OpenedFiles {
         Some(new_fh)
     }
 
+    /// Compressed file which is read directly
+    ///
+    /// The handler doesn't share data with other handlers of the inode
+    /// until it is opened without the index (see [`Self::share_indexed`])
+    #[allow(clippy::too_many_arguments)]
+    pub fn insert_indexed(
+        &mut self,
+        inode: Inode,
+        flags: i32,
+        file: File,
+        path: PathBuf,
+        index: Vec<index::Entry>,
+        readahead: Option<index::ReadAhead>,
+        source: SourceVersion,
+    ) -> Option<u64> {
+        let new_fh = self.new_fh_number()?;
+
+        let _ = self.handlers.insert(
+            new_fh,
+            FileHandler {
+                file,
+                flags,
+                needs_sync: false,
+                refs: Some(References { inode, path }),
+                mtime: None,
+                index: Some(index),
+                readahead,
+                last_frame: None,
+                indexed_source: Some(source),
+            },
+        );
+        self.indexed_mapping
+            .entry(inode)
+            .or_default()
+            .insert(new_fh);
+
+        Some(new_fh)
+    }
+
+    /// Indexed handlers of the inode read the decompressed `file` shared with other handlers
+    pub fn share_indexed(&mut self, inode: Inode, file: &File) -> io::Result<()> {
+        let Some(fhs) = self.indexed_mapping.remove(&inode) else {
+            return Ok(());
+        };
+        for fh in fhs {
+            let handler = self.handlers.get_mut(&fh).unwrap(); // should contain fh
+            handler.file = file.try_clone()?;
+            handler.index = None;
+            handler.readahead = None;
+            handler.last_frame = None;
+            handler.indexed_source = None;
+            self.mount_point_inode_mapping
+                .entry(inode)
+                .or_default()
+                .insert(fh);
+        }
+        Ok(())
+    }
+
     pub fn duplicate(&mut self, inode: Inode, flags: i32) -> io::Result<Option<u64>> {
         let mapping = if let Some(mapping) = self.mount_point_inode_mapping.get(&inode) {
             mapping
@@ -89,6 +208,11 @@ impl OpenedFiles {
                 inode,
                 path: handler.refs.as_ref().unwrap().path.clone(),
             }),
+            mtime: handler.mtime,
+            index: None,
+            readahead: None,
+            last_frame: None,
+            indexed_source: None,
         };
 
         // Update mappings and files
@@ -104,9 +228,22 @@ impl OpenedFiles {
     pub fn close(&mut self, fh: u64) -> Option<FileHandler> {
         if let Some(handler) = self.handlers.remove(&fh) {
             if let Some(refs) = handler.refs.as_ref() {
+                if let Some(mut indexed) = self.indexed_mapping.remove(&refs.inode) {
+                    let removed = indexed.remove(&fh);
+                    if !indexed.is_empty() {
+                        self.indexed_mapping.insert(refs.inode, indexed);
+                    }
+                    if removed {
+                        return Some(handler);
+                    }
+                }
                 if let Some(mut mapping) = self.mount_point_inode_mapping.remove(&refs.inode) {
                     if mapping.remove(&fh) && !mapping.is_empty() {
                         self.mount_point_inode_mapping.insert(refs.inode, mapping);
+                    } else {
+                        self.logical_sizes.remove(&refs.inode);
+                        self.sources.remove(&refs.inode);
+                        self.pending.remove(&refs.inode);
                     }
                 }
             }
@@ -117,7 +254,13 @@ impl OpenedFiles {
     }
 
     pub fn unlink(&mut self, ino: u64) -> Option<HashSet<u64>> {
+        for fh in self.indexed_mapping.remove(&ino).unwrap_or_default() {
+            self.handlers.get_mut(&fh).unwrap().refs = None;
+        }
         let handlers = self.mount_point_inode_mapping.remove(&ino)?;
+        self.logical_sizes.remove(&ino);
+        self.sources.remove(&ino);
+        self.pending.remove(&ino);
         // Clear refs
         handlers.iter().for_each(|fh| {
             let handler = self.handlers.get_mut(fh).unwrap();
@@ -126,6 +269,103 @@ impl OpenedFiles {
         Some(handlers)
     }
 
+    /// Updates path of all handlers of the inode
+    pub fn relocate(&mut self, ino: u64, path: PathBuf) {
+        if let Some(fhs) = self.mount_point_inode_mapping.get(&ino) {
+            for fh in fhs {
+                if let Some(refs) = self.handlers.get_mut(fh).and_then(|e| e.refs.as_mut()) {
+                    refs.path = path.clone();
+                }
+            }
+        }
+    }
+
+    /// Data were written to the inode up to `end`
+    pub fn extend(&mut self, ino: u64, end: u64) -> io::Result<()> {
+        let current = match self.logical_sizes.get(&ino) {
+            Some(size) => *size,
+            None => match self.mount_point_inode_mapping.get(&ino) {
+                Some(fhs) => match fhs.iter().next().and_then(|fh| self.handlers.get(fh)) {
+                    Some(handler) => handler.file.metadata()?.len(),
+                    None => return Ok(()),
+                },
+                None => return Ok(()),
+            },
+        };
+        self.logical_sizes.insert(ino, current.max(end));
+        Ok(())
+    }
+
+    /// Inode was truncated to `size`
+    pub fn truncate(&mut self, ino: u64, size: u64) {
+        if self.mount_point_inode_mapping.contains_key(&ino) {
+            self.logical_sizes.insert(ino, size);
+        }
+    }
+
+    /// Current size of opened inode
+    pub fn logical_size(&self, ino: u64) -> Option<u64> {
+        self.logical_sizes.get(&ino).copied()
+    }
+
+    /// Updates paths of handlers of files inside renamed directory
+    pub fn rename_dir(&mut self, from: &Path, to: &Path) {
+        for refs in self.handlers.values_mut().filter_map(|e| e.refs.as_mut()) {
+            if let Ok(rest) = refs.path.strip_prefix(from) {
+                refs.path = to.join(rest);
+            }
+        }
+    }
+
+    /// Compressed file of opened inode was stored or decompressed
+    pub fn set_source(&mut self, ino: Inode, version: SourceVersion) {
+        if self.mount_point_inode_mapping.contains_key(&ino) {
+            self.sources.insert(ino, version);
+        }
+    }
+
+    pub fn source(&self, ino: Inode) -> Option<SourceVersion> {
+        self.sources.get(&ino).copied()
+    }
+
+    pub fn set_pending(&mut self, ino: Inode, pending: Pending) {
+        if self.mount_point_inode_mapping.contains_key(&ino) {
+            self.pending.insert(ino, pending);
+        }
+    }
+
+    pub fn pending(&self, ino: Inode) -> Option<&Pending> {
+        self.pending.get(&ino)
+    }
+
+    pub fn pending_mut(&mut self, ino: Inode) -> Option<&mut Pending> {
+        self.pending.get_mut(&ino)
+    }
+
+    pub fn take_pending(&mut self, ino: Inode) -> Option<Pending> {
+        self.pending.remove(&ino)
+    }
+
+    /// Some handle of the inode has changes which were not stored yet
+    pub fn is_dirty(&self, ino: Inode) -> bool {
+        self.mount_point_inode_mapping
+            .get(&ino)
+            .map(|fhs| {
+                fhs.iter()
+                    .any(|fh| self.handlers.get(fh).is_some_and(|e| e.needs_sync))
+            })
+            .unwrap_or(false)
+    }
+
+    /// Handles with changes which were not stored yet
+    pub fn dirty(&self) -> Vec<u64> {
+        self.handlers
+            .iter()
+            .filter(|(_, e)| e.needs_sync)
+            .map(|(fh, _)| *fh)
+            .collect()
+    }
+
     pub fn get(&self, fh: u64) -> Option<&FileHandler> {
         self.handlers.get(&fh)
     }
@@ -137,4 +377,8 @@ impl OpenedFiles {
     pub fn get_fhs_from_mount_point_inode(&self, ino: u64) -> Option<&HashSet<u64>> {
         self.mount_point_inode_mapping.get(&ino)
     }
+
+    pub fn get_indexed_fhs(&self, ino: Inode) -> Option<&HashSet<u64>> {
+        self.indexed_mapping.get(&ino)
+    }
 }
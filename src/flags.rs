@@ -0,0 +1,55 @@
+//! Inode flags (`chattr`, `lsattr`) of files in data dir
+//!
+//! Flags set through the mount point are stored on the compressed file,
+//! so the backing filesystem has to support them.
+
+use std::{
+    fs::OpenOptions,
+    io,
+    os::unix::{fs::OpenOptionsExt, io::AsRawFd},
+    path::Path,
+};
+
+/// Data of the file can't be modified and the file can't be removed or renamed (`FS_IMMUTABLE_FL`)
+pub const IMMUTABLE: libc::c_int = 0x10;
+/// Data can only be appended and the file can't be removed or renamed (`FS_APPEND_FL`)
+pub const APPEND: libc::c_int = 0x20;
+
+/// Flags of the file (empty when the filesystem doesn't support them)
+pub fn get(path: &Path) -> io::Result<libc::c_int> {
+    let file = OpenOptions::new()
+        .read(true)
+        .custom_flags(libc::O_NONBLOCK | libc::O_NOFOLLOW)
+        .open(path)?;
+    let mut flags: libc::c_int = 0;
+    if unsafe { libc::ioctl(file.as_raw_fd(), libc::FS_IOC_GETFLAGS, &mut flags) } != 0 {
+        let err = io::Error::last_os_error();
+        return match err.raw_os_error() {
+            Some(libc::ENOTTY | libc::EOPNOTSUPP | libc::EINVAL) => Ok(0),
+            _ => Err(err),
+        };
+    }
+    Ok(flags)
+}
+
+pub fn set(path: &Path, flags: libc::c_int) -> io::Result<()> {
+    let file = OpenOptions::new()
+        .read(true)
+        .custom_flags(libc::O_NONBLOCK | libc::O_NOFOLLOW)
+        .open(path)?;
+    if unsafe { libc::ioctl(file.as_raw_fd(), libc::FS_IOC_SETFLAGS, &flags) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// File can't be removed or renamed (missing file is not protected)
+pub fn protected(path: &Path) -> io::Result<bool> {
+    match get(path) {
+        Ok(flags) => Ok(flags & (IMMUTABLE | APPEND) != 0),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(false),
+        // symlinks and sockets have no flags
+        Err(err) if matches!(err.raw_os_error(), Some(libc::ELOOP | libc::ENXIO)) => Ok(false),
+        Err(err) => Err(err),
+    }
+}
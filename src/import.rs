@@ -0,0 +1,87 @@
+//! Assigns inodes to a dataset which was not created by fuse-zstd
+//! (e.g. compressed by zstd CLI tool)
+
+use std::{
+    collections::HashSet,
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use fuser::FUSE_ROOT_ID;
+use log::debug;
+
+use crate::{xattrs, Inode};
+
+fn read_ino(path: &Path) -> io::Result<Option<Inode>> {
+    Ok(xattr::get(path, "user.ino")?.and_then(|e| xattrs::parse_number(&e)))
+}
+
+/// Lists directories and files in the same order each time
+pub fn walk(dir: &Path, skip: &Path, res: &mut Vec<PathBuf>) -> io::Result<()> {
+    let mut entries = fs::read_dir(dir)?
+        .map(|e| e.map(|e| e.path()))
+        .collect::<io::Result<Vec<_>>>()?;
+    entries.sort();
+
+    for path in entries {
+        if path == skip {
+            continue;
+        }
+        let file_type = fs::symlink_metadata(&path)?.file_type();
+        if file_type.is_dir() {
+            res.push(path.clone());
+            walk(&path, skip, res)?;
+        } else if file_type.is_file() {
+            res.push(path);
+        }
+    }
+    Ok(())
+}
+
+/// Sets `user.ino` to all entries which don't have it
+///
+/// Inodes are assigned in contiguous range starting after root inode
+/// and inodes which are already set are kept
+pub fn import(data_dir: &Path, cache_path: &Path) -> io::Result<usize> {
+    let mut paths = vec![];
+    walk(data_dir, cache_path, &mut paths)?;
+
+    let mut used = HashSet::new();
+    let mut missing = vec![];
+    for path in paths {
+        match read_ino(&path)? {
+            Some(ino) => {
+                used.insert(ino);
+            }
+            None => missing.push(path),
+        }
+    }
+
+    let mut next = FUSE_ROOT_ID + 1;
+    for path in &missing {
+        while used.contains(&next) {
+            next += 1;
+        }
+        debug!("Importing '{}' (inode=0x{:016x})", path.display(), next);
+        xattr::set(path, "user.ino", &next.to_be_bytes())?;
+        used.insert(next);
+    }
+
+    Ok(missing.len())
+}
+
+/// Lowest inode allocated from the top of the range (imported inodes are skipped)
+///
+/// Used to restore lost `user.ino_idx`, so allocated inodes are not assigned again
+pub fn lowest_allocated(data_dir: &Path, cache_path: &Path) -> io::Result<Option<Inode>> {
+    let mut paths = vec![];
+    walk(data_dir, cache_path, &mut paths)?;
+
+    let mut res = None;
+    for path in paths {
+        if let Some(ino) = read_ino(&path)?.filter(|e| *e > Inode::MAX / 2) {
+            res = Some(res.map_or(ino, |e: Inode| e.min(ino)));
+        }
+    }
+    Ok(res)
+}
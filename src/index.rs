@@ -0,0 +1,300 @@
+//! Files can be stored as a sequence of zstd frames where each frame
+//! is preceded by a skippable frame containing its offsets.
+//!
+//! Such files can be read from the middle without decompressing preceding data
+//! and they still remain readable by other zstd tools.
+
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{self, Read, Write},
+    ops::Range,
+    os::unix::fs::FileExt,
+    sync::Arc,
+    thread::{self, JoinHandle},
+};
+
+use zstd::zstd_safe::CParameter;
+
+use crate::{dict::Dictionaries, limit::Semaphore, params};
+
+/// Magic number of skippable frames containing the offsets
+const MAGIC: u32 = 0x184D2A5E;
+const PAYLOAD_SIZE: u32 = 24;
+const HEADER_SIZE: u64 = 8 + PAYLOAD_SIZE as u64;
+
+/// Max amount of decompressed data in a single frame
+pub const MAX_INTERVAL: usize = 1 << 30;
+
+/// Last decompressed frame of a handle (by its position in index)
+pub type LastFrame = Option<(usize, Vec<u8>)>;
+
+#[derive(Debug, Clone)]
+pub struct Entry {
+    /// Offset in decompressed data
+    pub offset: u64,
+    /// Size of decompressed data
+    pub size: u64,
+    /// Position of the frame in compressed file
+    pub position: u64,
+    /// Size of the compressed frame
+    pub compressed_size: u64,
+}
+
+fn compress_chunk(
+    chunk: &[u8],
+    level: i32,
+    params: &[CParameter],
+    dict: Option<&[u8]>,
+) -> io::Result<Vec<u8>> {
+    let mut encoder =
+        zstd::stream::Encoder::with_dictionary(vec![], level, dict.unwrap_or_default())?;
+    encoder.set_pledged_src_size(Some(chunk.len() as u64))?;
+    encoder.include_checksum(true)?;
+    params::apply(&mut encoder, params)?;
+    encoder.write_all(chunk)?;
+    encoder.finish()
+}
+
+/// Compresses the data to frames containing `interval` bytes of decompressed data
+pub fn write_indexed<R, W>(
+    mut source: R,
+    mut target: W,
+    level: i32,
+    interval: usize,
+    params: &[CParameter],
+    dict: Option<&[u8]>,
+) -> io::Result<()>
+where
+    R: Read,
+    W: Write,
+{
+    let mut chunk = vec![0; interval];
+    let mut offset: u64 = 0;
+    loop {
+        // fill the whole chunk
+        let mut size = 0;
+        while size < chunk.len() {
+            match source.read(&mut chunk[size..]) {
+                Ok(0) => break,
+                Ok(read) => size += read,
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                Err(err) => return Err(err),
+            }
+        }
+
+        // empty file still has to contain a single frame
+        if size == 0 && offset > 0 {
+            break;
+        }
+
+        let frame = compress_chunk(&chunk[..size], level, params, dict)?;
+        target.write_all(&MAGIC.to_le_bytes())?;
+        target.write_all(&PAYLOAD_SIZE.to_le_bytes())?;
+        target.write_all(&offset.to_le_bytes())?;
+        target.write_all(&(size as u64).to_le_bytes())?;
+        target.write_all(&(frame.len() as u64).to_le_bytes())?;
+        target.write_all(&frame)?;
+        offset += size as u64;
+
+        if size < chunk.len() {
+            break;
+        }
+    }
+    Ok(())
+}
+
+/// Reads offsets stored in skippable frames
+///
+/// Returns `None` when the file doesn't contain the offsets
+/// and an error when the offsets don't match the file
+pub fn read_index(file: &File) -> io::Result<Option<Vec<Entry>>> {
+    let file_size = file.metadata()?.len();
+    let mut res = vec![];
+    let mut position = 0;
+    while position < file_size {
+        let mut header = [0; HEADER_SIZE as usize];
+        if file.read_exact_at(&mut header, position).is_err() {
+            return Ok(None);
+        }
+        let read_u64 =
+            |start: usize| u64::from_le_bytes(header[start..start + 8].try_into().unwrap());
+        let magic = u32::from_le_bytes(header[0..4].try_into().unwrap());
+        let payload_size = u32::from_le_bytes(header[4..8].try_into().unwrap());
+        if magic != MAGIC || payload_size != PAYLOAD_SIZE {
+            return Ok(None);
+        }
+        let entry = Entry {
+            offset: read_u64(8),
+            size: read_u64(16),
+            position: position + HEADER_SIZE,
+            compressed_size: read_u64(24),
+        };
+        // sizes are checked before any buffer is allocated for the frame
+        let expected_offset = res.last().map(|e: &Entry| e.offset + e.size).unwrap_or(0);
+        if entry.offset != expected_offset
+            || entry.size > MAX_INTERVAL as u64
+            || entry.compressed_size > zstd::zstd_safe::compress_bound(entry.size as usize) as u64
+            || entry.compressed_size > file_size - entry.position.min(file_size)
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Index entry is corrupted (position={})", position),
+            ));
+        }
+        position = entry.position + entry.compressed_size;
+        res.push(entry);
+    }
+    Ok(if res.is_empty() { None } else { Some(res) })
+}
+
+/// Index of the entry which contains the offset
+pub fn find_entry(index: &[Entry], offset: u64) -> usize {
+    index
+        .partition_point(|e| e.offset + e.size <= offset)
+        .min(index.len().saturating_sub(1))
+}
+
+fn decompress_entry(file: &File, entry: &Entry, dicts: &Dictionaries) -> io::Result<Vec<u8>> {
+    let mut frame = vec![0; entry.compressed_size as usize];
+    file.read_exact_at(&mut frame, entry.position)?;
+    match dicts.for_frame(&frame)? {
+        Some(dict) => {
+            zstd::bulk::Decompressor::with_dictionary(dict)?.decompress(&frame, entry.size as usize)
+        }
+        None => zstd::bulk::decompress(&frame, entry.size as usize),
+    }
+}
+
+type Prefetched = io::Result<Vec<Vec<u8>>>;
+
+/// Frames which are decompressed in background before they are read
+#[derive(Debug)]
+pub struct ReadAhead {
+    /// Amount of decompressed data which should be prepared
+    size: u64,
+    /// Decompressed frames by their position in index
+    frames: HashMap<usize, Vec<u8>>,
+    pending: Option<(Range<usize>, JoinHandle<Prefetched>)>,
+    /// Limits the number of frames decompressed in background at once
+    limit: Option<Arc<Semaphore>>,
+}
+
+impl ReadAhead {
+    pub fn new(size: u64, limit: Option<Arc<Semaphore>>) -> Self {
+        Self {
+            size,
+            frames: HashMap::new(),
+            pending: None,
+            limit,
+        }
+    }
+
+    fn take(&mut self, idx: usize) -> Option<Vec<u8>> {
+        if self
+            .pending
+            .as_ref()
+            .map(|(range, _)| range.contains(&idx))
+            .unwrap_or(false)
+        {
+            let (range, handle) = self.pending.take().unwrap();
+            // failed frames are decompressed again when read
+            if let Ok(Ok(frames)) = handle.join() {
+                self.frames.extend(range.zip(frames));
+            }
+        }
+        self.frames.remove(&idx)
+    }
+
+    /// Starts to decompress frames following `next` in background
+    fn prefetch(
+        &mut self,
+        file: &File,
+        index: &[Entry],
+        dicts: &Dictionaries,
+        next: usize,
+    ) -> io::Result<()> {
+        // frames which were skipped won't be read
+        self.frames.retain(|idx, _| *idx >= next);
+
+        let mut start = next;
+        let mut prepared = 0;
+        while let Some(data) = self.frames.get(&start) {
+            prepared += data.len() as u64;
+            start += 1;
+        }
+        if let Some((range, _)) = self.pending.as_ref() {
+            if range.contains(&start) {
+                return Ok(());
+            }
+        }
+
+        let mut end = start;
+        while end < index.len() && prepared < self.size {
+            prepared += index[end].size;
+            end += 1;
+        }
+        if start >= end {
+            return Ok(());
+        }
+
+        let file = file.try_clone()?;
+        let entries = index[start..end].to_vec();
+        let dicts = dicts.clone();
+        let limit = self.limit.clone();
+        let handle = thread::spawn(move || {
+            let _permit = limit.as_ref().map(|e| e.acquire());
+            entries
+                .iter()
+                .map(|entry| decompress_entry(&file, entry, &dicts))
+                .collect()
+        });
+        self.pending = Some((start..end, handle));
+        Ok(())
+    }
+}
+
+/// Reads decompressed data starting with the closest frame
+///
+/// The last frame which was read is kept in `last`,
+/// so consecutive small reads decompress it only once.
+pub fn read_at(
+    file: &File,
+    index: &[Entry],
+    buf: &mut [u8],
+    offset: u64,
+    dicts: &Dictionaries,
+    mut readahead: Option<&mut ReadAhead>,
+    last: &mut LastFrame,
+) -> io::Result<usize> {
+    let mut read_size = 0;
+    let first = find_entry(index, offset);
+    let mut next = first;
+    for (idx, entry) in index.iter().enumerate().skip(first) {
+        if read_size >= buf.len() {
+            break;
+        }
+        let cached = last.take().filter(|(e, _)| *e == idx).map(|(_, data)| data);
+        let data = match cached.or_else(|| readahead.as_mut().and_then(|e| e.take(idx))) {
+            Some(data) => data,
+            None => decompress_entry(file, entry, dicts)?,
+        };
+        next = idx + 1;
+
+        let start = (offset + read_size as u64).saturating_sub(entry.offset) as usize;
+        if start >= data.len() {
+            continue;
+        }
+        let size = (data.len() - start).min(buf.len() - read_size);
+        buf[read_size..read_size + size].copy_from_slice(&data[start..start + size]);
+        read_size += size;
+
+        // rest of the frame is likely to be read next time
+        *last = Some((idx, data));
+    }
+
+    if let Some(readahead) = readahead {
+        readahead.prefetch(file, index, dicts, next)?;
+    }
+    Ok(read_size)
+}
@@ -0,0 +1,96 @@
+//! Limits the number of decompressions which run at the same time
+//! and the size of decompressed data
+
+use std::{
+    io::{self, Write},
+    sync::{Arc, Condvar, Mutex},
+};
+
+use log::debug;
+
+#[derive(Debug)]
+pub struct Semaphore {
+    max: usize,
+    running: Mutex<usize>,
+    released: Condvar,
+}
+
+/// Slot which is freed when dropped
+#[derive(Debug)]
+pub struct Permit {
+    semaphore: Arc<Semaphore>,
+}
+
+impl Semaphore {
+    pub fn new(max: usize) -> Arc<Self> {
+        Arc::new(Self {
+            max,
+            running: Mutex::new(0),
+            released: Condvar::new(),
+        })
+    }
+
+    fn take(self: &Arc<Self>, running: &mut usize) -> Permit {
+        *running += 1;
+        debug!("Decompression slot taken (running={})", running);
+        Permit {
+            semaphore: self.clone(),
+        }
+    }
+
+    /// Waits till a slot is free
+    pub fn acquire(self: &Arc<Self>) -> Permit {
+        let mut running = self.running.lock().unwrap();
+        while *running >= self.max {
+            running = self.released.wait(running).unwrap();
+        }
+        self.take(&mut running)
+    }
+
+    /// Returns `None` when all slots are taken
+    pub fn try_acquire(self: &Arc<Self>) -> Option<Permit> {
+        let mut running = self.running.lock().unwrap();
+        if *running >= self.max {
+            None
+        } else {
+            Some(self.take(&mut running))
+        }
+    }
+}
+
+impl Drop for Permit {
+    fn drop(&mut self) {
+        *self.semaphore.running.lock().unwrap() -= 1;
+        self.semaphore.released.notify_one();
+    }
+}
+
+/// Writer which fails with `EFBIG` once more than `max` bytes are written
+pub struct SizeLimit<W> {
+    inner: W,
+    remaining: u64,
+}
+
+impl<W: Write> SizeLimit<W> {
+    pub fn new(inner: W, max: u64) -> Self {
+        Self {
+            inner,
+            remaining: max,
+        }
+    }
+}
+
+impl<W: Write> Write for SizeLimit<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if buf.len() as u64 > self.remaining {
+            return Err(io::Error::from_raw_os_error(libc::EFBIG));
+        }
+        let written = self.inner.write(buf)?;
+        self.remaining -= written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
@@ -0,0 +1,50 @@
+//! Advisory locks of data dir
+//!
+//! Each mount holds a lock of a lock file in data dir for its whole lifetime.
+//! The lock is exclusive unless the data dir is shared (`--allow-shared`).
+//! Shared mounts allocate inodes while the data dir itself is locked
+//! so the same inode is never assigned twice.
+
+use std::{
+    fs::{File, OpenOptions},
+    io,
+    os::fd::AsRawFd,
+    path::Path,
+};
+
+pub const LOCK_FILE: &str = ".fuse-zstd-lock";
+
+fn flock(file: &File, operation: libc::c_int) -> io::Result<()> {
+    if unsafe { libc::flock(file.as_raw_fd(), operation) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Locks data dir for the mount, the lock is released when the file is closed
+pub fn lock_mount(data_dir: &Path, shared: bool) -> io::Result<File> {
+    let file = OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(data_dir.join(LOCK_FILE))?;
+    let operation = if shared { libc::LOCK_SH } else { libc::LOCK_EX };
+    match flock(&file, operation | libc::LOCK_NB) {
+        Err(err) if err.kind() == io::ErrorKind::WouldBlock => Err(io::Error::new(
+            io::ErrorKind::WouldBlock,
+            format!(
+                "Data dir '{}' is already mounted by another fuse-zstd process (all mounts of the same data dir need --allow-shared)",
+                data_dir.display()
+            ),
+        )),
+        Err(err) => Err(err),
+        Ok(()) => Ok(file),
+    }
+}
+
+/// Locks data dir exclusively until the returned file is dropped
+pub fn lock_dir(data_dir: &Path) -> io::Result<File> {
+    let dir = File::open(data_dir)?;
+    flock(&dir, libc::LOCK_EX)?;
+    Ok(dir)
+}
@@ -0,0 +1,60 @@
+//! JSON format of log records (`--log-format json`)
+//!
+//! Each record is written as a single line object. Log messages follow
+//! the `Operation (key=value, ...)` convention so the key value pairs
+//! in the trailing parentheses are exported as separate fields together
+//! with the first quoted path.
+
+use std::io::{self, Write};
+
+use env_logger::fmt::Formatter;
+use log::Record;
+use serde_json::{Map, Value};
+
+/// Splits `key=value` pairs from the end of the message
+fn fields(message: &str) -> Map<String, Value> {
+    let mut res = Map::new();
+    if let Some(start) = message.strip_suffix(')').and_then(|e| e.rfind(" (")) {
+        for pair in message[start + 2..message.len() - 1].split(", ") {
+            if let Some((key, value)) = pair.split_once('=') {
+                res.insert(key.to_string(), Value::String(value.to_string()));
+            }
+        }
+        // message starts with the name of the operation
+        if let Some(op) = message[..start].strip_prefix(|e: char| e.is_ascii_uppercase()) {
+            if op.chars().all(|e| e.is_ascii_lowercase()) {
+                res.insert(
+                    "op".to_string(),
+                    Value::String(message[..start].to_lowercase()),
+                );
+            }
+        }
+    }
+    if let Some((_, rest)) = message.split_once('\'') {
+        if let Some((path, _)) = rest.split_once('\'') {
+            res.insert("path".to_string(), Value::String(path.to_string()));
+        }
+    }
+    res
+}
+
+/// Writes a record as JSON object
+pub fn json(buf: &mut Formatter, record: &Record) -> io::Result<()> {
+    let message = record.args().to_string();
+    let mut object = Map::new();
+    object.insert(
+        "timestamp".to_string(),
+        Value::String(buf.timestamp_micros().to_string()),
+    );
+    object.insert(
+        "level".to_string(),
+        Value::String(record.level().as_str().to_lowercase()),
+    );
+    object.insert(
+        "target".to_string(),
+        Value::String(record.target().to_string()),
+    );
+    object.insert("fields".to_string(), Value::Object(fields(&message)));
+    object.insert("message".to_string(), Value::String(message));
+    writeln!(buf, "{}", Value::Object(object))
+}
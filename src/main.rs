@@ -1,28 +1,68 @@
+mod archive;
+mod bench;
 mod cache;
+mod cdc;
+mod codec;
+mod config;
+mod dict;
 mod errors;
 mod file;
+mod flags;
+mod import;
+mod index;
+mod limit;
+mod lock;
+mod logfmt;
+mod manifest;
+mod meta;
+mod migrate;
+mod mime;
+mod negative;
+mod params;
+mod probe;
+mod profile;
+mod reclaim;
+mod scrub;
+mod selftest;
+mod stats;
+mod xattrs;
 
 use clap::{crate_authors, crate_name, crate_version, Arg, ArgAction, Command};
-use errors::convert_io_error;
+use errors::{convert_decode_error, convert_io_error, is_out_of_space, is_unsupported};
+use filetime::FileTime;
 use fuser::{
     FileAttr, FileType, Filesystem, MountOption, ReplyAttr, ReplyData, ReplyDirectory, ReplyEntry,
     Request, FUSE_ROOT_ID,
 };
-use log::{debug, info, warn, LevelFilter};
+use log::{debug, error, info, trace, warn, LevelFilter};
 use std::{
-    ffi::OsStr,
-    fs::{self, File},
+    collections::HashMap,
+    env,
+    ffi::{self, OsStr},
+    fs,
     io::{self, Seek, SeekFrom},
+    mem,
     os::{
+        fd::AsRawFd,
         linux::fs::MetadataExt,
-        unix::fs::{FileExt, PermissionsExt},
+        unix::{
+            ffi::OsStrExt,
+            fs::{DirEntryExt, FileExt, FileTypeExt, PermissionsExt},
+        },
     },
-    path::{Path, PathBuf},
-    time::{Duration, UNIX_EPOCH},
+    path::{Component, Path, PathBuf},
+    process,
+    sync::Arc,
+    thread,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
 };
 use xattr::FileExt as XattrFileExt;
 
 pub const TTL: Duration = Duration::from_secs(1); // dcache lifetime
+/// Number of "." and ".." entries preceding real entries in readdir
+const DOT_ENTRIES: i64 = 2;
+/// Max size of data copied by a single copy_file_range when the file can't be cloned
+const COPY_CHUNK_SIZE: u64 = 1024 * 1024;
 
 type Inode = u64;
 
@@ -37,13 +77,8 @@ impl From<FileAttrWrapper> for FileAttr {
 }
 
 impl FileAttrWrapper {
-    fn update_realsize(&mut self, file: &File) -> Result<(), libc::c_int> {
-        self.file_attr.size = file
-            .get_xattr("user.real_size")
-            .map_err(convert_io_error)?
-            .map(|e| u64::from_be_bytes(e.to_vec().try_into().unwrap()))
-            .unwrap_or(0);
-        Ok(())
+    fn update_realsize(&mut self, meta: Option<meta::Meta>) {
+        self.file_attr.size = meta.map(|e| e.real_size).unwrap_or(0);
     }
 }
 
@@ -51,6 +86,11 @@ fn convert_ft(ft: fs::FileType) -> io::Result<fuser::FileType> {
     match ft {
         e if e.is_dir() => Ok(fuser::FileType::Directory),
         e if e.is_file() => Ok(fuser::FileType::RegularFile),
+        e if e.is_symlink() => Ok(fuser::FileType::Symlink),
+        e if e.is_fifo() => Ok(fuser::FileType::NamedPipe),
+        e if e.is_socket() => Ok(fuser::FileType::Socket),
+        e if e.is_block_device() => Ok(fuser::FileType::BlockDevice),
+        e if e.is_char_device() => Ok(fuser::FileType::CharDevice),
         _ => Err(io::Error::new(
             io::ErrorKind::Unsupported,
             "unsupported filetype",
@@ -58,18 +98,195 @@ fn convert_ft(ft: fs::FileType) -> io::Result<fuser::FileType> {
     }
 }
 
-fn access_all(fa: &mut FileAttr) {
+/// Entry of data dir taken when its directory is listed
+struct ListedEntry {
+    name: String,
+    /// `None` for unsupported types
+    file_type: Option<FileType>,
+    source_ino: u64,
+}
+
+/// Lists the directory in data dir
+fn list_dir(path: &Path) -> io::Result<Vec<ListedEntry>> {
+    let res = fs::read_dir(path)?
+        .map(|entry| {
+            let entry = entry?;
+            Ok(ListedEntry {
+                name: entry.file_name().to_string_lossy().to_string(),
+                file_type: entry.file_type().and_then(convert_ft).ok(),
+                source_ino: entry.ino(),
+            })
+        })
+        .collect::<io::Result<Vec<_>>>()?;
+    debug!(
+        "Directory '{}' listed ({} entries)",
+        path.display(),
+        res.len()
+    );
+    Ok(res)
+}
+
+/// Attributes of negative entry (kernel caches that the name doesn't exist)
+fn negative_attrs() -> FileAttr {
+    FileAttr {
+        ino: 0,
+        size: 0,
+        blocks: 0,
+        atime: UNIX_EPOCH,
+        mtime: UNIX_EPOCH,
+        ctime: UNIX_EPOCH,
+        crtime: UNIX_EPOCH,
+        kind: FileType::RegularFile,
+        perm: 0,
+        nlink: 0,
+        uid: 0,
+        gid: 0,
+        rdev: 0,
+        blksize: 0,
+        flags: 0,
+    }
+}
+
+/// Sockets, FIFOs and device nodes
+fn is_special(ft: fs::FileType) -> bool {
+    ft.is_fifo() || ft.is_socket() || ft.is_block_device() || ft.is_char_device()
+}
+
+/// Suffixes of compressed files in data dir
+const SUFFIXES: [&str; 2] = [".zst", cdc::SUFFIX];
+
+/// Name under which the compressed file is presented (`None` for other files)
+fn strip_suffix(name: &str) -> Option<&str> {
+    SUFFIXES.iter().find_map(|e| name.strip_suffix(e))
+}
+
+/// Path of uncompressed copy of the file
+fn plain_path(path: &Path) -> Option<PathBuf> {
+    let name = path.file_name()?.to_string_lossy();
+    Some(path.with_file_name(strip_suffix(&name)?))
+}
+
+/// Reads until the buffer is filled or EOF is reached,
+/// `read_at` may return less data than requested even before EOF
+fn read_full_at<F: FileExt>(file: &F, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+    let mut read_size = 0;
+    while read_size < buf.len() {
+        match file.read_at(&mut buf[read_size..], offset + read_size as u64) {
+            Ok(0) => break, // EOF reached
+            Ok(size) => read_size += size,
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+            Err(err) => return Err(err),
+        }
+    }
+    Ok(read_size)
+}
+
+/// Copies extended attributes and removes internal ones which source doesn't have
+fn replace_xattrs(source: &fs::File, target: &fs::File) -> io::Result<()> {
+    // e.g. index which the new data don't have
+    for name in target.list_xattr()?.filter(|e| xattrs::is_internal(e)) {
+        if source.get_xattr(&name)?.is_none() {
+            target.remove_xattr(name)?;
+        }
+    }
+    for name in source.list_xattr()? {
+        if let Some(value) = source.get_xattr(&name)? {
+            target.set_xattr(&name, &value)?;
+        }
+    }
+    Ok(())
+}
+
+/// Space available to unprivileged users on the filesystem of the file
+fn available_space(file: &fs::File) -> io::Result<u64> {
+    let mut stat = mem::MaybeUninit::<libc::statvfs>::uninit();
+    if unsafe { libc::fstatvfs(file.as_raw_fd(), stat.as_mut_ptr()) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let stat = unsafe { stat.assume_init() };
+    Ok(stat.f_bavail.saturating_mul(stat.f_frsize))
+}
+
+/// Replaces the whole content of the target by the content of the source
+fn copy_over(source: &fs::File, target: &mut fs::File) -> io::Result<()> {
+    target.set_len(0)?;
+    target.seek(SeekFrom::Start(0))?;
+    let mut source = source.try_clone()?;
+    source.seek(SeekFrom::Start(0))?;
+    io::copy(&mut source, target)?;
+    target.sync_all()
+}
+
+/// Overwrites content and attributes of a hardlinked file,
+/// all its names keep pointing to the same file
+///
+/// The file can't be replaced atomically, so the old data are copied
+/// to a sibling file first and they are restored when the copy fails.
+fn overwrite_linked(tmp_file: &fs::File, path: &Path) -> io::Result<fs::File> {
+    let mut file = fs::OpenOptions::new().read(true).write(true).open(path)?;
+    let new_size = tmp_file.metadata()?.len();
+    let old_size = file.metadata()?.len();
+    let available = available_space(&file)?;
+    if available < new_size.saturating_add(old_size) {
+        warn!(
+            "Not enough space to overwrite hardlinked file (path={}, needed={}, available={})",
+            path.display(),
+            new_size.saturating_add(old_size),
+            available
+        );
+        return Err(io::Error::from_raw_os_error(libc::ENOSPC));
+    }
+
+    let dir = path
+        .parent()
+        .ok_or_else(|| io::Error::from_raw_os_error(libc::EINVAL))?;
+    let mut backup = tempfile::NamedTempFile::new_in(dir)?;
+    io::copy(&mut file, backup.as_file_mut())?;
+    backup.as_file().sync_all()?;
+
+    if let Err(err) = copy_over(tmp_file, &mut file) {
+        if let Err(restore_err) = copy_over(backup.as_file(), &mut file) {
+            // backup is the only copy of the data now
+            let (_, backup_path) = backup.keep().map_err(|e| e.error)?;
+            error!(
+                "Failed to restore hardlinked file (path={}, backup={}, err={})",
+                path.display(),
+                backup_path.display(),
+                restore_err
+            );
+        }
+        return Err(err);
+    }
+    match replace_xattrs(tmp_file, &file) {
+        Err(err) if !is_unsupported(&err) => return Err(err),
+        _ => {}
+    }
+    file.set_permissions(tmp_file.metadata()?.permissions())?;
+    file.sync_all()?;
+    Ok(file)
+}
+
+/// Allows access to all unless the mode is configured
+fn access_all(fa: &mut FileAttr, config: &config::Config) {
     match fa.kind {
-        FileType::Directory => {
+        FileType::Directory if config.dir_mode.is_none() => {
             fa.perm = 0o777;
         }
-        FileType::RegularFile => {
+        FileType::RegularFile if config.file_mode.is_none() => {
             fa.perm = 0o666;
         }
         _ => {}
     }
 }
 
+/// Blocks are computed from the decompressed size when configured
+fn report_blocks(fa: &mut FileAttr, config: &config::Config) {
+    if config.report_logical_blocks && fa.kind == FileType::RegularFile {
+        let blksize = u64::from(fa.blksize.max(512));
+        fa.blocks = fa.size.div_ceil(blksize) * (blksize / 512);
+    }
+}
+
 impl TryFrom<fs::DirEntry> for FileAttrWrapper {
     type Error = io::Error;
     fn try_from(dir_entry: fs::DirEntry) -> Result<Self, Self::Error> {
@@ -81,15 +298,17 @@ impl TryFrom<fs::DirEntry> for FileAttrWrapper {
 impl TryFrom<fs::Metadata> for FileAttrWrapper {
     type Error = io::Error;
     fn try_from(metadata: fs::Metadata) -> Result<Self, Self::Error> {
+        let ctime = UNIX_EPOCH + Duration::from_secs(metadata.st_ctime() as u64);
         Ok(Self {
             file_attr: FileAttr {
                 ino: metadata.st_ino(),
                 size: metadata.st_size(),
                 blocks: metadata.st_blocks(),
                 atime: UNIX_EPOCH + Duration::from_secs(metadata.st_atime() as u64),
-                ctime: UNIX_EPOCH + Duration::from_secs(metadata.st_ctime() as u64),
+                ctime,
                 mtime: UNIX_EPOCH + Duration::from_secs(metadata.st_mtime() as u64),
-                crtime: UNIX_EPOCH + Duration::from_secs(metadata.st_ctime() as u64), // creation time on macos
+                // birth time from statx (when the filesystem records it)
+                crtime: metadata.created().unwrap_or(ctime),
                 kind: convert_ft(metadata.file_type())?,
                 perm: metadata.permissions().mode() as u16,
                 nlink: metadata.st_nlink() as u32,
@@ -104,29 +323,78 @@ impl TryFrom<fs::Metadata> for FileAttrWrapper {
 }
 
 struct ZstdFS {
-    compression_level: u8,
+    config: config::Config,
     data_dir: PathBuf,
     opened_files: file::OpenedFiles,
     inode_idx: u64,
     inode_cache: Option<cache::InodeCache>,
-    /// Convert uncompressed data from original directory
-    /// to compressed files
-    convert: bool,
+    /// Location of inode cache when it can't be stored in data dir
+    cache_root: Option<PathBuf>,
+    dicts: dict::Dictionaries,
+    stats: stats::Stats,
+    /// Slots for decompressions running at the same time
+    decompress_limit: Option<Arc<limit::Semaphore>>,
+    /// Archives by their extracted staging directories
+    archives: HashMap<PathBuf, PathBuf>,
+    /// Recently looked up names which don't exist
+    missing: negative::NegativeCache,
+    /// Handles kept opened because they couldn't be stored on release
+    failed_releases: Vec<(Inode, u64)>,
+    /// When changes of all opened files were stored last time
+    last_flush: Instant,
+    /// When the inode cache was checked last time and its size after the last compaction
+    last_compaction: (Instant, u64),
+    /// Removes deleted files in background
+    reclaimer: Option<reclaim::Reclaimer>,
+    /// Entries of opened directories by their handles
+    dir_snapshots: HashMap<u64, Arc<Vec<ListedEntry>>>,
+    next_dir_fh: u64,
+    /// Files checked by the scrubbing thread since stats were stored
+    scrub: Option<Arc<scrub::Progress>>,
+    /// Manifests of presented names by directories and versions of their files
+    manifests: HashMap<PathBuf, (file::SourceVersion, Arc<manifest::Manifest>)>,
+    /// Timings of hot paths (`--profile`)
+    profile: Option<profile::Profile>,
 }
 
 impl ZstdFS {
-    fn new(
-        data_dir: String,
-        compression_level: u8,
-        convert: bool,
-        inode_idx: u64,
-    ) -> io::Result<ZstdFS> {
+    fn new(data_dir: String, inode_idx: u64, config: config::Config) -> io::Result<ZstdFS> {
+        let mut dicts = dict::Dictionaries::load(Path::new(&data_dir))?;
+        if let Some(dir) = config.dictionary_dir.as_ref() {
+            let loaded = dicts.load_external(dir)?;
+            info!("Loaded {} dictionaries from '{}'", loaded, dir.display());
+        }
+        let stats = stats::Stats::load(Path::new(&data_dir))?;
+        let scrub = config.scrub.then(|| {
+            let data_path = Path::new(&data_dir);
+            scrub::Scrubber::spawn(
+                data_path,
+                &data_path.join(".fuse-zstd-inode_cache"),
+                dicts.clone(),
+                config.scrub_rate,
+            )
+        });
+        let decompress_limit = config.max_concurrent_decompress.map(limit::Semaphore::new);
         Ok(Self {
-            compression_level,
+            decompress_limit,
+            archives: HashMap::new(),
+            manifests: HashMap::new(),
+            scrub,
+            dir_snapshots: HashMap::new(),
+            next_dir_fh: 1,
+            missing: negative::NegativeCache::new(TTL),
+            failed_releases: vec![],
+            last_flush: Instant::now(),
+            last_compaction: (Instant::now(), 0),
+            reclaimer: config.async_unlink.then(reclaim::Reclaimer::new),
+            profile: config.profile.then(profile::Profile::default),
+            config,
             inode_cache: None,
+            cache_root: None,
+            dicts,
+            stats,
             data_dir: data_dir.into(),
             opened_files: file::OpenedFiles::new(),
-            convert,
             inode_idx,
         })
     }
@@ -135,8 +403,210 @@ impl ZstdFS {
         self.data_dir.clone()
     }
 
+    /// Records the duration of the operation when profiling is enabled
+    fn profiled(&mut self, op: &'static str, started: Instant) {
+        if let Some(profile) = self.profile.as_mut() {
+            profile.record(op, started.elapsed());
+        }
+    }
+
+    /// Directory which is mapped to the root of mount point
+    fn root_dir(&self) -> PathBuf {
+        if let Some(subdir) = self.config.subdir.as_ref() {
+            self.data_dir().join(subdir)
+        } else {
+            self.data_dir()
+        }
+    }
+
     fn cache_path(&self) -> PathBuf {
-        self.data_dir().join(".fuse-zstd-inode_cache")
+        self.cache_root
+            .clone()
+            .unwrap_or_else(|| self.data_dir().join(".fuse-zstd-inode_cache"))
+    }
+
+    /// Files in data dir which are used by fuse-zstd itself
+    fn is_hidden(&self, path: &Path) -> bool {
+        path.starts_with(self.cache_path())
+            || path == self.data_dir.join(cdc::CHUNKS_DIR)
+            || dict::is_dict_path(&self.data_dir, path)
+            || path == stats::Stats::path(&self.data_dir)
+            || path == self.data_dir.join(cache::METAS_NAME)
+            || path == self.data_dir.join(lock::LOCK_FILE)
+            || (self.config.name_manifests
+                && path.file_name() == Some(OsStr::new(manifest::MANIFEST_NAME)))
+    }
+
+    /// Reports plain file which has the same name as a compressed one in non-convert mode
+    /// (fails with `--overlap-policy error`)
+    ///
+    /// Returns `true` when the files overlap
+    fn check_overlap(&self, dir: &Path, name: &str) -> Result<bool, libc::c_int> {
+        if self.config.convert || self.config.keep_plain {
+            return Ok(false);
+        }
+        let plain = dir.join(name);
+        let Some(compressed) = SUFFIXES
+            .iter()
+            .map(|e| dir.join(format!("{}{}", name, e)))
+            .find(|e| e.is_file())
+        else {
+            return Ok(false);
+        };
+        if !plain.is_file() {
+            return Ok(false);
+        }
+        match self.config.overlap_policy {
+            config::OverlapPolicy::PreferCompressed => warn!(
+                "Plain file '{}' is hidden by '{}'",
+                plain.display(),
+                compressed.display()
+            ),
+            config::OverlapPolicy::PreferPlain => warn!(
+                "Plain file '{}' replaces '{}'",
+                plain.display(),
+                compressed.display()
+            ),
+            config::OverlapPolicy::Error => {
+                error!(
+                    "Plain file '{}' overlaps '{}' (remove one of them or set --overlap-policy)",
+                    plain.display(),
+                    compressed.display()
+                );
+                return Err(libc::EIO);
+            }
+        }
+        Ok(true)
+    }
+
+    /// Manifest of presented names of the directory (`--name-manifests`)
+    fn manifest(&mut self, dir: &Path) -> Result<Option<Arc<manifest::Manifest>>, libc::c_int> {
+        if !self.config.name_manifests {
+            return Ok(None);
+        }
+        let version = match fs::metadata(manifest::Manifest::path(dir)) {
+            Ok(metadata) => file::SourceVersion::new(&metadata),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {
+                self.manifests.remove(dir);
+                return Ok(None);
+            }
+            Err(err) => return Err(convert_io_error(err)),
+        };
+        if let Some((cached, manifest)) = self.manifests.get(dir) {
+            if *cached == version {
+                return Ok(Some(manifest.clone()));
+            }
+        }
+        let manifest = Arc::new(manifest::Manifest::load(dir).map_err(convert_io_error)?);
+        self.manifests
+            .insert(dir.to_path_buf(), (version, manifest.clone()));
+        Ok(Some(manifest))
+    }
+
+    /// Name of compressed file in data dir which is presented as `name`
+    fn backing_name(&mut self, dir: &Path, name: &str) -> Result<String, libc::c_int> {
+        self.compressed_name(dir, name, ".zst")
+    }
+
+    /// Name of existing compressed file presented as `name` or the name with `suffix`
+    /// when there is no such file
+    fn compressed_name(
+        &mut self,
+        dir: &Path,
+        name: &str,
+        suffix: &str,
+    ) -> Result<String, libc::c_int> {
+        let manifest = self.manifest(dir)?;
+        if let Some(backing) = manifest.as_ref().and_then(|e| e.backing(name)) {
+            return Ok(backing.to_string());
+        }
+        Ok(SUFFIXES
+            .iter()
+            .map(|e| format!("{}{}", name, e))
+            .find(|e| fs::symlink_metadata(dir.join(e)).is_ok())
+            .unwrap_or_else(|| format!("{}{}", name, suffix)))
+    }
+
+    /// Dictionary used to compress new files (only with `--auto-dictionary`,
+    /// zstd tool can't decompress such files without the dictionary)
+    fn compression_dict(&self) -> Option<&[u8]> {
+        self.config
+            .auto_dictionary
+            .then(|| self.dicts.current())
+            .flatten()
+    }
+
+    /// Suffix of newly created compressed files (chunked files have their own)
+    fn new_suffix(&self) -> &'static str {
+        if self.config.cdc {
+            cdc::SUFFIX
+        } else {
+            ".zst"
+        }
+    }
+
+    /// Names of files used by fuse-zstd can't be accessed nor created via mountpoint
+    fn check_reserved(&self, parent_path: &Path, name: &OsStr) -> Result<(), libc::c_int> {
+        let path = parent_path.join(name);
+        if self.is_hidden(&path)
+            || self.is_hidden(&parent_path.join(format!("{}.zst", name.to_string_lossy())))
+        {
+            debug!("Reserved path '{}' accessed", path.display());
+            return Err(libc::EPERM);
+        }
+        Ok(())
+    }
+
+    /// Anonymous file for decompressed data of opened file
+    fn scratch_file(&self) -> io::Result<fs::File> {
+        match self.config.work_dir.as_ref() {
+            Some(work_dir) => tempfile::tempfile_in(work_dir),
+            None => tempfile::tempfile(),
+        }
+    }
+
+    /// Stores running totals to data dir
+    fn save_stats(&mut self) {
+        let entries = self.icache().len() as u64;
+        self.stats.cache_len(entries);
+        if let Some(progress) = self.scrub.as_ref() {
+            let (checked, corrupted) = progress.take();
+            self.stats.scrubbed(checked, corrupted);
+        }
+        match self.stats.save(&self.data_dir) {
+            Ok(()) => {}
+            Err(err) if err.raw_os_error() == Some(libc::EROFS) => {
+                debug!("Stats can't be stored to read-only data dir")
+            }
+            Err(err) => warn!("Failed to store stats (err={})", err),
+        }
+    }
+
+    /// Persists cached metadata to data dir which lacks xattrs
+    /// (inode cache is removed on unmount)
+    fn save_metas(&mut self) {
+        if !self.config.xattrs_unsupported {
+            return;
+        }
+        let path = self.data_dir.join(cache::METAS_NAME);
+        if let Err(err) = self.icache().save_metas(&path) {
+            warn!("Failed to store metadata (err={})", err);
+        }
+    }
+
+    /// Flags of opened file passed to the kernel
+    fn open_flags(&self, flags: i32) -> u32 {
+        // reads and writes of files which may change underneath bypass page cache
+        if self.config.direct_io || flags & libc::O_DIRECT != 0 {
+            fuser::consts::FOPEN_DIRECT_IO
+        } else {
+            0
+        }
+    }
+
+    /// Directory where unlinked files are kept till they are closed
+    fn unlinked_path(&self) -> PathBuf {
+        self.cache_path().join("unlinked")
     }
 
     #[inline]
@@ -144,9 +614,23 @@ impl ZstdFS {
         self.inode_cache.as_mut().unwrap()
     }
 
+    /// Recreates the inode cache when its directory was removed
+    /// (checked only periodically, so it is not done on each path resolution)
+    fn check_cache_dir(&mut self) {
+        let cache_path = self.cache_path();
+        match self.icache().ensure_exists(&cache_path) {
+            Ok(true) => warn!(
+                "Inode cache directory was removed, recreated in '{}'",
+                cache_path.display()
+            ),
+            Ok(false) => {}
+            Err(err) => warn!("Failed to recreate inode cache (err={})", err),
+        }
+    }
+
     fn get_path(&mut self, ino: Inode) -> Result<PathBuf, libc::c_int> {
         if ino == FUSE_ROOT_ID {
-            Ok(self.data_dir().clone())
+            Ok(self.root_dir())
         } else {
             if let Ok(path) = self.icache().get_inode_path(ino) {
                 return Ok(Path::new(&path).to_path_buf());
@@ -167,17 +651,162 @@ impl ZstdFS {
                 }
             }
 
+            // entries which were not stored to failed inode cache
+            if self.icache().is_degraded() {
+                if let Some(path) = self.find_inode_path(ino) {
+                    let (parent, name) = (path.parent().ok_or(libc::EIO)?, path.file_name());
+                    let name = name.ok_or(libc::EIO)?.to_string_lossy().to_string();
+                    self.icache().set_inode_path(ino, parent, name)?;
+                    return Ok(path);
+                }
+            }
+
             Err(libc::ENOENT)
         }
     }
 
+    /// Searches data dir for a file or a directory with the inode (slow)
+    fn find_inode_path(&self, ino: Inode) -> Option<PathBuf> {
+        debug!("Searching data dir for inode 0x{:016x}", ino);
+        let mut dirs = vec![self.root_dir()];
+        while let Some(dir) = dirs.pop() {
+            let Ok(entries) = fs::read_dir(&dir) else {
+                continue;
+            };
+            for entry in entries.flatten() {
+                let path = entry.path();
+                let Ok(file_type) = entry.file_type() else {
+                    continue;
+                };
+                if self.is_hidden(&path) || !(file_type.is_file() || file_type.is_dir()) {
+                    continue;
+                }
+                if self.stored_inode(&path).ok().flatten() == Some(ino) {
+                    return Some(path);
+                }
+                if file_type.is_dir() {
+                    dirs.push(path);
+                }
+            }
+        }
+        None
+    }
+
+    /// Inode stored in extended attributes (`st_ino` when inodes are mirrored)
+    fn stored_inode(&self, path: &Path) -> io::Result<Option<Inode>> {
+        if self.config.mirror_inodes {
+            return Ok(Some(fs::symlink_metadata(path)?.st_ino()));
+        }
+        Ok(xattr::get(path, "user.ino")?.and_then(|e| xattrs::parse_number(&e)))
+    }
+
+    /// Stored inode or the inode assigned when the entry was listed (it is stored now)
+    fn known_inode(&mut self, path: &Path) -> Result<Option<Inode>, libc::c_int> {
+        if let Some(ino) = self.stored_inode(path).map_err(convert_io_error)? {
+            return Ok(Some(ino));
+        }
+        let source_ino = match fs::symlink_metadata(path) {
+            Ok(metadata) => metadata.st_ino(),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(err) => return Err(convert_io_error(err)),
+        };
+        let Some(ino) = self.icache().get_pending_inode(source_ino)? else {
+            return Ok(None);
+        };
+        // index has to be stored before the inode to avoid its reuse
+        self.store_inode_idx().map_err(convert_io_error)?;
+        xattr::set(path, "user.ino", &ino.to_be_bytes()).map_err(convert_io_error)?;
+        self.icache().del_pending_inode(source_ino)?;
+        Ok(Some(ino))
+    }
+
+    /// Metadata of compressed file (cached with `--network-backing`)
+    fn read_meta(&mut self, file: &fs::File) -> Result<Option<meta::Meta>, libc::c_int> {
+        if !self.config.network_backing {
+            return meta::Meta::read(file).map_err(convert_io_error);
+        }
+        let metadata = file.metadata().map_err(convert_io_error)?;
+        if let Some(meta) = self.icache().get_meta(&metadata)? {
+            return Ok(Some(meta));
+        }
+        let mut meta = meta::Meta::read(file).map_err(convert_io_error)?;
+        // metadata from previous mounts are lost without extended attributes
+        if meta.is_none() && self.config.xattrs_unsupported {
+            meta = Some(meta::Meta::new(0, self.decompress_to(file, io::sink())?));
+        }
+        if let Some(meta) = meta.as_ref() {
+            debug!(
+                "Metadata cached (inode=0x{:016x}, size={})",
+                metadata.st_ino(),
+                metadata.len()
+            );
+            self.icache().set_meta(&metadata, meta)?;
+        }
+        Ok(meta)
+    }
+
+    /// Keeps cached metadata of compressed file whose times were changed
+    fn recache_meta(
+        &mut self,
+        before: &fs::Metadata,
+        after: &fs::Metadata,
+    ) -> Result<(), libc::c_int> {
+        if !self.config.network_backing {
+            return Ok(());
+        }
+        if let Some(meta) = self.icache().get_meta(before)? {
+            self.icache().set_meta(after, &meta)?;
+        }
+        Ok(())
+    }
+
+    /// Stores metadata of compressed file (only to the cache when data dir lacks xattrs)
+    fn write_meta(&mut self, meta: &meta::Meta, file: &fs::File) -> io::Result<()> {
+        if !self.config.xattrs_unsupported {
+            meta.write(file)?;
+        }
+        if self.config.network_backing {
+            let metadata = file.metadata()?;
+            self.icache()
+                .set_meta(&metadata, meta)
+                .map_err(io::Error::from_raw_os_error)?;
+        }
+        Ok(())
+    }
+
     fn sync_to_fs(&mut self, fh: u64, close: bool, force_sync: bool) -> Result<(), libc::c_int> {
-        let (refs, needs_sync, file) = if close {
+        // clean handles (e.g. read only) have nothing to store
+        if !force_sync {
+            let needs_sync = self
+                .opened_files
+                .get(fh)
+                .map(|e| e.needs_sync)
+                .ok_or(if close { libc::EBADF } else { libc::ENOENT })?;
+            if !needs_sync {
+                debug!("Nothing to store (fh={})", fh);
+                if close {
+                    self.opened_files.close(fh);
+                }
+                return Ok(());
+            }
+        }
+
+        // whole file is stored, including the data which were not decompressed yet
+        if let Some(handler) = self.opened_files.get(fh) {
+            if let (true, Some(refs)) = (handler.needs_sync || force_sync, handler.refs.as_ref()) {
+                let ino = refs.inode;
+                let file = handler.file.try_clone().map_err(convert_io_error)?;
+                self.materialize(ino, &file)?;
+            }
+        }
+
+        let (refs, needs_sync, file, mtime) = if close {
             let fh = self.opened_files.close(fh).ok_or(libc::EBADF)?;
             (
                 fh.refs.clone(),
                 fh.needs_sync,
                 fh.file.try_clone().map_err(convert_io_error)?,
+                fh.mtime,
             )
         } else {
             let fh = self.opened_files.get(fh).ok_or(libc::ENOENT)?;
@@ -185,6 +814,7 @@ impl ZstdFS {
                 fh.refs.clone(),
                 fh.needs_sync,
                 fh.file.try_clone().map_err(convert_io_error)?,
+                fh.mtime,
             )
         };
 
@@ -192,13 +822,40 @@ impl ZstdFS {
             if let Some(refs) = refs {
                 let source_path = refs.path;
                 let dir_path = source_path.parent().unwrap().to_path_buf();
+                self.check_external(refs.inode, &source_path)?;
+
+                let (source_file, _) = self
+                    .store_to_source_file(
+                        &file,
+                        &dir_path,
+                        source_path.file_name().unwrap(),
+                        self.config.compression_level,
+                    )
+                    .inspect_err(|err| {
+                        if is_out_of_space(*err) {
+                            // previous file is kept and handler still needs to be synced
+                            warn!(
+                                "Not enough space to store '{}' (err={})",
+                                source_path.display(),
+                                err
+                            );
+                        }
+                    })?;
 
-                self.store_to_source_file(
-                    &file,
-                    &dir_path,
-                    source_path.file_name().unwrap(),
-                    self.compression_level,
-                )?;
+                // new file was created so mtime needs to be restored
+                if let (true, Some(mtime)) = (self.config.preserve_mtime, mtime) {
+                    let stored = source_file.metadata().map_err(convert_io_error)?;
+                    filetime::set_file_handle_times(
+                        &source_file,
+                        None,
+                        Some(FileTime::from_system_time(mtime)),
+                    )
+                    .map_err(convert_io_error)?;
+                    let metadata = source_file.metadata().map_err(convert_io_error)?;
+                    self.recache_meta(&stored, &metadata)?;
+                    self.opened_files
+                        .set_source(refs.inode, file::SourceVersion::new(&metadata));
+                }
 
                 // update needs_update because the file was synced
                 if !close {
@@ -214,42 +871,128 @@ impl ZstdFS {
 
     fn lookup_wrapper(&mut self, parent: u64, name: &OsStr) -> Result<FileAttr, libc::c_int> {
         let path = self.get_path(parent)?;
+        self.check_reserved(&path, name)?;
         let entries = fs::read_dir(&path).map_err(convert_io_error)?;
         let name = name.to_string_lossy().to_string();
-        let cache_path = self.cache_path();
+        let manifest = self.manifest(&path)?;
+        let backing_name = self.backing_name(&path, &name)?;
 
         for entry in entries {
             let entry = entry.map_err(convert_io_error)?;
 
             // add prefix .zstd for regular files
             let filename = if entry.file_type().map_err(convert_io_error)?.is_file() {
-                format!("{}.zst", &name)
+                backing_name.clone()
             } else {
                 name.clone()
             };
 
-            // skip cache_dir from root
-            if parent == FUSE_ROOT_ID && cache_path == path.join(entry.file_name()) {
+            // skip cache_dir and dictionaries from root
+            if self.is_hidden(&path.join(entry.file_name())) {
                 continue;
             }
 
-            if entry.file_name().to_string_lossy() == filename {
+            // directories stored as archives
+            if self.config.archive_dirs && entry.file_type().map_err(convert_io_error)?.is_file() {
+                match archive::dir_name(&entry.file_name().to_string_lossy()) {
+                    Some(dir_name) if dir_name == name => return self.archive_attrs(&entry.path()),
+                    Some(_) => continue,
+                    None => {}
+                }
+            }
+
+            // file is presented under another name
+            let presented = manifest
+                .as_ref()
+                .and_then(|e| e.presented(&entry.file_name().to_string_lossy()))
+                .is_some_and(|e| e != name);
+
+            if entry.file_name().to_string_lossy() == filename && !presented {
+                let file_type = entry.file_type().map_err(convert_io_error)?;
+                if is_special(file_type) {
+                    if !self.config.show_special {
+                        continue;
+                    }
+                    // special files are not opened (they have the same inodes as symlinks)
+                    return self.symlink_attrs(&path, &filename);
+                }
+
+                // symlinks are not followed
+                if file_type.is_symlink() {
+                    return self.symlink_attrs(&path, &filename);
+                }
+
+                // plain file with the same name replaces the compressed one
+                if file_type.is_file()
+                    && strip_suffix(&filename) == Some(name.as_str())
+                    && self.check_overlap(&path, &name)?
+                    && self.config.overlap_policy == config::OverlapPolicy::PreferPlain
+                {
+                    let source_file = fs::File::open(path.join(&name)).map_err(convert_io_error)?;
+                    self.store_to_source_file(
+                        &source_file,
+                        &path,
+                        &filename,
+                        self.config.compression_level,
+                    )?;
+                    fs::remove_file(path.join(&name)).map_err(convert_io_error)?;
+                    return self.lookup_wrapper(parent, OsStr::new(&name));
+                }
+
                 // Try to check the cache first
                 let mut faw = FileAttrWrapper::try_from(entry).map_err(convert_io_error)?;
                 // Update size from extended attributes
                 let file = fs::File::open(path.join(&filename)).map_err(convert_io_error)?;
-                faw.update_realsize(&file)?;
+                faw.update_realsize(self.read_meta(&file)?);
                 let ino = self.update_inode(&file).map_err(convert_io_error)?;
                 // Touch cache
-                self.icache().set_inode_path(ino, &path, filename)?;
+                self.icache().set_inode_path(ino, &path, &filename)?;
 
                 let mut attrs: FileAttr = faw.into();
                 // allow access to all
-                access_all(&mut attrs);
+                access_all(&mut attrs, &self.config);
+                report_blocks(&mut attrs, &self.config);
 
                 // cleanup uncompressed files in convert move
-                if self.convert && attrs.kind == FileType::RegularFile {
-                    let _ = fs::remove_file(path.join(&name));
+                if self.config.convert
+                    && !self.config.keep_plain
+                    && attrs.kind == FileType::RegularFile
+                    && path.join(&name).is_file()
+                {
+                    // compressed file may be a leftover of an interrupted conversion
+                    let corrupted = self.decompress_to(&file, io::sink()).is_err();
+                    if corrupted {
+                        warn!(
+                            "Compressed file '{}' is corrupted, converting '{}' again",
+                            path.join(&filename).display(),
+                            path.join(&name).display()
+                        );
+                    }
+                    if self.config.convert_dry_run {
+                        info!(
+                            "Convert dry run: '{}' would be {} (overlaps '{}')",
+                            path.join(&name).display(),
+                            if corrupted {
+                                "compressed again"
+                            } else {
+                                "removed"
+                            },
+                            path.join(&filename).display()
+                        );
+                    } else if corrupted {
+                        let source_file =
+                            fs::File::open(path.join(&name)).map_err(convert_io_error)?;
+                        self.store_to_source_file(
+                            &source_file,
+                            &path,
+                            &filename,
+                            self.config.compression_level,
+                        )?;
+                        fs::remove_file(path.join(&name)).map_err(convert_io_error)?;
+                        return self.lookup_wrapper(parent, OsStr::new(&name));
+                    } else {
+                        let _ = fs::remove_file(path.join(&name));
+                    }
                 }
 
                 // Update ino mp inodes
@@ -259,7 +1002,7 @@ impl ZstdFS {
             }
         }
 
-        if self.convert && !name.ends_with(".zst") {
+        if self.config.convert && strip_suffix(&name).is_none() {
             // Uncompressed file may exist lets try to find it and compress it
             //
             // note that in convert mode every only files without .zst extension
@@ -269,31 +1012,39 @@ impl ZstdFS {
                 let entry = entry.map_err(convert_io_error)?;
                 if entry.file_name().to_string_lossy() == name
                     && entry.file_type().map_err(convert_io_error)?.is_file()
+                    && !self.is_hidden(&entry.path())
                 {
-                    let zname = format!("{}.zst", &name);
+                    let zname = format!("{}{}", &name, self.new_suffix());
                     let source_file = fs::File::open(path.join(&name)).map_err(convert_io_error)?;
+
+                    if self.config.convert_dry_run {
+                        return self.convert_dry_run(&source_file, &path, &name);
+                    }
                     let (file, ino) = self.store_to_source_file(
                         &source_file,
                         &path,
                         &zname,
-                        self.compression_level,
+                        self.config.compression_level,
                     )?;
 
                     // File was copied now we can remove the original
-                    let _ = fs::remove_file(path.join(&name));
+                    if !self.config.keep_plain {
+                        let _ = fs::remove_file(path.join(&name));
+                    }
 
                     let mut faw = FileAttrWrapper::try_from(
                         source_file.metadata().map_err(convert_io_error)?,
                     )
                     .map_err(convert_io_error)?;
-                    faw.update_realsize(&file)?;
+                    faw.update_realsize(self.read_meta(&file)?);
 
                     // Touch cache
                     self.icache().set_inode_path(ino, path, zname)?;
 
                     let mut attrs: FileAttr = faw.into();
                     // allow access to all
-                    access_all(&mut attrs);
+                    access_all(&mut attrs, &self.config);
+                    report_blocks(&mut attrs, &self.config);
 
                     attrs.ino = ino;
 
@@ -304,70 +1055,195 @@ impl ZstdFS {
         Err(libc::ENOENT)
     }
 
-    fn readdir_wrapper(
+    /// Reports what would happen to the file in convert mode
+    fn convert_dry_run(
+        &mut self,
+        source_file: &fs::File,
+        path: &Path,
+        name: &str,
+    ) -> Result<FileAttr, libc::c_int> {
+        info!(
+            "Convert dry run: '{}' would be compressed to '{}{}'",
+            path.join(name).display(),
+            path.join(name).display(),
+            self.new_suffix()
+        );
+        if !self.config.keep_plain {
+            info!(
+                "Convert dry run: '{}' would be removed",
+                path.join(name).display()
+            );
+        }
+
+        let metadata = source_file.metadata().map_err(convert_io_error)?;
+        let ino = self.cached_inode(&metadata)?;
+        self.icache().set_inode_path(ino, path, name)?;
+
+        let mut attrs: FileAttr = FileAttrWrapper::try_from(metadata)
+            .map_err(convert_io_error)?
+            .into();
+        access_all(&mut attrs, &self.config);
+        attrs.ino = ino;
+        Ok(attrs)
+    }
+
+    /// Handle of opened directory which keeps the snapshot of its entries
+    fn opendir_wrapper(&mut self, ino: u64) -> Result<u64, libc::c_int> {
+        let path = self.get_path(ino)?;
+        if !path.is_dir() {
+            return Err(libc::ENOTDIR);
+        }
+        let fh = self.next_dir_fh;
+        self.next_dir_fh += 1;
+        Ok(fh)
+    }
+
+    /// Lists the directory, `add` returns true when the reply is full
+    fn readdir_wrapper<F>(
         &mut self,
         ino: u64,
-        _fh: u64,
+        fh: u64,
         offset: i64,
-        reply: &mut ReplyDirectory,
-    ) -> Result<(), libc::c_int> {
+        mut add: F,
+    ) -> Result<(), libc::c_int>
+    where
+        F: FnMut(&mut Self, Inode, i64, FileType, &str) -> Result<bool, libc::c_int>,
+    {
         let file_path = self.get_path(ino)?;
-        let cache_path = self.cache_path();
         let metadata = fs::metadata(&file_path).map_err(convert_io_error)?;
         if !metadata.is_dir() {
             return Err(libc::ENOTDIR);
         }
 
-        let entries = fs::read_dir(&file_path).map_err(convert_io_error)?;
-
-        for (i, entry) in entries.skip(offset as usize).enumerate() {
-            let entry = entry.map_err(convert_io_error)?;
+        // root is always reported as FUSE_ROOT_ID (even as a parent)
+        let parent_ino = match file_path.parent() {
+            Some(parent) if ino != FUSE_ROOT_ID => self.dir_inode(parent)?,
+            _ => FUSE_ROOT_ID,
+        };
+        for (i, (entry_ino, name)) in [(ino, "."), (parent_ino, "..")]
+            .into_iter()
+            .enumerate()
+            .skip(offset as usize)
+        {
+            if add(self, entry_ino, i as i64 + 1, FileType::Directory, name)? {
+                return Ok(());
+            }
+        }
+        let offset = offset.max(DOT_ENTRIES);
 
-            let file_type = convert_ft(entry.file_type().map_err(convert_io_error)?)
-                .map_err(convert_io_error)?;
+        let manifest = self.manifest(&file_path)?;
+        // listing is continued from the snapshot taken at its beginning
+        // (directory is read again when it is rewound)
+        let entries = match self.dir_snapshots.get(&fh) {
+            Some(snapshot) if offset > DOT_ENTRIES => snapshot.clone(),
+            _ => {
+                let snapshot = Arc::new(list_dir(&file_path).map_err(convert_io_error)?);
+                // handles are assigned by opendir
+                if fh != 0 {
+                    self.dir_snapshots.insert(fh, snapshot.clone());
+                }
+                snapshot
+            }
+        };
+        let start = ((offset - DOT_ENTRIES) as usize).min(entries.len());
 
-            let orig_file_name = entry.file_name().to_string_lossy().to_string();
+        for (i, entry) in entries[start..].iter().enumerate() {
+            let orig_file_name = entry.name.clone();
+            let Some(file_type) = entry.file_type else {
+                debug!("Skipping entry '{}' (unknown type)", orig_file_name);
+                continue;
+            };
 
-            // skip cache_dir from root
-            if ino == FUSE_ROOT_ID && cache_path == file_path.join(&orig_file_name) {
+            // skip cache_dir and dictionaries from root
+            if self.is_hidden(&file_path.join(&orig_file_name)) {
                 continue;
             }
 
             let file_name = match file_type {
                 FileType::RegularFile => {
-                    if !orig_file_name.ends_with(".zst") {
-                        if !self.convert {
+                    if strip_suffix(&orig_file_name).is_none() {
+                        if self.config.keep_plain
+                            && SUFFIXES.iter().any(|e| {
+                                file_path.join(format!("{}{}", orig_file_name, e)).exists()
+                            })
+                        {
+                            // Uncompressed copy of compressed file
+                            continue;
+                        } else if !self.config.convert {
                             // Hide non-zstd file in non converting mode
+                            self.check_overlap(&file_path, &orig_file_name)?;
                             continue;
                         } else {
                             orig_file_name.clone()
                         }
+                    } else if let Some(presented) =
+                        manifest.as_ref().and_then(|e| e.presented(&orig_file_name))
+                    {
+                        presented.to_string()
                     } else {
-                        orig_file_name.strip_suffix(".zst").unwrap().to_string()
+                        let name = strip_suffix(&orig_file_name).unwrap();
+                        // the name is presented by another file
+                        if manifest.as_ref().and_then(|e| e.backing(name)).is_some() {
+                            continue;
+                        }
+                        name.to_string()
                     }
                 }
-                FileType::Directory => orig_file_name.clone(),
+                FileType::Directory | FileType::Symlink => orig_file_name.clone(),
+                _ if self.config.show_special => orig_file_name.clone(),
                 _ => {
                     // skip other types
                     continue;
                 }
             };
 
-            // read ino from extended attributes
+            // directories stored as archives
+            if self.config.archive_dirs && file_type == FileType::RegularFile {
+                if let Some(dir_name) = archive::dir_name(&orig_file_name) {
+                    let entry_ino = match self.open_archive(&file_path.join(&orig_file_name)) {
+                        Ok((entry_ino, _)) => entry_ino,
+                        Err(err) => {
+                            debug!("Skipping archive '{}' (err={})", orig_file_name, err);
+                            continue;
+                        }
+                    };
+                    if add(
+                        self,
+                        entry_ino,
+                        offset + i as i64 + 1,
+                        FileType::Directory,
+                        dir_name,
+                    )? {
+                        break;
+                    }
+                    continue;
+                }
+            }
+
             let entry_path = file_path.join(&orig_file_name);
-            let entry_ino_opt = xattr::get(&entry_path, "user.ino")
-                .map_err(convert_io_error)?
-                .map(|e| u64::from_be_bytes(e.try_into().unwrap()));
-            let entry_ino = if let Some(ino) = entry_ino_opt {
-                // Ino exists
-                ino
-            } else {
-                // Make new inode
-                let ino = self.update_inode_idx().map_err(convert_io_error)?;
-                xattr::set(entry_path, "user.ino", &ino.to_be_bytes()).map_err(convert_io_error)?;
-                ino
+            let entry_ino = match self.entry_inode(entry, &entry_path, file_type) {
+                Ok(ino) => ino,
+                // e.g. the entry was removed while the directory is listed
+                Err(err) => {
+                    debug!("Skipping entry '{}' (err={})", entry_path.display(), err);
+                    continue;
+                }
             };
 
+            if self.config.fill_size_on_readdir
+                && !self.config.convert_dry_run
+                && file_type == FileType::RegularFile
+                && strip_suffix(&orig_file_name).is_some()
+            {
+                if let Err(err) = self.fill_size(&entry_path) {
+                    debug!(
+                        "Size of '{}' can't be filled (err={})",
+                        entry_path.display(),
+                        err
+                    );
+                }
+            }
+
             // update the cache
             self.icache()
                 .set_inode_path(entry_ino, &file_path, orig_file_name)?;
@@ -379,24 +1255,205 @@ impl ZstdFS {
                 &file_type,
                 &file_name,
             );
-            if reply.add(entry_ino, offset + i as i64 + 1, file_type, file_name) {
+            if add(
+                self,
+                entry_ino,
+                offset + i as i64 + 1,
+                file_type,
+                &file_name,
+            )? {
                 break;
             }
         }
         Ok(())
     }
 
-    fn getattr_wrapper(&mut self, ino: u64) -> Result<FileAttr, libc::c_int> {
-        let file_path = self.get_path(ino)?;
-        let file = fs::File::open(file_path).map_err(convert_io_error)?;
-        let metadata = file.metadata().map_err(convert_io_error)?;
+    /// Inode of a listed entry (inode is assigned to entries without it)
+    fn entry_inode(
+        &mut self,
+        entry: &ListedEntry,
+        entry_path: &Path,
+        file_type: FileType,
+    ) -> Result<Inode, libc::c_int> {
+        // read ino from extended attributes (inodes of symlinks and special files are only cached)
+        let entry_ino_opt =
+            if file_type != FileType::RegularFile && file_type != FileType::Directory {
+                let metadata = fs::symlink_metadata(entry_path).map_err(convert_io_error)?;
+                Some(self.cached_inode(&metadata)?)
+            } else {
+                self.stored_inode(entry_path).map_err(convert_io_error)?
+            };
+        if let Some(ino) = entry_ino_opt {
+            // Ino exists
+            Ok(ino)
+        } else if self.config.convert_dry_run {
+            let metadata = fs::symlink_metadata(entry_path).map_err(convert_io_error)?;
+            self.cached_inode(&metadata)
+        } else if self.config.allow_shared {
+            // Make new inode (other mounts need to see it right away)
+            let ino = self.update_inode_idx().map_err(convert_io_error)?;
+            xattr::set(entry_path, "user.ino", &ino.to_be_bytes()).map_err(convert_io_error)?;
+            Ok(ino)
+        } else {
+            // Inode is stored once the entry is looked up,
+            // so listing of a large directory doesn't write to each entry
+            let source_ino = entry.source_ino;
+            if let Some(ino) = self.icache().get_pending_inode(source_ino)? {
+                return Ok(ino);
+            }
+            let ino = self.next_inode();
+            self.icache().set_pending_inode(source_ino, ino)?;
+            Ok(ino)
+        }
+    }
+
+    /// Directory where the archive with the inode is extracted
+    fn staging_path(&self, ino: Inode) -> PathBuf {
+        self.cache_path()
+            .join("archives")
+            .join(format!("{:016x}", ino))
+    }
+
+    /// Extracts the archive (unless it was already extracted)
+    ///
+    /// Returns the inode of the archive and its staging directory
+    fn open_archive(&mut self, archive_path: &Path) -> Result<(Inode, PathBuf), libc::c_int> {
+        let file = fs::File::open(archive_path).map_err(convert_io_error)?;
+        let ino = self.update_inode(&file).map_err(convert_io_error)?;
+        let staging = self.staging_path(ino);
+        if !self.archives.contains_key(&staging) {
+            let _ = fs::remove_dir_all(&staging);
+            let members =
+                archive::extract(archive_path, &staging, &self.dicts, archive::STAGING_LEVEL)
+                    .map_err(|err| {
+                        warn!(
+                            "Failed to extract archive '{}' (err={})",
+                            archive_path.display(),
+                            err
+                        );
+                        convert_io_error(err)
+                    })?;
+            debug!(
+                "Archive '{}' extracted to '{}' ({} members)",
+                archive_path.display(),
+                staging.display(),
+                members
+            );
+            self.archives
+                .insert(staging.clone(), archive_path.to_path_buf());
+        }
+        self.icache().set_inode_path(ino, &staging, "")?;
+        Ok((ino, staging))
+    }
+
+    fn archive_attrs(&mut self, archive_path: &Path) -> Result<FileAttr, libc::c_int> {
+        let (ino, staging) = self.open_archive(archive_path)?;
+        let mut attrs: FileAttr =
+            FileAttrWrapper::try_from(fs::metadata(staging).map_err(convert_io_error)?)
+                .map_err(convert_io_error)?
+                .into();
+        access_all(&mut attrs, &self.config);
+        attrs.ino = ino;
+        Ok(attrs)
+    }
+
+    /// Packs the archive again when the path is inside of its staging directory
+    fn update_archive(&mut self, path: &Path) -> Result<(), libc::c_int> {
+        let (staging, archive_path) = match self
+            .archives
+            .iter()
+            .find(|(staging, _)| path.starts_with(staging))
+        {
+            Some((staging, archive_path)) => (staging.clone(), archive_path.clone()),
+            None => return Ok(()),
+        };
+
+        let tmp_file = tempfile::NamedTempFile::new_in(archive_path.parent().unwrap())
+            .map_err(convert_io_error)?;
+        let members = archive::pack(
+            &staging,
+            io::BufWriter::new(tmp_file.reopen().map_err(convert_io_error)?),
+            &self.dicts,
+            self.compression_dict(),
+            self.config.compression_level,
+            &self.config.zstd_params,
+        )
+        .map_err(convert_io_error)?;
+        if let Some(ino) = xattr::get(&archive_path, "user.ino").map_err(convert_io_error)? {
+            tmp_file
+                .as_file()
+                .set_xattr("user.ino", &ino)
+                .map_err(convert_io_error)?;
+        }
+        tmp_file.as_file().sync_all().map_err(convert_io_error)?;
+        tmp_file.persist(&archive_path).map_err(convert_io_error)?;
+        debug!(
+            "Archive '{}' updated ({} members)",
+            archive_path.display(),
+            members
+        );
+        Ok(())
+    }
+
+    /// Removes archive of an empty directory
+    fn remove_archive(&mut self, archive_path: &Path) -> Result<(), libc::c_int> {
+        let (ino, staging) = self.open_archive(archive_path)?;
+        if fs::read_dir(&staging)
+            .map_err(convert_io_error)?
+            .next()
+            .is_some()
+        {
+            return Err(libc::ENOTEMPTY);
+        }
+        fs::remove_file(archive_path).map_err(convert_io_error)?;
+        fs::remove_dir(&staging).map_err(convert_io_error)?;
+        self.archives.remove(&staging);
+        self.icache().del_inode_path(ino)?;
+        Ok(())
+    }
+
+    /// Inode of a directory in data dir
+    fn dir_inode(&mut self, path: &Path) -> Result<Inode, libc::c_int> {
+        if path == self.root_dir() {
+            return Ok(FUSE_ROOT_ID);
+        }
+        self.known_inode(path)?.ok_or(libc::EIO)
+    }
+
+    fn getattr_wrapper(&mut self, ino: u64) -> Result<FileAttr, libc::c_int> {
+        let file_path = self.get_path(ino)?;
+        let link_metadata = fs::symlink_metadata(&file_path).map_err(convert_io_error)?;
+        if link_metadata.file_type().is_symlink() || is_special(link_metadata.file_type()) {
+            let mut attrs: FileAttr = FileAttrWrapper::try_from(link_metadata)
+                .map_err(convert_io_error)?
+                .into();
+            attrs.ino = ino;
+            return Ok(attrs);
+        }
+
+        if let Err(err) = self.refresh_opened(ino) {
+            warn!(
+                "Failed to refresh opened '{}' (err={})",
+                file_path.display(),
+                err
+            );
+        }
+
+        let file = fs::File::open(file_path).map_err(convert_io_error)?;
+        let metadata = file.metadata().map_err(convert_io_error)?;
         let mut faw: FileAttrWrapper = metadata.try_into().map_err(convert_io_error)?;
         // Update size from ext attr
-        faw.update_realsize(&file)?;
+        faw.update_realsize(self.read_meta(&file)?);
         let mut attrs: FileAttr = faw.into();
 
+        // Opened file may contain data which were not compressed yet
+        if let Some(size) = self.opened_files.logical_size(ino) {
+            attrs.size = size;
+        }
+
         // Allow access to all
-        access_all(&mut attrs);
+        access_all(&mut attrs, &self.config);
+        report_blocks(&mut attrs, &self.config);
 
         // override to mp ino
         attrs.ino = ino;
@@ -404,16 +1461,84 @@ impl ZstdFS {
         Ok(attrs)
     }
 
+    /// Data of immutable and append-only files can't be truncated
+    fn check_truncate(&mut self, ino: u64, size: u64) -> Result<(), libc::c_int> {
+        let inode_flags = flags::get(&self.get_path(ino)?).map_err(convert_io_error)?;
+        if inode_flags & flags::IMMUTABLE != 0
+            || ((self.config.append_only || inode_flags & flags::APPEND != 0)
+                && size < self.getattr_wrapper(ino)?.size)
+        {
+            return Err(libc::EPERM);
+        }
+        Ok(())
+    }
+
+    /// Truncates data of all handles of the inode (or stored file when it is not opened)
+    fn truncate_wrapper(
+        &mut self,
+        ino: u64,
+        size: u64,
+        fh: Option<u64>,
+    ) -> Result<(), libc::c_int> {
+        self.check_truncate(ino, size)?;
+        // data after the new size are not needed
+        match self.opened_files.pending(ino) {
+            Some(pending) if size <= pending.written => {
+                debug!(
+                    "Decompression of inode 0x{:016x} skipped (file was truncated)",
+                    ino
+                );
+                self.opened_files.take_pending(ino);
+            }
+            Some(_) => {
+                if let Some(file) = self.inode_tmp_file(ino)? {
+                    self.materialize(ino, &file)?;
+                }
+            }
+            None => {}
+        }
+        if let Some(fh) = fh {
+            if let Some(file_handler) = self.opened_files.get_mut(fh) {
+                file_handler.file.set_len(size).map_err(convert_io_error)?;
+                file_handler.needs_sync = true;
+            }
+        }
+        // file is not opened so there is no handle to store it later
+        if self
+            .opened_files
+            .get_fhs_from_mount_point_inode(ino)
+            .is_none()
+        {
+            self.truncate_stored(ino, size)?;
+        }
+
+        if let Some(fhs) = self.opened_files.get_fhs_from_mount_point_inode(ino) {
+            fhs.to_owned()
+                .into_iter()
+                .filter_map(|fh| {
+                    if let Some(file_handler) = self.opened_files.get_mut(fh) {
+                        Some(file_handler.file.set_len(size))
+                    } else {
+                        None
+                    }
+                })
+                .collect::<io::Result<Vec<_>>>()
+                .map_err(convert_io_error)?;
+        }
+        self.opened_files.truncate(ino, size);
+        Ok(())
+    }
+
     #[allow(clippy::too_many_arguments)]
     fn setattr_wrapper(
         &mut self,
         ino: u64,
-        _mode: Option<u32>,
+        mode: Option<u32>,
         _uid: Option<u32>,
         _gid: Option<u32>,
         size: Option<u64>,
-        _atime: Option<fuser::TimeOrNow>,
-        _mtime: Option<fuser::TimeOrNow>,
+        atime: Option<fuser::TimeOrNow>,
+        mtime: Option<fuser::TimeOrNow>,
         _ctime: Option<std::time::SystemTime>,
         fh: Option<u64>,
         _crtime: Option<std::time::SystemTime>,
@@ -423,32 +1548,135 @@ impl ZstdFS {
     ) -> Result<FileAttr, libc::c_int> {
         // TODO allow setting other arguments
 
+        // Change permissions when they are not overridden
+        if let Some(mode) = mode {
+            let file_path = self.get_path(ino)?;
+            let overridden = if file_path.is_dir() {
+                self.config.dir_mode.is_none()
+            } else {
+                self.config.file_mode.is_none()
+            };
+            if !overridden {
+                fs::set_permissions(&file_path, fs::Permissions::from_mode(mode & 0o7777))
+                    .map_err(convert_io_error)?;
+            }
+        }
+
         // Truncate if required
         if let Some(size) = size {
-            if let Some(fh) = fh {
-                if let Some(file_handler) = self.opened_files.get(fh) {
-                    file_handler.file.set_len(size).map_err(convert_io_error)?;
-                }
-            }
+            self.truncate_wrapper(ino, size, fh)?;
+        }
 
-            if let Some(fhs) = self.opened_files.get_fhs_from_mount_point_inode(ino) {
-                fhs.to_owned()
-                    .into_iter()
-                    .filter_map(|fh| {
-                        if let Some(file_handler) = self.opened_files.get_mut(fh) {
-                            Some(file_handler.file.set_len(size))
-                        } else {
-                            None
-                        }
-                    })
-                    .collect::<io::Result<Vec<_>>>()
-                    .map_err(convert_io_error)?;
+        // Store times (omitted one is kept, e.g. `touch -a`)
+        let to_system_time = |time| match time {
+            fuser::TimeOrNow::SpecificTime(time) => time,
+            fuser::TimeOrNow::Now => SystemTime::now(),
+        };
+        let atime = atime.map(to_system_time);
+        let mtime = mtime
+            .filter(|_| self.config.preserve_mtime)
+            .map(to_system_time);
+        if atime.is_some() || mtime.is_some() {
+            let file_path = self.get_path(ino)?;
+            let metadata = fs::metadata(&file_path).map_err(convert_io_error)?;
+            filetime::set_file_times(
+                &file_path,
+                atime
+                    .map(FileTime::from_system_time)
+                    .unwrap_or_else(|| FileTime::from_last_access_time(&metadata)),
+                mtime
+                    .map(FileTime::from_system_time)
+                    .unwrap_or_else(|| FileTime::from_last_modification_time(&metadata)),
+            )
+            .map_err(convert_io_error)?;
+            let changed = fs::metadata(&file_path).map_err(convert_io_error)?;
+            self.recache_meta(&metadata, &changed)?;
+        }
+        if let Some(mtime) = mtime {
+            let file_path = self.get_path(ino)?;
+            let metadata = fs::metadata(&file_path).map_err(convert_io_error)?;
+            self.opened_files
+                .set_source(ino, file::SourceVersion::new(&metadata));
+
+            // opened files are going to be compressed again later
+            for fh in self
+                .opened_files
+                .get_fhs_from_mount_point_inode(ino)
+                .map(|e| e.to_owned())
+                .unwrap_or_default()
+            {
+                if let Some(file_handler) = self.opened_files.get_mut(fh) {
+                    file_handler.mtime = Some(mtime);
+                }
             }
         }
         self.getattr_wrapper(ino)
     }
 
     fn open_wrapper(&mut self, ino: u64, flags: i32) -> Result<u64, libc::c_int> {
+        // O_TRUNC is passed with FUSE_ATOMIC_O_TRUNC, the data shared
+        // by all handles of the file are truncated (as on a local filesystem)
+        let truncate = flags & libc::O_TRUNC != 0 && flags & libc::O_ACCMODE != libc::O_RDONLY;
+        if !truncate {
+            return self.open_file(ino, flags, false);
+        }
+        self.check_truncate(ino, 0)?;
+        let fh = self.open_file(ino, flags, true)?;
+        if let Err(err) = self.truncate_wrapper(ino, 0, Some(fh)) {
+            self.opened_files.close(fh);
+            return Err(err);
+        }
+        Ok(fh)
+    }
+
+    /// Opens the file, data of `truncate`d file are not decompressed when possible
+    fn open_file(&mut self, ino: u64, flags: i32, truncate: bool) -> Result<u64, libc::c_int> {
+        self.refresh_opened(ino)?;
+
+        // Read only file which contains index doesn't need to be decompressed
+        if flags & libc::O_ACCMODE == libc::O_RDONLY
+            && self
+                .opened_files
+                .get_fhs_from_mount_point_inode(ino)
+                .is_none()
+        {
+            let file_path = self.get_path(ino)?;
+            let source_file = fs::File::open(&file_path).map_err(convert_io_error)?;
+            // data of raw files may look like an index
+            let index = if meta::is_raw(&source_file).map_err(convert_io_error)? {
+                None
+            } else {
+                index::read_index(&source_file).map_err(convert_io_error)?
+            };
+            if let Some(index) = index {
+                debug!(
+                    "Reading '{}' using index ({} entries)",
+                    file_path.display(),
+                    index.len()
+                );
+                let metadata = source_file.metadata().map_err(convert_io_error)?;
+                return self
+                    .opened_files
+                    .insert_indexed(
+                        ino,
+                        flags,
+                        source_file,
+                        file_path,
+                        index,
+                        self.config
+                            .readahead
+                            .map(|e| index::ReadAhead::new(e, self.decompress_limit.clone())),
+                        file::SourceVersion::new(&metadata),
+                    )
+                    .ok_or(libc::EBUSY);
+            }
+        }
+
+        // data are shared with the new handle
+        if let Some(file) = self.inode_tmp_file(ino)?.filter(|_| !truncate) {
+            self.materialize(ino, &file)?;
+        }
+
         // Already opened by some other process
         if let Some(fh) = self
             .opened_files
@@ -459,36 +1687,149 @@ impl ZstdFS {
         }
         let file_path = self.get_path(ino)?;
         let source_file = fs::File::open(&file_path).map_err(convert_io_error)?;
-        let mut target_file = tempfile::tempfile().map_err(convert_io_error)?;
-        zstd::stream::copy_decode(
-            source_file.try_clone().map_err(convert_io_error)?,
-            target_file.try_clone().map_err(convert_io_error)?,
-        )
-        .map_err(|_| libc::EFAULT)?;
+
+        // Wait for a free slot unless opened in non-blocking mode
+        let _permit = match self.decompress_limit.as_ref() {
+            Some(limit) if flags & libc::O_NONBLOCK != 0 => {
+                Some(limit.try_acquire().ok_or(libc::EAGAIN)?)
+            }
+            Some(limit) => Some(limit.acquire()),
+            None => None,
+        };
+        let max_size = self.config.max_decompressed_size.unwrap_or(u64::MAX);
+        if let Some(size) = codec::frame_content_size(&source_file).map_err(convert_io_error)? {
+            if size > max_size && !meta::is_raw(&source_file).map_err(convert_io_error)? {
+                warn!(
+                    "File '{}' would be decompressed to {} bytes (max {})",
+                    file_path.display(),
+                    size,
+                    max_size
+                );
+                return Err(libc::EFBIG);
+            }
+        }
+        // file which is only written may be overwritten completely
+        if truncate
+            || (flags & libc::O_ACCMODE == libc::O_WRONLY
+                && flags & libc::O_APPEND == 0
+                && !self.config.append_only
+                && !self.config.verify_size)
+        {
+            if let Some(meta) = self.read_meta(&source_file)? {
+                let target_file = self.scratch_file().map_err(convert_io_error)?;
+                let metadata = source_file.metadata().map_err(convert_io_error)?;
+                let fh = self
+                    .opened_files
+                    .insert(ino, flags, target_file, file_path.clone())
+                    .ok_or(libc::EBUSY)?;
+                self.opened_files
+                    .set_source(ino, file::SourceVersion::new(&metadata));
+                self.opened_files.truncate(ino, meta.real_size);
+                self.opened_files.set_pending(
+                    ino,
+                    file::Pending {
+                        source: source_file,
+                        real_size: meta.real_size,
+                        written: 0,
+                    },
+                );
+                debug!("Decompression of '{}' postponed", file_path.display());
+                return self.share_indexed(ino, fh, truncate);
+            }
+        }
+
+        let mut target_file = self.scratch_file().map_err(convert_io_error)?;
+        let target = limit::SizeLimit::new(io::BufWriter::new(&target_file), max_size);
+        let started = Instant::now();
+        let res = self.decompress_file(&source_file, target);
+        self.profiled("decompress", started);
+        if let Err(err) = res {
+            if err == libc::EFBIG {
+                warn!(
+                    "File '{}' decompressed to more than {} bytes",
+                    file_path.display(),
+                    max_size
+                );
+            }
+            return Err(err);
+        }
         target_file
             .seek(SeekFrom::Start(0))
             .map_err(convert_io_error)?;
 
+        let real_size = target_file.metadata().map_err(convert_io_error)?.st_size();
+        self.stats.decompressed(real_size);
+
+        // compare stored size with the size of decompressed data
+        let stored_meta = self.read_meta(&source_file)?;
+        let stored_size = stored_meta.map(|e| e.real_size);
+        if self.config.verify_size {
+            match stored_size {
+                Some(stored_size) if stored_size != real_size => {
+                    warn!(
+                        "Real size of '{}' mismatch (stored={}, decompressed={}), fixing",
+                        file_path.display(),
+                        stored_size,
+                        real_size,
+                    );
+                }
+                _ => {}
+            }
+        }
+
         // update real file size to xattr of original file
-        source_file
-            .set_xattr(
-                "user.real_size",
-                &target_file
-                    .metadata()
-                    .map_err(convert_io_error)?
-                    .st_size()
-                    .to_be_bytes(),
-            )
-            .map_err(convert_io_error)?;
-        // Make sure that new size is written to original directory
-        source_file.sync_all().map_err(convert_io_error)?;
+        if stored_size != Some(real_size) {
+            let meta = meta::Meta {
+                real_size,
+                ..stored_meta.unwrap_or_else(|| meta::Meta::new(0, real_size))
+            };
+            match self.write_meta(&meta, &source_file) {
+                // xattrs are stored synchronously on network filesystems
+                Ok(()) if self.config.network_backing => {}
+                // Make sure that new size is written to original directory
+                Ok(()) => source_file.sync_all().map_err(convert_io_error)?,
+                // file can still be read from read-only data dir
+                Err(err) if err.raw_os_error() == Some(libc::EROFS) => {
+                    debug!("Real size of '{}' can't be updated", file_path.display())
+                }
+                Err(err) => return Err(convert_io_error(err)),
+            }
+        }
 
         // Store info about newly opened file
         let fh = self
             .opened_files
             .insert(ino, flags, target_file, file_path)
             .ok_or(libc::EBUSY)?;
+        let metadata = source_file.metadata().map_err(convert_io_error)?;
+        self.opened_files
+            .set_source(ino, file::SourceVersion::new(&metadata));
+
+        self.share_indexed(ino, fh, false)
+    }
 
+    /// Handles reading the compressed file using its index switch to the data decompressed
+    /// for the newly opened handle `fh`, so they see its changes and they don't read
+    /// the compressed file while it is stored
+    fn share_indexed(&mut self, ino: Inode, fh: u64, truncate: bool) -> Result<u64, libc::c_int> {
+        if self.opened_files.get_indexed_fhs(ino).is_none() {
+            return Ok(fh);
+        }
+        let res = self.inode_tmp_file(ino).and_then(|file| {
+            let file = file.ok_or(libc::EBADF)?;
+            // data of truncated file are not needed
+            if !truncate {
+                self.materialize(ino, &file)?;
+            }
+            self.opened_files
+                .share_indexed(ino, &file)
+                .map_err(convert_io_error)
+        });
+        if let Err(err) = res {
+            self.opened_files.close(fh);
+            return Err(err);
+        }
+        debug!("Indexed handles share data of inode 0x{:016x}", ino);
         Ok(fh)
     }
 
@@ -504,10 +1845,29 @@ impl ZstdFS {
 
         let file_handler = self.opened_files.get_mut(fh).ok_or(libc::ENOENT)?;
         let mut res = vec![0; size as usize];
-        let read_size = file_handler
-            .file
-            .read_at(&mut res, offset as u64)
+
+        if let Some(index) = file_handler.index.as_ref() {
+            let entry = &index[index::find_entry(index, offset as u64)];
+            debug!(
+                "Decompressing from index entry (offset={}, position={})",
+                entry.offset, entry.position
+            );
+            let read_size = index::read_at(
+                &file_handler.file,
+                index,
+                &mut res,
+                offset as u64,
+                &self.dicts,
+                file_handler.readahead.as_mut(),
+                &mut file_handler.last_frame,
+            )
             .map_err(convert_io_error)?;
+            res.truncate(read_size);
+            return Ok(res);
+        }
+
+        let read_size =
+            read_full_at(&file_handler.file, &mut res, offset as u64).map_err(convert_io_error)?;
         res.truncate(read_size);
         Ok(res)
     }
@@ -521,22 +1881,62 @@ impl ZstdFS {
         flags: i32,
     ) -> Result<(FileAttr, u64), libc::c_int> {
         // Create emtpy file in the tree dir
-        let name = name.to_string_lossy().to_string() + ".zst";
         let parent_path = self.get_path(parent)?;
+        self.check_reserved(&parent_path, name)?;
+        let name = name.to_string_lossy().to_string() + self.new_suffix();
+
+        // parent may be removed meanwhile (e.g. directly in data dir)
+        match fs::metadata(&parent_path) {
+            Ok(metadata) if metadata.is_dir() => {}
+            Ok(_) => return Err(libc::ENOTDIR),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {
+                debug!(
+                    "Parent '{}' of created file '{}' was removed",
+                    parent_path.display(),
+                    name
+                );
+                return Err(libc::ENOENT);
+            }
+            Err(err) => return Err(convert_io_error(err)),
+        }
+
+        // File could have been created by other process in the meantime
+        // in that case it should be opened instead of being overriden
+        let path = parent_path.join(&name);
+        if path.exists() {
+            if flags & libc::O_EXCL != 0 {
+                return Err(libc::EEXIST);
+            }
+            let file = fs::File::open(&path).map_err(convert_io_error)?;
+            let ino = self.update_inode(&file).map_err(convert_io_error)?;
+            self.icache().set_inode_path(ino, &parent_path, &name)?;
+
+            // shares the opened file with other handlers of the inode
+            let fh = self.open_wrapper(ino, flags)?;
+            let attrs = self.getattr_wrapper(ino)?;
+            return Ok((attrs, fh));
+        }
 
-        let opened_file = tempfile::tempfile().map_err(convert_io_error)?;
+        let opened_file = self.scratch_file().map_err(convert_io_error)?;
 
         // Write new file to source directory
-        let (source_file, ino) =
-            self.store_to_source_file(&opened_file, &parent_path, &name, self.compression_level)?;
+        let (source_file, ino) = self.store_to_source_file(
+            &opened_file,
+            &parent_path,
+            &name,
+            self.config.compression_level,
+        )?;
 
         // Obtain attrs of the new file
-        let faw = FileAttrWrapper::try_from(source_file.metadata().map_err(convert_io_error)?)
+        let mut faw = FileAttrWrapper::try_from(source_file.metadata().map_err(convert_io_error)?)
             .map_err(convert_io_error)?;
+        // size of the empty frame is not reported
+        faw.update_realsize(self.read_meta(&source_file)?);
         let mut attrs: FileAttr = faw.into();
 
         // allow access to all
-        access_all(&mut attrs);
+        access_all(&mut attrs, &self.config);
+        report_blocks(&mut attrs, &self.config);
         // user.ino has to be se in store_to_source_file()
         // so we need to read it here
         attrs.ino = ino;
@@ -561,19 +1961,54 @@ impl ZstdFS {
         fh: u64,
         offset: i64,
         data: &[u8],
-        _write_flags: u32,
+        write_flags: u32,
         _flags: i32,
         _lock_owner: Option<u64>,
     ) -> Result<usize, libc::c_int> {
         // Hit the cache
-        let _ = self.get_path(ino);
+        let inode_flags = match self.get_path(ino) {
+            Ok(path) => flags::get(&path).unwrap_or(0),
+            Err(_) => 0,
+        };
+        if inode_flags & flags::IMMUTABLE != 0 {
+            return Err(libc::EPERM);
+        }
+
+        // postponed data are needed unless the file is written from its beginning
+        if self
+            .opened_files
+            .pending(ino)
+            .is_some_and(|e| offset as u64 > e.written)
+        {
+            if let Some(file) = self.inode_tmp_file(ino)? {
+                self.materialize(ino, &file)?;
+            }
+        }
 
         let file_handler = self.opened_files.get_mut(fh).ok_or(libc::EBADF)?;
 
         // File should be synced to source dir
         file_handler.needs_sync = true;
 
-        let offset = if file_handler.flags & libc::O_APPEND != 0 {
+        // Data were modified after mtime was set
+        if file_handler.mtime.is_some() {
+            for fh in self
+                .opened_files
+                .get_fhs_from_mount_point_inode(ino)
+                .map(|e| e.to_owned())
+                .unwrap_or_default()
+            {
+                if let Some(file_handler) = self.opened_files.get_mut(fh) {
+                    file_handler.mtime = None;
+                }
+            }
+        }
+        let file_handler = self.opened_files.get_mut(fh).ok_or(libc::EBADF)?;
+
+        // pages written via mmap are written back to their own offsets
+        // (using any writable handle of the file)
+        let from_cache = write_flags & fuser::consts::FUSE_WRITE_CACHE != 0;
+        let offset = if file_handler.flags & libc::O_APPEND != 0 && !from_cache {
             // We need to append to file -> we need to get end position
             file_handler
                 .file
@@ -586,96 +2021,442 @@ impl ZstdFS {
         } else {
             offset as u64
         };
-        file_handler
+
+        // existing data can't be modified
+        if (self.config.append_only || inode_flags & flags::APPEND != 0)
+            && offset
+                < file_handler
+                    .file
+                    .metadata()
+                    .map_err(convert_io_error)?
+                    .len()
+        {
+            return Err(libc::EPERM);
+        }
+
+        let written = file_handler
             .file
             .write_at(data, offset)
-            .map_err(convert_io_error)
-    }
-
-    fn release_wrapper(&mut self, _ino: u64, fh: u64) -> Result<(), libc::c_int> {
-        // file will be closed and freed once this function ends
-        self.sync_to_fs(fh, true, false)?;
-        Ok(())
+            .map_err(convert_io_error)?;
+        self.opened_files
+            .extend(ino, offset + written as u64)
+            .map_err(convert_io_error)?;
+        if let Some(pending) = self.opened_files.pending_mut(ino) {
+            pending.written = pending.written.max(offset + written as u64);
+        }
+        Ok(written)
     }
 
-    fn mkdir_wrapper(
-        &mut self,
-        parent: u64,
-        name: &OsStr,
-        _mode: u32,
-        _umask: u32,
-    ) -> Result<FileAttr, libc::c_int> {
-        let parent_path = self.get_path(parent)?;
-        let path = parent_path.join(name);
-        fs::create_dir(&path).map_err(convert_io_error)?;
-        let metadata = fs::metadata(&path).map_err(convert_io_error)?;
-
-        let faw: FileAttrWrapper = metadata.try_into().map_err(convert_io_error)?;
-        let mut attrs: FileAttr = faw.into();
-        // allow access to all
-        access_all(&mut attrs);
-        attrs.ino = self.update_inode_idx().map_err(convert_io_error)?;
-
-        // store ino
-        xattr::set(path, "user.ino", &attrs.ino.to_be_bytes()).map_err(convert_io_error)?;
-
-        // update inode map
-        self.icache()
-            .set_inode_path(attrs.ino, parent_path, name.to_string_lossy())?;
-
-        Ok(attrs)
-    }
+    fn release_wrapper(&mut self, ino: u64, fh: u64) -> Result<(), libc::c_int> {
+        let path = self
+            .opened_files
+            .get(fh)
+            .and_then(|e| e.refs.as_ref())
+            .map(|e| e.path.clone());
+
+        let path_str = path
+            .as_ref()
+            .map(|e| e.display().to_string())
+            .unwrap_or_default();
+
+        if self.config.sync_on == config::SyncOn::Fsync {
+            if let Some(handler) = self.opened_files.get_mut(fh).filter(|e| e.needs_sync) {
+                warn!(
+                    "Changes of '{}' which were not synced are dropped",
+                    path_str
+                );
+                handler.needs_sync = false;
+            }
+        }
 
-    fn unlink_wrapper(&mut self, parent: u64, name: &OsStr) -> Result<(), libc::c_int> {
-        let parent_path = self.get_path(parent)?;
-        let path = parent_path.join(name.to_string_lossy().to_string() + ".zst");
-        if let Some(ino_data) = xattr::get(&path, "user.ino").map_err(convert_io_error)? {
-            let ino = u64::from_be_bytes(ino_data.try_into().unwrap());
-            self.icache().del_inode_path(ino)?;
-            self.opened_files.unlink(ino);
+        // applications usually ignore errors of close, so failures are logged
+        if self.config.strict_close {
+            // stored before the file is closed so it can be kept opened when it fails
+            if let Err(err) = self.sync_to_fs(fh, false, false) {
+                error!(
+                    "Failed to store '{}' on release, keeping it opened to retry (err={})",
+                    path_str, err
+                );
+                self.failed_releases.push((ino, fh));
+                return Err(err);
+            }
         }
-        fs::remove_file(path).map_err(convert_io_error)?;
-        Ok(())
-    }
 
-    fn rmdir_wrapper(&mut self, parent: u64, name: &OsStr) -> Result<(), libc::c_int> {
-        let parent_path = self.get_path(parent)?;
-        let path = parent_path.join(name.to_string_lossy().to_string());
-        if let Some(ino_data) = xattr::get(&path, "user.ino").map_err(convert_io_error)? {
-            let ino = u64::from_be_bytes(ino_data.try_into().unwrap());
-            self.icache().del_inode_path(ino)?;
-            self.opened_files.unlink(ino);
+        // file will be closed and freed once this function ends
+        match self.sync_to_fs(fh, true, false) {
+            Ok(()) | Err(libc::EBADF) => {}
+            Err(err) => {
+                error!(
+                    "Failed to store '{}' on release, changes are lost (err={})",
+                    path_str, err
+                );
+                return Err(err);
+            }
         }
 
-        // Don't try to remove cache dir
-        let cache_dir = self.cache_path();
-        if cache_dir == parent_path.join(name) {
-            warn!("Cache dir '{}' can't be removed!", cache_dir.display());
-            return Err(libc::ENOENT);
+        // Remove unlinked file when it was closed for the last time
+        let closed = self
+            .opened_files
+            .get_fhs_from_mount_point_inode(ino)
+            .is_none();
+        if let Some(path) = path.filter(|_| closed) {
+            if path.starts_with(self.unlinked_path()) {
+                debug!("Removing unlinked file '{}'", path.display());
+                match fs::remove_file(path) {
+                    // changes of file which was removed directly were never stored
+                    Err(err) if err.kind() == io::ErrorKind::NotFound => {}
+                    res => res.map_err(convert_io_error)?,
+                }
+            } else if path.parent() == Some(self.root_dir().as_path())
+                && path
+                    .file_name()
+                    .and_then(|e| strip_suffix(&e.to_string_lossy()).map(|e| e.to_string()))
+                    .is_some_and(|e| e == cache::REBUILD_NAME)
+            {
+                // control file is not kept
+                fs::remove_file(path).map_err(convert_io_error)?;
+                match self.rebuild_cache() {
+                    Ok(cached) => info!("Inode cache rebuilt (entries={})", cached),
+                    Err(err) => error!("Failed to rebuild inode cache (err={})", err),
+                }
+            }
         }
 
-        fs::remove_dir(path).map_err(convert_io_error)?;
+        self.retry_failed_releases();
         Ok(())
     }
 
-    fn rename_wrapper(
-        &mut self,
-        parent: u64,
-        name: &OsStr,
-        newparent: u64,
-        newname: &OsStr,
-        _flags: u32,
-    ) -> Result<(), libc::c_int> {
-        // First we should check filetype of source file
+    /// Replaces paths in the inode cache by the inodes stored in data dir
+    ///
+    /// Returns the number of cached entries
+    fn rebuild_cache(&mut self) -> Result<usize, libc::c_int> {
+        self.icache().clear_paths()?;
+        let cache_path = self.cache_path();
+        let mut dirs = vec![self.root_dir()];
+        // extracted archives are in the cache dir
+        for staging in self.archives.keys().cloned().collect::<Vec<_>>() {
+            let name = staging.file_name().unwrap_or_default().to_string_lossy();
+            if let Ok(ino) = u64::from_str_radix(&name, 16) {
+                self.icache().set_inode_path(ino, &staging, "")?;
+                dirs.push(staging);
+            }
+        }
+
+        let mut cached = 0;
+        while let Some(dir) = dirs.pop() {
+            let entries = match fs::read_dir(&dir) {
+                Ok(entries) => entries,
+                Err(err) => {
+                    warn!(
+                        "Failed to list '{}' while rebuilding inode cache (err={})",
+                        dir.display(),
+                        err
+                    );
+                    continue;
+                }
+            };
+            for entry in entries.flatten() {
+                let path = entry.path();
+                let Ok(metadata) = fs::symlink_metadata(&path) else {
+                    continue;
+                };
+                if !dir.starts_with(&cache_path) && self.is_hidden(&path) {
+                    continue;
+                }
+                let ino = if metadata.is_file() || metadata.is_dir() {
+                    match self.stored_inode(&path) {
+                        Ok(Some(ino)) => Some(ino),
+                        _ => self.icache().get_pending_inode(metadata.st_ino())?,
+                    }
+                } else {
+                    self.icache().get_symlink_inode(metadata.st_ino())?
+                };
+                if let Some(ino) = ino {
+                    self.icache()
+                        .set_inode_path(ino, &dir, entry.file_name().to_string_lossy())?;
+                    cached += 1;
+                }
+                // parent is cached before its entries
+                if metadata.is_dir() {
+                    dirs.push(path);
+                }
+            }
+        }
+        Ok(cached)
+    }
+
+    /// Stores changes of all opened files and running totals once the flush interval elapses
+    fn flush_dirty(&mut self) {
+        let Some(interval) = self.config.flush_interval else {
+            return;
+        };
+        if self.last_flush.elapsed() < interval {
+            return;
+        }
+        self.check_cache_dir();
+        for fh in self.opened_files.dirty() {
+            match self.sync_to_fs(fh, false, false) {
+                Ok(()) => debug!("Periodically flushed (fh={})", fh),
+                Err(err) => warn!("Periodic flush failed (fh={}, err={})", fh, err),
+            }
+        }
+        self.save_stats();
+        self.save_metas();
+        self.last_flush = Instant::now();
+    }
+
+    /// Rebuilds the inode cache once the compaction interval elapses
+    /// and the cache grew to twice its size after the last compaction
+    fn compact_cache(&mut self) {
+        let Some(interval) = self.config.compact_cache_interval else {
+            return;
+        };
+        let (checked, compacted) = self.last_compaction;
+        if checked.elapsed() < interval {
+            return;
+        }
+        self.check_cache_dir();
+        let mut size = self.icache().size_on_disk();
+        if size > compacted.saturating_mul(2) {
+            let cache_path = self.cache_path();
+            match self.icache().compact(&cache_path) {
+                Ok(new_size) => {
+                    info!(
+                        "Inode cache compacted (before={}, after={})",
+                        size, new_size
+                    );
+                    size = new_size;
+                }
+                Err(err) => warn!("Failed to compact inode cache (err={})", err),
+            }
+            self.last_compaction.1 = size;
+        }
+        self.last_compaction.0 = Instant::now();
+        self.stats.cache_size(size);
+        self.save_stats();
+        self.save_metas();
+    }
+
+    /// Tries to store files which failed to be stored on release again
+    fn retry_failed_releases(&mut self) {
+        for (ino, fh) in std::mem::take(&mut self.failed_releases) {
+            if self.release_wrapper(ino, fh).is_ok() {
+                info!("File released on retry (inode=0x{:016x}, fh={})", ino, fh);
+            }
+        }
+    }
+
+    /// Removes the file from the tree, but keeps it
+    /// available for opened file handlers
+    fn remove_source_file(&mut self, ino: u64, path: &Path) -> Result<(), libc::c_int> {
+        if self
+            .opened_files
+            .get_fhs_from_mount_point_inode(ino)
+            .is_some()
+        {
+            let unlinked_dir = self.unlinked_path();
+            fs::create_dir_all(&unlinked_dir).map_err(convert_io_error)?;
+            let unlinked_path = unlinked_dir.join(format!("{:016x}", ino));
+            debug!(
+                "Keeping unlinked file '{}' as '{}'",
+                path.display(),
+                unlinked_path.display()
+            );
+            match fs::rename(path, &unlinked_path) {
+                Ok(()) => {}
+                // cache dir outside of data dir (see `init`), opened handles keep the decompressed
+                // data (or the descriptor of the compressed file) so only the name is removed
+                // and the changes are stored to the unlinked path
+                Err(err) if err.raw_os_error() == Some(libc::EXDEV) => {
+                    debug!(
+                        "Unlinked file '{}' can't be moved to cache dir, removing it",
+                        path.display()
+                    );
+                    fs::remove_file(path).map_err(convert_io_error)?;
+                }
+                Err(err) => return Err(convert_io_error(err)),
+            }
+            self.opened_files.relocate(ino, unlinked_path);
+        } else {
+            self.delete_file(path)?;
+        }
+        Ok(())
+    }
+
+    /// Removes the file from data dir (in background with `--async-unlink`)
+    fn delete_file(&mut self, path: &Path) -> Result<(), libc::c_int> {
+        let deleted_dir = self.cache_path().join("deleted");
+        if let Some(reclaimer) = self.reclaimer.as_mut() {
+            if reclaimer
+                .delete(path, &deleted_dir)
+                .map_err(convert_io_error)?
+            {
+                return Ok(());
+            }
+        }
+        fs::remove_file(path).map_err(convert_io_error)
+    }
+
+    fn mkdir_wrapper(
+        &mut self,
+        parent: u64,
+        name: &OsStr,
+        _mode: u32,
+        _umask: u32,
+    ) -> Result<FileAttr, libc::c_int> {
+        let parent_path = self.get_path(parent)?;
+        self.check_reserved(&parent_path, name)?;
+        let path = parent_path.join(name);
+        fs::create_dir(&path).map_err(convert_io_error)?;
+        if let Some(mode) = self.config.dir_mode {
+            fs::set_permissions(&path, fs::Permissions::from_mode(mode))
+                .map_err(convert_io_error)?;
+        }
+        let metadata = fs::metadata(&path).map_err(convert_io_error)?;
+        let metadata_ino = metadata.st_ino();
+
+        let faw: FileAttrWrapper = metadata.try_into().map_err(convert_io_error)?;
+        let mut attrs: FileAttr = faw.into();
+        // allow access to all
+        access_all(&mut attrs, &self.config);
+        if self.config.mirror_inodes {
+            attrs.ino = metadata_ino;
+        } else {
+            attrs.ino = self.update_inode_idx().map_err(convert_io_error)?;
+            // store ino
+            xattr::set(&path, "user.ino", &attrs.ino.to_be_bytes()).map_err(convert_io_error)?;
+        }
+
+        // update inode map
+        self.icache()
+            .set_inode_path(attrs.ino, &parent_path, name.to_string_lossy())?;
+        self.update_archive(&parent_path)?;
+
+        Ok(attrs)
+    }
+
+    fn unlink_wrapper(&mut self, parent: u64, name: &OsStr) -> Result<(), libc::c_int> {
+        if self.config.append_only {
+            return Err(libc::EPERM);
+        }
+        let parent_path = self.get_path(parent)?;
+        self.check_reserved(&parent_path, name)?;
+
+        // symlinks and special files are stored without .zst extension
+        let link_path = parent_path.join(name);
+        if let Ok(metadata) = fs::symlink_metadata(&link_path) {
+            if metadata.file_type().is_symlink()
+                || (self.config.show_special && is_special(metadata.file_type()))
+            {
+                if let Some(ino) = self.icache().get_symlink_inode(metadata.st_ino())? {
+                    self.icache().del_inode_path(ino)?;
+                    self.icache().del_symlink_inode(metadata.st_ino())?;
+                }
+                fs::remove_file(link_path).map_err(convert_io_error)?;
+                return self.update_archive(&parent_path);
+            }
+        }
+
+        let path = parent_path.join(self.backing_name(&parent_path, &name.to_string_lossy())?);
+        if flags::protected(&path).map_err(convert_io_error)? {
+            return Err(libc::EPERM);
+        }
+        if self.config.keep_plain {
+            if let Some(plain_path) = plain_path(&path) {
+                let _ = fs::remove_file(plain_path);
+            }
+        }
+        if let Some(ino) = self.known_inode(&path)? {
+            self.icache().del_inode_path(ino)?;
+            self.remove_source_file(ino, &path)?;
+        } else {
+            self.delete_file(&path)?;
+        }
+        self.update_archive(&parent_path)
+    }
+
+    fn symlink_wrapper(
+        &mut self,
+        parent: u64,
+        link_name: &OsStr,
+        target: &Path,
+    ) -> Result<FileAttr, libc::c_int> {
+        let parent_path = self.get_path(parent)?;
+        self.check_reserved(&parent_path, link_name)?;
+        let name = link_name.to_string_lossy().to_string();
+
+        // compressed file with the same name already exists
+        if SUFFIXES
+            .iter()
+            .any(|e| parent_path.join(format!("{}{}", name, e)).exists())
+        {
+            return Err(libc::EEXIST);
+        }
+
+        std::os::unix::fs::symlink(target, parent_path.join(&name)).map_err(convert_io_error)?;
+        let attrs = self.symlink_attrs(&parent_path, &name)?;
+        self.update_archive(&parent_path)?;
+        Ok(attrs)
+    }
+
+    fn readlink_wrapper(&mut self, ino: u64) -> Result<Vec<u8>, libc::c_int> {
+        let path = self.get_path(ino)?;
+        let target = fs::read_link(path).map_err(convert_io_error)?;
+        Ok(target.as_os_str().as_bytes().to_vec())
+    }
+
+    fn rmdir_wrapper(&mut self, parent: u64, name: &OsStr) -> Result<(), libc::c_int> {
+        if self.config.append_only {
+            return Err(libc::EPERM);
+        }
+        let parent_path = self.get_path(parent)?;
+        self.check_reserved(&parent_path, name)?;
+        let path = parent_path.join(name.to_string_lossy().to_string());
+
+        let archive_path =
+            parent_path.join(format!("{}{}", name.to_string_lossy(), archive::SUFFIX));
+        if self.config.archive_dirs && archive_path.is_file() {
+            return self.remove_archive(&archive_path);
+        }
+
+        if flags::protected(&path).map_err(convert_io_error)? {
+            return Err(libc::EPERM);
+        }
+        if let Some(ino) = self.known_inode(&path)? {
+            self.icache().del_inode_path(ino)?;
+            self.opened_files.unlink(ino);
+        }
+
+        fs::remove_dir(path).map_err(convert_io_error)?;
+        self.update_archive(&parent_path)
+    }
+
+    fn rename_wrapper(
+        &mut self,
+        parent: u64,
+        name: &OsStr,
+        newparent: u64,
+        newname: &OsStr,
+        _flags: u32,
+    ) -> Result<(), libc::c_int> {
+        let to_parent_path = self.get_path(newparent)?;
+        self.check_reserved(&to_parent_path, newname)?;
+
+        // First we should check filetype of source file
         // and add .zst extension to both names
+        let from_parent_path = self.get_path(parent)?;
         let (name, newname, ino) = {
             let attrs = self.lookup_wrapper(parent, name)?;
             if matches!(attrs.kind, FileType::RegularFile) {
-                (
-                    format!("{}.zst", name.to_string_lossy()),
-                    format!("{}.zst", newname.to_string_lossy()),
-                    attrs.ino,
-                )
+                let name = self.backing_name(&from_parent_path, &name.to_string_lossy())?;
+                // format of the data is kept (an existing file is replaced)
+                let suffix = SUFFIXES
+                    .iter()
+                    .find(|e| name.ends_with(*e))
+                    .unwrap_or(&".zst");
+                let newname =
+                    self.compressed_name(&to_parent_path, &newname.to_string_lossy(), suffix)?;
+                (name, newname, attrs.ino)
             } else {
                 (
                     name.to_string_lossy().to_string(),
@@ -685,25 +2466,96 @@ impl ZstdFS {
             }
         };
 
-        let from_path = self.get_path(parent)?.join(name);
+        // archive can't be moved as a directory (it is copied instead)
+        let path = self.get_path(ino)?;
+        if self.archives.contains_key(&path) {
+            return Err(libc::EXDEV);
+        }
+
+        let from_path = from_parent_path.join(name);
 
-        let to_parent_path = self.get_path(newparent)?;
         let to_path = to_parent_path.join(&newname);
 
-        if let Some(orig_ino) = fs::metadata(&to_path).ok().map(|e| e.st_ino()) {
+        // overwritten file would be lost
+        if self.config.append_only && fs::symlink_metadata(&to_path).is_ok() {
+            return Err(libc::EPERM);
+        }
+        if flags::protected(&from_path).map_err(convert_io_error)?
+            || flags::protected(&to_path).map_err(convert_io_error)?
+        {
+            return Err(libc::EPERM);
+        }
+
+        if let Some(orig_ino) = self.known_inode(&to_path)? {
             self.icache().del_inode_path(orig_ino)?;
-            self.opened_files.unlink(orig_ino);
+            if to_path.is_file() {
+                // overridden file remains available for opened handlers
+                self.remove_source_file(orig_ino, &to_path)?;
+            } else {
+                self.opened_files.unlink(orig_ino);
+            }
         }
 
-        fs::rename(from_path, &to_path).map_err(convert_io_error)?;
+        fs::rename(&from_path, &to_path).map_err(convert_io_error)?;
+
+        // Move uncompressed copy as well
+        if self.config.keep_plain && !to_path.is_dir() {
+            if let (Some(from_plain), Some(to_plain)) =
+                (plain_path(&from_path), plain_path(&to_path))
+            {
+                if from_plain.exists() {
+                    fs::rename(from_plain, to_plain).map_err(convert_io_error)?;
+                }
+            }
+        }
 
         // Update inode mapping
-        self.icache().set_inode_path(ino, to_parent_path, newname)?;
+        self.icache()
+            .set_inode_path(ino, &to_parent_path, newname)?;
 
-        // TODO update opened files to match path
-        // without update the opened files will be written to old location
+        // Opened files should be written to the new location
+        let to_metadata = fs::symlink_metadata(&to_path).map_err(convert_io_error)?;
+        if to_metadata.is_dir() {
+            let updated = self.icache().rename_dir(&from_path, &to_path)?;
+            debug!(
+                "Paths of {} cached entries updated after directory rename",
+                updated
+            );
+            self.opened_files.rename_dir(&from_path, &to_path);
+        } else {
+            self.opened_files.relocate(ino, to_path);
+        }
 
-        Ok(())
+        self.update_archive(&from_parent_path)?;
+        self.update_archive(&to_parent_path)
+    }
+
+    fn forget_wrapper(&mut self, ino: u64, nlookup: u64) {
+        if !self.icache().forget(ino, nlookup) || ino == FUSE_ROOT_ID {
+            return;
+        }
+
+        // opened handles still need to know the path
+        if self
+            .opened_files
+            .get_fhs_from_mount_point_inode(ino)
+            .map(|fhs| !fhs.is_empty())
+            .unwrap_or(false)
+        {
+            return;
+        }
+
+        match self.icache().del_inode_path(ino) {
+            Ok(()) => debug!(
+                "Inode evicted from cache (inode=0x{:016x}, cached={})",
+                ino,
+                self.icache().len()
+            ),
+            Err(err) => warn!(
+                "Failed to evict inode from cache (inode=0x{:016x}, err={})",
+                ino, err
+            ),
+        }
     }
 
     fn fsync_wrapper(&mut self, _ino: u64, fh: u64, _datasync: bool) -> Result<(), libc::c_int> {
@@ -712,11 +2564,238 @@ impl ZstdFS {
     }
 
     fn flush_wrapper(&mut self, _ino: u64, fh: u64, _lock_owner: u64) -> Result<(), libc::c_int> {
+        if self.config.sync_on != config::SyncOn::Flush {
+            // changes are compressed later
+            return Ok(());
+        }
         self.sync_to_fs(fh, false, false)?;
         Ok(())
     }
 
+    fn ioctl_wrapper(
+        &mut self,
+        ino: u64,
+        cmd: u32,
+        in_data: &[u8],
+        out_size: u32,
+    ) -> Result<Vec<u8>, libc::c_int> {
+        let path = self.get_path(ino)?;
+
+        match cmd {
+            // Inode flags (immutable, append only, ...) of the compressed file
+            cmd if cmd == libc::FS_IOC_GETFLAGS as u32 || cmd == libc::FS_IOC32_GETFLAGS as u32 => {
+                let mut data = flags::get(&path)
+                    .map_err(convert_io_error)?
+                    .to_ne_bytes()
+                    .to_vec();
+                data.resize(out_size as usize, 0);
+                Ok(data)
+            }
+            cmd if cmd == libc::FS_IOC_SETFLAGS as u32 || cmd == libc::FS_IOC32_SETFLAGS as u32 => {
+                let data = in_data.get(..4).ok_or(libc::EINVAL)?;
+                let inode_flags = libc::c_int::from_ne_bytes(data.try_into().unwrap());
+                flags::set(&path, inode_flags).map_err(convert_io_error)?;
+                debug!(
+                    "Flags of '{}' updated (flags=0x{:x})",
+                    path.display(),
+                    inode_flags
+                );
+                Ok(vec![])
+            }
+            // Files are not terminals (TCGETS, ...) and other ioctls are not supported
+            _ => Err(libc::ENOTTY),
+        }
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn copy_file_range_wrapper(
+        &mut self,
+        ino_in: u64,
+        fh_in: u64,
+        offset_in: i64,
+        ino_out: u64,
+        fh_out: u64,
+        offset_out: i64,
+        len: u64,
+        flags: u32,
+    ) -> Result<u32, libc::c_int> {
+        if flags != 0 {
+            return Err(libc::EINVAL);
+        }
+        if offset_in == 0 && offset_out == 0 {
+            if let Some(size) = self.clone_file(ino_in, fh_in, ino_out, fh_out, len)? {
+                return Ok(size);
+            }
+        }
+
+        // copy decompressed data (the rest is copied by following calls)
+        let data = self.read_wrapper(ino_in, fh_in, offset_in, len.min(COPY_CHUNK_SIZE) as u32)?;
+        self.write_wrapper(ino_out, fh_out, offset_out, &data, 0, 0, None)
+            .map(|e| e as u32)
+    }
+
+    /// Clones whole compressed file to an empty file (`FICLONE`)
+    /// so it doesn't have to be compressed again
+    ///
+    /// Returns `None` when the file can't be cloned
+    fn clone_file(
+        &mut self,
+        ino_in: u64,
+        fh_in: u64,
+        ino_out: u64,
+        fh_out: u64,
+        len: u64,
+    ) -> Result<Option<u32>, libc::c_int> {
+        if self.config.keep_plain {
+            return Ok(None);
+        }
+
+        // source can't contain data which were not stored yet
+        // and target has to be empty
+        let dirty = self
+            .opened_files
+            .get_fhs_from_mount_point_inode(ino_in)
+            .into_iter()
+            .flatten()
+            .any(|fh| self.opened_files.get(*fh).is_some_and(|e| e.needs_sync));
+        let target_handler = self.opened_files.get(fh_out).ok_or(libc::EBADF)?;
+        if dirty
+            || target_handler.index.is_some()
+            || target_handler
+                .file
+                .metadata()
+                .map_err(convert_io_error)?
+                .len()
+                != 0
+            || self.opened_files.logical_size(ino_out).unwrap_or(0) != 0
+        {
+            return Ok(None);
+        }
+        let target_tmp = target_handler.file.try_clone().map_err(convert_io_error)?;
+
+        let source_path = self.get_path(ino_in)?;
+        let target_path = self.get_path(ino_out)?;
+        let source = fs::File::open(&source_path).map_err(convert_io_error)?;
+        let meta = match self.read_meta(&source)? {
+            Some(meta) if meta.real_size <= len && meta.real_size <= u32::MAX as u64 => meta,
+            _ => return Ok(None),
+        };
+        let target = fs::OpenOptions::new()
+            .write(true)
+            .open(&target_path)
+            .map_err(convert_io_error)?;
+        if unsafe { libc::ioctl(target.as_raw_fd(), libc::FICLONE, source.as_raw_fd()) } != 0 {
+            debug!(
+                "Can't clone '{}' to '{}' (err={})",
+                source_path.display(),
+                target_path.display(),
+                io::Error::last_os_error()
+            );
+            return Ok(None);
+        }
+        self.write_meta(&meta, &target).map_err(convert_io_error)?;
+        debug!(
+            "File '{}' cloned to '{}'",
+            source_path.display(),
+            target_path.display()
+        );
+
+        // data have to be available in opened target as well
+        let source_handler = self.opened_files.get(fh_in).ok_or(libc::EBADF)?;
+        if source_handler.index.is_some() {
+            self.decompress_to(&source, &target_tmp)?;
+        } else {
+            let mut reader = source_handler.file.try_clone().map_err(convert_io_error)?;
+            reader.seek(SeekFrom::Start(0)).map_err(convert_io_error)?;
+            io::copy(&mut reader, &mut &target_tmp).map_err(convert_io_error)?;
+        }
+        self.opened_files
+            .extend(ino_out, meta.real_size)
+            .map_err(convert_io_error)?;
+
+        Ok(Some(meta.real_size as u32))
+    }
+
+    fn setxattr_wrapper(
+        &mut self,
+        ino: u64,
+        name: &OsStr,
+        value: &[u8],
+        flags: i32,
+    ) -> Result<(), libc::c_int> {
+        // internal attributes can't be changed
+        if xattrs::is_internal(name) {
+            return Err(libc::EPERM);
+        }
+        let path = self.get_path(ino)?;
+        let exists = xattr::get(&path, name).map_err(convert_io_error)?.is_some();
+        if flags & libc::XATTR_CREATE != 0 && exists {
+            return Err(libc::EEXIST);
+        }
+        if flags & libc::XATTR_REPLACE != 0 && !exists {
+            return Err(libc::ENODATA);
+        }
+        let value = xattrs::encode(value, self.config.compress_xattrs).map_err(convert_io_error)?;
+        xattr::set(&path, name, &value).map_err(convert_io_error)
+    }
+
+    fn getxattr_wrapper(&mut self, ino: u64, name: &OsStr) -> Result<Vec<u8>, libc::c_int> {
+        if xattrs::is_internal(name) {
+            return Err(libc::ENODATA);
+        }
+        let path = self.get_path(ino)?;
+        let value = xattr::get(&path, name)
+            .map_err(convert_io_error)?
+            .ok_or(libc::ENODATA)?;
+        xattrs::decode(value).map_err(convert_io_error)
+    }
+
+    fn listxattr_wrapper(&mut self, ino: u64) -> Result<Vec<u8>, libc::c_int> {
+        let path = self.get_path(ino)?;
+        let mut res = vec![];
+        for name in xattr::list(&path).map_err(convert_io_error)? {
+            if !xattrs::is_internal(&name) {
+                res.extend(name.as_bytes());
+                res.push(0);
+            }
+        }
+        Ok(res)
+    }
+
+    fn removexattr_wrapper(&mut self, ino: u64, name: &OsStr) -> Result<(), libc::c_int> {
+        if xattrs::is_internal(name) {
+            return Err(libc::ENODATA);
+        }
+        let path = self.get_path(ino)?;
+        xattr::remove(&path, name).map_err(convert_io_error)
+    }
+
     fn update_inode_idx(&mut self) -> io::Result<u64> {
+        // other mounts of shared data dir may have allocated inodes meanwhile
+        let _lock = if self.config.allow_shared && !self.config.convert_dry_run {
+            let lock = lock::lock_dir(&self.data_dir)?;
+            if let Some(idx) =
+                xattr::get(&self.data_dir, "user.ino_idx")?.and_then(|e| xattrs::parse_number(&e))
+            {
+                self.inode_idx = self.inode_idx.min(idx);
+            }
+            Some(lock)
+        } else {
+            None
+        };
+        let res = self.next_inode();
+
+        // inodes assigned in dry run are only cached
+        if self.config.convert_dry_run {
+            return Ok(res);
+        }
+        self.store_inode_idx()?;
+
+        Ok(res)
+    }
+
+    /// Takes a new inode without storing the index to data dir
+    fn next_inode(&mut self) -> Inode {
         let res = self.inode_idx;
 
         if self.inode_idx - 1 <= FUSE_ROOT_ID {
@@ -726,7 +2805,10 @@ impl ZstdFS {
             self.inode_idx = u64::MAX;
         }
         self.inode_idx -= 1;
+        res
+    }
 
+    fn store_inode_idx(&self) -> io::Result<()> {
         debug!(
             "Updating 'ino_idx' at root {} to 0x{:016x}",
             self.data_dir().display(),
@@ -736,34 +2818,425 @@ impl ZstdFS {
             &self.data_dir,
             "user.ino_idx",
             &self.inode_idx.to_be_bytes(),
+        )
+    }
+
+    /// Decompresses zstd frames of source file, returns the size of decompressed data
+    fn decompress_to<W: io::Write>(
+        &self,
+        source: &fs::File,
+        mut target: W,
+    ) -> Result<u64, libc::c_int> {
+        if meta::is_raw(source).map_err(convert_io_error)? {
+            return io::copy(
+                &mut source.try_clone().map_err(convert_io_error)?,
+                &mut target,
+            )
+            .map_err(convert_io_error);
+        }
+        // file created outside of fuse-zstd (e.g. by touch) has no frame at all
+        if source.metadata().map_err(convert_io_error)?.len() == 0 {
+            return Ok(0);
+        }
+        let dict = self.dicts.for_file(source).map_err(|err| {
+            warn!("Failed to decompress (err={})", err);
+            convert_io_error(err)
+        })?;
+        let mut decoder = zstd::stream::Decoder::with_dictionary(
+            io::BufReader::new(source.try_clone().map_err(convert_io_error)?),
+            dict.unwrap_or_default(),
+        )
+        .map_err(|_| libc::EFAULT)?;
+        io::copy(&mut decoder, &mut target).map_err(convert_decode_error)
+    }
+
+    /// Checks whether the compressed file was replaced outside of fuse-zstd since
+    /// the opened file was decompressed, storing it would drop the external changes
+    fn check_external(&self, ino: Inode, path: &Path) -> Result<(), libc::c_int> {
+        let Some(version) = self.opened_files.source(ino) else {
+            return Ok(());
+        };
+        // removed file is created again
+        let Ok(metadata) = fs::symlink_metadata(path) else {
+            return Ok(());
+        };
+        if file::SourceVersion::new(&metadata) == version {
+            return Ok(());
+        }
+        if self.config.strict_external {
+            error!(
+                "File '{}' was changed outside of fuse-zstd, refusing to overwrite it by opened file",
+                path.display()
+            );
+            return Err(libc::EBUSY);
+        }
+        warn!(
+            "File '{}' was changed outside of fuse-zstd, overwriting it by opened file",
+            path.display()
+        );
+        Ok(())
+    }
+
+    /// Decompresses opened file again when its compressed file was replaced
+    /// outside of fuse-zstd (all handles of the file share the decompressed data)
+    fn refresh_opened(&mut self, ino: Inode) -> Result<(), libc::c_int> {
+        self.refresh_indexed(ino)?;
+        let Some(version) = self.opened_files.source(ino) else {
+            return Ok(());
+        };
+        // data were not decompressed yet
+        if self.opened_files.pending(ino).is_some() {
+            return Ok(());
+        }
+        let Some(mut target) = self.inode_tmp_file(ino)? else {
+            return Ok(());
+        };
+        let path = self.get_path(ino)?;
+        // removed file remains readable until it is closed
+        let Ok(source_file) = fs::File::open(&path) else {
+            return Ok(());
+        };
+        let current = file::SourceVersion::new(&source_file.metadata().map_err(convert_io_error)?);
+        if current == version {
+            return Ok(());
+        }
+        // conflict is resolved once the opened file is stored (see `check_external`)
+        if self.opened_files.is_dirty(ino) {
+            debug!(
+                "File '{}' was changed outside of fuse-zstd while it has unstored changes",
+                path.display()
+            );
+            return Ok(());
+        }
+        self.opened_files.set_source(ino, current);
+
+        info!(
+            "File '{}' was changed outside of fuse-zstd, decompressing again",
+            path.display()
+        );
+        target.set_len(0).map_err(convert_io_error)?;
+        target.seek(SeekFrom::Start(0)).map_err(convert_io_error)?;
+        let max_size = self.config.max_decompressed_size.unwrap_or(u64::MAX);
+        self.decompress_file(
+            &source_file,
+            limit::SizeLimit::new(io::BufWriter::new(&target), max_size),
         )?;
+        let size = target.metadata().map_err(convert_io_error)?.len();
+        self.stats.decompressed(size);
+        self.opened_files.truncate(ino, size);
+        Ok(())
+    }
 
-        Ok(res)
+    /// Reads the index again when the compressed file read by indexed handles was changed
+    /// (e.g. hardlinked file overwritten in place), file without index is decompressed
+    /// and its data are shared by the handles
+    fn refresh_indexed(&mut self, ino: Inode) -> Result<(), libc::c_int> {
+        let Some(fhs) = self.opened_files.get_indexed_fhs(ino).cloned() else {
+            return Ok(());
+        };
+        // removed file remains readable until it is closed
+        let Ok(path) = self.get_path(ino) else {
+            return Ok(());
+        };
+        let Ok(source_file) = fs::File::open(&path) else {
+            return Ok(());
+        };
+        let current = file::SourceVersion::new(&source_file.metadata().map_err(convert_io_error)?);
+        let changed: Vec<_> = fhs
+            .into_iter()
+            .filter(|fh| {
+                self.opened_files
+                    .get(*fh)
+                    .is_some_and(|e| e.indexed_source != Some(current))
+            })
+            .collect();
+        if changed.is_empty() {
+            return Ok(());
+        }
+
+        let index = if meta::is_raw(&source_file).map_err(convert_io_error)? {
+            None
+        } else {
+            index::read_index(&source_file).map_err(convert_io_error)?
+        };
+        let Some(index) = index else {
+            info!(
+                "Indexed file '{}' was changed and it has no index, decompressing it",
+                path.display()
+            );
+            let target = self.scratch_file().map_err(convert_io_error)?;
+            let max_size = self.config.max_decompressed_size.unwrap_or(u64::MAX);
+            self.decompress_file(
+                &source_file,
+                limit::SizeLimit::new(io::BufWriter::new(&target), max_size),
+            )?;
+            self.stats
+                .decompressed(target.metadata().map_err(convert_io_error)?.len());
+            self.opened_files
+                .share_indexed(ino, &target)
+                .map_err(convert_io_error)?;
+            self.opened_files.set_source(ino, current);
+            return Ok(());
+        };
+
+        info!(
+            "Indexed file '{}' was changed, reading its index again",
+            path.display()
+        );
+        for fh in changed {
+            let readahead = self
+                .config
+                .readahead
+                .map(|e| index::ReadAhead::new(e, self.decompress_limit.clone()));
+            let file = source_file.try_clone().map_err(convert_io_error)?;
+            if let Some(handler) = self.opened_files.get_mut(fh) {
+                handler.file = file;
+                handler.index = Some(index.clone());
+                handler.readahead = readahead;
+                handler.last_frame = None;
+                handler.indexed_source = Some(current);
+            }
+        }
+        Ok(())
+    }
+
+    /// Changes the size of a file which is not opened by compressing its data again
+    fn truncate_stored(&mut self, ino: Inode, size: u64) -> Result<(), libc::c_int> {
+        let path = self.get_path(ino)?;
+        if path.is_dir() {
+            return Err(libc::EISDIR);
+        }
+        // plain file which was not converted yet
+        if self.config.convert_dry_run
+            && path
+                .file_name()
+                .is_none_or(|e| strip_suffix(&e.to_string_lossy()).is_none())
+        {
+            return Err(libc::EROFS);
+        }
+        let source_file = fs::File::open(&path).map_err(convert_io_error)?;
+        if self.read_meta(&source_file)?.map(|e| e.real_size) == Some(size) {
+            return Ok(());
+        }
+        debug!(
+            "Truncating stored file '{}' (size={})",
+            path.display(),
+            size
+        );
+
+        let target = self.scratch_file().map_err(convert_io_error)?;
+        let max_size = self.config.max_decompressed_size.unwrap_or(u64::MAX);
+        self.decompress_file(
+            &source_file,
+            limit::SizeLimit::new(io::BufWriter::new(&target), max_size),
+        )?;
+        target.set_len(size).map_err(convert_io_error)?;
+        self.store_to_source_file(
+            &target,
+            path.parent().unwrap(),
+            path.file_name().unwrap(),
+            self.config.compression_level,
+        )?;
+        Ok(())
+    }
+
+    /// Decompressed data shared by all handles of the opened inode
+    fn inode_tmp_file(&self, ino: Inode) -> Result<Option<fs::File>, libc::c_int> {
+        self.opened_files
+            .get_fhs_from_mount_point_inode(ino)
+            .and_then(|fhs| fhs.iter().next())
+            .and_then(|fh| self.opened_files.get(*fh))
+            .map(|e| e.file.try_clone())
+            .transpose()
+            .map_err(convert_io_error)
+    }
+
+    /// Decompresses the data of opened file which were postponed
+    /// (except for the data which were overwritten meanwhile)
+    fn materialize(&mut self, ino: Inode, target: &fs::File) -> Result<(), libc::c_int> {
+        let Some(pending) = self.opened_files.take_pending(ino) else {
+            return Ok(());
+        };
+        if pending.written >= pending.real_size {
+            debug!(
+                "Decompression of inode 0x{:016x} skipped (file was overwritten)",
+                ino
+            );
+            return Ok(());
+        }
+        debug!(
+            "Decompressing postponed inode 0x{:016x} (written={}, size={})",
+            ino, pending.written, pending.real_size
+        );
+        let mut decompressed = self.scratch_file().map_err(convert_io_error)?;
+        let max_size = self.config.max_decompressed_size.unwrap_or(u64::MAX);
+        let started = Instant::now();
+        let res = self.decompress_file(
+            &pending.source,
+            limit::SizeLimit::new(io::BufWriter::new(&decompressed), max_size),
+        );
+        self.profiled("decompress", started);
+        res?;
+        self.stats
+            .decompressed(decompressed.metadata().map_err(convert_io_error)?.len());
+
+        // written data are kept
+        let mut target = target.try_clone().map_err(convert_io_error)?;
+        decompressed
+            .seek(SeekFrom::Start(pending.written))
+            .map_err(convert_io_error)?;
+        target
+            .seek(SeekFrom::Start(pending.written))
+            .map_err(convert_io_error)?;
+        io::copy(&mut decompressed, &mut target).map_err(convert_io_error)?;
+        Ok(())
+    }
+
+    /// Decompresses source file including the one stored in chunks
+    fn decompress_file<W: io::Write>(
+        &self,
+        source: &fs::File,
+        mut target: W,
+    ) -> Result<(), libc::c_int> {
+        let chunks = if meta::is_raw(source).map_err(convert_io_error)? {
+            None
+        } else {
+            cdc::read_chunks(source).map_err(convert_io_error)?
+        };
+        if let Some(chunks) = chunks {
+            cdc::decompress_chunks(&self.data_dir.join(cdc::CHUNKS_DIR), &chunks, target)
+                .map_err(convert_decode_error)
+        } else {
+            self.decompress_to(source, &mut target)?;
+            target.flush().map_err(convert_io_error)
+        }
+    }
+
+    /// Stores size of a file created outside of fuse-zstd (without metadata)
+    fn fill_size(&mut self, path: &Path) -> Result<(), libc::c_int> {
+        let file = fs::File::open(path).map_err(convert_io_error)?;
+        if meta::Meta::read(&file).map_err(convert_io_error)?.is_some() {
+            return Ok(());
+        }
+        // content size in frame header can't be trusted (more frames may follow)
+        let real_size = self.decompress_to(&file, io::sink())?;
+        self.write_meta(&meta::Meta::new(0, real_size), &file)
+            .map_err(convert_io_error)?;
+        debug!("Size of '{}' filled ({})", path.display(), real_size);
+        Ok(())
+    }
+
+    /// Inodes which can't be stored in extended attributes (symlinks
+    /// or any entry in convert dry run) are mapped from inodes of data dir
+    fn cached_inode(&mut self, metadata: &fs::Metadata) -> Result<Inode, libc::c_int> {
+        let source_ino = metadata.st_ino();
+        if self.config.mirror_inodes {
+            return Ok(source_ino);
+        }
+        if let Some(ino) = self.icache().get_symlink_inode(source_ino)? {
+            return Ok(ino);
+        }
+        let ino = self.update_inode_idx().map_err(convert_io_error)?;
+        self.icache().set_symlink_inode(source_ino, ino)?;
+        Ok(ino)
+    }
+
+    fn symlink_attrs(&mut self, parent_path: &Path, name: &str) -> Result<FileAttr, libc::c_int> {
+        let metadata = fs::symlink_metadata(parent_path.join(name)).map_err(convert_io_error)?;
+        let ino = self.cached_inode(&metadata)?;
+        self.icache().set_inode_path(ino, parent_path, name)?;
+
+        let mut attrs: FileAttr = FileAttrWrapper::try_from(metadata)
+            .map_err(convert_io_error)?
+            .into();
+        attrs.ino = ino;
+        Ok(attrs)
     }
 
     fn update_inode(&mut self, f: &fs::File) -> io::Result<Inode>
 where {
-        if let Some(data) = f.get_xattr("user.ino")? {
-            Ok(u64::from_be_bytes(data.try_into().unwrap()))
+        if self.config.mirror_inodes {
+            Ok(f.metadata()?.st_ino())
+        } else if let Some(ino) = f
+            .get_xattr("user.ino")?
+            .and_then(|e| xattrs::parse_number(&e))
+        {
+            Ok(ino)
+        } else if self.config.convert_dry_run {
+            self.cached_inode(&f.metadata()?)
+                .map_err(io::Error::from_raw_os_error)
         } else {
-            let ino = self.update_inode_idx()?;
+            // inode assigned when the file was listed
+            let source_ino = f.metadata()?.st_ino();
+            let pending = self
+                .icache()
+                .get_pending_inode(source_ino)
+                .map_err(io::Error::from_raw_os_error)?;
+            let ino = match pending {
+                Some(ino) => {
+                    // index has to be stored before the inode to avoid its reuse
+                    self.store_inode_idx()?;
+                    ino
+                }
+                None => self.update_inode_idx()?,
+            };
             f.set_xattr("user.ino", &ino.to_be_bytes())?;
+            if pending.is_some() {
+                self.icache()
+                    .del_pending_inode(source_ino)
+                    .map_err(io::Error::from_raw_os_error)?;
+            }
             Ok(ino)
         }
     }
 
+    /// Data of configured MIME types are stored uncompressed
+    fn skip_mime(&self, source: &fs::File, path: &Path) -> Result<bool, libc::c_int> {
+        if self.config.skip_mime.is_empty() {
+            return Ok(false);
+        }
+        let mut head = vec![0; mime::SAMPLE_SIZE];
+        let size = read_full_at(source, &mut head, 0).map_err(convert_io_error)?;
+        head.truncate(size);
+        let Some(detected) = mime::detect(&head[..size.min(mime::SNIFF_SIZE)]) else {
+            // unknown types are stored uncompressed when they don't compress
+            let skip = mime::incompressible(&head);
+            if skip {
+                debug!(
+                    "Storing '{}' uncompressed (incompressible data)",
+                    path.display()
+                );
+            }
+            return Ok(skip);
+        };
+        let skip = self
+            .config
+            .skip_mime
+            .iter()
+            .any(|e| mime::matches(e, detected));
+        if skip {
+            debug!(
+                "Storing '{}' uncompressed (detected {})",
+                path.display(),
+                detected
+            );
+        }
+        Ok(skip)
+    }
+
     fn store_to_source_file<P1, P2>(
         &mut self,
         source: &fs::File,
         dir_path: P1,
         name: P2,
-        compression_level: u8,
+        compression_level: i32,
     ) -> Result<(fs::File, u64), libc::c_int>
     where
         P1: AsRef<Path>,
         P2: AsRef<Path>,
     {
         // Atomically creates file in source directory
+        // (tmp file can't be in work dir, it may be on another device where rename fails)
         let tmp_file =
             tempfile::NamedTempFile::new_in(dir_path.as_ref()).map_err(convert_io_error)?;
         let path = dir_path.as_ref().join(name.as_ref());
@@ -772,62 +3245,178 @@ where {
 
         let real_size = source.metadata().map_err(convert_io_error)?.st_size();
         debug!("Before compression {}", real_size);
+        let started = Instant::now();
 
         let mut cloned_source = source.try_clone().map_err(convert_io_error)?;
         cloned_source
             .seek(SeekFrom::Start(0))
             .map_err(convert_io_error)?;
         // Compress file
-        let mut encoder = zstd::stream::Encoder::new(
-            tmp_file.reopen().map_err(convert_io_error)?,
-            compression_level as i32,
-        )
-        .map_err(convert_io_error)?;
-        encoder
-            .set_pledged_src_size(Some(real_size))
+        let raw = self.config.no_compress || self.skip_mime(source, &path)?;
+        if raw {
+            io::copy(
+                &mut cloned_source,
+                &mut tmp_file.reopen().map_err(convert_io_error)?,
+            )
             .map_err(convert_io_error)?;
-        encoder.include_checksum(true).map_err(convert_io_error)?;
-        io::copy(&mut cloned_source, &mut encoder).map_err(convert_io_error)?;
-        encoder.finish().map_err(convert_io_error)?;
-
-        // Try to update the ino of tmp file
-        let ino = match xattr::get(&path, "user.ino") {
-            Ok(Some(ino_data)) => {
-                tmp_file
-                    .as_file()
-                    .set_xattr("user.ino", &ino_data)
-                    .map_err(convert_io_error)?;
+        } else if path.to_string_lossy().ends_with(cdc::SUFFIX) {
+            cdc::write_chunked(
+                &mut cloned_source,
+                io::BufWriter::new(tmp_file.reopen().map_err(convert_io_error)?),
+                &self.data_dir.join(cdc::CHUNKS_DIR),
+                compression_level,
+                &self.config.zstd_params,
+            )
+            .map_err(convert_io_error)?;
+        } else {
+            codec::compress(
+                &mut cloned_source,
+                io::BufWriter::new(tmp_file.reopen().map_err(convert_io_error)?),
+                Some(real_size),
+                compression_level,
+                &self.config.zstd_params,
+                self.compression_dict(),
+                self.config.index_interval,
+            )
+            .map_err(convert_io_error)?;
+        }
 
-                u64::from_be_bytes(ino_data.try_into().unwrap())
-            }
-            _ => {
-                // Error or None -> create new ino
-                let new_ino = self.update_inode_idx().map_err(convert_io_error)?;
-                tmp_file
-                    .as_file()
-                    .set_xattr("user.ino", &new_ino.to_be_bytes())
-                    .map_err(convert_io_error)?;
+        // Try to update the ino of tmp file (mirrored inode is known once the file is stored)
+        let ino =
+            match xattr::get(&path, "user.ino").map(|e| e.and_then(|e| xattrs::parse_number(&e))) {
+                _ if self.config.mirror_inodes => 0,
+                Ok(Some(ino)) => {
+                    tmp_file
+                        .as_file()
+                        .set_xattr("user.ino", &ino.to_be_bytes())
+                        .map_err(convert_io_error)?;
+
+                    ino
+                }
+                _ => {
+                    // inode assigned when the file was listed or new ino
+                    let new_ino = match self.known_inode(&path)? {
+                        Some(ino) => ino,
+                        None => self.update_inode_idx().map_err(convert_io_error)?,
+                    };
+                    tmp_file
+                        .as_file()
+                        .set_xattr("user.ino", &new_ino.to_be_bytes())
+                        .map_err(convert_io_error)?;
+
+                    tmp_file.as_file().sync_all().map_err(convert_io_error)?;
+
+                    new_ino
+                }
+            };
 
-                tmp_file.as_file().sync_all().map_err(convert_io_error)?;
+        // Keep permissions of the original file
+        let permissions = match fs::metadata(&path) {
+            Ok(metadata) => Some(metadata.permissions()),
+            Err(_) => self.config.file_mode.map(fs::Permissions::from_mode),
+        };
+        if let Some(permissions) = permissions {
+            tmp_file
+                .as_file()
+                .set_permissions(permissions)
+                .map_err(convert_io_error)?;
+        }
 
-                new_ino
+        // Keep extended attributes set by the user
+        if let Ok(names) = xattr::list(&path) {
+            for name in names.filter(|e| !xattrs::is_internal(e)) {
+                if let Some(value) = xattr::get(&path, &name).map_err(convert_io_error)? {
+                    tmp_file
+                        .as_file()
+                        .set_xattr(&name, &value)
+                        .map_err(convert_io_error)?;
+                }
             }
+        }
+
+        // update filesize in xattrs
+        // before the file is moved so that the old file is kept when it fails
+        let meta = if raw {
+            meta::Meta::raw(real_size)
+        } else {
+            meta::Meta::new(compression_level, real_size)
         };
+        if !self.config.xattrs_unsupported {
+            meta.write(tmp_file.as_file()).map_err(convert_io_error)?;
+        }
+        tmp_file.as_file().sync_all().map_err(convert_io_error)?;
 
         // Should atomically move file to its destination
-        let file = tmp_file.persist(&path).map_err(convert_io_error)?;
-
-        // update filesize in xattrs
-        file.set_xattr("user.real_size", &real_size.to_be_bytes())
-            .map_err(convert_io_error)?;
+        // (hardlinked file would be split and mirrored inode would change, it is overwritten instead)
+        let overwrite = fs::metadata(&path)
+            .map(|e| e.st_nlink() > 1 || self.config.mirror_inodes)
+            .unwrap_or(false);
+        // append-only file can't be replaced (the flag is set to the new file afterwards)
+        let inode_flags = flags::get(&path).unwrap_or(0);
+        if inode_flags & flags::APPEND != 0 {
+            flags::set(&path, inode_flags & !flags::APPEND).map_err(convert_io_error)?;
+        }
+        let file = if overwrite {
+            debug!("Overwriting file '{}' in place", path.display());
+            overwrite_linked(tmp_file.as_file(), &path).map_err(convert_io_error)?
+        } else {
+            tmp_file.persist(&path).map_err(convert_io_error)?
+        };
+        if inode_flags & flags::APPEND != 0 {
+            flags::set(&path, inode_flags).map_err(convert_io_error)?;
+        }
+        let metadata = file.metadata().map_err(convert_io_error)?;
+        let ino = if self.config.mirror_inodes {
+            metadata.st_ino()
+        } else {
+            ino
+        };
+        if self.config.network_backing {
+            self.icache().set_meta(&metadata, &meta)?;
+        }
+        self.opened_files
+            .set_source(ino, file::SourceVersion::new(&metadata));
+        // readers of the previous data (e.g. when the file was overwritten in place)
+        self.refresh_indexed(ino)?;
+        self.stats
+            .compressed(real_size, file.metadata().map_err(convert_io_error)?.len());
+
+        // Store uncompressed copy next to the compressed file
+        if self.config.keep_plain {
+            if let Some(plain_path) = plain_path(&path) {
+                let mut plain_file =
+                    tempfile::NamedTempFile::new_in(dir_path.as_ref()).map_err(convert_io_error)?;
+                cloned_source
+                    .seek(SeekFrom::Start(0))
+                    .map_err(convert_io_error)?;
+                io::copy(&mut cloned_source, &mut plain_file).map_err(convert_io_error)?;
+                plain_file.as_file().sync_all().map_err(convert_io_error)?;
+                plain_file.persist(plain_path).map_err(convert_io_error)?;
+            }
+        }
 
-        // Sync it
-        file.sync_all().map_err(convert_io_error)?;
-        debug!(
-            "After compression {}",
-            file.metadata().map_err(convert_io_error)?.st_size()
+        // Sync it (data were synced before rename which is synchronous on network filesystems)
+        if !self.config.network_backing {
+            file.sync_all().map_err(convert_io_error)?;
+        }
+        let compressed_size = file.metadata().map_err(convert_io_error)?.st_size();
+        debug!("After compression {}", compressed_size);
+        trace!(
+            "Compression stats (path={}, level={}, input={}, output={}, ratio={:.3}, elapsed_ms={:.3})",
+            path.display(),
+            compression_level,
+            real_size,
+            compressed_size,
+            if real_size > 0 {
+                compressed_size as f64 / real_size as f64
+            } else {
+                0.0
+            },
+            started.elapsed().as_secs_f64() * 1000.0,
         );
+        self.profiled("compress", started);
 
+        self.update_archive(dir_path.as_ref())?;
         Ok((file, ino))
     }
 }
@@ -836,27 +3425,79 @@ impl Filesystem for ZstdFS {
     fn init(
         &mut self,
         _req: &Request<'_>,
-        _config: &mut fuser::KernelConfig,
+        config: &mut fuser::KernelConfig,
     ) -> Result<(), libc::c_int> {
-        fs::create_dir_all(Path::new(&self.data_dir())).map_err(convert_io_error)?;
-
-        let cache_root = self.cache_path();
-        if fs::remove_dir_all(&cache_root)
-            .map_err(convert_io_error)
-            .is_ok()
-        {
-            debug!("Clearing root cache directory {}", cache_root.display());
+        // FUSE_POSIX_LOCKS and FUSE_FLOCK_LOCKS are not requested, so the kernel
+        // enforces flock and fcntl locks among users of the mount point itself.
+        // Handling them here would block the whole (single threaded) loop on F_SETLKW.
+
+        // attributes are returned with directory entries (saves a lookup per entry),
+        // converted files are not because the conversion is done on lookup
+        if !self.config.convert {
+            if let Err(missing) = config.add_capabilities(fuser::consts::FUSE_DO_READDIRPLUS) {
+                debug!(
+                    "Readdirplus not supported by kernel (missing=0x{:x})",
+                    missing
+                );
+            }
+        }
+        // opened file is truncated by open instead of a separate setattr,
+        // so its data don't need to be decompressed
+        if let Err(missing) = config.add_capabilities(fuser::consts::FUSE_ATOMIC_O_TRUNC) {
+            debug!(
+                "Atomic O_TRUNC not supported by kernel (missing=0x{:x})",
+                missing
+            );
         }
-        debug!("Creating cache root directory {}", cache_root.display());
-        fs::create_dir_all(&cache_root).map_err(convert_io_error)?;
 
-        let cache = cache::InodeCache::new(&cache_root)?;
+        fs::create_dir_all(Path::new(&self.data_dir())).map_err(convert_io_error)?;
+        fs::create_dir_all(self.root_dir()).map_err(convert_io_error)?;
+
+        let clear = !self.config.allow_shared;
+        // parents are referenced by user.ino which isn't used for mirrored inodes
+        let tree = self.config.cache_compression && !self.config.mirror_inodes;
+        let cache = match create_cache(&self.cache_path(), &self.data_dir, clear, tree) {
+            Ok(cache) => cache,
+            Err(err) if [libc::EROFS, libc::EACCES, libc::EPERM].contains(&err) => {
+                let fallback = self
+                    .config
+                    .cache_dir
+                    .clone()
+                    .unwrap_or_else(env::temp_dir)
+                    .join(format!(".fuse-zstd-inode_cache-{}", process::id()));
+                warn!(
+                    "Inode cache can't be created in data dir (err={}), using '{}'",
+                    err,
+                    fallback.display()
+                );
+                let cache =
+                    create_cache(&fallback, &self.data_dir, clear, tree).inspect_err(|err| {
+                        error!(
+                            "Inode cache can't be created in '{}' nor in '{}' (err={})",
+                            self.data_dir().display(),
+                            fallback.display(),
+                            err
+                        );
+                    })?;
+                self.cache_root = Some(fallback);
+                cache
+            }
+            Err(err) => return Err(err),
+        };
         let cache_path = cache.cache_data_dir().path().display();
 
         debug!("Initializing inode cache at '{}'", cache_path);
 
         self.inode_cache = Some(cache);
 
+        if self.config.xattrs_unsupported {
+            let path = self.data_dir.join(cache::METAS_NAME);
+            match self.icache().load_metas(&path) {
+                Ok(loaded) => debug!("Metadata loaded (entries={})", loaded),
+                Err(err) => warn!("Failed to load metadata (err={})", err),
+            }
+        }
+
         Ok(())
     }
 
@@ -866,11 +3507,22 @@ impl Filesystem for ZstdFS {
             parent,
             name.to_str().unwrap_or_default()
         );
+        if self.missing.contains(parent, name) {
+            debug!("Lookup Err (code={}, cached)", libc::ENOENT);
+            reply.entry(&TTL, &negative_attrs(), 0);
+            return;
+        }
         match self.lookup_wrapper(parent, name) {
             Ok(attrs) => {
                 debug!("Lookup OK (inode=0x{:016x})", attrs.ino);
+                self.icache().lookup(attrs.ino);
                 reply.entry(&TTL, &attrs, 0);
             }
+            Err(libc::ENOENT) => {
+                debug!("Lookup Err (code={})", libc::ENOENT);
+                self.missing.insert(parent, name);
+                reply.entry(&TTL, &negative_attrs(), 0);
+            }
             Err(err) => {
                 debug!("Lookup Err (code={})", err);
                 reply.error(err);
@@ -878,6 +3530,18 @@ impl Filesystem for ZstdFS {
         }
     }
 
+    fn forget(&mut self, _req: &Request, ino: u64, nlookup: u64) {
+        debug!("Forget (inode=0x{:016x}, nlookup={})", ino, nlookup);
+        self.forget_wrapper(ino, nlookup);
+    }
+
+    fn batch_forget(&mut self, _req: &Request, nodes: &[fuser::fuse_forget_one]) {
+        debug!("Batch forget (count={})", nodes.len());
+        for node in nodes {
+            self.forget_wrapper(node.nodeid, node.nlookup);
+        }
+    }
+
     fn getattr(&mut self, _req: &Request, ino: u64, reply: ReplyAttr) {
         debug!("Getattr (inode=0x{:016x})", ino);
         match self.getattr_wrapper(ino) {
@@ -943,9 +3607,13 @@ impl Filesystem for ZstdFS {
             "Read (inode=0x{:016x}, offset={}, size={}, fh={})",
             ino, offset, size, fh
         );
-        match self.read_wrapper(ino, fh, offset, size) {
+        let started = Instant::now();
+        let res = self.read_wrapper(ino, fh, offset, size);
+        self.profiled("read", started);
+        match res {
             Ok(data) => {
                 debug!("read {}", data.len());
+                self.stats.read(data.len() as u64);
                 reply.data(&data);
             }
             Err(err) => {
@@ -955,6 +3623,27 @@ impl Filesystem for ZstdFS {
         }
     }
 
+    fn opendir(&mut self, _req: &Request<'_>, ino: u64, flags: i32, reply: fuser::ReplyOpen) {
+        debug!("Opendir (inode=0x{:016x}, flags={:x})", ino, flags);
+        match self.opendir_wrapper(ino) {
+            Ok(fh) => reply.opened(fh, 0),
+            Err(err) => reply.error(err),
+        }
+    }
+
+    fn releasedir(
+        &mut self,
+        _req: &Request<'_>,
+        ino: u64,
+        fh: u64,
+        _flags: i32,
+        reply: fuser::ReplyEmpty,
+    ) {
+        debug!("Releasedir (inode=0x{:016x}, fh={})", ino, fh);
+        self.dir_snapshots.remove(&fh);
+        reply.ok();
+    }
+
     fn readdir(
         &mut self,
         _req: &Request,
@@ -967,7 +3656,52 @@ impl Filesystem for ZstdFS {
             "Readdir (inode=0x{:016x}, offset={}, fh={})",
             ino, offset, fh
         );
-        match self.readdir_wrapper(ino, fh, offset, &mut reply) {
+        let started = Instant::now();
+        let res = self.readdir_wrapper(ino, fh, offset, |_, ino, offset, kind, name| {
+            Ok(reply.add(ino, offset, kind, name))
+        });
+        self.profiled("readdir", started);
+        match res {
+            Ok(_) => {
+                reply.ok();
+            }
+            Err(err) => {
+                reply.error(err);
+            }
+        }
+    }
+
+    fn readdirplus(
+        &mut self,
+        _req: &Request<'_>,
+        ino: u64,
+        fh: u64,
+        offset: i64,
+        mut reply: fuser::ReplyDirectoryPlus,
+    ) {
+        debug!(
+            "Readdirplus (inode=0x{:016x}, offset={}, fh={})",
+            ino, offset, fh
+        );
+        let started = Instant::now();
+        let res = self.readdir_wrapper(ino, fh, offset, |fs, entry_ino, offset, _, name| {
+            let attrs = match fs.getattr_wrapper(entry_ino) {
+                Ok(attrs) => attrs,
+                // e.g. the entry was removed while the directory is listed
+                Err(err) => {
+                    debug!("Skipping entry '{}' (err={})", name, err);
+                    return Ok(false);
+                }
+            };
+            let full = reply.add(entry_ino, offset, name, &TTL, &attrs, 0);
+            // kernel counts a lookup for each sent entry except dot entries
+            if !full && name != "." && name != ".." {
+                fs.icache().lookup(entry_ino);
+            }
+            Ok(full)
+        });
+        self.profiled("readdir", started);
+        match res {
             Ok(_) => {
                 reply.ok();
             }
@@ -979,10 +3713,13 @@ impl Filesystem for ZstdFS {
 
     fn open(&mut self, _req: &Request<'_>, ino: u64, flags: i32, reply: fuser::ReplyOpen) {
         debug!("Open (inode=0x{:016x}, flags={:x})", ino, flags);
-        match self.open_wrapper(ino, flags) {
+        let started = Instant::now();
+        let res = self.open_wrapper(ino, flags);
+        self.profiled("open", started);
+        match res {
             Ok(fh) => {
                 debug!("opened (fh={})", fh);
-                reply.opened(fh, 0);
+                reply.opened(fh, self.open_flags(flags));
             }
             Err(err) => {
                 debug!("open error (err={})", err);
@@ -1012,7 +3749,7 @@ impl Filesystem for ZstdFS {
                 reply.ok();
             }
             Err(err) => {
-                warn!("Release error (inode=0x{:016x}, fh={})", ino, fh);
+                debug!("release failed (err={})", err);
                 reply.error(err);
             }
         }
@@ -1032,10 +3769,12 @@ impl Filesystem for ZstdFS {
             "Create (iparent=0x{:016x}, name={:?}, mode={:o}, umask={:o}, flags={:x})",
             parent, name, mode, umask, flags
         );
+        self.missing.remove(parent, name);
         match self.create_wrapper(parent, name, mode, umask, flags) {
             Ok((attrs, fh)) => {
                 debug!("created (inode=0x{:016x}, fh={})", attrs.ino, fh);
-                reply.created(&TTL, &attrs, 0, fh, flags as u32);
+                self.icache().lookup(attrs.ino);
+                reply.created(&TTL, &attrs, 0, fh, self.open_flags(flags));
             }
             Err(err) => {
                 debug!("create failed (err={})", err);
@@ -1060,15 +3799,66 @@ impl Filesystem for ZstdFS {
             "Write (ino=0x{:016x}, fh={}, offset={}, data_len={}, write_flags={:x}, flags={:x}), lock={:?}",
             ino, fh, offset, data.len(), write_flags, flags, lock_owner
         );
-        match self.write_wrapper(ino, fh, offset, data, write_flags, flags, lock_owner) {
+        let started = Instant::now();
+        let res = self.write_wrapper(ino, fh, offset, data, write_flags, flags, lock_owner);
+        self.profiled("write", started);
+        match res {
             Ok(size) => {
                 debug!("written (size={})", size);
+                self.stats.written(size as u64);
                 reply.written(size as u32);
             }
             Err(err) => {
                 reply.error(err);
             }
         }
+        self.flush_dirty();
+    }
+
+    fn statfs(&mut self, _req: &Request<'_>, ino: u64, reply: fuser::ReplyStatfs) {
+        debug!("Statfs (inode=0x{:016x})", ino);
+        // same values as the default implementation
+        reply.statfs(0, 0, 0, 0, 0, 512, 255, 0);
+        // requested periodically when flush or compaction interval is set
+        self.flush_dirty();
+        self.compact_cache();
+    }
+
+    fn symlink(
+        &mut self,
+        _req: &Request<'_>,
+        parent: u64,
+        link_name: &OsStr,
+        target: &Path,
+        reply: ReplyEntry,
+    ) {
+        debug!(
+            "Symlink (iparent=0x{:016x}, name={:?}, target={:?})",
+            parent, link_name, target
+        );
+        self.missing.remove(parent, link_name);
+        match self.symlink_wrapper(parent, link_name, target) {
+            Ok(attrs) => {
+                debug!("symlink created (ino=0x{:016x})", attrs.ino);
+                self.icache().lookup(attrs.ino);
+                reply.entry(&TTL, &attrs, 0);
+            }
+            Err(err) => {
+                debug!("symlink failed (err={})", err);
+                reply.error(err);
+            }
+        }
+    }
+
+    fn readlink(&mut self, _req: &Request<'_>, ino: u64, reply: fuser::ReplyData) {
+        debug!("Readlink (inode=0x{:016x})", ino);
+        match self.readlink_wrapper(ino) {
+            Ok(target) => reply.data(&target),
+            Err(err) => {
+                debug!("readlink failed (err={})", err);
+                reply.error(err);
+            }
+        }
     }
 
     fn mkdir(
@@ -1084,9 +3874,11 @@ impl Filesystem for ZstdFS {
             "Mkdir (iparent=0x{:016x}, name={:?}, mode={:o}, umask={:o})",
             parent, name, mode, umask
         );
+        self.missing.remove(parent, name);
         match self.mkdir_wrapper(parent, name, mode, umask) {
             Ok(attrs) => {
                 debug!("mkdir passed (ino=0x{:016x})", attrs.ino);
+                self.icache().lookup(attrs.ino);
                 reply.entry(&TTL, &attrs, 0);
             }
             Err(err) => {
@@ -1138,6 +3930,7 @@ impl Filesystem for ZstdFS {
             "Rename (from_iparent=0x{:016x}, from_name={:?}, to_iparent=0x{:016x}, to_iname={:?}, flags={:x})",
             parent, name, newparent, newname, flags
         );
+        self.missing.remove(newparent, newname);
         match self.rename_wrapper(parent, name, newparent, newname, flags) {
             Ok(()) => {
                 debug!("rename passed");
@@ -1186,7 +3979,10 @@ impl Filesystem for ZstdFS {
             "Flush (ino=0x{:016x}, fh={:?}, lock_owner={:?}",
             ino, fh, lock_owner
         );
-        match self.flush_wrapper(ino, fh, lock_owner) {
+        let started = Instant::now();
+        let res = self.flush_wrapper(ino, fh, lock_owner);
+        self.profiled("flush", started);
+        match res {
             Ok(()) => {
                 debug!("flush passed");
                 reply.ok();
@@ -1198,12 +3994,361 @@ impl Filesystem for ZstdFS {
         }
     }
 
+    fn copy_file_range(
+        &mut self,
+        _req: &Request<'_>,
+        ino_in: u64,
+        fh_in: u64,
+        offset_in: i64,
+        ino_out: u64,
+        fh_out: u64,
+        offset_out: i64,
+        len: u64,
+        flags: u32,
+        reply: fuser::ReplyWrite,
+    ) {
+        debug!(
+            "Copy file range (ino_in=0x{:016x}, fh_in={}, offset_in={}, ino_out=0x{:016x}, fh_out={}, offset_out={}, len={}, flags={:x})",
+            ino_in, fh_in, offset_in, ino_out, fh_out, offset_out, len, flags
+        );
+        match self.copy_file_range_wrapper(
+            ino_in, fh_in, offset_in, ino_out, fh_out, offset_out, len, flags,
+        ) {
+            Ok(size) => {
+                debug!("copy file range passed (size={})", size);
+                reply.written(size);
+            }
+            Err(err) => {
+                debug!("copy file range failed (err={})", err);
+                reply.error(err);
+            }
+        }
+    }
+
+    fn ioctl(
+        &mut self,
+        _req: &Request<'_>,
+        ino: u64,
+        fh: u64,
+        flags: u32,
+        cmd: u32,
+        in_data: &[u8],
+        out_size: u32,
+        reply: fuser::ReplyIoctl,
+    ) {
+        debug!(
+            "Ioctl (ino=0x{:016x}, fh={}, flags={:x}, cmd=0x{:x}, out_size={})",
+            ino, fh, flags, cmd, out_size
+        );
+        match self.ioctl_wrapper(ino, cmd, in_data, out_size) {
+            Ok(data) => {
+                debug!("ioctl passed");
+                reply.ioctl(0, &data);
+            }
+            Err(err) => {
+                debug!("ioctl failed (err={})", err);
+                reply.error(err);
+            }
+        }
+    }
+
+    fn setxattr(
+        &mut self,
+        _req: &Request<'_>,
+        ino: u64,
+        name: &OsStr,
+        value: &[u8],
+        flags: i32,
+        position: u32,
+        reply: fuser::ReplyEmpty,
+    ) {
+        debug!(
+            "Setxattr (ino=0x{:016x}, name={:?}, size={}, flags={:x}, position={})",
+            ino,
+            name,
+            value.len(),
+            flags,
+            position
+        );
+        match self.setxattr_wrapper(ino, name, value, flags) {
+            Ok(()) => {
+                debug!("setxattr passed");
+                reply.ok();
+            }
+            Err(err) => {
+                debug!("setxattr failed (err={})", err);
+                reply.error(err);
+            }
+        }
+    }
+
+    fn getxattr(
+        &mut self,
+        _req: &Request<'_>,
+        ino: u64,
+        name: &OsStr,
+        size: u32,
+        reply: fuser::ReplyXattr,
+    ) {
+        debug!(
+            "Getxattr (ino=0x{:016x}, name={:?}, size={})",
+            ino, name, size
+        );
+        match self.getxattr_wrapper(ino, name) {
+            Ok(value) if size == 0 => {
+                debug!("getxattr size {}", value.len());
+                reply.size(value.len() as u32);
+            }
+            Ok(value) if value.len() > size as usize => {
+                debug!("getxattr buffer too small ({} > {})", value.len(), size);
+                reply.error(libc::ERANGE);
+            }
+            Ok(value) => {
+                debug!("getxattr passed");
+                reply.data(&value);
+            }
+            Err(err) => {
+                debug!("getxattr failed (err={})", err);
+                reply.error(err);
+            }
+        }
+    }
+
+    fn listxattr(&mut self, _req: &Request<'_>, ino: u64, size: u32, reply: fuser::ReplyXattr) {
+        debug!("Listxattr (ino=0x{:016x}, size={})", ino, size);
+        match self.listxattr_wrapper(ino) {
+            Ok(names) if size == 0 => {
+                debug!("listxattr size {}", names.len());
+                reply.size(names.len() as u32);
+            }
+            Ok(names) if names.len() > size as usize => {
+                debug!("listxattr buffer too small ({} > {})", names.len(), size);
+                reply.error(libc::ERANGE);
+            }
+            Ok(names) => {
+                debug!("listxattr passed");
+                reply.data(&names);
+            }
+            Err(err) => {
+                debug!("listxattr failed (err={})", err);
+                reply.error(err);
+            }
+        }
+    }
+
+    fn removexattr(
+        &mut self,
+        _req: &Request<'_>,
+        ino: u64,
+        name: &OsStr,
+        reply: fuser::ReplyEmpty,
+    ) {
+        debug!("Removexattr (ino=0x{:016x}, name={:?})", ino, name);
+        match self.removexattr_wrapper(ino, name) {
+            Ok(()) => {
+                debug!("removexattr passed");
+                reply.ok();
+            }
+            Err(err) => {
+                debug!("removexattr failed (err={})", err);
+                reply.error(err);
+            }
+        }
+    }
+
     fn destroy(&mut self) {
+        self.retry_failed_releases();
+        for (ino, fh) in self.failed_releases.drain(..) {
+            error!(
+                "Changes of opened file are lost (inode=0x{:016x}, fh={})",
+                ino, fh
+            );
+        }
+        self.save_stats();
+        self.save_metas();
+        if let Some(profile) = self.profile.as_ref() {
+            for line in profile.summary() {
+                info!("{}", line);
+            }
+        }
+
         let cache_dir = self.icache().cache_data_dir().path().to_owned();
         debug!("Discarding inode cache at '{}'", cache_dir.display());
         // Should drop the cache and delete tmp directory
         self.inode_cache = None;
+
+        // Cache root outside of data dir is not reused
+        if let Some(cache_root) = self.cache_root.take() {
+            let _ = fs::remove_dir_all(cache_root);
+        }
+    }
+}
+
+/// Creates inode cache in empty cache root
+/// Caches of other mounts are not cleared when data dir is shared
+fn create_cache(
+    cache_root: &Path,
+    data_dir: &Path,
+    clear: bool,
+    tree: bool,
+) -> Result<cache::InodeCache, libc::c_int> {
+    if clear
+        && fs::remove_dir_all(cache_root)
+            .map_err(convert_io_error)
+            .is_ok()
+    {
+        debug!("Clearing root cache directory {}", cache_root.display());
+    }
+    debug!("Creating cache root directory {}", cache_root.display());
+    fs::create_dir_all(cache_root).map_err(convert_io_error)?;
+
+    cache::InodeCache::new(cache_root, data_dir, tree)
+}
+
+/// Mounts an empty temporary data dir and runs the selftest on it
+fn run_selftest(config: config::Config) -> io::Result<usize> {
+    let data_dir = tempfile::TempDir::new()?;
+    let mount_point = tempfile::TempDir::new()?;
+    let session = fuser::spawn_mount2(
+        ZstdFS::new(data_dir.path().display().to_string(), u64::MAX, config)?,
+        mount_point.path(),
+        &[
+            MountOption::RW,
+            MountOption::FSName("fuse-zstd-selftest".to_string()),
+        ],
+    )?;
+    let res = selftest::run(data_dir.path(), mount_point.path());
+    session.join();
+    res
+}
+
+/// Reads fuse-zstd inode index from data dir
+fn load_inode_idx(data_dir: &Path) -> io::Result<u64> {
+    match xattr::get(data_dir, "user.ino_idx")?.and_then(|e| xattrs::parse_number(&e)) {
+        Some(inode_idx) => Ok(inode_idx),
+        // index was removed, inodes which are already used can't be assigned again
+        None => match import::lowest_allocated(data_dir, &data_dir.join(".fuse-zstd-inode_cache"))?
+        {
+            Some(ino) => {
+                warn!("Inode index is missing, continuing below 0x{:016x}", ino);
+                Ok(ino - 1)
+            }
+            None => Ok(u64::MAX),
+        },
+    }
+}
+
+/// Path of a file in data dir given by its name relative to the mount point
+fn one_shot_path(data_dir: &Path, name: &str) -> io::Result<(PathBuf, String)> {
+    let name = Path::new(name);
+    let file_name = match name.file_name() {
+        Some(file_name)
+            if name
+                .components()
+                .all(|e| matches!(e, Component::Normal(_) | Component::CurDir)) =>
+        {
+            format!("{}.zst", file_name.to_string_lossy())
+        }
+        _ => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Wrong name '{}'", name.display()),
+            ))
+        }
+    };
+    Ok((data_dir.join(name.parent().unwrap()), file_name))
+}
+
+/// Compresses standard input to a file in data dir without mounting
+fn run_compress(data_dir: &Path, name: &str, config: config::Config) -> io::Result<()> {
+    fs::create_dir_all(data_dir)?;
+    let data_dir = fs::canonicalize(data_dir)?;
+    // inodes are allocated the same way as in shared mounts
+    let _lock = lock::lock_mount(&data_dir, true)?;
+    let (dir_path, file_name) = one_shot_path(&data_dir, name)?;
+    fs::create_dir_all(&dir_path)?;
+
+    let inode_idx = load_inode_idx(&data_dir)?;
+    let config = config::Config {
+        allow_shared: true,
+        ..config
+    };
+    let mut fs = ZstdFS::new(data_dir.display().to_string(), inode_idx, config)?;
+    let mut source = fs.scratch_file()?;
+    io::copy(&mut io::stdin().lock(), &mut source)?;
+    let compression_level = fs.config.compression_level;
+    fs.store_to_source_file(&source, &dir_path, &file_name, compression_level)
+        .map_err(io::Error::from_raw_os_error)?;
+    fs.save_stats();
+    Ok(())
+}
+
+/// Decompresses a file in data dir to standard output without mounting
+fn run_decompress(data_dir: &Path, name: &str, config: config::Config) -> io::Result<()> {
+    let (dir_path, file_name) = one_shot_path(data_dir, name)?;
+    let chunked = dir_path.join(format!(
+        "{}{}",
+        strip_suffix(&file_name).unwrap(),
+        cdc::SUFFIX
+    ));
+    let source = if chunked.is_file() {
+        fs::File::open(chunked)?
+    } else {
+        fs::File::open(dir_path.join(file_name))?
+    };
+    let fs = ZstdFS::new(data_dir.display().to_string(), u64::MAX, config)?;
+    fs.decompress_file(&source, io::stdout().lock())
+        .map_err(io::Error::from_raw_os_error)
+}
+
+/// Makes sure that mount point is an existing directory
+fn check_mount_point(mountpoint: &Path, create: bool) -> io::Result<()> {
+    if mountpoint.as_os_str().is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "Mount point is not set (use --mount-point)",
+        ));
+    }
+
+    if !mountpoint.exists() {
+        if create {
+            info!("Creating mount point '{}'", mountpoint.display());
+            fs::create_dir_all(mountpoint)?;
+        } else {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!(
+                    "Mount point '{}' doesn't exist (use --mkdir-mount-point to create it)",
+                    mountpoint.display()
+                ),
+            ));
+        }
+    }
+
+    if !mountpoint.is_dir() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("Mount point '{}' is not a directory", mountpoint.display()),
+        ));
     }
+
+    if fs::read_dir(mountpoint)?.next().is_some() {
+        warn!(
+            "Mount point '{}' is not empty, its content will be hidden",
+            mountpoint.display()
+        );
+    }
+
+    Ok(())
+}
+
+fn log_builder(log_level: LevelFilter, json: bool) -> env_logger::Builder {
+    let mut builder = env_logger::builder();
+    builder.filter_level(log_level);
+    if json {
+        builder.format(logfmt::json);
+    }
+    builder
 }
 
 fn main() -> io::Result<()> {
@@ -1211,14 +4356,223 @@ fn main() -> io::Result<()> {
         .version(crate_version!())
         .author(crate_authors!())
         .arg(
-            Arg::new("mount-point")
-                .long("mount-point")
-                .value_name("MOUNT_POINT")
-                .default_value("")
-                .help("Where FUSE fs shall be mounted")
-                .env("FUSE_ZSTD_MOUNT_POINT")
+            Arg::new("mount-point")
+                .long("mount-point")
+                .value_name("MOUNT_POINT")
+                .default_value("")
+                .help("Where FUSE fs shall be mounted")
+                .env("FUSE_ZSTD_MOUNT_POINT")
+                .num_args(1),
+        )
+        .arg(
+            Arg::new("import")
+                .long("import")
+                .action(ArgAction::SetTrue)
+                .help("Assigns contiguous inodes to files in data dir which don't have any before mounting"),
+        )
+        .arg(
+            Arg::new("auto-dictionary")
+                .long("auto-dictionary")
+                .action(ArgAction::SetTrue)
+                .help("Trains compression dictionary from small files in data dir when there is none and compresses new files with it"),
+        )
+        .arg(
+            Arg::new("retrain-dictionary")
+                .long("retrain-dictionary")
+                .action(ArgAction::SetTrue)
+                .requires("auto-dictionary")
+                .help("Trains compression dictionary again (files compressed by older dictionaries remain readable)"),
+        )
+        .arg(
+            Arg::new("allow-other")
+                .long("allow-other")
+                .action(ArgAction::SetTrue)
+                .help("Makes the mount point accessible to other users (requires user_allow_other in /etc/fuse.conf)"),
+        )
+        .arg(
+            Arg::new("max-concurrent-decompress")
+                .long("max-concurrent-decompress")
+                .value_name("N")
+                .help("Max number of files (or readahead frames) decompressed at the same time")
+                .env("FUSE_ZSTD_MAX_CONCURRENT_DECOMPRESS")
+                .action(ArgAction::Set)
+                .num_args(1),
+        )
+        .arg(
+            Arg::new("show-special")
+                .long("show-special")
+                .action(ArgAction::SetTrue)
+                .help("Shows sockets, FIFOs and device nodes of data dir so they can be removed"),
+        )
+        .arg(
+            Arg::new("archive-dirs")
+                .long("archive-dirs")
+                .action(ArgAction::SetTrue)
+                .help("Presents NAME.tar.zst archives in data dir as directories NAME"),
+        )
+        .arg(
+            Arg::new("append-only")
+                .long("append-only")
+                .action(ArgAction::SetTrue)
+                .help("Files can be only created and appended (no overwrites, truncation or removal)"),
+        )
+        .arg(
+            Arg::new("no-compress")
+                .long("no-compress")
+                .action(ArgAction::SetTrue)
+                .conflicts_with_all(["cdc", "index-interval"])
+                .help("Stores files uncompressed (e.g. to debug issues or for data which don't compress)"),
+        )
+        .arg(
+            Arg::new("flush-interval")
+                .long("flush-interval")
+                .value_name("SECONDS")
+                .help("Stores changes of all opened files and running totals at least once per interval (limits data lost on crash)")
+                .env("FUSE_ZSTD_FLUSH_INTERVAL")
+                .action(ArgAction::Set)
+                .num_args(1),
+        )
+        .arg(
+            Arg::new("strict-external")
+                .long("strict-external")
+                .action(ArgAction::SetTrue)
+                .help("Fails to store opened file with EBUSY when its compressed file was changed outside of fuse-zstd meanwhile (instead of overwriting the changes)"),
+        )
+        .arg(
+            Arg::new("profile")
+                .long("profile")
+                .action(ArgAction::SetTrue)
+                .help("Measures durations of open, read, write, flush, readdir and (de)compression, the summary is logged on unmount (-vv)"),
+        )
+        .arg(
+            Arg::new("overlap-policy")
+                .long("overlap-policy")
+                .value_name("POLICY")
+                .value_parser(["prefer-compressed", "prefer-plain", "error"])
+                .default_value("prefer-compressed")
+                .conflicts_with_all(["convert", "keep-plain"])
+                .help("Which file is served when both 'name' and 'name.zst' exist in data dir (prefer-plain compresses the plain file over the other one)")
+                .env("FUSE_ZSTD_OVERLAP_POLICY")
+                .action(ArgAction::Set)
+                .num_args(1),
+        )
+        .arg(
+            Arg::new("scrub")
+                .long("scrub")
+                .action(ArgAction::SetTrue)
+                .help("Verifies all compressed files in background once per day (corrupted files are logged)"),
+        )
+        .arg(
+            Arg::new("scrub-rate")
+                .long("scrub-rate")
+                .value_name("MBPS")
+                .requires("scrub")
+                .help("Limits the rate of data read by --scrub (MB of compressed data per second)")
+                .env("FUSE_ZSTD_SCRUB_RATE")
+                .action(ArgAction::Set)
+                .num_args(1),
+        )
+        .arg(
+            Arg::new("dictionary-dir")
+                .long("dictionary-dir")
+                .value_name("PATH")
+                .help("Directory with external zstd dictionaries named by their ids (for files compressed by other systems)")
+                .env("FUSE_ZSTD_DICTIONARY_DIR")
+                .action(ArgAction::Set)
+                .num_args(1),
+        )
+        .arg(
+            Arg::new("compact-cache-interval")
+                .long("compact-cache-interval")
+                .value_name("SECONDS")
+                .help("Checks the size of inode cache once per interval and rebuilds it when it grew (reclaims space of removed entries)")
+                .env("FUSE_ZSTD_COMPACT_CACHE_INTERVAL")
+                .action(ArgAction::Set)
+                .num_args(1),
+        )
+        .arg(
+            Arg::new("name-manifests")
+                .long("name-manifests")
+                .action(ArgAction::SetTrue)
+                .help("Presents compressed files under names from .fuse-zstd-names manifests of their directories"),
+        )
+        .arg(
+            Arg::new("network-backing")
+                .long("network-backing")
+                .action(ArgAction::SetTrue)
+                .help("Data dir is on a network filesystem (metadata are cached, xattrs are optional)"),
+        )
+        .arg(
+            Arg::new("mirror-inodes")
+                .long("mirror-inodes")
+                .action(ArgAction::SetTrue)
+                .help("Reports inodes of files in data dir (they change when the files are recreated outside of the mount)"),
+        )
+        .arg(
+            Arg::new("log-format")
+                .long("log-format")
+                .value_name("FORMAT")
+                .value_parser(["text", "json"])
+                .default_value("text")
+                .help("Format of log records (json writes one object per line)")
+                .env("FUSE_ZSTD_LOG_FORMAT")
+                .action(ArgAction::Set)
+                .num_args(1),
+        )
+        .arg(
+            Arg::new("cache-compression")
+                .long("cache-compression")
+                .action(ArgAction::SetTrue)
+                .help("Stores paths in inode cache as parent inode and name (smaller cache, cheap directory renames)"),
+        )
+        .arg(
+            Arg::new("max-decompressed-size")
+                .long("max-decompressed-size")
+                .value_name("BYTES")
+                .help("Files which decompress to more bytes fail to open with EFBIG")
+                .env("FUSE_ZSTD_MAX_DECOMPRESSED_SIZE")
+                .action(ArgAction::Set)
                 .num_args(1),
         )
+        .arg(
+            Arg::new("async-unlink")
+                .long("async-unlink")
+                .action(ArgAction::SetTrue)
+                .help("Removes deleted files in background (names disappear right away)"),
+        )
+        .arg(
+            Arg::new("allow-shared")
+                .long("allow-shared")
+                .action(ArgAction::SetTrue)
+                .help("Allows other fuse-zstd processes to mount the same data dir (all of them need this option)"),
+        )
+        .arg(
+            Arg::new("fill-size-on-readdir")
+                .long("fill-size-on-readdir")
+                .action(ArgAction::SetTrue)
+                .help("Stores sizes of files without metadata when directory is listed (the files are decompressed)"),
+        )
+        .arg(
+            Arg::new("skip-mime")
+                .long("skip-mime")
+                .value_name("TYPES")
+                .help("Comma separated MIME types (e.g. image/*,application/zip) detected from content which are stored uncompressed (as well as incompressible data of unknown types)")
+                .env("FUSE_ZSTD_SKIP_MIME")
+                .action(ArgAction::Set)
+                .num_args(1),
+        )
+        .arg(
+            Arg::new("cdc")
+                .long("cdc")
+                .action(ArgAction::SetTrue)
+                .help("Splits new files to chunks at content defined boundaries and stores identical chunks only once (files are stored with .zstc suffix)"),
+        )
+        .arg(
+            Arg::new("mkdir-mount-point")
+                .long("mkdir-mount-point")
+                .action(ArgAction::SetTrue)
+                .help("Creates mount point directory if it doesn't exist"),
+        )
         .arg(
             Arg::new("data-dir")
                 .long("data-dir")
@@ -1229,16 +4583,138 @@ fn main() -> io::Result<()> {
                 .action(ArgAction::Set)
                 .num_args(1),
         )
+        .arg(
+            Arg::new("subdir")
+                .long("subdir")
+                .value_name("SUBDIR")
+                .help("Subdirectory of data dir (relative path) which will be mounted")
+                .env("FUSE_ZSTD_SUBDIR")
+                .action(ArgAction::Set)
+                .num_args(1),
+        )
         .arg(
             Arg::new("compression-level")
             .short('c')
             .long("compression-level")
             .value_name("LEVEL")
             .default_value("0")
-            .help("Set compression level of zstd (0-19), 0 means use default value provided by library")
+            .allow_hyphen_values(true)
+            .help("Set compression level of zstd (range supported by the library is checked), 0 means use default value provided by library, store means no compression (same as --no-compress)")
             .env("FUSE_ZSTD_COMPRESSION_LEVEL")
             .num_args(1)
         )
+        .arg(
+            Arg::new("compress-xattrs")
+                .long("compress-xattrs")
+                .value_name("BYTES")
+                .help("Extended attributes larger than BYTES will be stored compressed")
+                .env("FUSE_ZSTD_COMPRESS_XATTRS")
+                .action(ArgAction::Set)
+                .num_args(1),
+        )
+        .arg(
+            Arg::new("file-mode")
+                .long("file-mode")
+                .value_name("MODE")
+                .help("Permissions (octal) of newly created files, permissions of files are preserved")
+                .env("FUSE_ZSTD_FILE_MODE")
+                .action(ArgAction::Set)
+                .num_args(1),
+        )
+        .arg(
+            Arg::new("dir-mode")
+                .long("dir-mode")
+                .value_name("MODE")
+                .help("Permissions (octal) of newly created directories, permissions of directories are preserved")
+                .env("FUSE_ZSTD_DIR_MODE")
+                .action(ArgAction::Set)
+                .num_args(1),
+        )
+        .arg(
+            Arg::new("cache-dir")
+                .long("cache-dir")
+                .value_name("PATH")
+                .help("Directory for inode cache when it can't be created in data dir [default: $TMPDIR]")
+                .env("FUSE_ZSTD_CACHE_DIR")
+                .action(ArgAction::Set)
+                .num_args(1),
+        )
+        .arg(
+            Arg::new("strict")
+                .long("strict")
+                .action(ArgAction::SetTrue)
+                .help("Refuses to mount when data dir lacks features (xattrs, atomic rename, sparse files)"),
+        )
+        .arg(
+            Arg::new("strict-close")
+                .long("strict-close")
+                .action(ArgAction::SetTrue)
+                .help("Keeps files which failed to be stored on release opened and retries later"),
+        )
+        .arg(
+            Arg::new("du-reports")
+                .long("du-reports")
+                .value_name("SIZE")
+                .value_parser(["physical", "logical"])
+                .default_value("physical")
+                .help("Blocks reported for du: physical are blocks of compressed files (du shows compression savings), logical are computed from decompressed size (du matches du --apparent-size)")
+                .env("FUSE_ZSTD_DU_REPORTS")
+                .action(ArgAction::Set)
+                .num_args(1),
+        )
+        .arg(
+            Arg::new("report-logical-blocks")
+                .long("report-logical-blocks")
+                .action(ArgAction::SetTrue)
+                .conflicts_with("du-reports")
+                .help("Same as --du-reports logical"),
+        )
+        .arg(
+            Arg::new("sync-on")
+                .long("sync-on")
+                .value_name("EVENT")
+                .value_parser(["flush", "release", "fsync"])
+                .default_value("flush")
+                .help("When changes of opened files are compressed (fsync drops changes which were not synced)")
+                .env("FUSE_ZSTD_SYNC_ON")
+                .action(ArgAction::Set)
+                .num_args(1),
+        )
+        .arg(
+            Arg::new("work-dir")
+                .long("work-dir")
+                .value_name("PATH")
+                .help("Directory for decompressed data of opened files [default: $TMPDIR]")
+                .env("FUSE_ZSTD_WORK_DIR")
+                .action(ArgAction::Set)
+                .num_args(1),
+        )
+        .arg(
+            Arg::new("index-interval")
+                .long("index-interval")
+                .value_name("BYTES")
+                .help("Compress files to frames of BYTES size which can be read without decompressing the whole file")
+                .env("FUSE_ZSTD_INDEX_INTERVAL")
+                .action(ArgAction::Set)
+                .num_args(1),
+        )
+        .arg(
+            Arg::new("readahead")
+                .long("readahead")
+                .value_name("KB")
+                .help("Decompress KB of indexed files ahead of sequential reads in background")
+                .env("FUSE_ZSTD_READAHEAD")
+                .action(ArgAction::Set)
+                .num_args(1),
+        )
+        .arg(
+            Arg::new("zstd-param")
+                .long("zstd-param")
+                .value_name("NAME=VALUE")
+                .help("Sets advanced zstd compression parameter (e.g. strategy=btopt, hashLog=20), can be repeated")
+                .action(ArgAction::Append)
+                .num_args(1),
+        )
         .arg(
             Arg::new("v")
                 .short('v')
@@ -1250,6 +4726,160 @@ fn main() -> io::Result<()> {
                 .long("convert")
                 .action(ArgAction::SetTrue)
                 .help("Will convert files uncompressed files from data dir"),
+        )
+        .arg(
+            Arg::new("convert-dry-run")
+                .long("convert-dry-run")
+                .action(ArgAction::SetTrue)
+                .requires("convert")
+                .help("Only logs what would be converted, mounts read-only and keeps data dir untouched"),
+        )
+        .arg(
+            Arg::new("direct-io")
+                .long("direct-io")
+                .action(ArgAction::SetTrue)
+                .help("Disables kernel page cache so that reads always reflect the current data"),
+        )
+        .arg(
+            Arg::new("preserve-mtime")
+                .long("preserve-mtime")
+                .action(ArgAction::SetTrue)
+                .help("Keeps mtime set by the user when the file is compressed again"),
+        )
+        .arg(
+            Arg::new("verify-size")
+                .long("verify-size")
+                .action(ArgAction::SetTrue)
+                .help("Reports and fixes stored real size which doesn't match decompressed data"),
+        )
+        .arg(
+            Arg::new("keep-plain")
+                .long("keep-plain")
+                .action(ArgAction::SetTrue)
+                .help("Keeps uncompressed copy of each file in data dir"),
+        )
+        .subcommand(
+            Command::new("bench")
+                .about("Measures compression ratio and throughput of files in data dir without mounting")
+                .arg(
+                    Arg::new("data-dir")
+                        .long("data-dir")
+                        .value_name("DATA_DIR")
+                        .required(true)
+                        .help("Directory with sample files")
+                        .action(ArgAction::Set)
+                        .num_args(1),
+                )
+                .arg(
+                    Arg::new("levels")
+                        .long("levels")
+                        .value_name("LEVELS")
+                        .default_value("1,3,9,19")
+                        .help("Comma separated compression levels to measure")
+                        .action(ArgAction::Set)
+                        .num_args(1),
+                ),
+        )
+        .subcommand(
+            Command::new("selftest")
+                .about("Mounts a temporary filesystem and checks that written data are read back"),
+        )
+        .subcommand(
+            Command::new("compress")
+                .about("Compresses standard input to a file in data dir without mounting")
+                .arg(
+                    Arg::new("data-dir")
+                        .long("data-dir")
+                        .value_name("DATA_DIR")
+                        .required(true)
+                        .help("Data directory of the filesystem")
+                        .action(ArgAction::Set)
+                        .num_args(1),
+                )
+                .arg(
+                    Arg::new("name")
+                        .long("name")
+                        .value_name("NAME")
+                        .required(true)
+                        .help("Path of the file relative to the mount point")
+                        .action(ArgAction::Set)
+                        .num_args(1),
+                ),
+        )
+        .subcommand(
+            Command::new("decompress")
+                .about("Decompresses a file in data dir to standard output without mounting")
+                .arg(
+                    Arg::new("data-dir")
+                        .long("data-dir")
+                        .value_name("DATA_DIR")
+                        .required(true)
+                        .help("Data directory of the filesystem")
+                        .action(ArgAction::Set)
+                        .num_args(1),
+                )
+                .arg(
+                    Arg::new("name")
+                        .long("name")
+                        .value_name("NAME")
+                        .required(true)
+                        .help("Path of the file relative to the mount point")
+                        .action(ArgAction::Set)
+                        .num_args(1),
+                ),
+        )
+        .subcommand(
+            Command::new("archive")
+                .about("Replaces a directory in data dir by a single archive (see --archive-dirs)")
+                .arg(
+                    Arg::new("data-dir")
+                        .long("data-dir")
+                        .value_name("DATA_DIR")
+                        .required(true)
+                        .help("Data directory of the filesystem")
+                        .action(ArgAction::Set)
+                        .num_args(1),
+                )
+                .arg(
+                    Arg::new("dir")
+                        .long("dir")
+                        .value_name("DIR")
+                        .required(true)
+                        .help("Directory to archive (relative to data dir)")
+                        .action(ArgAction::Set)
+                        .num_args(1),
+                ),
+        )
+        .subcommand(
+            Command::new("migrate")
+                .about("Rewrites metadata of compressed files in data dir to another version without mounting")
+                .arg(
+                    Arg::new("data-dir")
+                        .long("data-dir")
+                        .value_name("DATA_DIR")
+                        .required(true)
+                        .help("Data directory of the filesystem")
+                        .action(ArgAction::Set)
+                        .num_args(1),
+                )
+                .arg(
+                    Arg::new("to-version")
+                        .long("to-version")
+                        .value_name("VERSION")
+                        .value_parser(clap::value_parser!(u8))
+                        .help("Target version of metadata, 0 stores only user.real_size [default: current version]")
+                        .action(ArgAction::Set)
+                        .num_args(1),
+                )
+                .arg(
+                    Arg::new("from-version")
+                        .long("from-version")
+                        .value_name("VERSION")
+                        .value_parser(clap::value_parser!(u8))
+                        .help("Only files with this version are migrated [default: detected for each file]")
+                        .action(ArgAction::Set)
+                        .num_args(1),
+                ),
         );
 
     #[cfg(feature = "with_sentry")]
@@ -1267,6 +4897,120 @@ fn main() -> io::Result<()> {
 
     let verbosity: u8 = matches.get_count("v");
     let convert: bool = matches.get_flag("convert");
+    let preserve_mtime: bool = matches.get_flag("preserve-mtime");
+    let verify_size: bool = matches.get_flag("verify-size");
+    let keep_plain: bool = matches.get_flag("keep-plain");
+    let direct_io: bool = matches.get_flag("direct-io");
+    let cdc: bool = matches.get_flag("cdc");
+    let no_compress: bool = matches.get_flag("no-compress");
+    let fill_size_on_readdir: bool = matches.get_flag("fill-size-on-readdir");
+    let allow_shared: bool = matches.get_flag("allow-shared");
+    let async_unlink: bool = matches.get_flag("async-unlink");
+    let cache_compression: bool = matches.get_flag("cache-compression");
+    let mut mirror_inodes: bool = matches.get_flag("mirror-inodes");
+    let network_backing: bool = matches.get_flag("network-backing");
+    let name_manifests: bool = matches.get_flag("name-manifests");
+    let scrub: bool = matches.get_flag("scrub");
+    let flush_interval = matches
+        .get_one::<String>("flush-interval")
+        .map(|e| {
+            e.parse::<u64>()
+                .ok()
+                .filter(|e| *e > 0)
+                .map(Duration::from_secs)
+                .ok_or_else(|| {
+                    io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        format!("Wrong flush interval '{}'", e),
+                    )
+                })
+        })
+        .transpose()?;
+    let scrub_rate = matches
+        .get_one::<String>("scrub-rate")
+        .map(|e| {
+            e.parse::<u64>()
+                .ok()
+                .filter(|e| *e > 0)
+                .map(|e| e * 1024 * 1024)
+                .ok_or_else(|| {
+                    io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        format!("Wrong scrub rate '{}'", e),
+                    )
+                })
+        })
+        .transpose()?;
+    let compact_cache_interval = matches
+        .get_one::<String>("compact-cache-interval")
+        .map(|e| {
+            e.parse::<u64>()
+                .ok()
+                .filter(|e| *e > 0)
+                .map(Duration::from_secs)
+                .ok_or_else(|| {
+                    io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        format!("Wrong compact cache interval '{}'", e),
+                    )
+                })
+        })
+        .transpose()?;
+    let skip_mime = matches
+        .get_one::<String>("skip-mime")
+        .map(|e| {
+            e.split(',')
+                .filter(|e| !e.is_empty())
+                .map(|e| {
+                    if e.contains('/') {
+                        Ok(e.to_string())
+                    } else {
+                        Err(io::Error::new(
+                            io::ErrorKind::InvalidInput,
+                            format!("Wrong MIME type '{}'", e),
+                        ))
+                    }
+                })
+                .collect::<io::Result<Vec<_>>>()
+        })
+        .transpose()?
+        .unwrap_or_default();
+    let append_only: bool = matches.get_flag("append-only");
+    let convert_dry_run: bool = matches.get_flag("convert-dry-run");
+    let archive_dirs: bool = matches.get_flag("archive-dirs");
+    let show_special: bool = matches.get_flag("show-special");
+    let strict: bool = matches.get_flag("strict");
+    let strict_close: bool = matches.get_flag("strict-close");
+    let report_logical_blocks: bool = matches.get_flag("report-logical-blocks")
+        || matches.get_one::<String>("du-reports").unwrap() == "logical";
+    let max_concurrent_decompress = matches
+        .get_one::<String>("max-concurrent-decompress")
+        .map(|e| {
+            e.parse::<usize>().ok().filter(|e| *e > 0).ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("Wrong max concurrent decompress '{}'", e),
+                )
+            })
+        })
+        .transpose()?;
+    let max_decompressed_size = matches
+        .get_one::<String>("max-decompressed-size")
+        .map(|e| {
+            e.parse::<u64>().map_err(|_| {
+                io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("Wrong max decompressed size '{}'", e),
+                )
+            })
+        })
+        .transpose()?;
+    let mkdir_mount_point: bool = matches.get_flag("mkdir-mount-point");
+    let allow_other: bool = matches.get_flag("allow-other");
+    let import: bool = matches.get_flag("import");
+    let auto_dictionary: bool = matches.get_flag("auto-dictionary");
+    let retrain_dictionary: bool = matches.get_flag("retrain-dictionary");
+    let json_logs = matches.get_one::<String>("log-format").unwrap() == "json";
     let log_level = match verbosity {
         0 => LevelFilter::Error,
         1 => LevelFilter::Warn,
@@ -1280,25 +5024,140 @@ fn main() -> io::Result<()> {
         .map(String::to_owned)
         .unwrap_or_default()
         .to_string();
+    let subdir: Option<PathBuf> = matches.get_one::<String>("subdir").map(PathBuf::from);
+    if let Some(subdir) = subdir.as_ref() {
+        if !subdir
+            .components()
+            .all(|e| matches!(e, Component::Normal(_) | Component::CurDir))
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "Subdir '{}' has to be a relative path within data dir",
+                    subdir.display()
+                ),
+            ));
+        }
+    }
     let compression_level = matches
         .get_one("compression-level")
         .map(String::to_owned)
         .unwrap_or_default();
-    let compression_level = compression_level.parse::<u8>().unwrap_or_else(|_| {
-        warn!("Error parsing compression level. Using default.");
-        0
-    });
-    let compression_level = if compression_level > 19 {
-        warn!("Wrong compression level. Using default.");
+    // `store` is an explicit way to disable the compression, 0 is the default level of zstd
+    let store = compression_level == "store";
+    let no_compress = no_compress || store;
+    // supported levels depend on the version of zstd library
+    let levels = zstd::compression_level_range();
+    let compression_level = if store {
         0
     } else {
         compression_level
+            .parse::<i32>()
+            .ok()
+            .filter(|e| *e == 0 || levels.contains(e))
+            .ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!(
+                        "Wrong compression level '{}' (supported {}..={}, 0 means default, or store)",
+                        compression_level,
+                        levels.start(),
+                        levels.end()
+                    ),
+                )
+            })?
+    };
+
+    let compress_xattrs = matches.get_one::<String>("compress-xattrs").and_then(|e| {
+        e.parse::<usize>()
+            .map_err(|_| warn!("Error parsing xattrs compression threshold. Ignoring."))
+            .ok()
+    });
+
+    let parse_mode = |name: &str| -> io::Result<Option<u32>> {
+        matches
+            .get_one::<String>(name)
+            .map(|e| {
+                u32::from_str_radix(e, 8)
+                    .ok()
+                    .filter(|e| *e <= 0o7777)
+                    .ok_or_else(|| {
+                        io::Error::new(
+                            io::ErrorKind::InvalidInput,
+                            format!("Wrong {} '{}'", name, e),
+                        )
+                    })
+            })
+            .transpose()
+    };
+    let index_interval = matches
+        .get_one::<String>("index-interval")
+        .map(|e| {
+            e.parse::<usize>()
+                .ok()
+                .filter(|e| *e > 0 && *e <= index::MAX_INTERVAL)
+                .ok_or_else(|| {
+                    io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        format!("Wrong index interval '{}'", e),
+                    )
+                })
+        })
+        .transpose()?;
+    if store && (cdc || index_interval.is_some()) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "Compression level 'store' can't be used together with --cdc or --index-interval",
+        ));
+    }
+    let readahead = matches
+        .get_one::<String>("readahead")
+        .map(|e| {
+            e.parse::<u64>()
+                .ok()
+                .filter(|e| *e > 0)
+                .map(|e| e * 1024)
+                .ok_or_else(|| {
+                    io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        format!("Wrong readahead '{}'", e),
+                    )
+                })
+        })
+        .transpose()?;
+    let cache_dir: Option<PathBuf> = matches.get_one::<String>("cache-dir").map(PathBuf::from);
+    let work_dir: Option<PathBuf> = matches.get_one::<String>("work-dir").map(PathBuf::from);
+    let dictionary_dir: Option<PathBuf> = matches
+        .get_one::<String>("dictionary-dir")
+        .map(PathBuf::from);
+    let sync_on = match matches.get_one::<String>("sync-on").unwrap().as_str() {
+        "release" => config::SyncOn::Release,
+        "fsync" => config::SyncOn::Fsync,
+        _ => config::SyncOn::Flush,
+    };
+    let overlap_policy = match matches
+        .get_one::<String>("overlap-policy")
+        .unwrap()
+        .as_str()
+    {
+        "prefer-plain" => config::OverlapPolicy::PreferPlain,
+        "error" => config::OverlapPolicy::Error,
+        _ => config::OverlapPolicy::PreferCompressed,
     };
+    let profile: bool = matches.get_flag("profile");
+    let strict_external: bool = matches.get_flag("strict-external");
+    if let Some(work_dir) = work_dir.as_ref().filter(|e| !e.is_dir()) {
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("Work dir '{}' is not a directory", work_dir.display()),
+        ));
+    }
+    let file_mode = parse_mode("file-mode")?;
+    let dir_mode = parse_mode("dir-mode")?;
 
     #[cfg(feature = "with_sentry")]
     let _guard = if let Some(url) = matches.get_one("sentry-url").map(String::to_owned) {
-        let mut log_builder = env_logger::builder();
-        log_builder.filter_level(log_level);
+        let mut log_builder = log_builder(log_level, json_logs);
         let logger = sentry_log::SentryLogger::with_dest(log_builder.build());
 
         log::set_boxed_logger(Box::new(logger)).unwrap();
@@ -1311,39 +5170,366 @@ fn main() -> io::Result<()> {
             },
         )))
     } else {
-        env_logger::builder().filter_level(log_level).init();
+        log_builder(log_level, json_logs).init();
         None
     };
     #[cfg(not(feature = "with_sentry"))]
-    env_logger::builder().filter_level(log_level).init();
+    log_builder(log_level, json_logs).init();
+
+    let mut zstd_params = vec![];
+    for spec in matches.get_many::<String>("zstd-param").unwrap_or_default() {
+        match params::parse(spec)? {
+            Some(param) => zstd_params.push(param),
+            None => warn!("Unknown zstd parameter '{}' ignored", spec),
+        }
+    }
+
+    if let Some(("bench", bench_matches)) = matches.subcommand() {
+        let bench_dir: &String = bench_matches.get_one("data-dir").unwrap();
+        let levels = bench_matches
+            .get_one::<String>("levels")
+            .unwrap()
+            .split(',')
+            .map(|e| {
+                e.trim().parse::<i32>().map_err(|_| {
+                    io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        format!("Wrong compression level '{}'", e),
+                    )
+                })
+            })
+            .collect::<io::Result<Vec<_>>>()?;
+        let results = bench::bench(Path::new(bench_dir), &levels, &zstd_params, index_interval)?;
+        bench::print(&results);
+        return Ok(());
+    }
+
+    if let Some(("selftest", _)) = matches.subcommand() {
+        let config = config::Config {
+            compression_level,
+            zstd_params,
+            ..Default::default()
+        };
+        match run_selftest(config) {
+            Ok(steps) => {
+                println!("selftest passed ({} steps)", steps);
+                return Ok(());
+            }
+            Err(err) => {
+                eprintln!("selftest failed: {}", err);
+                process::exit(1);
+            }
+        }
+    }
+
+    if let Some(("archive", archive_matches)) = matches.subcommand() {
+        let archive_data_dir = Path::new(archive_matches.get_one::<String>("data-dir").unwrap());
+        let dir = archive_data_dir.join(archive_matches.get_one::<String>("dir").unwrap());
+        let dicts = dict::Dictionaries::load(archive_data_dir)?;
+        let dict = auto_dictionary.then(|| dicts.current()).flatten();
+        let archive_path = archive::create(&dir, &dicts, dict, compression_level, &zstd_params)?;
+        println!("{}", archive_path.display());
+        return Ok(());
+    }
+
+    if let Some(("migrate", migrate_matches)) = matches.subcommand() {
+        let migrate_data_dir = Path::new(migrate_matches.get_one::<String>("data-dir").unwrap());
+        // mounted data dir would be changed under the mount
+        let _lock = lock::lock_mount(migrate_data_dir, false)?;
+        let summary = migrate::migrate(
+            migrate_data_dir,
+            &migrate_data_dir.join(".fuse-zstd-inode_cache"),
+            migrate_matches.get_one::<u8>("from-version").copied(),
+            migrate_matches
+                .get_one::<u8>("to-version")
+                .copied()
+                .unwrap_or(meta::VERSION),
+        )?;
+        println!(
+            "{} migrated, {} up to date, {} skipped",
+            summary.migrated, summary.up_to_date, summary.skipped
+        );
+        return Ok(());
+    }
+
+    if let Some((command @ ("compress" | "decompress"), one_shot_matches)) = matches.subcommand() {
+        let one_shot_data_dir = Path::new(one_shot_matches.get_one::<String>("data-dir").unwrap());
+        let name = one_shot_matches.get_one::<String>("name").unwrap();
+        let config = config::Config {
+            compression_level,
+            zstd_params,
+            index_interval,
+            cdc,
+            no_compress,
+            skip_mime,
+            file_mode,
+            work_dir,
+            dictionary_dir,
+            ..Default::default()
+        };
+        return if command == "compress" {
+            run_compress(one_shot_data_dir, name, config)
+        } else {
+            run_decompress(one_shot_data_dir, name, config)
+        };
+    }
+
+    // fuser only reports that the mount failed
+    if allow_other && unsafe { libc::geteuid() } != 0 {
+        match probe::user_allow_other(Path::new(probe::FUSE_CONF)) {
+            Ok(true) => {}
+            Ok(false) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::PermissionDenied,
+                    format!(
+                        "Option --allow-other requires 'user_allow_other' in {} when not running as root, add the line there or mount without --allow-other",
+                        probe::FUSE_CONF
+                    ),
+                ));
+            }
+            // let the mount decide
+            Err(err) => warn!("Failed to read '{}': {}", probe::FUSE_CONF, err),
+        }
+    }
 
     let mountpoint: String = matches
         .get_one("mount-point")
         .map(String::to_owned)
         .unwrap_or_default()
         .to_string();
-    let options = vec![
-        MountOption::RW,
+    check_mount_point(Path::new(&mountpoint), mkdir_mount_point)?;
+
+    // xattrs of symlinked data dir would be stored to the link itself
+    fs::create_dir_all(&data_dir)?;
+    let data_dir = fs::canonicalize(&data_dir)?.display().to_string();
+
+    // held until the filesystem is unmounted
+    let _lock = match lock::lock_mount(Path::new(&data_dir), allow_shared) {
+        Ok(lock) => Some(lock),
+        Err(err) if err.kind() == io::ErrorKind::WouldBlock => return Err(err),
+        // e.g. read-only data dir
+        Err(err) => {
+            warn!("Failed to lock data dir '{}': {}", data_dir, err);
+            None
+        }
+    };
+
+    // data dir is not modified in dry run
+    let mut xattrs_unsupported = false;
+    if !convert_dry_run {
+        let msg = match probe::missing(Path::new(&data_dir)) {
+            Ok(mut missing) if network_backing && missing.contains(&probe::XATTRS_UNSUPPORTED) => {
+                // uncompressed data can't be told apart without metadata
+                if no_compress || !skip_mime.is_empty() {
+                    return Err(io::Error::new(
+                        io::ErrorKind::Unsupported,
+                        format!(
+                            "Data dir '{}' doesn't support xattrs which are required to store files uncompressed",
+                            data_dir
+                        ),
+                    ));
+                }
+                info!(
+                    "Data dir '{}' doesn't support xattrs, inodes are mirrored and metadata are kept in inode cache",
+                    data_dir
+                );
+                xattrs_unsupported = true;
+                mirror_inodes = true;
+                missing.retain(|e| *e != probe::XATTRS_UNSUPPORTED);
+                (!missing.is_empty()).then(|| {
+                    format!(
+                        "Data dir '{}' lacks required features: {}",
+                        data_dir,
+                        missing.join(", ")
+                    )
+                })
+            }
+            Ok(missing) if missing.is_empty() => None,
+            Ok(missing) => Some(format!(
+                "Data dir '{}' lacks required features: {}",
+                data_dir,
+                missing.join(", ")
+            )),
+            // e.g. data dir is not writable
+            Err(err) => Some(format!("Failed to probe data dir '{}': {}", data_dir, err)),
+        };
+        if let Some(msg) = msg {
+            if strict {
+                return Err(io::Error::new(io::ErrorKind::Unsupported, msg));
+            }
+            warn!("{}", msg);
+        }
+    }
+
+    let mut options = vec![
+        if convert_dry_run {
+            MountOption::RO
+        } else {
+            MountOption::RW
+        },
         MountOption::FSName(data_dir.clone()),
         MountOption::AutoUnmount,
-        MountOption::AllowOther,
     ];
+    if allow_other {
+        options.push(MountOption::AllowOther);
+    }
     info!(
-        "Starting fuse-zstd ({}) with compression level={}, convert={}",
+        "Starting fuse-zstd ({}) with compression level={} (supported {}..={}), convert={}, preserve_mtime={}",
         crate_version!(),
         compression_level,
+        levels.start(),
+        levels.end(),
         convert,
+        preserve_mtime,
     );
 
-    // Read fuse-zstd inode index from
-    let inode_idx = xattr::get(&data_dir, "user.ino_idx")?
-        .map(|e| u64::from_be_bytes(e.to_vec().try_into().unwrap()))
-        .unwrap_or(u64::MAX);
+    if import {
+        let data_path = Path::new(&data_dir);
+        let imported = import::import(data_path, &data_path.join(".fuse-zstd-inode_cache"))?;
+        info!("Imported {} entries from '{}'", imported, data_dir);
+    }
+
+    if auto_dictionary {
+        let data_path = Path::new(&data_dir);
+        if retrain_dictionary || dict::Dictionaries::load(data_path)?.current().is_none() {
+            match dict::train(data_path, &data_path.join(".fuse-zstd-inode_cache")) {
+                Ok(id) => info!("Dictionary trained (id={})", id),
+                // e.g. there are not enough samples
+                Err(err) => warn!("Failed to train dictionary: {}", err),
+            }
+        }
+    }
+
+    if cdc {
+        match cdc::collect_garbage(Path::new(&data_dir)) {
+            Ok(removed) => info!("Removed {} unreferenced chunks", removed),
+            Err(err) => error!("Removal of unreferenced chunks aborted (err={})", err),
+        }
+    }
+
+    // inodes are not allocated when they are mirrored
+    let inode_idx = if xattrs_unsupported {
+        u64::MAX
+    } else {
+        load_inode_idx(Path::new(&data_dir))?
+    };
     debug!("Root inode index 0x{:016x}", inode_idx);
 
+    let config = config::Config {
+        compression_level,
+        convert,
+        preserve_mtime,
+        subdir,
+        verify_size,
+        compress_xattrs,
+        keep_plain,
+        file_mode,
+        dir_mode,
+        index_interval,
+        zstd_params,
+        readahead,
+        cache_dir,
+        work_dir,
+        sync_on,
+        direct_io,
+        cdc,
+        append_only,
+        max_concurrent_decompress,
+        convert_dry_run,
+        archive_dirs,
+        show_special,
+        strict_close,
+        report_logical_blocks,
+        no_compress,
+        skip_mime,
+        fill_size_on_readdir,
+        allow_shared,
+        flush_interval,
+        async_unlink,
+        max_decompressed_size,
+        cache_compression,
+        mirror_inodes,
+        network_backing,
+        xattrs_unsupported,
+        name_manifests,
+        compact_cache_interval,
+        dictionary_dir,
+        scrub,
+        scrub_rate,
+        overlap_policy,
+        profile,
+        strict_external,
+        auto_dictionary,
+    };
+
+    // requests are processed in a single thread, statfs wakes it up to flush opened files
+    // (or to compact inode cache)
+    let wake_interval = match (config.flush_interval, config.compact_cache_interval) {
+        (Some(flush), Some(compact)) => Some(flush.min(compact)),
+        (flush, compact) => flush.or(compact),
+    };
+    if let Some(interval) = wake_interval {
+        let path = ffi::CString::new(mountpoint.as_bytes())?;
+        thread::spawn(move || loop {
+            thread::sleep(interval);
+            let mut stat = mem::MaybeUninit::<libc::statvfs>::uninit();
+            unsafe { libc::statvfs(path.as_ptr(), stat.as_mut_ptr()) };
+        });
+    }
+
     fuser::mount2(
-        ZstdFS::new(data_dir, compression_level, convert, inode_idx)?,
+        ZstdFS::new(data_dir, inode_idx, config)?,
         mountpoint,
         &options,
     )
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+
+    /// Returns at most `chunk` bytes per call and fails with EINTR every other call
+    struct ShortReader {
+        data: Vec<u8>,
+        chunk: usize,
+        interrupted: Cell<bool>,
+    }
+
+    impl FileExt for ShortReader {
+        fn read_at(&self, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+            if !self.interrupted.replace(true) {
+                return Err(io::ErrorKind::Interrupted.into());
+            }
+            self.interrupted.set(false);
+            let start = (offset as usize).min(self.data.len());
+            let size = buf.len().min(self.chunk).min(self.data.len() - start);
+            buf[..size].copy_from_slice(&self.data[start..start + size]);
+            Ok(size)
+        }
+
+        fn write_at(&self, _buf: &[u8], _offset: u64) -> io::Result<usize> {
+            Err(io::ErrorKind::Unsupported.into())
+        }
+    }
+
+    #[test]
+    fn read_full_at_short_reads() {
+        let reader = ShortReader {
+            data: (0..10_000).map(|e| (e % 251) as u8).collect(),
+            chunk: 1000,
+            interrupted: Cell::new(false),
+        };
+
+        // filled from several short reads
+        let mut buf = vec![0; 4096];
+        assert_eq!(read_full_at(&reader, &mut buf, 100).unwrap(), 4096);
+        assert_eq!(buf, reader.data[100..4196]);
+
+        // stops at EOF
+        let mut buf = vec![0; 4096];
+        assert_eq!(read_full_at(&reader, &mut buf, 8000).unwrap(), 2000);
+        assert_eq!(buf[..2000], reader.data[8000..]);
+        assert_eq!(read_full_at(&reader, &mut buf, 20_000).unwrap(), 0);
+    }
+}
@@ -0,0 +1,77 @@
+//! Presented names of compressed files (`--name-manifests`)
+//!
+//! A directory in data dir may contain a manifest with lines `backing -> presented`
+//! (e.g. `blob_abc123.zst -> report.pdf`). Compressed files listed in the manifest
+//! are shown under the presented name instead of their name without `.zst` suffix,
+//! so content addressed files can have human friendly names.
+
+use std::{
+    collections::HashMap,
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use log::debug;
+
+pub const MANIFEST_NAME: &str = ".fuse-zstd-names";
+const SEPARATOR: &str = " -> ";
+
+#[derive(Debug, Default)]
+pub struct Manifest {
+    /// Presented names by names of compressed files
+    presented: HashMap<String, String>,
+    /// Names of compressed files by presented names
+    backing: HashMap<String, String>,
+}
+
+impl Manifest {
+    pub fn path(dir: &Path) -> PathBuf {
+        dir.join(MANIFEST_NAME)
+    }
+
+    pub fn parse(data: &str) -> Self {
+        let mut res = Self::default();
+        for line in data.lines().filter(|e| !e.trim().is_empty()) {
+            let valid = line
+                .split_once(SEPARATOR)
+                .filter(|(backing, presented)| {
+                    backing.ends_with(".zst")
+                        && !backing.contains('/')
+                        && !presented.is_empty()
+                        && !presented.contains('/')
+                        && *presented != "."
+                        && *presented != ".."
+                })
+                .filter(|(backing, presented)| {
+                    !res.presented.contains_key(*backing) && !res.backing.contains_key(*presented)
+                });
+            match valid {
+                Some((backing, presented)) => {
+                    res.presented
+                        .insert(backing.to_string(), presented.to_string());
+                    res.backing
+                        .insert(presented.to_string(), backing.to_string());
+                }
+                None => debug!("Skipping manifest line '{}'", line),
+            }
+        }
+        res
+    }
+
+    /// Reads manifest of the directory (empty when the directory has none)
+    pub fn load(dir: &Path) -> io::Result<Self> {
+        match fs::read_to_string(Self::path(dir)) {
+            Ok(data) => Ok(Self::parse(&data)),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(err) => Err(err),
+        }
+    }
+
+    pub fn presented(&self, backing: &str) -> Option<&str> {
+        self.presented.get(backing).map(String::as_str)
+    }
+
+    pub fn backing(&self, presented: &str) -> Option<&str> {
+        self.backing.get(presented).map(String::as_str)
+    }
+}
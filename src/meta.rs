@@ -0,0 +1,117 @@
+//! Metadata of compressed files stored in a single extended attribute
+//!
+//! The value is a versioned binary struct (all numbers are big-endian):
+//!
+//! | field        | size | note                                 |
+//! |--------------|------|--------------------------------------|
+//! | version      | 1    | currently 1                          |
+//! | codec        | 1    | 0 - stored uncompressed, 1 - zstd    |
+//! | level        | 4    | compression level (0 - default)      |
+//! | real_size    | 8    | size of decompressed data            |
+//!
+//! Files created by older versions store only `user.real_size`
+//! which is read when the new attribute is missing. It is still written
+//! next to the new attribute, so older versions (and other tools) know the size
+//! and data dir can be mounted by them again.
+
+use std::{fs::File, io};
+
+use xattr::FileExt;
+
+use crate::{errors::is_unsupported, xattrs};
+
+pub const META_XATTR: &str = "user.fuse_zstd.meta";
+/// Separate attribute with decompressed size used before [`META_XATTR`]
+pub const LEGACY_REAL_SIZE_XATTR: &str = "user.real_size";
+
+pub const VERSION: u8 = 1;
+/// Data are stored as they are (`--no-compress`)
+pub const CODEC_RAW: u8 = 0;
+pub const CODEC_ZSTD: u8 = 1;
+
+const SIZE: usize = 14;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Meta {
+    pub codec: u8,
+    pub level: i32,
+    pub real_size: u64,
+}
+
+impl Meta {
+    pub fn new(level: i32, real_size: u64) -> Self {
+        Self {
+            codec: CODEC_ZSTD,
+            level,
+            real_size,
+        }
+    }
+
+    /// Metadata of a file stored without compression
+    pub fn raw(real_size: u64) -> Self {
+        Self {
+            codec: CODEC_RAW,
+            ..Self::new(0, real_size)
+        }
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut res = Vec::with_capacity(SIZE);
+        res.push(VERSION);
+        res.push(self.codec);
+        res.extend(self.level.to_be_bytes());
+        res.extend(self.real_size.to_be_bytes());
+        res
+    }
+
+    pub fn decode(data: &[u8]) -> io::Result<Self> {
+        let invalid = |msg: &str| io::Error::new(io::ErrorKind::InvalidData, msg.to_string());
+        if data.len() < SIZE {
+            return Err(invalid("Metadata too short"));
+        }
+        if data[0] != VERSION {
+            return Err(invalid(&format!(
+                "Unsupported metadata version {}",
+                data[0]
+            )));
+        }
+        Ok(Self {
+            codec: data[1],
+            level: i32::from_be_bytes(data[2..6].try_into().unwrap()),
+            real_size: u64::from_be_bytes(data[6..14].try_into().unwrap()),
+        })
+    }
+
+    /// Reads metadata of the file (falls back to older attributes)
+    ///
+    /// Files on filesystems without extended attributes have no metadata
+    pub fn read(file: &File) -> io::Result<Option<Self>> {
+        let meta = match file.get_xattr(META_XATTR) {
+            Ok(Some(data)) => Some(Self::decode(&data)?),
+            Ok(None) => None,
+            Err(err) if is_unsupported(&err) => return Ok(None),
+            Err(err) => return Err(err),
+        };
+        let legacy = file
+            .get_xattr(LEGACY_REAL_SIZE_XATTR)?
+            .and_then(|e| xattrs::parse_number(&e))
+            .map(|real_size| Self::new(0, real_size));
+        Ok(match (meta, legacy) {
+            // file was stored by an older version which kept the new attribute
+            (Some(meta), Some(legacy)) if meta.real_size != legacy.real_size => Some(legacy),
+            (Some(meta), _) => Some(meta),
+            (None, legacy) => legacy,
+        })
+    }
+
+    /// Stores metadata together with the attribute used by older versions
+    pub fn write(&self, file: &File) -> io::Result<()> {
+        file.set_xattr(META_XATTR, &self.encode())?;
+        file.set_xattr(LEGACY_REAL_SIZE_XATTR, &self.real_size.to_be_bytes())
+    }
+}
+
+/// File contains uncompressed data
+pub fn is_raw(file: &File) -> io::Result<bool> {
+    Ok(Meta::read(file)?.is_some_and(|e| e.codec == CODEC_RAW))
+}
@@ -0,0 +1,127 @@
+//! Migration of metadata of compressed files between schema versions
+//!
+//! Version 0 stores only the decompressed size in `user.real_size`,
+//! newer versions store [`Meta`] in a single attribute (and keep `user.real_size`).
+//! Compressed data are not touched and files which already have
+//! the target version are skipped, so the migration can be run again.
+
+use std::{fs::File, io, path::Path};
+
+use log::{debug, warn};
+use xattr::FileExt;
+
+use crate::{
+    cdc, import,
+    meta::{Meta, CODEC_ZSTD, LEGACY_REAL_SIZE_XATTR, META_XATTR, VERSION},
+};
+
+/// Only `user.real_size` is stored
+pub const LEGACY_VERSION: u8 = 0;
+
+#[derive(Debug, Default)]
+pub struct Summary {
+    pub migrated: usize,
+    pub up_to_date: usize,
+    pub skipped: usize,
+}
+
+/// Version of the metadata of the file (`None` when it has no metadata)
+pub fn detect(file: &File) -> io::Result<Option<u8>> {
+    if let Some(data) = file.get_xattr(META_XATTR)? {
+        return data
+            .first()
+            .copied()
+            .map(Some)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "Metadata too short"));
+    }
+    Ok(file
+        .get_xattr(LEGACY_REAL_SIZE_XATTR)?
+        .map(|_| LEGACY_VERSION))
+}
+
+fn write_legacy(file: &File, meta: &Meta) -> io::Result<()> {
+    file.set_xattr(LEGACY_REAL_SIZE_XATTR, &meta.real_size.to_be_bytes())?;
+    file.remove_xattr(META_XATTR)
+}
+
+/// Rewrites metadata of all compressed files in data dir to version `to`
+///
+/// Only files with version `from` are migrated when it is set
+pub fn migrate(
+    data_dir: &Path,
+    cache_path: &Path,
+    from: Option<u8>,
+    to: u8,
+) -> io::Result<Summary> {
+    if to > VERSION {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "Unsupported metadata version {} (supported {}..={})",
+                to, LEGACY_VERSION, VERSION
+            ),
+        ));
+    }
+    let mut paths = vec![];
+    import::walk(data_dir, cache_path, &mut paths)?;
+
+    let mut res = Summary::default();
+    for path in paths {
+        if path.starts_with(data_dir.join(cdc::CHUNKS_DIR))
+            || path
+                .extension()
+                .is_none_or(|e| e != "zst" && e != cdc::EXTENSION)
+            || !path.is_file()
+        {
+            continue;
+        }
+        let file = File::open(&path)?;
+        let Some(version) = detect(&file)? else {
+            continue;
+        };
+        if version == to {
+            res.up_to_date += 1;
+            continue;
+        }
+        if from.is_some_and(|e| e != version) {
+            debug!(
+                "Skipping '{}' (metadata version {})",
+                path.display(),
+                version
+            );
+            res.skipped += 1;
+            continue;
+        }
+        let meta = match Meta::read(&file) {
+            Ok(Some(meta)) => meta,
+            Ok(None) => continue,
+            Err(err) => {
+                warn!("Skipping '{}' (err={})", path.display(), err);
+                res.skipped += 1;
+                continue;
+            }
+        };
+        if to == LEGACY_VERSION {
+            if meta.codec != CODEC_ZSTD {
+                warn!(
+                    "Skipping '{}' (uncompressed data can't be described by metadata version {})",
+                    path.display(),
+                    to
+                );
+                res.skipped += 1;
+                continue;
+            }
+            write_legacy(&file, &meta)?;
+        } else {
+            meta.write(&file)?;
+        }
+        debug!(
+            "Metadata of '{}' migrated (from={}, to={})",
+            path.display(),
+            version,
+            to
+        );
+        res.migrated += 1;
+    }
+    Ok(res)
+}
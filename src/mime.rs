@@ -0,0 +1,37 @@
+//! Detection of already compressed data
+//!
+//! The type is detected from magic bytes by `infer` crate. Data of unknown type
+//! are checked by compressing a sample of them, compressing them again wastes CPU
+//! and saves almost no space.
+
+/// Number of bytes at the beginning of the file which are inspected
+pub const SNIFF_SIZE: usize = 8192;
+
+/// Number of bytes which are compressed to check whether the data are compressible
+pub const SAMPLE_SIZE: usize = 128 * 1024;
+
+/// Detects MIME type of the data
+pub fn detect(head: &[u8]) -> Option<&'static str> {
+    infer::get(head).map(|e| e.mime_type())
+}
+
+/// Compressing the sample with the fastest level saves less than 2 %
+pub fn incompressible(sample: &[u8]) -> bool {
+    if sample.is_empty() {
+        return false;
+    }
+    zstd::bulk::compress(sample, 1)
+        .map(|e| e.len() >= sample.len() - sample.len() / 50)
+        .unwrap_or(false)
+}
+
+/// Pattern is either a full type or `type/*`
+pub fn matches(pattern: &str, mime: &str) -> bool {
+    match pattern.strip_suffix("/*") {
+        Some(prefix) => mime
+            .split_once('/')
+            .map(|(e, _)| e == prefix || prefix == "*")
+            .unwrap_or(false),
+        None => pattern == mime,
+    }
+}
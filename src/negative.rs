@@ -0,0 +1,57 @@
+//! Names which were recently looked up and not found
+//!
+//! Lookups of missing names (e.g. searching `$PATH` or include directories)
+//! would read the whole directory each time. Missing names are remembered
+//! for a short time and forgotten when an entry with such name is created.
+
+use std::{
+    collections::HashMap,
+    ffi::{OsStr, OsString},
+    time::{Duration, Instant},
+};
+
+use crate::Inode;
+
+/// Max number of remembered names (all are dropped when reached)
+const MAX_ENTRIES: usize = 4096;
+
+#[derive(Debug)]
+pub struct NegativeCache {
+    ttl: Duration,
+    entries: HashMap<(Inode, OsString), Instant>,
+}
+
+impl NegativeCache {
+    pub fn new(ttl: Duration) -> Self {
+        Self {
+            ttl,
+            entries: HashMap::new(),
+        }
+    }
+
+    /// Name in the parent directory is known to be missing
+    pub fn contains(&mut self, parent: Inode, name: &OsStr) -> bool {
+        let key = (parent, name.to_os_string());
+        match self.entries.get(&key) {
+            Some(added) if added.elapsed() < self.ttl => true,
+            Some(_) => {
+                self.entries.remove(&key);
+                false
+            }
+            None => false,
+        }
+    }
+
+    pub fn insert(&mut self, parent: Inode, name: &OsStr) {
+        if self.entries.len() >= MAX_ENTRIES {
+            self.entries.clear();
+        }
+        self.entries
+            .insert((parent, name.to_os_string()), Instant::now());
+    }
+
+    /// Entry with the name is created
+    pub fn remove(&mut self, parent: Inode, name: &OsStr) {
+        self.entries.remove(&(parent, name.to_os_string()));
+    }
+}
@@ -0,0 +1,79 @@
+//! Advanced zstd compression parameters which can be set from command line
+
+use std::io;
+
+use zstd::zstd_safe::{self, CParameter, Strategy};
+
+fn parse_strategy(value: &str) -> Option<Strategy> {
+    Some(match value.to_lowercase().as_str() {
+        "1" | "fast" => Strategy::ZSTD_fast,
+        "2" | "dfast" => Strategy::ZSTD_dfast,
+        "3" | "greedy" => Strategy::ZSTD_greedy,
+        "4" | "lazy" => Strategy::ZSTD_lazy,
+        "5" | "lazy2" => Strategy::ZSTD_lazy2,
+        "6" | "btlazy2" => Strategy::ZSTD_btlazy2,
+        "7" | "btopt" => Strategy::ZSTD_btopt,
+        "8" | "btultra" => Strategy::ZSTD_btultra,
+        "9" | "btultra2" => Strategy::ZSTD_btultra2,
+        _ => return None,
+    })
+}
+
+/// Parses `name=value` parameter
+///
+/// Returns `None` when the name of the parameter is not known
+pub fn parse(spec: &str) -> io::Result<Option<CParameter>> {
+    let invalid = || {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("Wrong zstd parameter '{}'", spec),
+        )
+    };
+
+    let (name, value) = spec.split_once('=').ok_or_else(invalid)?;
+    let number = || value.trim().parse::<u32>().map_err(|_| invalid());
+    let param = match name.trim().to_lowercase().as_str() {
+        "windowlog" => CParameter::WindowLog(number()?),
+        "hashlog" => CParameter::HashLog(number()?),
+        "chainlog" => CParameter::ChainLog(number()?),
+        "searchlog" => CParameter::SearchLog(number()?),
+        "minmatch" => CParameter::MinMatch(number()?),
+        "targetlength" => CParameter::TargetLength(number()?),
+        "strategy" => CParameter::Strategy(parse_strategy(value.trim()).ok_or_else(invalid)?),
+        "long" | "enablelongdistancematching" => {
+            CParameter::EnableLongDistanceMatching(number()? != 0)
+        }
+        "ldmhashlog" => CParameter::LdmHashLog(number()?),
+        "ldmminmatch" => CParameter::LdmMinMatch(number()?),
+        "ldmbucketsizelog" => CParameter::LdmBucketSizeLog(number()?),
+        "ldmhashratelog" => CParameter::LdmHashRateLog(number()?),
+        _ => return Ok(None),
+    };
+
+    // Let zstd check the range of the value
+    zstd_safe::CCtx::create()
+        .set_parameter(param)
+        .map_err(|code| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "Wrong zstd parameter '{}' ({})",
+                    spec,
+                    zstd_safe::get_error_name(code)
+                ),
+            )
+        })?;
+
+    Ok(Some(param))
+}
+
+/// Sets parameters on the encoder
+pub fn apply<W>(encoder: &mut zstd::stream::Encoder<'_, W>, params: &[CParameter]) -> io::Result<()>
+where
+    W: io::Write,
+{
+    for param in params {
+        encoder.set_parameter(*param)?;
+    }
+    Ok(())
+}
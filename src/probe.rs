@@ -0,0 +1,102 @@
+//! Checks of features of the filesystem where data dir is stored
+//!
+//! Compressed files need extended attributes to store metadata, atomic rename
+//! to replace files and sparse files to keep tempfiles of truncated files small.
+//! Missing features cause confusing errors later so they are checked at startup.
+//! The same applies to `user_allow_other` in fuse configuration (`--allow-other`).
+
+use std::{
+    fs::{self, File},
+    io,
+    os::unix::fs::MetadataExt,
+    path::Path,
+};
+
+use log::debug;
+use xattr::FileExt;
+
+const PROBE_XATTR: &str = "user.fuse_zstd.probe";
+/// Name of the missing feature which `--network-backing` can work without
+pub const XATTRS_UNSUPPORTED: &str = "xattrs unsupported";
+/// Configuration of fuse which allows non-root users to use `allow_other`
+pub const FUSE_CONF: &str = "/etc/fuse.conf";
+/// Size of the sparse file which is created
+const SPARSE_SIZE: u64 = 16 * 1024 * 1024;
+
+type Check = fn(&Path) -> io::Result<()>;
+
+fn xattrs(dir: &Path) -> io::Result<()> {
+    let file = File::create(dir.join("xattr"))?;
+    file.set_xattr(PROBE_XATTR, b"1")?;
+    if file.get_xattr(PROBE_XATTR)?.as_deref() != Some(b"1") {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "attribute value differs",
+        ));
+    }
+    Ok(())
+}
+
+fn rename(dir: &Path) -> io::Result<()> {
+    fs::write(dir.join("source"), b"source")?;
+    fs::write(dir.join("target"), b"target")?;
+    // target is replaced
+    fs::rename(dir.join("source"), dir.join("target"))?;
+    if dir.join("source").exists() || fs::read(dir.join("target"))? != b"source" {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "target was not replaced",
+        ));
+    }
+    Ok(())
+}
+
+fn sparse(dir: &Path) -> io::Result<()> {
+    let file = File::create(dir.join("sparse"))?;
+    file.set_len(SPARSE_SIZE)?;
+    // st_blocks are in 512 byte units
+    if file.metadata()?.blocks() * 512 >= SPARSE_SIZE {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "holes are allocated",
+        ));
+    }
+    Ok(())
+}
+
+/// Returns features which are missing in data dir
+pub fn missing(data_dir: &Path) -> io::Result<Vec<&'static str>> {
+    let dir = tempfile::Builder::new()
+        .prefix(".fuse-zstd-probe")
+        .tempdir_in(data_dir)?;
+    let checks: [(&str, Check); 3] = [
+        (XATTRS_UNSUPPORTED, xattrs),
+        ("atomic rename unsupported", rename),
+        ("sparse files unsupported", sparse),
+    ];
+    let mut res = vec![];
+    for (name, check) in checks {
+        if let Err(err) = check(dir.path()) {
+            debug!(
+                "Probe '{}' of '{}' failed: {}",
+                name,
+                data_dir.display(),
+                err
+            );
+            res.push(name);
+        }
+    }
+    Ok(res)
+}
+
+/// Whether `user_allow_other` is enabled in fuse configuration
+pub fn user_allow_other(conf: &Path) -> io::Result<bool> {
+    match fs::read_to_string(conf) {
+        Ok(data) => Ok(data
+            .lines()
+            .filter_map(|e| e.split('#').next())
+            .any(|e| e.trim() == "user_allow_other")),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(false),
+        Err(err) => Err(err),
+    }
+}
@@ -0,0 +1,82 @@
+//! Timing of hot paths (`--profile`)
+//!
+//! Durations of operations are counted in buckets by powers of two of microseconds,
+//! so only a fixed amount of memory is used regardless of the number of calls.
+//! The summary is logged when the filesystem is unmounted. Decompression and
+//! compression are timed separately from the operations which run them (e.g. `open`
+//! and `flush`), so the share of codec in the time of an operation can be seen.
+
+use std::{collections::BTreeMap, time::Duration};
+
+const BUCKETS: usize = 40;
+
+#[derive(Debug, Clone)]
+struct Timing {
+    calls: u64,
+    total: Duration,
+    max: Duration,
+    /// Bucket `i` counts durations below `2^i` microseconds
+    buckets: [u64; BUCKETS],
+}
+
+impl Default for Timing {
+    fn default() -> Self {
+        Self {
+            calls: 0,
+            total: Duration::ZERO,
+            max: Duration::ZERO,
+            buckets: [0; BUCKETS],
+        }
+    }
+}
+
+impl Timing {
+    /// Upper bound of the duration of `q` part of calls (in microseconds)
+    fn percentile(&self, q: f64) -> u64 {
+        let target = ((self.calls as f64 * q).ceil() as u64).max(1);
+        let mut seen = 0;
+        for (i, count) in self.buckets.iter().enumerate() {
+            seen += count;
+            if seen >= target {
+                return 1 << i;
+            }
+        }
+        self.max.as_micros() as u64
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Profile {
+    timings: BTreeMap<&'static str, Timing>,
+}
+
+impl Profile {
+    pub fn record(&mut self, op: &'static str, elapsed: Duration) {
+        let timing = self.timings.entry(op).or_default();
+        timing.calls += 1;
+        timing.total += elapsed;
+        timing.max = timing.max.max(elapsed);
+        let micros = elapsed.as_micros() as u64;
+        let bucket = (u64::BITS - micros.leading_zeros()) as usize;
+        timing.buckets[bucket.min(BUCKETS - 1)] += 1;
+    }
+
+    /// One line per operation in `Profile (op=..., calls=..., ...)` format
+    pub fn summary(&self) -> Vec<String> {
+        self.timings
+            .iter()
+            .map(|(op, timing)| {
+                format!(
+                    "Profile (op={}, calls={}, total_us={}, mean_us={}, p50_us={}, p99_us={}, max_us={})",
+                    op,
+                    timing.calls,
+                    timing.total.as_micros(),
+                    timing.total.as_micros() / u128::from(timing.calls),
+                    timing.percentile(0.5),
+                    timing.percentile(0.99),
+                    timing.max.as_micros()
+                )
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,53 @@
+//! Removal of deleted files in a background thread (`--async-unlink`)
+//!
+//! Deleted file is only moved out of its directory so its name disappears
+//! right away. The file is removed later by the background thread.
+
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+    process,
+    sync::mpsc,
+    thread,
+};
+
+use log::{debug, warn};
+
+pub struct Reclaimer {
+    sender: mpsc::Sender<PathBuf>,
+    next: u64,
+}
+
+impl Reclaimer {
+    pub fn new() -> Self {
+        let (sender, receiver) = mpsc::channel::<PathBuf>();
+        thread::spawn(move || {
+            for path in receiver {
+                match fs::remove_file(&path) {
+                    Ok(()) => debug!("Deleted file '{}' reclaimed", path.display()),
+                    Err(err) => warn!("Failed to reclaim '{}' (err={})", path.display(), err),
+                }
+            }
+        });
+        Self { sender, next: 0 }
+    }
+
+    /// Moves the file to `dir` and removes it in background
+    ///
+    /// Returns `false` when the file can't be moved (`dir` is on another device)
+    pub fn delete(&mut self, path: &Path, dir: &Path) -> io::Result<bool> {
+        fs::create_dir_all(dir)?;
+        let target = dir.join(format!("{}-{:016x}", process::id(), self.next));
+        self.next += 1;
+        match fs::rename(path, &target) {
+            Ok(()) => {}
+            Err(err) if err.raw_os_error() == Some(libc::EXDEV) => return Ok(false),
+            Err(err) => return Err(err),
+        }
+        if let Err(mpsc::SendError(target)) = self.sender.send(target) {
+            // thread is not running
+            fs::remove_file(target)?;
+        }
+        Ok(true)
+    }
+}
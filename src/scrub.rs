@@ -0,0 +1,240 @@
+//! Verification of compressed files in a background thread (`--scrub`)
+//!
+//! All compressed files in data dir are decompressed (checksums of zstd frames
+//! are verified by the decoder) and the size of decompressed data is compared
+//! with the size stored in metadata. Files are read at a limited rate
+//! (`--scrub-rate`) so the mount is not slowed down. The pass is repeated
+//! once per [`PERIOD`].
+
+use std::{
+    fs::{self, File},
+    io::{self, Read},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use log::{debug, error, info};
+
+use crate::{cdc, dict::Dictionaries, file::SourceVersion, meta};
+
+/// Pause between the starts of passes
+pub const PERIOD: Duration = Duration::from_secs(24 * 60 * 60);
+/// Rate is kept within windows of this length
+const BUDGET_WINDOW: Duration = Duration::from_secs(60);
+
+/// Files checked since the counters were taken last time
+#[derive(Debug, Default)]
+pub struct Progress {
+    checked: AtomicU64,
+    corrupted: AtomicU64,
+}
+
+impl Progress {
+    /// Returns checked and corrupted files and resets the counters
+    pub fn take(&self) -> (u64, u64) {
+        (
+            self.checked.swap(0, Ordering::Relaxed),
+            self.corrupted.swap(0, Ordering::Relaxed),
+        )
+    }
+}
+
+/// Amount of data read since `started`, limited by `rate` (bytes per second)
+struct Budget {
+    rate: Option<u64>,
+    started: Instant,
+    read: u64,
+}
+
+impl Budget {
+    fn consume(&mut self, size: usize) {
+        self.read += size as u64;
+        let Some(rate) = self.rate else {
+            return;
+        };
+        let expected = Duration::from_secs_f64(self.read as f64 / rate as f64);
+        if let Some(ahead) = expected.checked_sub(self.started.elapsed()) {
+            thread::sleep(ahead);
+        }
+        // idle time (e.g. between passes) doesn't allow to exceed the rate later
+        if self.started.elapsed() > BUDGET_WINDOW {
+            self.started = Instant::now();
+            self.read = 0;
+        }
+    }
+}
+
+/// Reader which keeps the rate of read data within the budget
+struct Throttle<'a, R> {
+    inner: R,
+    budget: &'a mut Budget,
+}
+
+impl<R: Read> Read for Throttle<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let size = self.inner.read(buf)?;
+        self.budget.consume(size);
+        Ok(size)
+    }
+}
+
+pub struct Scrubber {
+    data_dir: PathBuf,
+    /// Cache dir (it is not scrubbed)
+    skip: PathBuf,
+    dicts: Dictionaries,
+    rate: Option<u64>,
+    progress: Arc<Progress>,
+}
+
+impl Scrubber {
+    /// Starts the scrubbing thread, `rate` is in bytes per second
+    pub fn spawn(
+        data_dir: &Path,
+        skip: &Path,
+        dicts: Dictionaries,
+        rate: Option<u64>,
+    ) -> Arc<Progress> {
+        let progress = Arc::new(Progress::default());
+        let scrubber = Self {
+            data_dir: data_dir.to_path_buf(),
+            skip: skip.to_path_buf(),
+            dicts,
+            rate,
+            progress: progress.clone(),
+        };
+        thread::spawn(move || loop {
+            let started = Instant::now();
+            let (checked, corrupted) = scrubber.pass();
+            info!(
+                "Scrub finished (checked={}, corrupted={}, elapsed_s={})",
+                checked,
+                corrupted,
+                started.elapsed().as_secs()
+            );
+            thread::sleep(PERIOD.saturating_sub(started.elapsed()));
+        });
+        progress
+    }
+
+    /// Checks all files in data dir, returns checked and corrupted files
+    fn pass(&self) -> (u64, u64) {
+        let (mut checked, mut corrupted) = (0, 0);
+        let mut budget = Budget {
+            rate: self.rate,
+            started: Instant::now(),
+            read: 0,
+        };
+        let mut dirs = vec![self.data_dir.clone()];
+        while let Some(dir) = dirs.pop() {
+            // files may be removed during the pass
+            let Ok(entries) = fs::read_dir(&dir) else {
+                continue;
+            };
+            for entry in entries.flatten() {
+                let path = entry.path();
+                let Ok(file_type) = entry.file_type() else {
+                    continue;
+                };
+                if path == self.skip || path == self.data_dir.join(cdc::CHUNKS_DIR) {
+                    continue;
+                } else if file_type.is_dir() {
+                    dirs.push(path);
+                    continue;
+                } else if !file_type.is_file()
+                    || path
+                        .extension()
+                        .is_none_or(|e| e != "zst" && e != cdc::EXTENSION)
+                {
+                    continue;
+                }
+                match self.check(&path, &mut budget) {
+                    Ok(true) => {}
+                    Ok(false) => {
+                        debug!("Scrub skipped '{}' (changed)", path.display());
+                        continue;
+                    }
+                    Err(err) => {
+                        error!(
+                            "Scrub found corrupted file '{}' (err={})",
+                            path.display(),
+                            err
+                        );
+                        corrupted += 1;
+                        self.progress.corrupted.fetch_add(1, Ordering::Relaxed);
+                    }
+                }
+                checked += 1;
+                self.progress.checked.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+        (checked, corrupted)
+    }
+
+    /// Decompresses the file, `false` is returned when it was changed meanwhile
+    fn check(&self, path: &Path, budget: &mut Budget) -> io::Result<bool> {
+        let file = File::open(path)?;
+        let version = SourceVersion::new(&file.metadata()?);
+        let reader = Throttle {
+            inner: file.try_clone()?,
+            budget,
+        };
+        let result = self.decompressed_size(&file, reader);
+        // file was overwritten in place
+        if SourceVersion::new(&file.metadata()?) != version {
+            return Ok(false);
+        }
+        let (size, expected) = result?;
+        match expected {
+            Some(expected) if expected != size => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("decompressed size {} differs from {}", size, expected),
+            )),
+            _ => Ok(true),
+        }
+    }
+
+    /// Returns the size of decompressed data and the size stored in metadata
+    fn decompressed_size<R: Read>(&self, file: &File, reader: R) -> io::Result<(u64, Option<u64>)> {
+        let meta = meta::Meta::read(file)?;
+        let expected = meta.map(|e| e.real_size);
+        if meta.is_some_and(|e| e.codec == meta::CODEC_RAW) {
+            return Ok((file.metadata()?.len(), expected));
+        }
+        if let Some(chunks) = cdc::read_chunks(file)? {
+            let mut counter = Counter(0);
+            cdc::decompress_chunks(&self.data_dir.join(cdc::CHUNKS_DIR), &chunks, &mut counter)?;
+            return Ok((counter.0, expected));
+        }
+        // file created outside of fuse-zstd (e.g. by touch) has no frame at all
+        if file.metadata()?.len() == 0 {
+            return Ok((0, expected));
+        }
+        let dict = self.dicts.for_file(file)?;
+        let mut decoder = zstd::stream::Decoder::with_dictionary(
+            io::BufReader::new(reader),
+            dict.unwrap_or_default(),
+        )?;
+        let size = io::copy(&mut decoder, &mut io::sink())?;
+        Ok((size, expected))
+    }
+}
+
+/// Counts written bytes
+struct Counter(u64);
+
+impl io::Write for Counter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0 += buf.len() as u64;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
@@ -0,0 +1,142 @@
+//! Smoke test which round-trips random data through a mounted filesystem
+//!
+//! Files are written and read back via the mount point and the compressed
+//! files in data dir are checked as well.
+
+use std::{
+    fs::{self, OpenOptions},
+    io::{self, Write},
+    path::Path,
+};
+
+use log::info;
+
+/// Sizes of written files (the last one is larger than zstd window of default level)
+const SIZES: [usize; 6] = [0, 1, 4096, 128 * 1024 + 1, 1024 * 1024, 9 * 1024 * 1024];
+
+/// Pseudo-random data which compress only partially
+fn random_data(size: usize, seed: u64) -> Vec<u8> {
+    let mut state = seed.wrapping_mul(0x9e37_79b9_7f4a_7c15) | 1;
+    (0..size)
+        .map(|i| {
+            // every other block repeats to be compressible
+            if (i / 512) % 2 == 1 {
+                return (i % 251) as u8;
+            }
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state as u8
+        })
+        .collect()
+}
+
+fn mismatch(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+fn check_content(mount_point: &Path, name: &str, expected: &[u8]) -> io::Result<()> {
+    let data = fs::read(mount_point.join(name))?;
+    if data != expected {
+        return Err(mismatch(format!(
+            "'{}' differs (read {} bytes, expected {} bytes)",
+            name,
+            data.len(),
+            expected.len()
+        )));
+    }
+    Ok(())
+}
+
+fn check_compressed(data_dir: &Path, name: &str, expected: &[u8]) -> io::Result<()> {
+    let data = zstd::stream::decode_all(fs::File::open(data_dir.join(format!("{}.zst", name)))?)?;
+    if data != expected {
+        return Err(mismatch(format!("compressed '{}.zst' differs", name)));
+    }
+    Ok(())
+}
+
+fn check_missing(path: &Path) -> io::Result<()> {
+    if fs::symlink_metadata(path).is_ok() {
+        return Err(mismatch(format!("'{}' still exists", path.display())));
+    }
+    Ok(())
+}
+
+fn step<F>(name: &str, steps: &mut usize, f: F) -> io::Result<()>
+where
+    F: FnOnce() -> io::Result<()>,
+{
+    f().map_err(|e| io::Error::new(e.kind(), format!("step '{}' failed: {}", name, e)))?;
+    info!("Selftest step '{}' passed", name);
+    *steps += 1;
+    Ok(())
+}
+
+/// Runs all steps, returns the number of passed steps
+pub fn run(data_dir: &Path, mount_point: &Path) -> io::Result<usize> {
+    let mut steps = 0;
+
+    for (i, size) in SIZES.iter().enumerate() {
+        let name = format!("file-{}", size);
+        let data = random_data(*size, i as u64);
+        step(&format!("write {} bytes", size), &mut steps, || {
+            fs::write(mount_point.join(&name), &data)?;
+            check_content(mount_point, &name, &data)?;
+            check_compressed(data_dir, &name, &data)
+        })?;
+    }
+
+    let mut data = random_data(100_000, 42);
+    step("append", &mut steps, || {
+        fs::write(mount_point.join("append"), &data)?;
+        let more = random_data(50_000, 43);
+        OpenOptions::new()
+            .append(true)
+            .open(mount_point.join("append"))?
+            .write_all(&more)?;
+        data.extend(more);
+        check_content(mount_point, "append", &data)
+    })?;
+
+    step("truncate", &mut steps, || {
+        OpenOptions::new()
+            .write(true)
+            .open(mount_point.join("append"))?
+            .set_len(1000)?;
+        check_content(mount_point, "append", &data[..1000])?;
+        OpenOptions::new()
+            .write(true)
+            .open(mount_point.join("append"))?
+            .set_len(2000)?;
+        let mut expected = data[..1000].to_vec();
+        expected.resize(2000, 0);
+        check_content(mount_point, "append", &expected)
+    })?;
+
+    step("rename", &mut steps, || {
+        let data = fs::read(mount_point.join("file-4096"))?;
+        fs::rename(mount_point.join("file-4096"), mount_point.join("renamed"))?;
+        check_missing(&mount_point.join("file-4096"))?;
+        check_content(mount_point, "renamed", &data)
+    })?;
+
+    step("mkdir", &mut steps, || {
+        let data = random_data(10_000, 44);
+        fs::create_dir(mount_point.join("dir"))?;
+        fs::write(mount_point.join("dir/nested"), &data)?;
+        fs::rename(mount_point.join("dir"), mount_point.join("moved"))?;
+        check_content(mount_point, "moved/nested", &data)?;
+        check_compressed(data_dir, "moved/nested", &data)
+    })?;
+
+    step("unlink", &mut steps, || {
+        fs::remove_file(mount_point.join("moved/nested"))?;
+        check_missing(&mount_point.join("moved/nested"))?;
+        check_missing(&data_dir.join("moved/nested.zst"))?;
+        fs::remove_dir(mount_point.join("moved"))?;
+        check_missing(&mount_point.join("moved"))
+    })?;
+
+    Ok(steps)
+}
@@ -0,0 +1,188 @@
+//! Running totals of operations persisted in data dir
+//!
+//! Counters are stored in `.fuse-zstd-stats` as `name=value` lines
+//! so they survive remounts and can be read without walking the data dir.
+//!
+//! Compressed files are also counted by the class of their size
+//! (`size_<class>_files`, `size_<class>_bytes` and `size_<class>_compressed`)
+//! so the compression ratio of e.g. tiny files can be compared with large ones.
+//!
+//! `cache_bytes` is the size of inode cache on disk when it was checked last time
+//! (`--compact-cache-interval`), `cache_entries` is the number of its entries
+//! when the counters were stored.
+
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use log::warn;
+
+pub const STATS_NAME: &str = ".fuse-zstd-stats";
+
+/// Names and upper bounds (exclusive) of file size classes
+pub const SIZE_CLASSES: [(&str, u64); 4] = [
+    ("lt_4k", 4 * 1024),
+    ("4k_64k", 64 * 1024),
+    ("64k_1m", 1024 * 1024),
+    ("ge_1m", u64::MAX),
+];
+
+/// Compressed files of a size class
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct SizeClass {
+    pub files: u64,
+    /// Uncompressed bytes
+    pub bytes: u64,
+    pub compressed: u64,
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Stats {
+    /// Uncompressed bytes stored to data dir
+    pub bytes_compressed: u64,
+    /// Uncompressed bytes restored from data dir
+    pub bytes_decompressed: u64,
+    pub compressions: u64,
+    pub decompressions: u64,
+    pub bytes_read: u64,
+    pub bytes_written: u64,
+    pub reads: u64,
+    pub writes: u64,
+    /// Size of inode cache on disk (not a running total)
+    pub cache_bytes: u64,
+    /// Entries of inode cache (not a running total)
+    pub cache_entries: u64,
+    /// Files verified by scrubbing (`--scrub`)
+    pub scrub_checked: u64,
+    pub scrub_corrupted: u64,
+    /// Compressed files by [`SIZE_CLASSES`]
+    pub size_classes: [SizeClass; SIZE_CLASSES.len()],
+    /// Counters changed since they were stored
+    dirty: bool,
+}
+
+impl Stats {
+    fn counters_mut(&mut self) -> Vec<(String, &mut u64)> {
+        let mut res: Vec<(String, &mut u64)> = vec![
+            ("bytes_compressed".into(), &mut self.bytes_compressed),
+            ("bytes_decompressed".into(), &mut self.bytes_decompressed),
+            ("compressions".into(), &mut self.compressions),
+            ("decompressions".into(), &mut self.decompressions),
+            ("bytes_read".into(), &mut self.bytes_read),
+            ("bytes_written".into(), &mut self.bytes_written),
+            ("reads".into(), &mut self.reads),
+            ("writes".into(), &mut self.writes),
+            ("cache_bytes".into(), &mut self.cache_bytes),
+            ("cache_entries".into(), &mut self.cache_entries),
+            ("scrub_checked".into(), &mut self.scrub_checked),
+            ("scrub_corrupted".into(), &mut self.scrub_corrupted),
+        ];
+        for ((name, _), class) in SIZE_CLASSES.iter().zip(self.size_classes.iter_mut()) {
+            res.push((format!("size_{}_files", name), &mut class.files));
+            res.push((format!("size_{}_bytes", name), &mut class.bytes));
+            res.push((format!("size_{}_compressed", name), &mut class.compressed));
+        }
+        res
+    }
+
+    pub fn path(data_dir: &Path) -> PathBuf {
+        data_dir.join(STATS_NAME)
+    }
+
+    /// Loads counters stored by previous mounts
+    pub fn load(data_dir: &Path) -> io::Result<Self> {
+        let mut res = Self::default();
+        let content = match fs::read_to_string(Self::path(data_dir)) {
+            Ok(content) => content,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(res),
+            Err(err) => return Err(err),
+        };
+        for line in content.lines() {
+            let parsed = line
+                .split_once('=')
+                .and_then(|(name, value)| Some((name.trim(), value.trim().parse::<u64>().ok()?)));
+            let (name, value) = if let Some(parsed) = parsed {
+                parsed
+            } else {
+                warn!("Wrong line in stats file '{}', skipping", line);
+                continue;
+            };
+            if let Some((_, counter)) = res.counters_mut().into_iter().find(|(e, _)| *e == name) {
+                *counter = value;
+            }
+        }
+        Ok(res)
+    }
+
+    /// Stores counters when they changed
+    pub fn save(&mut self, data_dir: &Path) -> io::Result<()> {
+        if !self.dirty {
+            return Ok(());
+        }
+        let content: String = self
+            .counters_mut()
+            .into_iter()
+            .map(|(name, value)| format!("{}={}\n", name, value))
+            .collect();
+        let tmp_file = tempfile::NamedTempFile::new_in(data_dir)?;
+        fs::write(tmp_file.path(), content)?;
+        tmp_file.persist(Self::path(data_dir))?;
+        self.dirty = false;
+        Ok(())
+    }
+
+    pub fn compressed(&mut self, size: u64, compressed_size: u64) {
+        self.compressions += 1;
+        self.bytes_compressed += size;
+        let idx = SIZE_CLASSES
+            .iter()
+            .position(|(_, bound)| size < *bound)
+            .unwrap_or(SIZE_CLASSES.len() - 1);
+        let class = &mut self.size_classes[idx];
+        class.files += 1;
+        class.bytes += size;
+        class.compressed += compressed_size;
+        self.dirty = true;
+    }
+
+    pub fn decompressed(&mut self, size: u64) {
+        self.decompressions += 1;
+        self.bytes_decompressed += size;
+        self.dirty = true;
+    }
+
+    pub fn read(&mut self, size: u64) {
+        self.reads += 1;
+        self.bytes_read += size;
+        self.dirty = true;
+    }
+
+    pub fn cache_size(&mut self, size: u64) {
+        if self.cache_bytes != size {
+            self.cache_bytes = size;
+            self.dirty = true;
+        }
+    }
+
+    pub fn cache_len(&mut self, entries: u64) {
+        if self.cache_entries != entries {
+            self.cache_entries = entries;
+            self.dirty = true;
+        }
+    }
+
+    pub fn scrubbed(&mut self, checked: u64, corrupted: u64) {
+        if checked > 0 {
+            self.scrub_checked += checked;
+            self.scrub_corrupted += corrupted;
+            self.dirty = true;
+        }
+    }
+
+    pub fn written(&mut self, size: u64) {
+        self.writes += 1;
+        self.bytes_written += size;
+        self.dirty = true;
+    }
+}
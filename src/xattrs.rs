@@ -0,0 +1,88 @@
+use std::{ffi::OsStr, io};
+
+/// Extended attributes used by fuse-zstd itself
+/// these are not visible in mount point
+const INTERNAL: &[&str] = &[
+    "user.ino",
+    "user.ino_idx",
+    "user.real_size",
+    "user.fuse_zstd.meta",
+];
+
+/// Prefix of the values which were compressed
+const COMPRESSED_MARKER: &[u8] = b"\0fuse-zstd\0";
+/// Prefix of raw values which start with one of the markers
+const ESCAPED_MARKER: &[u8] = b"\0fuse-zstd\x01";
+
+/// Max size of xattr value on linux
+const XATTR_SIZE_MAX: usize = 65536;
+
+pub fn is_internal(name: &OsStr) -> bool {
+    INTERNAL.iter().any(|e| OsStr::new(e) == name)
+}
+
+/// Compresses the value if it is larger than threshold and compression helps
+///
+/// Fails with `E2BIG` when the stored value would be too large
+pub fn encode(value: &[u8], threshold: Option<usize>) -> io::Result<Vec<u8>> {
+    let res = match threshold {
+        Some(threshold) if value.len() > threshold => {
+            let compressed = zstd::bulk::compress(value, 0)?;
+            if compressed.len() + COMPRESSED_MARKER.len() < value.len() {
+                COMPRESSED_MARKER
+                    .iter()
+                    .copied()
+                    .chain(compressed)
+                    .collect()
+            } else {
+                escape(value)
+            }
+        }
+        _ => escape(value),
+    };
+    if res.len() > XATTR_SIZE_MAX {
+        return Err(io::Error::from_raw_os_error(libc::E2BIG));
+    }
+    Ok(res)
+}
+
+/// Raw value which looks like an encoded one is prefixed,
+/// so it isn't decoded when it is read
+fn escape(value: &[u8]) -> Vec<u8> {
+    if value.starts_with(COMPRESSED_MARKER) || value.starts_with(ESCAPED_MARKER) {
+        ESCAPED_MARKER.iter().chain(value).copied().collect()
+    } else {
+        value.to_vec()
+    }
+}
+
+/// Decompresses the value if it was compressed
+pub fn decode(value: Vec<u8>) -> io::Result<Vec<u8>> {
+    if let Some(raw) = value.strip_prefix(ESCAPED_MARKER) {
+        Ok(raw.to_vec())
+    } else if let Some(compressed) = value.strip_prefix(COMPRESSED_MARKER) {
+        zstd::bulk::decompress(compressed, XATTR_SIZE_MAX)
+    } else {
+        Ok(value)
+    }
+}
+
+/// Reads a number stored by fuse-zstd (`user.ino`, `user.real_size`, ...)
+///
+/// Values written by other tools as decimal strings are accepted as well,
+/// other 8 bytes are read as a big-endian number (digits and whitespace
+/// as a binary number would be larger than any real inode or size)
+pub fn parse_number(value: &[u8]) -> Option<u64> {
+    let decimal = value.iter().any(|e| e.is_ascii_digit())
+        && value
+            .iter()
+            .all(|e| e.is_ascii_digit() || e.is_ascii_whitespace());
+    if let (false, Ok(bytes)) = (decimal, value.try_into()) {
+        return Some(u64::from_be_bytes(bytes));
+    }
+    std::str::from_utf8(value)
+        .ok()?
+        .trim_matches(|e: char| e.is_whitespace() || e == '\0')
+        .parse()
+        .ok()
+}
@@ -301,3 +301,275 @@ fn rmdir(populated_mounted_fs: utils::FuseZstdProcess) {
         .failure();
     assert!(!dd.join("first/non-existing.txt.zst").exists());
 }
+
+#[rstest]
+fn root_inode(populated_mounted_fs: utils::FuseZstdProcess) {
+    let mp = populated_mounted_fs.mount_point();
+    Command::new("stat")
+        .args(["-c", "%i"])
+        .arg(&mp)
+        .assert()
+        .success()
+        .stdout("1\n");
+
+    Command::new("stat")
+        .args(["-c", "%i", ".."])
+        .current_dir(mp.join("first"))
+        .assert()
+        .success()
+        .stdout("1\n");
+
+    // dot entries of readdir
+    Command::new("ls")
+        .args(["-1ai", "--ignore=[!.]*"])
+        .arg(&mp)
+        .assert()
+        .success()
+        .stdout("1 .\n1 ..\n");
+
+    let output = Command::new("stat")
+        .args(["-c", "%i"])
+        .arg(mp.join("first"))
+        .output()
+        .unwrap();
+    let first_ino = String::from_utf8(output.stdout).unwrap();
+    Command::new("ls")
+        .args(["-1ai", "--ignore=[!.]*"])
+        .arg(mp.join("first"))
+        .assert()
+        .success()
+        .stdout(format!("{} .\n1 ..\n", first_ino.trim()));
+}
+
+#[rstest]
+fn bench() {
+    let corpus = tempfile::TempDir::new_in("/tmp/").unwrap();
+    fs::create_dir(corpus.path().join("dir")).unwrap();
+    let text: String = (0..20000).map(|e| format!("line {}\n", e % 100)).collect();
+    fs::write(corpus.path().join("plain.txt"), &text).unwrap();
+    fs::write(
+        corpus.path().join("dir/compressed.txt.zst"),
+        zstd::encode_all(text.as_bytes(), 3).unwrap(),
+    )
+    .unwrap();
+
+    let assert = Command::cargo_bin("fuse-zstd")
+        .unwrap()
+        .arg("bench")
+        .arg("--data-dir")
+        .arg(corpus.path())
+        .args(["--levels", "1,3,19"])
+        .assert()
+        .success();
+    let stdout = String::from_utf8(assert.get_output().stdout.clone()).unwrap();
+    let lines: Vec<Vec<&str>> = stdout
+        .lines()
+        .map(|e| e.split_whitespace().collect())
+        .collect();
+    assert_eq!(lines[0], vec!["level", "ratio", "MB/s"]);
+    assert_eq!(lines.len(), 4);
+    for (line, level) in lines[1..].iter().zip(["1", "3", "19"]) {
+        assert_eq!(line[0], level);
+        let ratio: f64 = line[1].parse().unwrap();
+        assert!((0.0..0.5).contains(&ratio));
+        assert!(line[2].parse::<f64>().unwrap() > 0.0);
+    }
+}
+
+#[rstest]
+fn selftest() {
+    let assert = Command::cargo_bin("fuse-zstd")
+        .unwrap()
+        .arg("selftest")
+        .assert()
+        .success();
+    let stdout = String::from_utf8(assert.get_output().stdout.clone()).unwrap();
+    assert!(stdout.starts_with("selftest passed"));
+
+    // temporary directories can't be created
+    let assert = Command::cargo_bin("fuse-zstd")
+        .unwrap()
+        .arg("selftest")
+        .env("TMPDIR", "/nonexistent/")
+        .assert()
+        .failure();
+    let stderr = String::from_utf8(assert.get_output().stderr.clone()).unwrap();
+    assert!(stderr.contains("selftest failed"));
+}
+
+#[rstest]
+#[case::too_high("23")]
+#[case::too_low("-200000")]
+#[case::not_number("high")]
+fn wrong_compression_level(#[case] level: &str) {
+    let range = zstd::compression_level_range();
+    let assert = Command::cargo_bin("fuse-zstd")
+        .unwrap()
+        .args(["--data-dir", "/tmp/", "--mount-point", "/tmp/"])
+        .arg(format!("--compression-level={}", level))
+        .assert()
+        .failure();
+    let stderr = String::from_utf8(assert.get_output().stderr.clone()).unwrap();
+    assert!(stderr.contains(&format!(
+        "Wrong compression level '{}' (supported {}..={}",
+        level,
+        range.start(),
+        range.end()
+    )));
+}
+
+#[rstest]
+fn allow_other_without_fuse_conf() {
+    let enabled = fs::read_to_string("/etc/fuse.conf")
+        .map(|e| e.lines().any(|e| e.trim() == "user_allow_other"))
+        .unwrap_or(false);
+    if enabled {
+        return;
+    }
+    // run as a regular user (root is allowed to use allow_other)
+    let binary = assert_cmd::cargo::cargo_bin("fuse-zstd");
+    let mut command = if unsafe { libc::geteuid() } == 0 {
+        let mut command = std::process::Command::new("setpriv");
+        command
+            .args(["--reuid=65534", "--regid=65534", "--clear-groups"])
+            .arg(binary);
+        command
+    } else {
+        std::process::Command::new(binary)
+    };
+    let Ok(output) = command
+        .args([
+            "--data-dir",
+            "/tmp/",
+            "--mount-point",
+            "/tmp/",
+            "--allow-other",
+        ])
+        .output()
+    else {
+        return;
+    };
+    assert!(!output.status.success());
+    let stderr = String::from_utf8(output.stderr.clone()).unwrap();
+    assert!(stderr.contains("requires 'user_allow_other' in /etc/fuse.conf"));
+}
+
+#[rstest]
+fn strict_missing_xattrs() {
+    // e.g. mounted FAT or exFAT filesystem
+    let Ok(data_dir) = std::env::var("FUSE_ZSTD_TEST_NO_XATTR_DIR") else {
+        return;
+    };
+    let mount_point = tempfile::tempdir().unwrap();
+    let assert = Command::cargo_bin("fuse-zstd")
+        .unwrap()
+        .args(["--data-dir", &data_dir, "--strict", "--mount-point"])
+        .arg(mount_point.path())
+        .assert()
+        .failure();
+    let stderr = String::from_utf8(assert.get_output().stderr.clone()).unwrap();
+    assert!(stderr.contains("lacks required features"));
+    assert!(stderr.contains("xattrs unsupported"));
+}
+
+#[rstest]
+fn compress_decompress() {
+    let text: String = (0..1000).map(|e| format!("line {}\n", e)).collect();
+    let mounted_fs = utils::FuseZstdProcess::with_data(false, &[], |dd| {
+        Command::cargo_bin("fuse-zstd")
+            .unwrap()
+            .arg("compress")
+            .arg("--data-dir")
+            .arg(dd)
+            .args(["--name", "dir/file.txt"])
+            .write_stdin(text.clone())
+            .assert()
+            .success();
+        assert!(xattr::get(dd.join("dir/file.txt.zst"), "user.ino")
+            .unwrap()
+            .is_some());
+    });
+    let mp = mounted_fs.mount_point();
+    let dd = mounted_fs.data_dir();
+    assert_eq!(fs::read_to_string(mp.join("dir/file.txt")).unwrap(), text);
+    assert_eq!(
+        fs::metadata(mp.join("dir/file.txt")).unwrap().len(),
+        text.len() as u64
+    );
+
+    fs::write(mp.join("written.txt"), b"WRITTEN").unwrap();
+    Command::cargo_bin("fuse-zstd")
+        .unwrap()
+        .arg("decompress")
+        .arg("--data-dir")
+        .arg(&dd)
+        .args(["--name", "written.txt"])
+        .assert()
+        .success()
+        .stdout("WRITTEN");
+
+    // names outside of data dir are refused
+    Command::cargo_bin("fuse-zstd")
+        .unwrap()
+        .arg("decompress")
+        .arg("--data-dir")
+        .arg(&dd)
+        .args(["--name", "../file.txt"])
+        .assert()
+        .failure();
+}
+
+#[test]
+fn migrate_metadata() {
+    let migrate = |dd: &std::path::Path, args: &[&str], stdout: &str| {
+        Command::cargo_bin("fuse-zstd")
+            .unwrap()
+            .arg("migrate")
+            .arg("--data-dir")
+            .arg(dd)
+            .args(args)
+            .assert()
+            .success()
+            .stdout(format!("{}\n", stdout));
+    };
+    let mounted_fs = utils::FuseZstdProcess::with_data(false, &[], |dd| {
+        // dataset created by older version
+        let path = dd.join("legacy.txt.zst");
+        fs::write(&path, zstd::encode_all(&b"LEGACY"[..], 0).unwrap()).unwrap();
+        xattr::set(&path, "user.real_size", &6u64.to_be_bytes()).unwrap();
+
+        migrate(dd, &[], "1 migrated, 0 up to date, 0 skipped");
+        assert!(xattr::get(&path, "user.fuse_zstd.meta").unwrap().is_some());
+        // older versions can still read the size
+        assert_eq!(
+            xattr::get(&path, "user.real_size").unwrap().unwrap(),
+            6u64.to_be_bytes()
+        );
+        migrate(dd, &[], "0 migrated, 1 up to date, 0 skipped");
+
+        // migrated back and forth
+        migrate(
+            dd,
+            &["--to-version", "0"],
+            "1 migrated, 0 up to date, 0 skipped",
+        );
+        assert!(xattr::get(&path, "user.fuse_zstd.meta").unwrap().is_none());
+        assert_eq!(
+            xattr::get(&path, "user.real_size").unwrap().unwrap(),
+            6u64.to_be_bytes()
+        );
+        migrate(
+            dd,
+            &["--from-version", "1"],
+            "0 migrated, 0 up to date, 1 skipped",
+        );
+        migrate(
+            dd,
+            &["--from-version", "0", "--to-version", "1"],
+            "1 migrated, 0 up to date, 0 skipped",
+        );
+    });
+    let mp = mounted_fs.mount_point();
+    assert_eq!(fs::metadata(mp.join("legacy.txt")).unwrap().len(), 6);
+    assert_eq!(fs::read(mp.join("legacy.txt")).unwrap(), b"LEGACY");
+}
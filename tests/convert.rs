@@ -171,7 +171,7 @@ mod no_convert {
 mod convert {
     use super::utils;
     use rstest::*;
-    use std::fs;
+    use std::{ffi::CString, fs, os::unix::ffi::OsStrExt, process};
 
     #[fixture]
     fn mounted_fs() -> utils::FuseZstdProcess {
@@ -211,6 +211,43 @@ mod convert {
         assert!(mp.join("directory/uncompressed.txt").exists());
     }
 
+    #[rstest]
+    fn truncate(mounted_fs: utils::FuseZstdProcess) {
+        let dd = mounted_fs.data_dir();
+        let mp = mounted_fs.mount_point();
+
+        let status = process::Command::new("truncate")
+            .args(["-s", "5"])
+            .arg(mp.join("uncompressed.txt"))
+            .status()
+            .unwrap();
+        assert!(status.success());
+        assert_eq!(
+            fs::read_to_string(mp.join("uncompressed.txt")).unwrap(),
+            "compr"
+        );
+        assert!(!dd.join("uncompressed.txt").exists());
+        assert_eq!(
+            utils::get_real_size(dd.join("uncompressed.txt.zst")),
+            Some(5)
+        );
+
+        // without an opened file
+        let path =
+            CString::new(mp.join("directory/uncompressed.txt").as_os_str().as_bytes()).unwrap();
+        assert_eq!(unsafe { libc::truncate(path.as_ptr(), 5) }, 0);
+        assert_eq!(
+            fs::metadata(mp.join("directory/uncompressed.txt"))
+                .unwrap()
+                .len(),
+            5
+        );
+        assert_eq!(
+            utils::get_compressed_content(dd.join("directory/uncompressed.txt.zst")),
+            "compr"
+        );
+    }
+
     #[rstest]
     fn overlap(mounted_fs: utils::FuseZstdProcess) {
         let dd = mounted_fs.data_dir();
@@ -262,4 +299,34 @@ mod convert {
             "overlap compressed"
         );
     }
+
+    #[rstest]
+    fn corrupted_overlap(mounted_fs: utils::FuseZstdProcess) {
+        let dd = mounted_fs.data_dir();
+        let mp = mounted_fs.mount_point();
+
+        // leftover of an interrupted conversion
+        let compressed = super::compress(b"corrupted compressed", 0).unwrap();
+        fs::write(dd.join("corrupted.txt"), b"corrupted plain").unwrap();
+        fs::write(
+            dd.join("corrupted.txt.zst"),
+            &compressed[..compressed.len() / 2],
+        )
+        .unwrap();
+
+        // mp.join will cause lookup
+        assert!(mp.join("corrupted.txt").exists());
+
+        // plain file was compressed again
+        assert!(!dd.join("corrupted.txt").exists());
+        assert_eq!(
+            utils::get_compressed_content(dd.join("corrupted.txt.zst")),
+            "corrupted plain"
+        );
+        assert_eq!(utils::get_real_size(dd.join("corrupted.txt.zst")), Some(15));
+        assert_eq!(
+            fs::read_to_string(mp.join("corrupted.txt")).unwrap(),
+            "corrupted plain"
+        );
+    }
 }
@@ -1,5 +1,20 @@
 use rstest::*;
-use std::{fs, io::Write, mem, os::linux::fs::MetadataExt, path, thread::sleep, time::Duration};
+use std::{
+    fs,
+    io::{self, Read, Write},
+    mem,
+    os::{
+        linux::fs::MetadataExt,
+        unix::{
+            self,
+            fs::{DirEntryExt, FileExt, OpenOptionsExt},
+            io::AsRawFd,
+        },
+    },
+    path, process, ptr,
+    thread::{self, sleep},
+    time::Duration,
+};
 
 #[path = "utils.rs"]
 pub mod utils;
@@ -279,10 +294,1504 @@ fn using_opened_fh_when_cache_fails(#[case] mounted_fs: utils::FuseZstdProcess)
 
     assert!(file1.metadata().is_ok());
 
-    // Removing the file should make impossible to reach the file
+    // Removed file should remain reachable till it is closed
     fs::remove_file(mp.join("file.txt")).unwrap();
 
     sleep(Duration::from_millis(2500)); // 2.5 seconds should be enough
 
-    assert!(file1.metadata().is_err());
+    assert!(file1.metadata().is_ok());
+    assert!(!mp.join("file.txt").exists());
+}
+
+#[rstest]
+#[case::no_convert(mounted_fs_no_convert())]
+#[case::convert(mounted_fs_convert())]
+fn parallel_create(#[case] mounted_fs: utils::FuseZstdProcess) {
+    let mp = mounted_fs.mount_point();
+
+    let threads: Vec<_> = ["FIRST_", "SECOND"]
+        .into_iter()
+        .map(|content| {
+            let path = mp.join("file.txt");
+            thread::spawn(move || {
+                let mut file = fs::OpenOptions::new()
+                    .write(true)
+                    .create(true)
+                    .truncate(false)
+                    .open(path)
+                    .unwrap();
+                file.write_all(content.as_bytes()).unwrap();
+                let ino = file.metadata().unwrap().st_ino();
+                file.sync_all().unwrap();
+                ino
+            })
+        })
+        .collect();
+    let inodes: Vec<u64> = threads.into_iter().map(|e| e.join().unwrap()).collect();
+
+    assert_eq!(inodes[0], inodes[1], "Both handles should share the inode");
+    assert_eq!(
+        fs::metadata(mp.join("file.txt")).unwrap().st_ino(),
+        inodes[0]
+    );
+    let content = fs::read_to_string(mp.join("file.txt")).unwrap();
+    assert!(content == "FIRST_" || content == "SECOND");
+}
+
+#[rstest]
+#[case::no_convert(mounted_fs_no_convert())]
+#[case::convert(mounted_fs_convert())]
+fn large_read(#[case] mounted_fs: utils::FuseZstdProcess) {
+    let mp = mounted_fs.mount_point();
+
+    let data: Vec<u8> = (0..4 * 1024 * 1024).map(|e| (e % 251) as u8).collect();
+    fs::write(mp.join("file.txt"), &data).unwrap();
+
+    // single read should return the whole file
+    let file = fs::File::open(mp.join("file.txt")).unwrap();
+    let mut buffer = vec![0; data.len()];
+    let size = file.read_at(&mut buffer, 0).unwrap();
+    assert_eq!(size, data.len());
+    assert_eq!(buffer, data);
+}
+
+#[rstest]
+#[case::no_convert(mounted_fs_no_convert())]
+#[case::convert(mounted_fs_convert())]
+fn open_path_only(#[case] mounted_fs: utils::FuseZstdProcess) {
+    let mp = mounted_fs.mount_point();
+    let dd = mounted_fs.data_dir();
+
+    // Compressed, but no realsize written to xattrs
+    fs::write(
+        dd.join("file.txt.zst"),
+        zstd::bulk::compress(b"compressed data", 0).unwrap(),
+    )
+    .unwrap();
+
+    // O_PATH open is handled by the kernel (no FUSE open request)
+    let mut file = fs::OpenOptions::new()
+        .read(true)
+        .custom_flags(libc::O_PATH)
+        .open(mp.join("file.txt"))
+        .unwrap();
+
+    // file should not be decompressed
+    assert!(xattr::get(dd.join("file.txt.zst"), "user.real_size")
+        .unwrap()
+        .is_none());
+    assert!(utils::get_real_size(dd.join("file.txt.zst")).is_none());
+
+    let mut buffer = vec![];
+    assert_eq!(
+        file.read_to_end(&mut buffer).unwrap_err().raw_os_error(),
+        Some(libc::EBADF)
+    );
+}
+
+#[rstest]
+#[case::no_convert(mounted_fs_no_convert())]
+#[case::convert(mounted_fs_convert())]
+fn unlink_opened_file(#[case] mounted_fs: utils::FuseZstdProcess) {
+    let mp = mounted_fs.mount_point();
+    let dd = mounted_fs.data_dir();
+
+    let mut file = fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(mp.join("file.txt"))
+        .unwrap();
+    file.write_all(b"BEFORE").unwrap();
+    file.sync_all().unwrap();
+
+    fs::remove_file(mp.join("file.txt")).unwrap();
+    assert!(!mp.join("file.txt").exists());
+    assert!(!dd.join("file.txt.zst").exists());
+
+    // file is still accessible via opened file
+    file.write_all(b" AND AFTER").unwrap();
+    file.sync_all().unwrap();
+    let mut buffer = vec![0; 16];
+    assert_eq!(file.read_at(&mut buffer, 0).unwrap(), 16);
+    assert_eq!(buffer, b"BEFORE AND AFTER");
+
+    // closing the file should remove the data
+    mem::drop(file);
+    let unlinked: Vec<_> = fs::read_dir(dd.join(".fuse-zstd-inode_cache/unlinked"))
+        .unwrap()
+        .collect();
+    assert!(unlinked.is_empty());
+}
+
+/// Waits till the number of inode cache entries stored in stats file matches `cond`
+fn wait_for_cache_entries<F>(data_dir: &path::Path, cond: F) -> u64
+where
+    F: Fn(u64) -> bool,
+{
+    // stats are stored once per flush interval
+    for _ in 0..50 {
+        let entries = fs::read_to_string(data_dir.join(".fuse-zstd-stats"))
+            .ok()
+            .and_then(|e| {
+                e.lines()
+                    .find_map(|e| e.strip_prefix("cache_entries="))
+                    .and_then(|e| e.parse().ok())
+            });
+        match entries {
+            Some(entries) if cond(entries) => return entries,
+            _ => sleep(Duration::from_millis(100)),
+        }
+    }
+    panic!("Cache entries were not stored");
+}
+
+#[rstest]
+#[ignore = "requires root to drop kernel caches"]
+fn forget_evicts_inodes() {
+    let mounted_fs = utils::FuseZstdProcess::with_logs(false, &["--flush-interval", "1"]);
+    let mp = mounted_fs.mount_point();
+    let dd = mounted_fs.data_dir();
+
+    for i in 0..50 {
+        fs::write(mp.join(format!("file{}.txt", i)), b"DATA").unwrap();
+    }
+    for i in 0..50 {
+        assert_eq!(
+            fs::read(mp.join(format!("file{}.txt", i))).unwrap(),
+            b"DATA"
+        );
+    }
+    let cached = wait_for_cache_entries(&dd, |e| e >= 50);
+
+    // ask kernel to drop dentries and inodes
+    fs::write("/proc/sys/vm/drop_caches", b"2").unwrap();
+    wait_for_cache_entries(&dd, |e| e < cached);
+    assert!(mounted_fs.logs().contains("Inode evicted from cache"));
+
+    // evicted files are still accessible
+    for i in 0..50 {
+        assert_eq!(
+            fs::read(mp.join(format!("file{}.txt", i))).unwrap(),
+            b"DATA"
+        );
+    }
+}
+
+#[rstest]
+#[case::no_convert(mounted_fs_no_convert())]
+#[case::convert(mounted_fs_convert())]
+fn size_before_flush(#[case] mounted_fs: utils::FuseZstdProcess) {
+    let mp = mounted_fs.mount_point();
+
+    let file = fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(mp.join("file.txt"))
+        .unwrap();
+    file.write_at(b"DATA", 1024 * 1024).unwrap();
+    assert_eq!(file.metadata().unwrap().len(), 1024 * 1024 + 4);
+
+    // size is visible to other processes as well
+    sleep(Duration::from_millis(1100));
+    assert_eq!(
+        fs::metadata(mp.join("file.txt")).unwrap().len(),
+        1024 * 1024 + 4
+    );
+
+    file.set_len(10).unwrap();
+    assert_eq!(file.metadata().unwrap().len(), 10);
+}
+
+#[rstest]
+fn out_of_space() {
+    let mounted_fs = utils::FuseZstdProcess::with_data(false, &[], |dd| {
+        // small filesystem behaves the same way as exceeded quota (requires root)
+        let _ = process::Command::new("mount")
+            .args(["-t", "tmpfs", "-o", "size=256k", "tmpfs"])
+            .arg(dd)
+            .status();
+    });
+    let mp = mounted_fs.mount_point();
+    let dd = mounted_fs.data_dir();
+    let small_fs = process::Command::new("mountpoint")
+        .arg("-q")
+        .arg(&dd)
+        .status()
+        .map(|e| e.success())
+        .unwrap_or(false);
+    if !small_fs {
+        return;
+    }
+
+    fs::write(mp.join("file.txt"), b"BEFORE").unwrap();
+
+    // incompressible data
+    let mut state: u32 = 1;
+    let data: Vec<u8> = (0..150 * 1024)
+        .map(|_| {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            state as u8
+        })
+        .collect();
+    let err = fs::write(mp.join("file.txt"), &data).unwrap_err();
+    assert!(matches!(
+        err.raw_os_error(),
+        Some(libc::ENOSPC) | Some(libc::EDQUOT)
+    ));
+
+    // previous content is kept
+    assert_eq!(
+        utils::get_compressed_content(dd.join("file.txt.zst")),
+        "BEFORE"
+    );
+
+    let _ = process::Command::new("umount").arg("-l").arg(&dd).status();
+}
+
+#[rstest]
+#[case::no_convert(mounted_fs_no_convert())]
+#[case::convert(mounted_fs_convert())]
+fn rename_dir_with_cached_children(#[case] mounted_fs: utils::FuseZstdProcess) {
+    let mp = mounted_fs.mount_point();
+    let dd = mounted_fs.data_dir();
+
+    fs::create_dir_all(mp.join("dir/sub")).unwrap();
+    fs::write(mp.join("dir/sub/file.txt"), b"FIRST").unwrap();
+    fs::write(mp.join("dir/other.txt"), b"OTHER").unwrap();
+    assert_eq!(fs::metadata(mp.join("dir/sub/file.txt")).unwrap().len(), 5);
+
+    let mut file = fs::OpenOptions::new()
+        .append(true)
+        .open(mp.join("dir/sub/file.txt"))
+        .unwrap();
+
+    fs::rename(mp.join("dir"), mp.join("renamed")).unwrap();
+
+    // wait till kernel cache expires so inodes are resolved again
+    sleep(Duration::from_millis(1100));
+    assert_eq!(
+        fs::metadata(mp.join("renamed/sub/file.txt")).unwrap().len(),
+        5
+    );
+    assert_eq!(fs::read(mp.join("renamed/other.txt")).unwrap(), b"OTHER");
+
+    // opened file is stored to the new location
+    file.write_all(b" SECOND").unwrap();
+    mem::drop(file);
+    assert_eq!(
+        utils::get_compressed_content(dd.join("renamed/sub/file.txt.zst")),
+        "FIRST SECOND"
+    );
+    assert!(!dd.join("dir").exists());
+}
+
+#[rstest]
+#[case::no_convert(mounted_fs_no_convert())]
+#[case::convert(mounted_fs_convert())]
+fn symlink_loop(#[case] mounted_fs: utils::FuseZstdProcess) {
+    let mp = mounted_fs.mount_point();
+
+    fs::write(mp.join("file.txt"), b"DATA").unwrap();
+    unix::fs::symlink("file.txt", mp.join("link")).unwrap();
+    assert_eq!(fs::read(mp.join("link")).unwrap(), b"DATA");
+
+    unix::fs::symlink("b", mp.join("a")).unwrap();
+    unix::fs::symlink("a", mp.join("b")).unwrap();
+    assert_eq!(
+        fs::metadata(mp.join("a")).unwrap_err().raw_os_error(),
+        Some(libc::ELOOP)
+    );
+    assert_eq!(
+        fs::read(mp.join("b")).unwrap_err().raw_os_error(),
+        Some(libc::ELOOP)
+    );
+
+    // links themselves are accessible
+    assert!(fs::symlink_metadata(mp.join("a"))
+        .unwrap()
+        .file_type()
+        .is_symlink());
+    assert_eq!(fs::read_link(mp.join("a")).unwrap(), path::Path::new("b"));
+    let mut names: Vec<_> = fs::read_dir(&mp)
+        .unwrap()
+        .map(|e| e.unwrap().file_name().to_string_lossy().to_string())
+        .collect();
+    names.sort();
+    assert_eq!(names, vec!["a", "b", "file.txt", "link"]);
+
+    fs::remove_file(mp.join("a")).unwrap();
+    assert!(fs::symlink_metadata(mp.join("a")).is_err());
+}
+
+#[rstest]
+fn ioctl() {
+    use std::os::fd::AsRawFd;
+
+    let mounted_fs = utils::FuseZstdProcess::new(false);
+    let mp = mounted_fs.mount_point();
+    fs::write(mp.join("file.txt"), b"DATA").unwrap();
+
+    let file = fs::File::open(mp.join("file.txt")).unwrap();
+
+    // not a terminal - the same as on a normal filesystem
+    assert_eq!(unsafe { libc::isatty(file.as_raw_fd()) }, 0);
+    assert_eq!(
+        std::io::Error::last_os_error().raw_os_error(),
+        Some(libc::ENOTTY)
+    );
+
+    let mut flags: libc::c_long = -1;
+    assert_eq!(
+        unsafe { libc::ioctl(file.as_raw_fd(), libc::FS_IOC_GETFLAGS, &mut flags) },
+        0
+    );
+    assert_eq!(flags, 0);
+}
+
+#[rstest]
+fn metadata_xattr() {
+    let mounted_fs = utils::FuseZstdProcess::with_args(false, &["--compression-level", "7"]);
+    let mp = mounted_fs.mount_point();
+    let dd = mounted_fs.data_dir();
+
+    fs::write(mp.join("file.txt"), b"some data").unwrap();
+
+    let mut names: Vec<_> = xattr::list(dd.join("file.txt.zst"))
+        .unwrap()
+        .map(|e| e.to_string_lossy().to_string())
+        .collect();
+    names.sort();
+    assert_eq!(
+        names,
+        vec!["user.fuse_zstd.meta", "user.ino", "user.real_size"]
+    );
+
+    let meta = xattr::get(dd.join("file.txt.zst"), "user.fuse_zstd.meta")
+        .unwrap()
+        .unwrap();
+    assert_eq!(meta[0], 1); // version
+    assert_eq!(meta[1], 1); // zstd
+    assert_eq!(i32::from_be_bytes(meta[2..6].try_into().unwrap()), 7);
+    assert_eq!(utils::get_real_size(dd.join("file.txt.zst")), Some(9));
+    assert_eq!(meta.len(), 14);
+    // read by older versions
+    assert_eq!(
+        xattr::get(dd.join("file.txt.zst"), "user.real_size").unwrap(),
+        Some(9u64.to_be_bytes().to_vec())
+    );
+
+    // size stored by an older version wins over stale metadata
+    xattr::set(
+        dd.join("file.txt.zst"),
+        "user.real_size",
+        &4u64.to_be_bytes(),
+    )
+    .unwrap();
+    sleep(Duration::from_millis(1100));
+    assert_eq!(fs::metadata(mp.join("file.txt")).unwrap().len(), 4);
+    xattr::set(
+        dd.join("file.txt.zst"),
+        "user.real_size",
+        &9u64.to_be_bytes(),
+    )
+    .unwrap();
+    sleep(Duration::from_millis(1100));
+
+    // internal attribute is hidden
+    assert!(xattr::get(mp.join("file.txt"), "user.fuse_zstd.meta")
+        .unwrap()
+        .is_none());
+    assert_eq!(fs::metadata(mp.join("file.txt")).unwrap().len(), 9);
+}
+
+#[rstest]
+fn long_path() {
+    let mounted_fs = utils::FuseZstdProcess::new(false);
+    let mp = mounted_fs.mount_point();
+    let dd = mounted_fs.data_dir();
+
+    // close to PATH_MAX including the data dir prefix
+    let component = "d".repeat(200);
+    let mut relative = path::PathBuf::new();
+    while dd.join(&relative).as_os_str().len() + component.len() + 20 < libc::PATH_MAX as usize {
+        relative.push(&component);
+    }
+    fs::create_dir_all(mp.join(&relative)).unwrap();
+    fs::write(mp.join(relative.join("file.txt")), b"DEEP").unwrap();
+    assert!(dd.join(relative.join("file.txt.zst")).exists());
+
+    // resolved via cached inode
+    let file = fs::File::open(mp.join(relative.join("file.txt"))).unwrap();
+    let mut data = vec![];
+    (&file).read_to_end(&mut data).unwrap();
+    assert_eq!(data, b"DEEP");
+    assert_eq!(file.metadata().unwrap().len(), 4);
+
+    let names: Vec<_> = fs::read_dir(mp.join(&relative))
+        .unwrap()
+        .map(|e| e.unwrap().file_name())
+        .collect();
+    assert_eq!(names, vec!["file.txt"]);
+}
+
+#[rstest]
+fn locking() {
+    use std::os::fd::AsRawFd;
+
+    let mounted_fs = utils::FuseZstdProcess::new(false);
+    let mp = mounted_fs.mount_point();
+    fs::write(mp.join("file.db"), b"DATA").unwrap();
+
+    // flock held by other process
+    let mut holder = process::Command::new("flock")
+        .args(["-x"])
+        .arg(mp.join("file.db"))
+        .args(["sleep", "2"])
+        .spawn()
+        .unwrap();
+    sleep(Duration::from_millis(500));
+    let status = process::Command::new("flock")
+        .args(["-n", "-x"])
+        .arg(mp.join("file.db"))
+        .arg("true")
+        .status()
+        .unwrap();
+    assert!(!status.success());
+    assert!(holder.wait().unwrap().success());
+    let status = process::Command::new("flock")
+        .args(["-n", "-x"])
+        .arg(mp.join("file.db"))
+        .arg("true")
+        .status()
+        .unwrap();
+    assert!(status.success());
+
+    // byte range locks of different open files
+    let lock = |file: &fs::File, start: i64, len: i64| {
+        let mut flock: libc::flock = unsafe { mem::zeroed() };
+        flock.l_type = libc::F_WRLCK as i16;
+        flock.l_whence = libc::SEEK_SET as i16;
+        flock.l_start = start;
+        flock.l_len = len;
+        unsafe { libc::fcntl(file.as_raw_fd(), libc::F_OFD_SETLK, &flock) }
+    };
+    let first = fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(mp.join("file.db"))
+        .unwrap();
+    let second = fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(mp.join("file.db"))
+        .unwrap();
+    assert_eq!(lock(&first, 0, 2), 0);
+    assert_eq!(lock(&second, 1, 2), -1);
+    assert_eq!(
+        std::io::Error::last_os_error().raw_os_error(),
+        Some(libc::EAGAIN)
+    );
+    assert_eq!(lock(&second, 2, 2), 0);
+    mem::drop(first);
+    assert_eq!(lock(&second, 0, 2), 0);
+}
+
+#[rstest]
+fn size_after_create() {
+    let mounted_fs = utils::FuseZstdProcess::new(false);
+    let mp = mounted_fs.mount_point();
+    let dd = mounted_fs.data_dir();
+
+    // attributes from create reply are cached by the kernel
+    let file = fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(mp.join("new.txt"))
+        .unwrap();
+    assert_eq!(file.metadata().unwrap().len(), 0);
+    assert_eq!(fs::metadata(mp.join("new.txt")).unwrap().len(), 0);
+
+    // the empty frame itself is not empty
+    assert!(fs::metadata(dd.join("new.txt.zst")).unwrap().len() > 0);
+}
+
+#[rstest]
+fn copy_file_range() {
+    use std::os::fd::AsRawFd;
+
+    let mounted_fs = utils::FuseZstdProcess::new(false);
+    let mp = mounted_fs.mount_point();
+    let dd = mounted_fs.data_dir();
+    let data: Vec<u8> = (0..3_000_000).map(|e| (e % 251) as u8).collect();
+    fs::write(mp.join("a"), &data).unwrap();
+    sleep(Duration::from_millis(200));
+
+    // whole file copied by cp
+    let status = process::Command::new("cp")
+        .arg(mp.join("a"))
+        .arg(mp.join("b"))
+        .status()
+        .unwrap();
+    assert!(status.success());
+    assert_eq!(fs::read(mp.join("b")).unwrap(), data);
+    assert_eq!(
+        utils::get_real_size(dd.join("b.zst")),
+        Some(data.len() as u64)
+    );
+    assert_ne!(
+        xattr::get(dd.join("a.zst"), "user.ino").unwrap(),
+        xattr::get(dd.join("b.zst"), "user.ino").unwrap()
+    );
+
+    // compressed file is cloned when data dir supports it
+    let probe = dd.join("probe");
+    fs::write(&probe, b"PROBE").unwrap();
+    let probe_clone = fs::File::create(dd.join("probe_clone")).unwrap();
+    let probe = fs::File::open(&probe).unwrap();
+    if unsafe { libc::ioctl(probe_clone.as_raw_fd(), libc::FICLONE, probe.as_raw_fd()) } == 0 {
+        assert_eq!(
+            fs::read(dd.join("a.zst")).unwrap(),
+            fs::read(dd.join("b.zst")).unwrap()
+        );
+    }
+
+    // partial copy
+    let source = fs::File::open(mp.join("a")).unwrap();
+    let target = fs::File::create(mp.join("c")).unwrap();
+    let mut offset_in: i64 = 1000;
+    let mut offset_out: i64 = 0;
+    let copied = unsafe {
+        libc::copy_file_range(
+            source.as_raw_fd(),
+            &mut offset_in,
+            target.as_raw_fd(),
+            &mut offset_out,
+            5000,
+            0,
+        )
+    };
+    assert_eq!(copied, 5000);
+    drop(target);
+    assert_eq!(fs::read(mp.join("c")).unwrap(), &data[1000..6000]);
+
+    // copy is changed independently of the source
+    fs::OpenOptions::new()
+        .write(true)
+        .open(mp.join("b"))
+        .unwrap()
+        .write_all_at(b"CHANGED", 0)
+        .unwrap();
+    assert_eq!(fs::read(mp.join("a")).unwrap(), data);
+    assert_eq!(&fs::read(mp.join("b")).unwrap()[..7], b"CHANGED");
+}
+
+#[rstest]
+#[case::no_convert(false)]
+#[case::convert(true)]
+fn no_internal_entries(#[case] convert: bool) {
+    fn walk(dir: &path::Path, names: &mut Vec<String>) {
+        for entry in fs::read_dir(dir).unwrap() {
+            let entry = entry.unwrap();
+            names.push(entry.file_name().to_string_lossy().to_string());
+            if entry.file_type().unwrap().is_dir() {
+                walk(&entry.path(), names);
+            }
+        }
+    }
+
+    let mounted_fs = utils::FuseZstdProcess::new(convert);
+    let mp = mounted_fs.mount_point();
+    let dd = mounted_fs.data_dir();
+
+    fs::create_dir_all(mp.join("first/second")).unwrap();
+    fs::write(mp.join("file.txt"), b"ROOT").unwrap();
+    fs::write(mp.join("first/second/file.txt"), b"NESTED").unwrap();
+    // unlinked file which is still opened is kept in the cache
+    let opened = fs::File::open(mp.join("first/second/file.txt")).unwrap();
+    fs::remove_file(mp.join("first/second/file.txt")).unwrap();
+
+    // inode database is inside of the hidden cache directory
+    let cache_dir = dd.join(".fuse-zstd-inode_cache");
+    assert!(fs::read_dir(&cache_dir)
+        .unwrap()
+        .any(|e| e.unwrap().path().join("conf").exists()));
+
+    let mut names = vec![];
+    walk(&mp, &mut names);
+    names.sort();
+    assert_eq!(names, vec!["file.txt", "first", "second"]);
+    drop(opened);
+}
+
+#[rstest]
+fn negative_lookup() {
+    let mounted_fs = utils::FuseZstdProcess::with_logs(false, &[]);
+    let mp = mounted_fs.mount_point();
+
+    for _ in 0..20 {
+        assert!(fs::metadata(mp.join("missing.h")).is_err());
+    }
+    let lookups = mounted_fs
+        .logs()
+        .lines()
+        .filter(|e| e.contains("Lookup (") && e.contains("name='missing.h'"))
+        .count();
+    assert!((1..5).contains(&lookups), "lookups={}", lookups);
+
+    // created file is found right away
+    fs::write(mp.join("missing.h"), b"FOUND").unwrap();
+    assert_eq!(fs::read(mp.join("missing.h")).unwrap(), b"FOUND");
+    assert!(fs::metadata(mp.join("other.h")).is_err());
+    fs::rename(mp.join("missing.h"), mp.join("other.h")).unwrap();
+    assert_eq!(fs::read(mp.join("other.h")).unwrap(), b"FOUND");
+}
+
+#[rstest]
+fn symlinked_data_dir() {
+    let real_dir = tempfile::TempDir::new_in("/tmp/").unwrap();
+    let links = tempfile::TempDir::new_in("/tmp/").unwrap();
+    let mount_point = tempfile::TempDir::new_in("/tmp/").unwrap();
+    let link = links.path().join("data");
+    unix::fs::symlink(real_dir.path(), &link).unwrap();
+
+    let mut process = process::Command::new(assert_cmd::cargo::cargo_bin("fuse-zstd"))
+        .arg("--data-dir")
+        .arg(&link)
+        .arg("--mount-point")
+        .arg(mount_point.path())
+        .spawn()
+        .unwrap();
+    for _ in 0..50 {
+        if utils::FuseZstdProcess::check_mounted(mount_point.path()) {
+            break;
+        }
+        sleep(Duration::from_millis(200));
+    }
+    let mp = mount_point.path();
+
+    fs::create_dir(mp.join("dir")).unwrap();
+    fs::write(mp.join("dir/file.txt"), b"DATA").unwrap();
+    let ino = fs::metadata(mp.join("dir/file.txt")).unwrap().st_ino();
+
+    // attributes are stored in the real directory, not on the link
+    assert!(xattr::get(real_dir.path(), "user.ino_idx")
+        .unwrap()
+        .is_some());
+    assert!(xattr::get(&link, "user.ino_idx").unwrap_or(None).is_none());
+    assert_eq!(
+        xattr::get(real_dir.path().join("dir/file.txt.zst"), "user.ino")
+            .unwrap()
+            .map(|e| u64::from_be_bytes(e.try_into().unwrap())),
+        Some(ino)
+    );
+    assert_eq!(fs::read(mp.join("dir/file.txt")).unwrap(), b"DATA");
+    assert_eq!(fs::metadata(mp.join("dir/file.txt")).unwrap().st_ino(), ino);
+
+    let _ = process.kill();
+    let _ = process.wait();
+}
+
+#[rstest]
+fn tmpfile() {
+    let mounted_fs = utils::FuseZstdProcess::new(false);
+    let mp = mounted_fs.mount_point();
+
+    // kernel doesn't pass O_TMPFILE to FUSE
+    let err = fs::OpenOptions::new()
+        .write(true)
+        .custom_flags(libc::O_TMPFILE)
+        .open(&mp)
+        .unwrap_err();
+    assert_eq!(err.raw_os_error(), Some(libc::EOPNOTSUPP));
+
+    // usual fallback of tools works
+    fs::write(mp.join(".file.tmp"), b"DATA").unwrap();
+    fs::rename(mp.join(".file.tmp"), mp.join("file")).unwrap();
+    assert_eq!(fs::read(mp.join("file")).unwrap(), b"DATA");
+}
+
+#[rstest]
+fn readdirplus() {
+    let mounted_fs = utils::FuseZstdProcess::with_logs(false, &[]);
+    let mp = mounted_fs.mount_point();
+
+    fs::create_dir(mp.join("dir")).unwrap();
+    for i in 0..100 {
+        fs::write(mp.join(format!("dir/file{}.txt", i)), vec![b'a'; i]).unwrap();
+    }
+    // cached entries expire
+    sleep(Duration::from_millis(1500));
+
+    let count = || {
+        mounted_fs
+            .logs()
+            .lines()
+            .filter(|e| e.contains("Lookup (") || e.contains("Getattr ("))
+            .count()
+    };
+    let before = count();
+    let output = process::Command::new("ls")
+        .arg("-l")
+        .arg(mp.join("dir"))
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let listing = String::from_utf8(output.stdout).unwrap();
+    // sizes are returned with the entries
+    assert!(listing
+        .lines()
+        .any(|e| e.contains(" 99 ") && e.ends_with("file99.txt")));
+    let calls = count() - before;
+    assert!(calls < 20, "calls={}", calls);
+}
+
+#[rstest]
+fn readdir_while_removing() {
+    let mounted_fs = utils::FuseZstdProcess::new(false);
+    let mp = mounted_fs.mount_point();
+    let dd = mounted_fs.data_dir();
+
+    fs::create_dir(mp.join("dir")).unwrap();
+    for i in 0..500 {
+        fs::write(mp.join(format!("dir/file{}.txt", i)), b"DATA").unwrap();
+    }
+
+    // files are removed directly from data dir while the directory is listed
+    let dir = dd.join("dir");
+    let remover = thread::spawn(move || {
+        for i in 0..500 {
+            fs::remove_file(dir.join(format!("file{}.txt.zst", i))).unwrap();
+        }
+    });
+    while !remover.is_finished() {
+        let mut names = fs::read_dir(mp.join("dir"))
+            .unwrap()
+            .map(|e| e.unwrap().file_name())
+            .collect::<Vec<_>>();
+        let count = names.len();
+        names.sort();
+        names.dedup();
+        assert_eq!(names.len(), count);
+        assert!(count <= 500);
+    }
+    remover.join().unwrap();
+
+    assert_eq!(fs::read_dir(mp.join("dir")).unwrap().count(), 0);
+    assert!(utils::FuseZstdProcess::check_mounted(&mp));
+}
+
+#[rstest]
+fn full_inode_cache() {
+    let mut cache_dir = None;
+    let mounted_fs = utils::FuseZstdProcess::with_data(false, &[], |dd| {
+        // small filesystem for the cache (mounting requires root)
+        let dir = dd.join(".fuse-zstd-inode_cache");
+        fs::create_dir(&dir).unwrap();
+        let mounted = process::Command::new("mount")
+            .args(["-t", "tmpfs", "-o", "size=4m", "tmpfs"])
+            .arg(&dir)
+            .status()
+            .map(|e| e.success())
+            .unwrap_or(false);
+        if mounted {
+            cache_dir = Some(dir);
+        }
+    });
+    let Some(cache_dir) = cache_dir else {
+        return;
+    };
+    let mp = mounted_fs.mount_point();
+
+    let mut filler = fs::File::create(cache_dir.join("filler")).unwrap();
+    while filler.write_all(&[0; 4096]).is_ok() {}
+
+    for i in 0..50 {
+        fs::create_dir(mp.join(format!("dir{}", i))).unwrap();
+        fs::write(mp.join(format!("dir{}/file.txt", i)), format!("DATA{}", i)).unwrap();
+    }
+    fs::rename(mp.join("dir0"), mp.join("renamed")).unwrap();
+    // cached entries of kernel expire so inodes are resolved again
+    sleep(Duration::from_millis(1500));
+    for i in 1..50 {
+        let path = mp.join(format!("dir{}/file.txt", i));
+        assert_eq!(fs::read(&path).unwrap(), format!("DATA{}", i).as_bytes());
+        fs::metadata(path).unwrap();
+    }
+    assert_eq!(fs::read(mp.join("renamed/file.txt")).unwrap(), b"DATA0");
+
+    // detached lazily because the files are still opened
+    process::Command::new("umount")
+        .arg("-l")
+        .arg(&cache_dir)
+        .status()
+        .unwrap();
+}
+
+#[rstest]
+fn removed_inode_cache() {
+    // cache directory is checked once per flush interval
+    let mounted_fs = utils::FuseZstdProcess::with_args(false, &["--flush-interval", "1"]);
+    let mp = mounted_fs.mount_point();
+    let dd = mounted_fs.data_dir();
+
+    fs::create_dir(mp.join("dir")).unwrap();
+    fs::write(mp.join("dir/file.txt"), b"DATA").unwrap();
+    fs::remove_dir_all(dd.join(".fuse-zstd-inode_cache")).unwrap();
+
+    fs::write(mp.join("dir/other.txt"), b"OTHER").unwrap();
+    // cached entries of kernel expire so inodes are resolved again
+    sleep(Duration::from_millis(2500));
+    assert!(dd.join(".fuse-zstd-inode_cache").exists());
+    assert_eq!(fs::read(mp.join("dir/file.txt")).unwrap(), b"DATA");
+    assert_eq!(fs::read(mp.join("dir/other.txt")).unwrap(), b"OTHER");
+}
+
+#[rstest]
+fn lost_inode_index() {
+    let mounted_fs = utils::FuseZstdProcess::new(false);
+    let mp = mounted_fs.mount_point();
+    let dd = mounted_fs.data_dir();
+    fs::write(mp.join("first.txt"), b"FIRST").unwrap();
+    fs::write(mp.join("second.txt"), b"SECOND").unwrap();
+    let used = [
+        fs::metadata(mp.join("first.txt")).unwrap().st_ino(),
+        fs::metadata(mp.join("second.txt")).unwrap().st_ino(),
+    ];
+
+    // index removed while not mounted
+    process::Command::new("kill")
+        .args(["-9", &mounted_fs.pid().to_string()])
+        .status()
+        .unwrap();
+    sleep(Duration::from_millis(200));
+    xattr::remove(&dd, "user.ino_idx").unwrap();
+
+    let mount_point = tempfile::TempDir::new_in("/tmp/").unwrap();
+    let mut process = process::Command::new(assert_cmd::cargo::cargo_bin("fuse-zstd"))
+        .arg("--data-dir")
+        .arg(&dd)
+        .arg("--mount-point")
+        .arg(mount_point.path())
+        .spawn()
+        .unwrap();
+    for _ in 0..50 {
+        if utils::FuseZstdProcess::check_mounted(mount_point.path()) {
+            break;
+        }
+        sleep(Duration::from_millis(200));
+    }
+    let mp = mount_point.path();
+
+    fs::write(mp.join("third.txt"), b"THIRD").unwrap();
+    let ino = fs::metadata(mp.join("third.txt")).unwrap().st_ino();
+    assert!(!used.contains(&ino));
+    assert_eq!(fs::read(mp.join("first.txt")).unwrap(), b"FIRST");
+
+    process.kill().unwrap();
+    process.wait().unwrap();
+}
+
+#[rstest]
+fn reserved_names() {
+    let mounted_fs = utils::FuseZstdProcess::with_data(false, &[], |dd| {
+        fs::write(dd.join(".fuse-zstd-stats"), b"compressions=1\n").unwrap();
+    });
+    let mp = mounted_fs.mount_point();
+    let dd = mounted_fs.data_dir();
+
+    let err = fs::remove_file(mp.join(".fuse-zstd-stats")).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::PermissionDenied);
+    assert!(dd.join(".fuse-zstd-stats").exists());
+
+    let err = fs::remove_dir(mp.join(".fuse-zstd-inode_cache")).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::PermissionDenied);
+    assert!(dd.join(".fuse-zstd-inode_cache").exists());
+
+    fs::write(mp.join("file.txt"), b"DATA").unwrap();
+    let err = fs::rename(mp.join("file.txt"), mp.join(".fuse-zstd-lock")).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::PermissionDenied);
+    assert!(fs::write(mp.join(".fuse-zstd-stats"), b"").is_err());
+    assert!(fs::create_dir(mp.join(".fuse-zstd-inode_cache")).is_err());
+    assert_eq!(fs::read(mp.join("file.txt")).unwrap(), b"DATA");
+}
+
+#[rstest]
+fn hardlinked_backing_file() {
+    let mounted_fs = utils::FuseZstdProcess::new(false);
+    let mp = mounted_fs.mount_point();
+    let dd = mounted_fs.data_dir();
+
+    fs::write(mp.join("a.txt"), b"FIRST").unwrap();
+    fs::hard_link(dd.join("a.txt.zst"), dd.join("b.txt.zst")).unwrap();
+    assert_eq!(fs::read(mp.join("b.txt")).unwrap(), b"FIRST");
+
+    fs::write(mp.join("a.txt"), b"SECOND VERSION").unwrap();
+    assert_eq!(fs::metadata(mp.join("b.txt")).unwrap().len(), 14);
+    assert_eq!(fs::read(mp.join("b.txt")).unwrap(), b"SECOND VERSION");
+
+    fs::write(mp.join("b.txt"), b"THIRD").unwrap();
+    assert_eq!(fs::read(mp.join("a.txt")).unwrap(), b"THIRD");
+    assert_eq!(
+        fs::metadata(dd.join("a.txt.zst")).unwrap().st_ino(),
+        fs::metadata(dd.join("b.txt.zst")).unwrap().st_ino()
+    );
+    assert_eq!(utils::get_compressed_content(dd.join("b.txt.zst")), "THIRD");
+}
+
+#[rstest]
+fn hardlinked_out_of_space() {
+    let mounted_fs = utils::FuseZstdProcess::with_data(false, &[], |dd| {
+        // requires root
+        let _ = process::Command::new("mount")
+            .args(["-t", "tmpfs", "-o", "size=256k", "tmpfs"])
+            .arg(dd)
+            .status();
+    });
+    let mp = mounted_fs.mount_point();
+    let dd = mounted_fs.data_dir();
+    let small_fs = process::Command::new("mountpoint")
+        .arg("-q")
+        .arg(&dd)
+        .status()
+        .map(|e| e.success())
+        .unwrap_or(false);
+    if !small_fs {
+        return;
+    }
+
+    fs::write(mp.join("a.txt"), b"BEFORE").unwrap();
+    fs::hard_link(dd.join("a.txt.zst"), dd.join("b.txt.zst")).unwrap();
+
+    // incompressible data which fit to the filesystem only once
+    let mut state: u32 = 1;
+    let data: Vec<u8> = (0..140 * 1024)
+        .map(|_| {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            state as u8
+        })
+        .collect();
+    assert!(fs::write(mp.join("a.txt"), &data).is_err());
+
+    // both names keep the previous content
+    for name in ["a.txt.zst", "b.txt.zst"] {
+        assert_eq!(utils::get_compressed_content(dd.join(name)), "BEFORE");
+    }
+
+    let _ = process::Command::new("umount").arg("-l").arg(&dd).status();
+}
+
+#[rstest]
+fn many_small_files() {
+    let mounted_fs = utils::FuseZstdProcess::new(false);
+    let mp = mounted_fs.mount_point();
+    let dd = mounted_fs.data_dir();
+
+    // compression context is reused by following files
+    let content = |i: usize| format!("{{\"id\": {}}}\n", i).repeat(i % 20 + 1);
+    for i in 0..2000 {
+        fs::write(mp.join(format!("{}.json", i)), content(i)).unwrap();
+    }
+    for i in 0..2000 {
+        let name = format!("{}.json", i);
+        assert_eq!(fs::read_to_string(mp.join(&name)).unwrap(), content(i));
+        assert_eq!(
+            utils::get_compressed_content(dd.join(format!("{}.zst", name))),
+            content(i)
+        );
+    }
+}
+
+#[rstest]
+fn changed_externally() {
+    let mounted_fs = utils::FuseZstdProcess::new(false);
+    let mp = mounted_fs.mount_point();
+    let dd = mounted_fs.data_dir();
+
+    fs::write(mp.join("file.txt"), b"OLD").unwrap();
+    fs::write(mp.join("other.txt"), b"NEW CONTENT").unwrap();
+    let opened = fs::File::open(mp.join("file.txt")).unwrap();
+    let mut buf = [0; 64];
+    assert_eq!(opened.read_at(&mut buf, 0).unwrap(), 3);
+
+    // compressed file is rewritten by another process
+    fs::copy(dd.join("other.txt.zst"), dd.join("file.txt.zst")).unwrap();
+    for name in ["user.fuse_zstd.meta", "user.real_size"] {
+        let value = xattr::get(dd.join("other.txt.zst"), name).unwrap().unwrap();
+        xattr::set(dd.join("file.txt.zst"), name, &value).unwrap();
+    }
+    sleep(Duration::from_millis(1100));
+
+    // opened file is decompressed again
+    assert_eq!(fs::read(mp.join("file.txt")).unwrap(), b"NEW CONTENT");
+    let size = opened.read_at(&mut buf, 0).unwrap();
+    assert_eq!(&buf[..size], b"NEW CONTENT");
+}
+
+#[rstest]
+fn overwrite_without_decompression() {
+    let mounted_fs = utils::FuseZstdProcess::with_logs(false, &[]);
+    let mp = mounted_fs.mount_point();
+    let dd = mounted_fs.data_dir();
+
+    fs::write(mp.join("file.txt"), b"OLD CONTENT").unwrap();
+
+    // whole file is written again
+    let mut file = fs::OpenOptions::new()
+        .write(true)
+        .open(mp.join("file.txt"))
+        .unwrap();
+    file.write_all(b"NEW CONTENT").unwrap();
+    drop(file);
+    assert_eq!(
+        utils::get_compressed_content(dd.join("file.txt.zst")),
+        "NEW CONTENT"
+    );
+    assert!(mounted_fs.logs().contains("skipped (file was overwritten)"));
+
+    // the rest of the file is decompressed
+    let mut file = fs::OpenOptions::new()
+        .write(true)
+        .open(mp.join("file.txt"))
+        .unwrap();
+    file.write_all(b"OLD").unwrap();
+    drop(file);
+    assert_eq!(fs::read(mp.join("file.txt")).unwrap(), b"OLD CONTENT");
+
+    // data before the written part
+    let file = fs::OpenOptions::new()
+        .write(true)
+        .open(mp.join("file.txt"))
+        .unwrap();
+    file.write_all_at(b"TEXT", 7).unwrap();
+    drop(file);
+    assert_eq!(fs::read(mp.join("file.txt")).unwrap(), b"OLD CONTEXT");
+    assert_eq!(
+        mounted_fs
+            .logs()
+            .lines()
+            .filter(|e| e.contains("Decompressing postponed"))
+            .count(),
+        2
+    );
+}
+
+#[rstest]
+fn open_directory() {
+    let mounted_fs = utils::FuseZstdProcess::new(false);
+    let mp = mounted_fs.mount_point();
+    fs::create_dir(mp.join("dir")).unwrap();
+    fs::write(mp.join("dir/file.txt"), b"FILE").unwrap();
+
+    // kernel opens directories without FUSE open and refuses to read them as files
+    let dir = fs::File::open(mp.join("dir")).unwrap();
+    assert!(dir.metadata().unwrap().is_dir());
+    let mut buf = [0; 4];
+    assert_eq!(
+        (&dir).read(&mut buf).unwrap_err().raw_os_error(),
+        Some(libc::EISDIR)
+    );
+    drop(dir);
+
+    let err = fs::OpenOptions::new()
+        .write(true)
+        .open(mp.join("dir"))
+        .unwrap_err();
+    assert_eq!(err.raw_os_error(), Some(libc::EISDIR));
+    assert_eq!(fs::read(mp.join("dir/file.txt")).unwrap(), b"FILE");
+}
+
+#[test]
+fn read_only_close() {
+    let mounted_fs = utils::FuseZstdProcess::with_logs(false, &[]);
+    let mp = mounted_fs.mount_point();
+    for i in 0..10 {
+        fs::write(mp.join(format!("{}.txt", i)), b"READ ONLY").unwrap();
+    }
+    let logs = mounted_fs.logs();
+    let stored = logs.matches("After compression").count();
+    let clean = logs.matches("Nothing to store").count();
+
+    for i in 0..10 {
+        assert_eq!(
+            fs::read(mp.join(format!("{}.txt", i))).unwrap(),
+            b"READ ONLY"
+        );
+    }
+
+    // flush and release of clean handles only close them
+    let logs = mounted_fs.logs();
+    assert_eq!(logs.matches("After compression").count(), stored);
+    assert!(logs.matches("Nothing to store").count() >= clean + 20);
+}
+
+#[rstest]
+#[case(false)]
+#[case(true)]
+fn zero_byte_files(#[case] external: bool) {
+    let mounted_fs = utils::FuseZstdProcess::with_data(false, &[], |dd| {
+        // empty compressed file without any frame
+        fs::File::create(dd.join("external.txt.zst")).unwrap();
+    });
+    let mp = mounted_fs.mount_point();
+    let path = if external {
+        mp.join("external.txt")
+    } else {
+        fs::File::create(mp.join("created.txt")).unwrap();
+        assert_eq!(
+            utils::get_compressed_content(mounted_fs.data_dir().join("created.txt.zst")),
+            ""
+        );
+        mp.join("created.txt")
+    };
+
+    assert_eq!(fs::metadata(&path).unwrap().len(), 0);
+    let file = fs::File::open(&path).unwrap();
+    let mut buf = [0; 16];
+    assert_eq!(file.read_at(&mut buf, 0).unwrap(), 0);
+    assert_eq!(file.read_at(&mut buf, 8).unwrap(), 0);
+    drop(file);
+    assert_eq!(fs::read(&path).unwrap(), b"");
+
+    // opened for writing and closed without changes
+    drop(fs::OpenOptions::new().append(true).open(&path).unwrap());
+    assert_eq!(fs::read(&path).unwrap(), b"");
+    assert_eq!(fs::metadata(&path).unwrap().len(), 0);
+}
+
+#[rstest]
+fn deferred_inodes() {
+    let mounted_fs = utils::FuseZstdProcess::with_data(false, &[], |dd| {
+        // imported dataset without inodes
+        fs::create_dir(dd.join("dir")).unwrap();
+        for i in 0..100 {
+            fs::write(
+                dd.join(format!("dir/file{}.txt.zst", i)),
+                zstd::encode_all(&b"DATA"[..], 0).unwrap(),
+            )
+            .unwrap();
+        }
+    });
+    let mp = mounted_fs.mount_point();
+    let dd = mounted_fs.data_dir();
+
+    let list = || {
+        let mut entries: Vec<(String, u64)> = fs::read_dir(mp.join("dir"))
+            .unwrap()
+            .map(|e| e.unwrap())
+            .map(|e| (e.file_name().to_string_lossy().to_string(), e.ino()))
+            .collect();
+        entries.sort();
+        entries
+    };
+    let entries = list();
+    assert_eq!(entries.len(), 100);
+
+    // listing doesn't store inodes
+    for (name, _) in &entries {
+        let path = dd.join(format!("dir/{}.zst", name));
+        assert_eq!(xattr::get(path, "user.ino").unwrap(), None);
+    }
+    assert_eq!(list(), entries);
+
+    // inode is stored once the file is looked up and it stays the same
+    let (name, ino) = &entries[0];
+    assert_eq!(
+        fs::metadata(mp.join("dir").join(name)).unwrap().st_ino(),
+        *ino
+    );
+    assert_eq!(
+        xattr::get(dd.join(format!("dir/{}.zst", name)), "user.ino").unwrap(),
+        Some(ino.to_be_bytes().to_vec())
+    );
+    assert_eq!(fs::read(mp.join("dir").join(name)).unwrap(), b"DATA");
+    assert_eq!(list(), entries);
+
+    // listed entry may be written without a lookup (readdirplus)
+    let (name, ino) = &entries[99];
+    fs::write(mp.join("dir").join(name), b"UPDATED").unwrap();
+    assert_eq!(
+        xattr::get(dd.join(format!("dir/{}.zst", name)), "user.ino").unwrap(),
+        Some(ino.to_be_bytes().to_vec())
+    );
+    assert_eq!(list(), entries);
+}
+
+#[rstest]
+fn create_in_removed_dir() {
+    let mounted_fs = utils::FuseZstdProcess::new(false);
+    let mp = mounted_fs.mount_point();
+    let dd = mounted_fs.data_dir();
+
+    // removed directly in data dir while it is known to the kernel
+    fs::create_dir(mp.join("dir")).unwrap();
+    assert!(mp.join("dir").is_dir());
+    fs::remove_dir(dd.join("dir")).unwrap();
+    let err = fs::write(mp.join("dir/file.txt"), b"DATA").unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::NotFound);
+    assert!(!dd.join("dir").exists());
+
+    // directory removed by another process via mount point
+    for i in 0..50 {
+        let dir = mp.join(format!("dir{}", i));
+        fs::create_dir(&dir).unwrap();
+        let remover = {
+            let dir = dir.clone();
+            thread::spawn(move || fs::remove_dir(dir))
+        };
+        let created = fs::write(dir.join("file.txt"), b"DATA");
+        let removed = remover.join().unwrap();
+        match created {
+            Ok(()) => {
+                assert!(removed.is_err());
+                assert_eq!(fs::read(dir.join("file.txt")).unwrap(), b"DATA");
+            }
+            Err(err) => {
+                assert_eq!(err.kind(), io::ErrorKind::NotFound);
+                assert!(removed.is_ok());
+                assert!(!dir.exists());
+            }
+        }
+    }
+
+    // inode cache is still consistent
+    fs::create_dir(mp.join("other")).unwrap();
+    fs::write(mp.join("other/file.txt"), b"DATA").unwrap();
+    assert_eq!(fs::read(mp.join("other/file.txt")).unwrap(), b"DATA");
+}
+
+#[rstest]
+fn readdir_continuation() {
+    let mounted_fs = utils::FuseZstdProcess::with_data_logs(false, &[], |dd| {
+        // more entries than fit into a single reply
+        fs::create_dir(dd.join("big")).unwrap();
+        for i in 0..3000 {
+            fs::write(
+                dd.join(format!("big/file-with-quite-a-long-name-{:05}.txt.zst", i)),
+                zstd::encode_all(&b"DATA"[..], 0).unwrap(),
+            )
+            .unwrap();
+        }
+    });
+    let mp = mounted_fs.mount_point();
+    let dd = mounted_fs.data_dir();
+
+    let mut names: Vec<String> = fs::read_dir(mp.join("big"))
+        .unwrap()
+        .map(|e| e.unwrap().file_name().to_string_lossy().to_string())
+        .collect();
+    names.sort();
+    let expected: Vec<String> = (0..3000)
+        .map(|e| format!("file-with-quite-a-long-name-{:05}.txt", e))
+        .collect();
+    assert_eq!(names, expected);
+
+    // directory is read only once, continuations are served from the snapshot
+    let logs = mounted_fs.logs();
+    assert!(logs.matches("Readdir (").count() > 2);
+    assert_eq!(
+        logs.matches(&format!("Directory '{}' listed", dd.join("big").display()))
+            .count(),
+        1
+    );
+}
+
+#[rstest]
+fn inode_flags(mounted_fs_no_convert: utils::FuseZstdProcess) {
+    let mp = mounted_fs_no_convert.mount_point();
+    let dd = mounted_fs_no_convert.data_dir();
+    let path = mp.join("file.txt");
+    fs::write(&path, b"DATA").unwrap();
+
+    let chattr = |flags: &str| {
+        let status = process::Command::new("chattr")
+            .arg(flags)
+            .arg(&path)
+            .status()
+            .unwrap();
+        assert!(status.success());
+    };
+
+    // immutable file can't be modified or removed
+    chattr("+i");
+    let output = process::Command::new("lsattr").arg(&path).output().unwrap();
+    let listed = String::from_utf8_lossy(&output.stdout);
+    assert!(listed.split_whitespace().next().unwrap().contains('i'));
+    let output = process::Command::new("rm").arg(&path).output().unwrap();
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Operation not permitted"));
+    let err = fs::write(&path, b"UPDATED").unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::PermissionDenied);
+    let err = fs::rename(&path, mp.join("renamed.txt")).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::PermissionDenied);
+    chattr("-i");
+
+    // data can only be appended to append-only file
+    chattr("+a");
+    let mut file = fs::OpenOptions::new().append(true).open(&path).unwrap();
+    file.write_all(b"MORE").unwrap();
+    drop(file);
+    assert_eq!(fs::read(&path).unwrap(), b"DATAMORE");
+    let file = fs::OpenOptions::new().write(true).open(&path).unwrap();
+    let err = file.write_at(b"data", 0).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::PermissionDenied);
+    drop(file);
+    assert!(fs::remove_file(&path).is_err());
+    chattr("-a");
+
+    fs::remove_file(&path).unwrap();
+    assert!(!dd.join("file.txt.zst").exists());
+}
+
+#[rstest]
+fn rebuild_cache(mounted_fs_no_convert: utils::FuseZstdProcess) {
+    let mp = mounted_fs_no_convert.mount_point();
+    let dd = mounted_fs_no_convert.data_dir();
+    fs::write(mp.join("file.txt"), b"DATA").unwrap();
+    let file = fs::File::open(mp.join("file.txt")).unwrap();
+    let ino = file.metadata().unwrap().st_ino();
+
+    // moved directly in data dir, cached path of the opened inode is wrong
+    fs::rename(dd.join("file.txt.zst"), dd.join("moved.txt.zst")).unwrap();
+    sleep(Duration::from_secs(2));
+    assert!(file.metadata().is_err());
+
+    // cache is rebuilt once the control file is closed
+    fs::write(mp.join(".fuse-zstd-rebuild-cache"), b"1").unwrap();
+    let mut retries = 0;
+    while file.metadata().is_err() && retries < 50 {
+        sleep(Duration::from_millis(100));
+        retries += 1;
+    }
+    let metadata = file.metadata().unwrap();
+    assert_eq!(metadata.st_ino(), ino);
+    assert_eq!(metadata.len(), 4);
+    assert_eq!(fs::metadata(mp.join("moved.txt")).unwrap().st_ino(), ino);
+    assert_eq!(fs::read(mp.join("moved.txt")).unwrap(), b"DATA");
+    assert!(!dd.join(".fuse-zstd-rebuild-cache.zst").exists());
+}
+
+#[rstest]
+fn mmap_write(mounted_fs_no_convert: utils::FuseZstdProcess) {
+    let mp = mounted_fs_no_convert.mount_point();
+    let dd = mounted_fs_no_convert.data_dir();
+    let path = mp.join("file.txt");
+    fs::write(&path, b"0123456789").unwrap();
+
+    let file = fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(&path)
+        .unwrap();
+    unsafe {
+        let addr = libc::mmap(
+            ptr::null_mut(),
+            10,
+            libc::PROT_READ | libc::PROT_WRITE,
+            libc::MAP_SHARED,
+            file.as_raw_fd(),
+            0,
+        );
+        assert_ne!(addr, libc::MAP_FAILED);
+        let data = std::slice::from_raw_parts_mut(addr as *mut u8, 10);
+        data[..4].copy_from_slice(b"ABCD");
+
+        // changes are compressed on msync
+        assert_eq!(libc::msync(addr, 10, libc::MS_SYNC), 0);
+        assert_eq!(
+            utils::get_compressed_content(dd.join("file.txt.zst")),
+            "ABCD456789"
+        );
+
+        // pages are written back when the mapping is removed
+        data[9] = b'Z';
+        assert_eq!(libc::munmap(addr, 10), 0);
+    }
+    mem::drop(file);
+
+    // release is sent asynchronously
+    let mut retries = 0;
+    while utils::get_compressed_content(dd.join("file.txt.zst")) != "ABCD45678Z" && retries < 50 {
+        sleep(Duration::from_millis(100));
+        retries += 1;
+    }
+    assert_eq!(
+        utils::get_compressed_content(dd.join("file.txt.zst")),
+        "ABCD45678Z"
+    );
+    assert_eq!(fs::read(&path).unwrap(), b"ABCD45678Z");
+}
+
+#[rstest]
+fn decimal_xattrs() {
+    let mounted_fs = utils::FuseZstdProcess::with_data(false, &[], |dd| {
+        // written by a third-party tool
+        let path = dd.join("file.txt.zst");
+        fs::write(&path, zstd::encode_all(&b"hello world"[..], 0).unwrap()).unwrap();
+        xattr::set(&path, "user.real_size", b"11").unwrap();
+        xattr::set(&path, "user.ino", b"4242\n").unwrap();
+
+        // 8 digits have the same length as a binary number
+        let path = dd.join("eight.txt.zst");
+        fs::write(&path, zstd::encode_all(&b"eight digits"[..], 0).unwrap()).unwrap();
+        xattr::set(&path, "user.real_size", b"12").unwrap();
+        xattr::set(&path, "user.ino", b"12345678").unwrap();
+    });
+    let mp = mounted_fs.mount_point();
+    let dd = mounted_fs.data_dir();
+
+    let metadata = fs::metadata(mp.join("file.txt")).unwrap();
+    assert_eq!(metadata.len(), 11);
+    assert_eq!(metadata.st_ino(), 4242);
+    let metadata = fs::metadata(mp.join("eight.txt")).unwrap();
+    assert_eq!(metadata.len(), 12);
+    assert_eq!(metadata.st_ino(), 12345678);
+    assert_eq!(fs::read(mp.join("file.txt")).unwrap(), b"hello world");
+
+    // stored file gets binary values
+    fs::write(mp.join("file.txt"), b"updated").unwrap();
+    assert_eq!(
+        xattr::get(dd.join("file.txt.zst"), "user.ino").unwrap(),
+        Some(4242u64.to_be_bytes().to_vec())
+    );
+    assert_eq!(fs::metadata(mp.join("file.txt")).unwrap().st_ino(), 4242);
+}
+
+#[rstest]
+fn truncate_on_open(mounted_fs_no_convert: utils::FuseZstdProcess) {
+    let mp = mounted_fs_no_convert.mount_point();
+    let dd = mounted_fs_no_convert.data_dir();
+    let path = mp.join("file.txt");
+    fs::write(&path, b"ORIGINAL DATA").unwrap();
+
+    let first = fs::File::open(&path).unwrap();
+    let mut buf = vec![0; 13];
+    first.read_exact_at(&mut buf, 0).unwrap();
+    assert_eq!(buf, b"ORIGINAL DATA");
+
+    // data shared by both handles are truncated
+    let mut second = fs::OpenOptions::new()
+        .write(true)
+        .truncate(true)
+        .open(&path)
+        .unwrap();
+    assert_eq!(first.metadata().unwrap().len(), 0);
+    assert_eq!(first.read_at(&mut buf, 0).unwrap(), 0);
+
+    second.write_all(b"NEW").unwrap();
+    assert_eq!(first.read_at(&mut buf, 0).unwrap(), 3);
+    assert_eq!(&buf[..3], b"NEW");
+    mem::drop(second);
+    mem::drop(first);
+    assert_eq!(
+        utils::get_compressed_content(dd.join("file.txt.zst")),
+        "NEW"
+    );
 }
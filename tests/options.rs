@@ -0,0 +1,2048 @@
+use assert_cmd::{cargo::cargo_bin, Command};
+use rstest::*;
+use std::{
+    fs,
+    io::Write,
+    mem,
+    os::unix::fs::{DirEntryExt, FileExt, FileTypeExt, MetadataExt, PermissionsExt},
+    path, process, thread,
+    time::{Duration, UNIX_EPOCH},
+};
+use tempfile::TempDir;
+
+#[path = "utils.rs"]
+pub mod utils;
+
+#[rstest]
+#[case::no_convert(false)]
+#[case::convert(true)]
+fn preserve_mtime(#[case] convert: bool) {
+    let mounted_fs = utils::FuseZstdProcess::with_args(convert, &["--preserve-mtime"]);
+    let mp = mounted_fs.mount_point();
+    let dd = mounted_fs.data_dir();
+    let mtime = UNIX_EPOCH + Duration::from_secs(946684800); // 2000-01-01
+
+    let mut file = fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(mp.join("file.txt"))
+        .unwrap();
+    file.write_all(b"OLD DATA").unwrap();
+    file.set_modified(mtime).unwrap();
+    // fsync will compress the file again
+    file.sync_all().unwrap();
+    mem::drop(file);
+
+    assert_eq!(
+        utils::get_compressed_content(dd.join("file.txt.zst")),
+        "OLD DATA"
+    );
+    assert_eq!(
+        fs::metadata(dd.join("file.txt.zst"))
+            .unwrap()
+            .modified()
+            .unwrap(),
+        mtime
+    );
+    assert_eq!(
+        fs::metadata(mp.join("file.txt"))
+            .unwrap()
+            .modified()
+            .unwrap(),
+        mtime
+    );
+}
+
+#[rstest]
+fn omitted_times() {
+    let mounted_fs = utils::FuseZstdProcess::with_args(false, &["--preserve-mtime"]);
+    let mp = mounted_fs.mount_point();
+    let path = mp.join("file.txt");
+    let time = UNIX_EPOCH + Duration::from_secs(946684800); // 2000-01-01
+    fs::write(&path, b"DATA").unwrap();
+    let mtime = fs::metadata(&path).unwrap().modified().unwrap();
+
+    // only atime is updated
+    let status = process::Command::new("touch")
+        .args(["-a", "-d", "@946684800"])
+        .arg(&path)
+        .status()
+        .unwrap();
+    assert!(status.success());
+    let metadata = fs::metadata(&path).unwrap();
+    assert_eq!(metadata.accessed().unwrap(), time);
+    assert_eq!(
+        metadata.mtime(),
+        mtime.duration_since(UNIX_EPOCH).unwrap().as_secs() as i64
+    );
+
+    // only mtime is updated
+    let status = process::Command::new("touch")
+        .args(["-m", "-d", "@978307200"])
+        .arg(&path)
+        .status()
+        .unwrap();
+    assert!(status.success());
+    let metadata = fs::metadata(&path).unwrap();
+    assert_eq!(metadata.accessed().unwrap(), time);
+    assert_eq!(
+        metadata.modified().unwrap(),
+        UNIX_EPOCH + Duration::from_secs(978307200)
+    );
+}
+
+#[rstest]
+fn compression_stats() {
+    let mounted_fs = utils::FuseZstdProcess::with_logs(false, &["-v"]);
+    let mp = mounted_fs.mount_point();
+
+    for i in 0..5 {
+        fs::write(mp.join(format!("file{}.txt", i)), b"DATA DATA DATA").unwrap();
+    }
+
+    let logs = mounted_fs.logs();
+    for i in 0..5 {
+        let needle = format!("file{}.txt.zst, level=0, input=14,", i);
+        assert!(
+            logs.lines()
+                .filter(|e| e.contains("Compression stats") && e.contains(&needle))
+                .count()
+                == 1,
+            "Missing compression stats for file{}.txt",
+            i
+        );
+    }
+}
+
+#[rstest]
+fn subdir() {
+    let mounted_fs = utils::FuseZstdProcess::with_args(false, &["--subdir", "project-a"]);
+    let mp = mounted_fs.mount_point();
+    let dd = mounted_fs.data_dir();
+
+    fs::create_dir_all(dd.join("project-b")).unwrap();
+    fs::write(
+        dd.join("project-b/hidden.txt.zst"),
+        zstd::bulk::compress(b"hidden", 0).unwrap(),
+    )
+    .unwrap();
+
+    fs::write(mp.join("file.txt"), b"VISIBLE").unwrap();
+    fs::create_dir(mp.join("directory")).unwrap();
+
+    assert_eq!(
+        utils::get_compressed_content(dd.join("project-a/file.txt.zst")),
+        "VISIBLE"
+    );
+    assert!(dd.join("project-a/directory").is_dir());
+    assert!(!dd.join("file.txt.zst").exists());
+
+    let mut entries: Vec<String> = fs::read_dir(&mp)
+        .unwrap()
+        .map(|e| e.unwrap().file_name().to_string_lossy().to_string())
+        .collect();
+    entries.sort();
+    assert_eq!(entries, vec!["directory", "file.txt"]);
+    assert!(!mp.join("project-b").exists());
+
+    // inode index is still stored in data dir
+    assert!(xattr::get(&dd, "user.ino_idx").unwrap().is_some());
+}
+
+#[rstest]
+fn verify_size() {
+    let mounted_fs = utils::FuseZstdProcess::with_logs(false, &["--verify-size"]);
+    let mp = mounted_fs.mount_point();
+    let dd = mounted_fs.data_dir();
+
+    fs::write(
+        dd.join("file.txt.zst"),
+        zstd::bulk::compress(b"compressed data", 0).unwrap(),
+    )
+    .unwrap();
+    xattr::set(
+        dd.join("file.txt.zst"),
+        "user.real_size",
+        &42u64.to_be_bytes(),
+    )
+    .unwrap();
+
+    let _file = fs::File::open(mp.join("file.txt")).unwrap();
+
+    assert_eq!(
+        xattr::get(dd.join("file.txt.zst"), "user.real_size")
+            .unwrap()
+            .map(|e| u64::from_be_bytes(e.try_into().unwrap())),
+        Some(15)
+    );
+    assert_eq!(utils::get_real_size(dd.join("file.txt.zst")), Some(15));
+    assert!(mounted_fs
+        .logs()
+        .contains("(stored=42, decompressed=15), fixing"));
+}
+
+#[rstest]
+fn compress_xattrs() {
+    let mounted_fs = utils::FuseZstdProcess::with_args(false, &["--compress-xattrs", "128"]);
+    let mp = mounted_fs.mount_point();
+    let dd = mounted_fs.data_dir();
+    let value = b"metadata ".repeat(300);
+
+    fs::write(mp.join("file.txt"), b"DATA").unwrap();
+    xattr::set(mp.join("file.txt"), "user.small", b"small").unwrap();
+    xattr::set(mp.join("file.txt"), "user.large", &value).unwrap();
+
+    // only large value is compressed
+    assert_eq!(
+        xattr::get(dd.join("file.txt.zst"), "user.small").unwrap(),
+        Some(b"small".to_vec())
+    );
+    assert!(
+        xattr::get(dd.join("file.txt.zst"), "user.large")
+            .unwrap()
+            .unwrap()
+            .len()
+            < value.len()
+    );
+    assert_eq!(
+        xattr::get(mp.join("file.txt"), "user.large").unwrap(),
+        Some(value.clone())
+    );
+
+    // internal attributes are hidden
+    let mut names: Vec<String> = xattr::list(mp.join("file.txt"))
+        .unwrap()
+        .map(|e| e.to_string_lossy().to_string())
+        .collect();
+    names.sort();
+    assert_eq!(names, vec!["user.large", "user.small"]);
+
+    // raw value which starts like a compressed one is kept as it is
+    let tricky = b"\0fuse-zstd\0not compressed".to_vec();
+    xattr::set(mp.join("file.txt"), "user.tricky", &tricky).unwrap();
+    assert_eq!(
+        xattr::get(mp.join("file.txt"), "user.tricky").unwrap(),
+        Some(tricky)
+    );
+    xattr::remove(mp.join("file.txt"), "user.tricky").unwrap();
+
+    // escaped value would exceed the size limit
+    let mut state: u32 = 1;
+    let mut huge = b"\0fuse-zstd\0".to_vec();
+    huge.extend((huge.len()..65536).map(|_| {
+        state ^= state << 13;
+        state ^= state >> 17;
+        state ^= state << 5;
+        state as u8
+    }));
+    let err = xattr::set(mp.join("file.txt"), "user.huge", &huge).unwrap_err();
+    assert_eq!(err.raw_os_error(), Some(libc::E2BIG));
+
+    // attributes are kept when the file is compressed again
+    fs::write(mp.join("file.txt"), b"NEW DATA").unwrap();
+    assert_eq!(
+        xattr::get(mp.join("file.txt"), "user.large").unwrap(),
+        Some(value)
+    );
+}
+
+#[rstest]
+#[case::no_convert(false)]
+#[case::convert(true)]
+fn keep_plain(#[case] convert: bool) {
+    let mounted_fs = utils::FuseZstdProcess::with_args(convert, &["--keep-plain"]);
+    let mp = mounted_fs.mount_point();
+    let dd = mounted_fs.data_dir();
+
+    fs::write(mp.join("file.txt"), b"BOTH COPIES").unwrap();
+    assert_eq!(
+        utils::get_compressed_content(dd.join("file.txt.zst")),
+        "BOTH COPIES"
+    );
+    assert_eq!(
+        fs::read_to_string(dd.join("file.txt")).unwrap(),
+        "BOTH COPIES"
+    );
+
+    // file is listed only once
+    let entries: Vec<String> = fs::read_dir(&mp)
+        .unwrap()
+        .map(|e| e.unwrap().file_name().to_string_lossy().to_string())
+        .collect();
+    assert_eq!(entries, vec!["file.txt"]);
+
+    fs::rename(mp.join("file.txt"), mp.join("renamed.txt")).unwrap();
+    assert!(dd.join("renamed.txt.zst").exists());
+    assert!(dd.join("renamed.txt").exists());
+    assert!(!dd.join("file.txt").exists());
+
+    fs::remove_file(mp.join("renamed.txt")).unwrap();
+    assert!(!dd.join("renamed.txt.zst").exists());
+    assert!(!dd.join("renamed.txt").exists());
+}
+
+#[rstest]
+fn file_and_dir_mode() {
+    let mounted_fs =
+        utils::FuseZstdProcess::with_args(false, &["--file-mode", "640", "--dir-mode", "750"]);
+    let mp = mounted_fs.mount_point();
+    let dd = mounted_fs.data_dir();
+
+    fs::write(mp.join("file.txt"), b"DATA").unwrap();
+    fs::create_dir(mp.join("directory")).unwrap();
+
+    let mode = |path: path::PathBuf| fs::metadata(path).unwrap().permissions().mode() & 0o7777;
+    assert_eq!(mode(mp.join("file.txt")), 0o640);
+    assert_eq!(mode(dd.join("file.txt.zst")), 0o640);
+    assert_eq!(mode(mp.join("directory")), 0o750);
+    assert_eq!(mode(dd.join("directory")), 0o750);
+
+    // permissions are kept when the file is compressed again
+    fs::write(mp.join("file.txt"), b"NEW DATA").unwrap();
+    assert_eq!(mode(mp.join("file.txt")), 0o640);
+
+    fs::set_permissions(mp.join("file.txt"), fs::Permissions::from_mode(0o600)).unwrap();
+    assert_eq!(mode(mp.join("file.txt")), 0o600);
+}
+
+#[rstest]
+fn index_interval() {
+    let mounted_fs = utils::FuseZstdProcess::with_logs(false, &["--index-interval", "65536"]);
+    let mp = mounted_fs.mount_point();
+    let dd = mounted_fs.data_dir();
+
+    let data: Vec<u8> = (0..1024 * 1024).map(|e| (e % 251) as u8).collect();
+    fs::write(mp.join("file.bin"), &data).unwrap();
+
+    // readable by other zstd tools
+    assert_eq!(
+        zstd::decode_all(fs::File::open(dd.join("file.bin.zst")).unwrap()).unwrap(),
+        data
+    );
+
+    // read near the end of the file
+    let file = fs::File::open(mp.join("file.bin")).unwrap();
+    let mut buffer = vec![0; 100];
+    file.read_exact_at(&mut buffer, 1000000).unwrap();
+    assert_eq!(buffer, data[1000000..1000100]);
+    assert!(mounted_fs
+        .logs()
+        .contains("Decompressing from index entry (offset=983040,"));
+
+    // whole file
+    assert_eq!(fs::read(mp.join("file.bin")).unwrap(), data);
+
+    // corrupted size of the first frame is not trusted
+    fs::write(mp.join("other.bin"), &data).unwrap();
+    let file = fs::OpenOptions::new()
+        .write(true)
+        .open(dd.join("other.bin.zst"))
+        .unwrap();
+    file.write_all_at(&(1u64 << 40).to_le_bytes(), 24).unwrap();
+    drop(file);
+    thread::sleep(Duration::from_millis(1100));
+    assert!(fs::read(mp.join("other.bin")).is_err());
+    assert_eq!(fs::read(mp.join("file.bin")).unwrap(), data);
+}
+
+#[rstest]
+#[case::single(false)]
+#[case::hardlinked(true)]
+fn index_reader_while_writing(#[case] hardlinked: bool) {
+    let mounted_fs = utils::FuseZstdProcess::with_logs(false, &["--index-interval", "65536"]);
+    let mp = mounted_fs.mount_point();
+    let dd = mounted_fs.data_dir();
+
+    let data: Vec<u8> = (0..1024 * 1024).map(|e| (e % 251) as u8).collect();
+    fs::write(mp.join("file.bin"), &data).unwrap();
+    if hardlinked {
+        // stored in place, so the reader's descriptor sees the rewritten data
+        fs::hard_link(dd.join("file.bin.zst"), dd.join("link.bin.zst")).unwrap();
+    }
+
+    let reader = fs::File::open(mp.join("file.bin")).unwrap();
+    let mut buffer = vec![0; 100];
+    reader.read_exact_at(&mut buffer, 1000000).unwrap();
+    assert_eq!(buffer, data[1000000..1000100]);
+    assert!(mounted_fs.logs().contains("using index"));
+
+    // file which is not opened for writing is stored again
+    let path = std::ffi::CString::new(mp.join("file.bin").to_str().unwrap()).unwrap();
+    assert_eq!(unsafe { libc::truncate(path.as_ptr(), 500000) }, 0);
+    assert_eq!(reader.metadata().unwrap().len(), 500000);
+    assert_eq!(reader.read_at(&mut buffer, 1000000).unwrap(), 0);
+    reader.read_exact_at(&mut buffer, 400000).unwrap();
+    assert_eq!(buffer, data[400000..400100]);
+
+    // reader shares the data of the writer
+    let writer = fs::OpenOptions::new()
+        .write(true)
+        .open(mp.join("file.bin"))
+        .unwrap();
+    writer.write_all_at(b"CHANGED", 300000).unwrap();
+    writer.sync_all().unwrap();
+    let mut changed = vec![0; 7];
+    reader.read_exact_at(&mut changed, 300000).unwrap();
+    assert_eq!(changed, b"CHANGED");
+    reader.read_exact_at(&mut buffer, 200000).unwrap();
+    assert_eq!(buffer, data[200000..200100]);
+    mem::drop(writer);
+
+    reader.read_exact_at(&mut changed, 300000).unwrap();
+    assert_eq!(changed, b"CHANGED");
+    mem::drop(reader);
+    let mut expected = data[..500000].to_vec();
+    expected[300000..300007].copy_from_slice(b"CHANGED");
+    assert_eq!(fs::read(mp.join("file.bin")).unwrap(), expected);
+}
+
+#[rstest]
+fn missing_mount_point() {
+    let data_dir = TempDir::new_in("/tmp/").unwrap();
+    let root = TempDir::new_in("/tmp/").unwrap();
+    let mount_point = root.path().join("missing");
+
+    let assert = Command::cargo_bin("fuse-zstd")
+        .unwrap()
+        .args(["--data-dir", data_dir.path().to_str().unwrap()])
+        .args(["--mount-point", mount_point.to_str().unwrap()])
+        .assert()
+        .failure();
+    let stderr = String::from_utf8_lossy(&assert.get_output().stderr).to_string();
+    assert!(stderr.contains("doesn't exist"));
+    assert!(!mount_point.exists());
+
+    // mount point is a file
+    fs::write(&mount_point, b"FILE").unwrap();
+    let assert = Command::cargo_bin("fuse-zstd")
+        .unwrap()
+        .args(["--data-dir", data_dir.path().to_str().unwrap()])
+        .args(["--mount-point", mount_point.to_str().unwrap()])
+        .assert()
+        .failure();
+    let stderr = String::from_utf8_lossy(&assert.get_output().stderr).to_string();
+    assert!(stderr.contains("is not a directory"));
+}
+
+#[rstest]
+fn mkdir_mount_point() {
+    let data_dir = TempDir::new_in("/tmp/").unwrap();
+    let root = TempDir::new_in("/tmp/").unwrap();
+    let mount_point = root.path().join("created");
+
+    let mut process = process::Command::new(cargo_bin("fuse-zstd"))
+        .args(["--data-dir", data_dir.path().to_str().unwrap()])
+        .args(["--mount-point", mount_point.to_str().unwrap()])
+        .arg("--mkdir-mount-point")
+        .spawn()
+        .unwrap();
+
+    // wait till the fs is mounted
+    for _ in 0..50 {
+        if fs::write(mount_point.join("file.txt"), b"DATA").is_ok() {
+            break;
+        }
+        thread::sleep(Duration::from_millis(200));
+    }
+    process.kill().unwrap();
+    process.wait().unwrap();
+
+    assert!(data_dir.path().join("file.txt.zst").exists());
+}
+
+#[rstest]
+fn zstd_param() {
+    let default_fs = utils::FuseZstdProcess::new(false);
+    let tuned_fs = utils::FuseZstdProcess::with_args(false, &["--zstd-param", "strategy=btultra2"]);
+
+    let data: Vec<u8> = (0..100_000u32)
+        .flat_map(|e| format!("line {} {}\n", e % 97, e % 13).into_bytes())
+        .collect();
+    fs::write(default_fs.mount_point().join("file.txt"), &data).unwrap();
+    fs::write(tuned_fs.mount_point().join("file.txt"), &data).unwrap();
+
+    let default_compressed = fs::read(default_fs.data_dir().join("file.txt.zst")).unwrap();
+    let tuned_compressed = fs::read(tuned_fs.data_dir().join("file.txt.zst")).unwrap();
+    assert_ne!(default_compressed, tuned_compressed);
+    assert_eq!(zstd::decode_all(&tuned_compressed[..]).unwrap(), data);
+    assert_eq!(
+        fs::read(tuned_fs.mount_point().join("file.txt")).unwrap(),
+        data
+    );
+
+    // unknown name is ignored but wrong value fails
+    let assert = Command::cargo_bin("fuse-zstd")
+        .unwrap()
+        .args(["--data-dir", default_fs.data_dir().to_str().unwrap()])
+        .args(["--mount-point", "/nonexistent"])
+        .args(["--zstd-param", "hashLog=1000"])
+        .assert()
+        .failure();
+    let stderr = String::from_utf8_lossy(&assert.get_output().stderr).to_string();
+    assert!(stderr.contains("Wrong zstd parameter 'hashLog=1000'"));
+}
+
+#[rstest]
+fn import() {
+    let mounted_fs = utils::FuseZstdProcess::with_data(false, &["--import"], |dd| {
+        // dataset created by zstd cli
+        fs::create_dir_all(dd.join("dir")).unwrap();
+        for path in ["first.txt.zst", "dir/second.txt.zst", "dir/third.txt.zst"] {
+            fs::write(dd.join(path), zstd::encode_all(&b"DATA"[..], 0).unwrap()).unwrap();
+        }
+    });
+    let mp = mounted_fs.mount_point();
+    let dd = mounted_fs.data_dir();
+
+    let stored_ino = |path: &str| {
+        u64::from_be_bytes(
+            xattr::get(dd.join(path), "user.ino")
+                .unwrap()
+                .unwrap()
+                .try_into()
+                .unwrap(),
+        )
+    };
+    assert_eq!(stored_ino("dir"), 2);
+    assert_eq!(stored_ino("dir/second.txt.zst"), 3);
+    assert_eq!(stored_ino("dir/third.txt.zst"), 4);
+    assert_eq!(stored_ino("first.txt.zst"), 5);
+
+    // inodes are not changed by listing
+    for _ in 0..2 {
+        let inodes: Vec<_> = ["dir", "dir/second.txt", "dir/third.txt", "first.txt"]
+            .iter()
+            .map(|e| fs::metadata(mp.join(e)).unwrap().ino())
+            .collect();
+        assert_eq!(inodes, vec![2, 3, 4, 5]);
+        fs::read_dir(&mp).unwrap().for_each(drop);
+        fs::read_dir(mp.join("dir")).unwrap().for_each(drop);
+    }
+}
+
+#[rstest]
+fn readahead() {
+    let mounted_fs = utils::FuseZstdProcess::with_args(
+        false,
+        &["--index-interval", "16384", "--readahead", "64"],
+    );
+    let mp = mounted_fs.mount_point();
+
+    let data: Vec<u8> = (0..4 * 1024 * 1024).map(|e| (e % 251) as u8).collect();
+    fs::write(mp.join("file.bin"), &data).unwrap();
+
+    // sequential read
+    let file = fs::File::open(mp.join("file.bin")).unwrap();
+    let mut buffer = vec![0; 5000];
+    let mut offset = 0;
+    while offset < data.len() {
+        let size = file.read_at(&mut buffer, offset as u64).unwrap();
+        assert!(size > 0);
+        assert_eq!(buffer[..size], data[offset..offset + size]);
+        offset += size;
+    }
+
+    // jumping back and forth drops prefetched frames
+    for offset in [3000000, 100, 2000000, 2016384, 50000] {
+        file.read_exact_at(&mut buffer, offset).unwrap();
+        assert_eq!(buffer, data[offset as usize..offset as usize + 5000]);
+    }
+}
+
+fn json_record(i: usize) -> String {
+    format!(
+        r#"{{"id": {}, "name": "user-{}", "email": "user-{}@example.com", "active": {}, "roles": ["reader", "writer"], "address": {{"city": "City {}", "zip": "{:05}"}}}}"#,
+        i,
+        i,
+        i,
+        i.is_multiple_of(2),
+        i % 17,
+        i * 7,
+    )
+}
+
+#[rstest]
+fn auto_dictionary() {
+    let prepare = |dd: &path::Path| {
+        for i in 0..500 {
+            fs::write(
+                dd.join(format!("record{}.json.zst", i)),
+                zstd::encode_all(json_record(i).as_bytes(), 0).unwrap(),
+            )
+            .unwrap();
+        }
+    };
+    let plain_fs = utils::FuseZstdProcess::with_data(false, &[], prepare);
+    let dict_fs = utils::FuseZstdProcess::with_data(false, &["--auto-dictionary"], prepare);
+    assert!(dict_fs.data_dir().join(".fuse-zstd-dict").exists());
+
+    // dictionary is not visible
+    assert!(!fs::read_dir(dict_fs.mount_point()).unwrap().any(|e| e
+        .unwrap()
+        .file_name()
+        .to_string_lossy()
+        .contains("dict")));
+
+    let record = json_record(1000);
+    fs::write(plain_fs.mount_point().join("new.json"), &record).unwrap();
+    fs::write(dict_fs.mount_point().join("new.json"), &record).unwrap();
+
+    let plain_size = fs::metadata(plain_fs.data_dir().join("new.json.zst"))
+        .unwrap()
+        .len();
+    let dict_size = fs::metadata(dict_fs.data_dir().join("new.json.zst"))
+        .unwrap()
+        .len();
+    assert!(dict_size < plain_size);
+
+    // files are readable using the dictionary
+    assert_eq!(
+        fs::read_to_string(dict_fs.mount_point().join("new.json")).unwrap(),
+        record
+    );
+    assert_eq!(
+        fs::read_to_string(dict_fs.mount_point().join("record5.json")).unwrap(),
+        json_record(5)
+    );
+
+    // dictionary is used for new files only with --auto-dictionary (but it is loaded for reading)
+    let dict = fs::read(dict_fs.data_dir().join(".fuse-zstd-dict")).unwrap();
+    let compressed = fs::read(dict_fs.data_dir().join("new.json.zst")).unwrap();
+    let other_fs = utils::FuseZstdProcess::with_data(false, &[], |dd| {
+        fs::write(dd.join(".fuse-zstd-dict"), &dict).unwrap();
+        fs::write(dd.join("dict.json.zst"), &compressed).unwrap();
+    });
+    fs::write(other_fs.mount_point().join("new.json"), &record).unwrap();
+    assert_eq!(
+        utils::get_compressed_content(other_fs.data_dir().join("new.json.zst")),
+        record
+    );
+    assert_eq!(
+        fs::read_to_string(other_fs.mount_point().join("dict.json")).unwrap(),
+        record
+    );
+}
+
+#[rstest]
+fn dictionary_dir() {
+    // dictionary shared with another system
+    let samples: Vec<Vec<u8>> = (0..500).map(|e| json_record(e).into_bytes()).collect();
+    let dict = zstd::dict::from_samples(&samples, 4096).unwrap();
+    let id = zstd::zstd_safe::get_dict_id_from_dict(&dict).unwrap().get();
+    let dict_dir = TempDir::new().unwrap();
+    fs::write(dict_dir.path().join(id.to_string()), &dict).unwrap();
+
+    let record = json_record(1000);
+    let compressed = zstd::bulk::Compressor::with_dictionary(3, &dict)
+        .unwrap()
+        .compress(record.as_bytes())
+        .unwrap();
+    let prepare = |dd: &path::Path| fs::write(dd.join("record.json.zst"), &compressed).unwrap();
+
+    let mounted_fs = utils::FuseZstdProcess::with_data(
+        false,
+        &["--dictionary-dir", dict_dir.path().to_str().unwrap()],
+        prepare,
+    );
+    assert_eq!(
+        fs::read_to_string(mounted_fs.mount_point().join("record.json")).unwrap(),
+        record
+    );
+
+    // dictionary is required
+    let mounted_fs = utils::FuseZstdProcess::with_data(false, &[], prepare);
+    assert!(fs::read(mounted_fs.mount_point().join("record.json")).is_err());
+}
+
+#[rstest]
+#[case::single_user(&[], false)]
+#[case::allow_other(&["--allow-other"], true)]
+fn allow_other(#[case] args: &[&str], #[case] accessible: bool) {
+    let mounted_fs = utils::FuseZstdProcess::with_args(false, args);
+    let mp = mounted_fs.mount_point();
+    fs::write(mp.join("file.txt"), b"DATA").unwrap();
+
+    // access as a different user (requires root)
+    let status = process::Command::new("setpriv")
+        .args(["--reuid=65534", "--regid=65534", "--clear-groups", "cat"])
+        .arg(mp.join("file.txt"))
+        .status();
+    if let Ok(status) = status {
+        assert_eq!(status.success(), accessible);
+    }
+}
+
+#[rstest]
+fn read_only_data_dir() {
+    let cache_dir = TempDir::new_in("/tmp/").unwrap();
+    let mounted_fs = utils::FuseZstdProcess::with_data(
+        false,
+        &["--cache-dir", cache_dir.path().to_str().unwrap()],
+        |dd| {
+            let path = dd.join("file.txt.zst");
+            fs::write(&path, zstd::encode_all(&b"DATA"[..], 0).unwrap()).unwrap();
+            xattr::set(&path, "user.ino", &2u64.to_be_bytes()).unwrap();
+
+            // read-only bind mount (requires root)
+            let _ = process::Command::new("mount")
+                .args(["--bind", "-o", "ro"])
+                .arg(dd)
+                .arg(dd)
+                .status();
+        },
+    );
+    let mp = mounted_fs.mount_point();
+    let dd = mounted_fs.data_dir();
+
+    assert_eq!(fs::read(mp.join("file.txt")).unwrap(), b"DATA");
+    if fs::write(dd.join("test"), b"").is_err() {
+        // cache was created in the fallback location
+        assert!(!dd.join(".fuse-zstd-inode_cache").exists());
+        assert_eq!(fs::read_dir(cache_dir.path()).unwrap().count(), 1);
+        let _ = process::Command::new("umount").arg("-l").arg(&dd).status();
+    }
+}
+
+#[rstest]
+fn unlink_opened_with_cache_dir() {
+    // cache dir on another device than data dir (tmpfs when available)
+    let cache_dir = TempDir::new_in("/dev/shm/")
+        .or_else(|_| TempDir::new_in("/tmp/"))
+        .unwrap();
+    let mounted_fs = utils::FuseZstdProcess::with_data(
+        false,
+        &["--cache-dir", cache_dir.path().to_str().unwrap()],
+        |dd| {
+            fs::create_dir(dd.join("sub")).unwrap();
+            xattr::set(dd.join("sub"), "user.ino", &2u64.to_be_bytes()).unwrap();
+            let path = dd.join("sub/file.txt.zst");
+            fs::write(&path, zstd::encode_all(&b"DATA"[..], 0).unwrap()).unwrap();
+            xattr::set(&path, "user.ino", &3u64.to_be_bytes()).unwrap();
+
+            // only the subdirectory is writable (ignored by root)
+            fs::set_permissions(dd, fs::Permissions::from_mode(0o555)).unwrap();
+        },
+    );
+    let mp = mounted_fs.mount_point();
+    let dd = mounted_fs.data_dir();
+
+    let file = fs::File::open(mp.join("sub/file.txt")).unwrap();
+    fs::remove_file(mp.join("sub/file.txt")).unwrap();
+    assert!(!mp.join("sub/file.txt").exists());
+    assert!(!dd.join("sub/file.txt.zst").exists());
+
+    // file is still accessible via opened file
+    let mut buffer = vec![0; 4];
+    assert_eq!(file.read_at(&mut buffer, 0).unwrap(), 4);
+    assert_eq!(buffer, b"DATA");
+    mem::drop(file);
+
+    fs::set_permissions(&dd, fs::Permissions::from_mode(0o755)).unwrap();
+}
+
+#[rstest]
+fn direct_io() {
+    let mounted_fs = utils::FuseZstdProcess::with_args(false, &["--direct-io"]);
+    let mp = mounted_fs.mount_point();
+    let dd = mounted_fs.data_dir();
+
+    fs::write(mp.join("file.txt"), b"FIRST").unwrap();
+    assert_eq!(fs::read(mp.join("file.txt")).unwrap(), b"FIRST");
+
+    // modify the backing file externally
+    let path = dd.join("file.txt.zst");
+    let ino = xattr::get(&path, "user.ino").unwrap().unwrap();
+    fs::write(&path, zstd::encode_all(&b"SECOND"[..], 0).unwrap()).unwrap();
+    xattr::set(&path, "user.ino", &ino).unwrap();
+
+    assert_eq!(fs::read(mp.join("file.txt")).unwrap(), b"SECOND");
+}
+
+#[rstest]
+#[case::default(&[])]
+#[case::indexed(&["--index-interval", "65536"])]
+fn zstd_cli_interop(#[case] args: &[&str]) {
+    if process::Command::new("zstd")
+        .arg("--version")
+        .output()
+        .is_err()
+    {
+        eprintln!("zstd tool is not installed, skipping");
+        return;
+    }
+    let mounted_fs = utils::FuseZstdProcess::with_args(false, args);
+    let mp = mounted_fs.mount_point();
+    let dd = mounted_fs.data_dir();
+    let data: Vec<u8> = (0..200_000u32)
+        .flat_map(|e| format!("{} {}\n", e, e.wrapping_mul(2654435761)).into_bytes())
+        .collect();
+
+    // stored file is decompressed by zstd tool
+    fs::write(mp.join("file.bin"), &data).unwrap();
+    let output = process::Command::new("zstd")
+        .args(["-d", "-c"])
+        .arg(dd.join("file.bin.zst"))
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    assert_eq!(output.stdout, data);
+
+    // frames contain checksum and decompressed size
+    let header = fs::read(dd.join("file.bin.zst")).unwrap();
+    assert_eq!(header[..4], [0x28, 0xb5, 0x2f, 0xfd]);
+    let descriptor = header[4];
+    assert_ne!(descriptor & 0x04, 0);
+    assert!(descriptor >> 6 != 0 || descriptor & 0x20 != 0);
+    let status = process::Command::new("zstd")
+        .arg("-t")
+        .arg(dd.join("file.bin.zst"))
+        .status()
+        .unwrap();
+    assert!(status.success());
+
+    // file compressed by zstd tool is read through the mount point
+    let plain = dd.join("plain.bin");
+    fs::write(&plain, &data).unwrap();
+    let status = process::Command::new("zstd")
+        .args(["-q", "--rm", "-o"])
+        .arg(dd.join("external.bin.zst"))
+        .arg(&plain)
+        .status()
+        .unwrap();
+    assert!(status.success());
+    // size is known once the file is opened, cached attributes expire meanwhile
+    drop(fs::File::open(mp.join("external.bin")).unwrap());
+    thread::sleep(Duration::from_millis(1100));
+    assert_eq!(fs::read(mp.join("external.bin")).unwrap(), data);
+}
+
+#[rstest]
+fn cdc() {
+    let mounted_fs = utils::FuseZstdProcess::with_args(false, &["--cdc"]);
+    let mp = mounted_fs.mount_point();
+    let dd = mounted_fs.data_dir();
+
+    // incompressible data
+    let mut state: u32 = 7;
+    let first: Vec<u8> = (0..2 * 1024 * 1024)
+        .map(|_| {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            state as u8
+        })
+        .collect();
+    let mut second = first.clone();
+    second.splice(1024 * 1024..1024 * 1024, vec![1; 100]);
+
+    fs::write(mp.join("first.bin"), &first).unwrap();
+    fs::write(mp.join("second.bin"), &second).unwrap();
+
+    // shared chunks are stored once
+    let stored_size: u64 = fs::read_dir(dd.join(".fuse-zstd-chunks"))
+        .unwrap()
+        .flat_map(|e| fs::read_dir(e.unwrap().path()).unwrap())
+        .map(|e| e.unwrap().metadata().unwrap().len())
+        .sum();
+    assert!(stored_size < first.len() as u64 * 3 / 2);
+
+    // chunked files are not mistaken for plain zstd files
+    assert!(dd.join("first.bin.zstc").is_file());
+    assert!(!dd.join("first.bin.zst").exists());
+
+    assert_eq!(fs::read(mp.join("first.bin")).unwrap(), first);
+    assert_eq!(fs::read(mp.join("second.bin")).unwrap(), second);
+
+    // chunk dir is not visible
+    assert_eq!(fs::read_dir(&mp).unwrap().count(), 2);
+}
+
+#[rstest]
+fn cdc_invalid_lists() {
+    let list = |name: &str| {
+        let mut payload = 4u64.to_le_bytes().to_vec();
+        payload.push(name.len() as u8);
+        payload.extend(name.as_bytes());
+        let mut data = 0x184D2A5Fu32.to_le_bytes().to_vec();
+        data.extend((payload.len() as u32).to_le_bytes());
+        data.extend(payload);
+        data
+    };
+    let mounted_fs = utils::FuseZstdProcess::with_data(false, &["--cdc"], |dd| {
+        fs::create_dir_all(dd.join(".fuse-zstd-chunks/ab")).unwrap();
+        fs::write(
+            dd.join(".fuse-zstd-chunks/ab/abcd"),
+            zstd::encode_all(&b"DATA"[..], 0).unwrap(),
+        )
+        .unwrap();
+        fs::write(
+            dd.join("secret"),
+            zstd::encode_all(&b"SECRET"[..], 0).unwrap(),
+        )
+        .unwrap();
+        fs::write(dd.join("escaping.bin.zstc"), list("../secret")).unwrap();
+        // truncated list may reference the chunk
+        let mut truncated = list("ab/abcd");
+        truncated.truncate(truncated.len() - 2);
+        fs::write(dd.join("truncated.bin.zstc"), truncated).unwrap();
+    });
+    let mp = mounted_fs.mount_point();
+    let dd = mounted_fs.data_dir();
+
+    // chunks are kept when any list can't be read
+    assert!(dd.join(".fuse-zstd-chunks/ab/abcd").exists());
+    assert!(fs::read(mp.join("escaping.bin")).is_err());
+    assert!(fs::read(mp.join("truncated.bin")).is_err());
+}
+
+#[rstest]
+fn append_only() {
+    let mounted_fs = utils::FuseZstdProcess::with_args(false, &["--append-only"]);
+    let mp = mounted_fs.mount_point();
+    let dd = mounted_fs.data_dir();
+
+    fs::write(mp.join("log.txt"), b"FIRST").unwrap();
+
+    // appending works
+    let mut file = fs::OpenOptions::new()
+        .append(true)
+        .open(mp.join("log.txt"))
+        .unwrap();
+    file.write_all(b" SECOND").unwrap();
+    mem::drop(file);
+    assert_eq!(
+        utils::get_compressed_content(dd.join("log.txt.zst")),
+        "FIRST SECOND"
+    );
+
+    // existing data can't be changed
+    let file = fs::OpenOptions::new()
+        .write(true)
+        .open(mp.join("log.txt"))
+        .unwrap();
+    assert_eq!(
+        file.write_at(b"XXX", 0).unwrap_err().raw_os_error(),
+        Some(libc::EPERM)
+    );
+    assert_eq!(
+        file.set_len(3).unwrap_err().raw_os_error(),
+        Some(libc::EPERM)
+    );
+    mem::drop(file);
+    assert_eq!(
+        fs::write(mp.join("log.txt"), b"NEW")
+            .unwrap_err()
+            .raw_os_error(),
+        Some(libc::EPERM)
+    );
+    assert_eq!(
+        fs::remove_file(mp.join("log.txt"))
+            .unwrap_err()
+            .raw_os_error(),
+        Some(libc::EPERM)
+    );
+    assert_eq!(
+        utils::get_compressed_content(dd.join("log.txt.zst")),
+        "FIRST SECOND"
+    );
+}
+
+fn read_stats(data_dir: &path::Path) -> Option<(u64, u64)> {
+    let content = fs::read_to_string(data_dir.join(".fuse-zstd-stats")).ok()?;
+    let counter = |name: &str| {
+        content
+            .lines()
+            .find_map(|e| e.strip_prefix(&format!("{}=", name)))
+            .and_then(|e| e.parse().ok())
+    };
+    Some((counter("compressions")?, counter("bytes_compressed")?))
+}
+
+fn wait_for_stats(data_dir: &path::Path, compressions: u64) -> (u64, u64) {
+    // stored once per flush interval
+    for _ in 0..50 {
+        match read_stats(data_dir) {
+            Some(stats) if stats.0 >= compressions => return stats,
+            _ => thread::sleep(Duration::from_millis(100)),
+        }
+    }
+    panic!("Stats were not stored");
+}
+
+#[rstest]
+fn persisted_stats() {
+    let mounted_fs = utils::FuseZstdProcess::with_args(false, &["--flush-interval", "1"]);
+    let mp = mounted_fs.mount_point();
+    let dd = mounted_fs.data_dir();
+
+    fs::write(mp.join("first.txt"), b"FIRST").unwrap();
+    assert_eq!(fs::read(mp.join("first.txt")).unwrap(), b"FIRST");
+    let (compressions, bytes) = wait_for_stats(&dd, 1);
+    assert_eq!((compressions, bytes), (1, 5));
+    let stats = fs::read(dd.join(".fuse-zstd-stats")).unwrap();
+    assert!(!fs::read_dir(&mp)
+        .unwrap()
+        .any(|e| e.unwrap().file_name() == ".fuse-zstd-stats"));
+    mem::drop(mounted_fs);
+
+    // counters continue after remount
+    let mounted_fs = utils::FuseZstdProcess::with_data(false, &["--flush-interval", "1"], |dd| {
+        fs::write(dd.join(".fuse-zstd-stats"), &stats).unwrap();
+    });
+    let mp = mounted_fs.mount_point();
+    let dd = mounted_fs.data_dir();
+    fs::write(mp.join("second.txt"), b"SECOND").unwrap();
+    assert_eq!(wait_for_stats(&dd, 2), (2, 11));
+}
+
+#[rstest]
+fn max_concurrent_decompress() {
+    let mounted_fs = utils::FuseZstdProcess::with_logs(
+        false,
+        &[
+            "--max-concurrent-decompress",
+            "2",
+            "--index-interval",
+            "65536",
+            "--readahead",
+            "256",
+        ],
+    );
+    let mp = mounted_fs.mount_point();
+
+    let data: Vec<u8> = (0..2 * 1024 * 1024).map(|e| (e % 251) as u8).collect();
+    for i in 0..8 {
+        fs::write(mp.join(format!("file{}.bin", i)), &data).unwrap();
+    }
+
+    // whole files are decompressed when opened for writing
+    // and readahead decompresses frames in background
+    let handles: Vec<_> = (0..8)
+        .map(|i| {
+            let path = mp.join(format!("file{}.bin", i));
+            let data = data.clone();
+            thread::spawn(move || {
+                let read_only = i % 2 == 0;
+                let file = fs::OpenOptions::new()
+                    .read(true)
+                    .write(!read_only)
+                    .open(path)
+                    .unwrap();
+                let mut buffer = vec![0; 100000];
+                let mut offset = 0;
+                while offset < data.len() {
+                    let size = file.read_at(&mut buffer, offset as u64).unwrap();
+                    assert_eq!(buffer[..size], data[offset..offset + size]);
+                    offset += size;
+                }
+            })
+        })
+        .collect();
+    for handle in handles {
+        handle.join().unwrap();
+    }
+
+    let running: Vec<usize> = mounted_fs
+        .logs()
+        .lines()
+        .filter_map(|e| e.split("Decompression slot taken (running=").nth(1))
+        .map(|e| e.trim_end_matches(')').parse().unwrap())
+        .collect();
+    assert!(!running.is_empty());
+    assert!(running.iter().all(|e| *e <= 2));
+}
+
+#[rstest]
+fn convert_dry_run() {
+    let mounted_fs = utils::FuseZstdProcess::with_logs(true, &["--convert-dry-run"]);
+    let mp = mounted_fs.mount_point();
+    let dd = mounted_fs.data_dir();
+
+    fs::write(dd.join("plain.txt"), b"PLAIN").unwrap();
+    fs::write(dd.join("both.txt"), b"BOTH").unwrap();
+    fs::write(
+        dd.join("both.txt.zst"),
+        zstd::encode_all(&b"BOTH"[..], 3).unwrap(),
+    )
+    .unwrap();
+
+    let mut names: Vec<_> = fs::read_dir(&mp)
+        .unwrap()
+        .map(|e| e.unwrap().file_name().to_string_lossy().to_string())
+        .collect();
+    names.sort();
+    names.dedup();
+    assert_eq!(names, vec!["both.txt", "plain.txt"]);
+    assert_eq!(fs::metadata(mp.join("plain.txt")).unwrap().len(), 5);
+    assert!(fs::metadata(mp.join("both.txt")).is_ok());
+
+    let logs = mounted_fs.logs();
+    assert!(logs.contains(&format!(
+        "'{}' would be compressed to '{}.zst'",
+        dd.join("plain.txt").display(),
+        dd.join("plain.txt").display()
+    )));
+    assert!(logs.contains(&format!(
+        "'{}' would be removed (overlaps",
+        dd.join("both.txt").display()
+    )));
+
+    // nothing was changed
+    let mut names: Vec<_> = fs::read_dir(&dd)
+        .unwrap()
+        .map(|e| e.unwrap().file_name().to_string_lossy().to_string())
+        .filter(|e| !e.starts_with(".fuse-zstd"))
+        .collect();
+    names.sort();
+    assert_eq!(names, vec!["both.txt", "both.txt.zst", "plain.txt"]);
+    assert_eq!(fs::read(dd.join("plain.txt")).unwrap(), b"PLAIN");
+    assert_eq!(xattr::get(dd.join("plain.txt"), "user.ino").unwrap(), None);
+    assert_eq!(xattr::get(&dd, "user.ino_idx").unwrap(), None);
+
+    // mounted read-only
+    assert_eq!(
+        fs::write(mp.join("new.txt"), b"NEW")
+            .unwrap_err()
+            .raw_os_error(),
+        Some(libc::EROFS)
+    );
+}
+
+#[rstest]
+fn archive_dirs() {
+    let mounted_fs = utils::FuseZstdProcess::with_data(false, &["--archive-dirs"], |dd| {
+        let many = dd.join("many");
+        fs::create_dir(&many).unwrap();
+        for i in 0..1000 {
+            fs::write(
+                many.join(format!("{}.txt.zst", i)),
+                zstd::encode_all(format!("file number {}", i).as_bytes(), 3).unwrap(),
+            )
+            .unwrap();
+        }
+        let per_file_blocks: u64 = fs::read_dir(&many)
+            .unwrap()
+            .map(|e| e.unwrap().metadata().unwrap().blocks())
+            .sum();
+
+        Command::cargo_bin("fuse-zstd")
+            .unwrap()
+            .args([
+                "archive",
+                "--data-dir",
+                dd.to_str().unwrap(),
+                "--dir",
+                "many",
+            ])
+            .assert()
+            .success();
+        assert!(!many.exists());
+        let archive_blocks = fs::metadata(dd.join("many.tar.zst")).unwrap().blocks();
+        assert!(archive_blocks * 10 < per_file_blocks);
+    });
+    let mp = mounted_fs.mount_point();
+    let dd = mounted_fs.data_dir();
+
+    assert!(fs::metadata(mp.join("many")).unwrap().is_dir());
+    assert!(fs::metadata(mp.join("many.tar")).is_err());
+    assert_eq!(fs::read_dir(mp.join("many")).unwrap().count(), 1000);
+    for i in 0..1000 {
+        assert_eq!(
+            fs::read_to_string(mp.join("many").join(format!("{}.txt", i))).unwrap(),
+            format!("file number {}", i)
+        );
+    }
+
+    // changes are packed to the archive
+    fs::write(mp.join("many/new.txt"), b"NEW FILE").unwrap();
+    let mut packed = vec![];
+    for _ in 0..50 {
+        packed = zstd::decode_all(fs::File::open(dd.join("many.tar.zst")).unwrap()).unwrap();
+        if packed.windows(7).any(|e| e == b"new.txt") {
+            break;
+        }
+        thread::sleep(Duration::from_millis(100));
+    }
+    assert!(packed.windows(7).any(|e| e == b"new.txt"));
+    assert!(packed.windows(8).any(|e| e == b"NEW FILE"));
+}
+
+#[rstest]
+#[case::hidden(false)]
+#[case::shown(true)]
+fn show_special(#[case] show: bool) {
+    let args: &[&str] = if show { &["--show-special"] } else { &[] };
+    let mounted_fs = utils::FuseZstdProcess::with_data(false, args, |dd| {
+        let fifo = std::ffi::CString::new(dd.join("fifo").to_str().unwrap()).unwrap();
+        assert_eq!(unsafe { libc::mkfifo(fifo.as_ptr(), 0o644) }, 0);
+    });
+    let mp = mounted_fs.mount_point();
+    let dd = mounted_fs.data_dir();
+
+    let names: Vec<_> = fs::read_dir(&mp)
+        .unwrap()
+        .map(|e| e.unwrap().file_name().to_string_lossy().to_string())
+        .collect();
+    assert_eq!(names.contains(&"fifo".to_string()), show);
+
+    if show {
+        assert!(fs::symlink_metadata(mp.join("fifo"))
+            .unwrap()
+            .file_type()
+            .is_fifo());
+        fs::remove_file(mp.join("fifo")).unwrap();
+        assert!(fs::symlink_metadata(dd.join("fifo")).is_err());
+    } else {
+        assert!(fs::symlink_metadata(mp.join("fifo")).is_err());
+        assert!(fs::remove_file(mp.join("fifo")).is_err());
+        assert!(fs::symlink_metadata(dd.join("fifo")).is_ok());
+    }
+}
+
+#[rstest]
+fn work_dir() {
+    // preferably on a different device than data dir
+    let work_dir = if path::Path::new("/dev/shm").is_dir() {
+        TempDir::new_in("/dev/shm").unwrap()
+    } else {
+        TempDir::new().unwrap()
+    };
+    let mounted_fs = utils::FuseZstdProcess::with_args(
+        false,
+        &["--work-dir", work_dir.path().to_str().unwrap()],
+    );
+    let mp = mounted_fs.mount_point();
+    let dd = mounted_fs.data_dir();
+
+    let mut file = fs::File::create(mp.join("file.txt")).unwrap();
+    file.write_all(b"ATOMIC").unwrap();
+
+    // decompressed data of opened file are in work dir
+    let opened: Vec<_> = fs::read_dir(format!("/proc/{}/fd", mounted_fs.pid()))
+        .unwrap()
+        .filter_map(|e| fs::read_link(e.unwrap().path()).ok())
+        .collect();
+    assert!(opened.iter().any(|e| e.starts_with(work_dir.path())));
+    drop(file);
+    thread::sleep(Duration::from_millis(200));
+
+    // compressed file is committed to data dir without leftovers
+    assert_eq!(fs::read(mp.join("file.txt")).unwrap(), b"ATOMIC");
+    assert_eq!(
+        utils::get_compressed_content(dd.join("file.txt.zst")),
+        "ATOMIC"
+    );
+    let names: Vec<_> = fs::read_dir(&dd)
+        .unwrap()
+        .map(|e| e.unwrap().file_name().to_string_lossy().to_string())
+        .filter(|e| !e.starts_with(".fuse-zstd"))
+        .collect();
+    assert_eq!(names, vec!["file.txt.zst"]);
+    assert_eq!(fs::read_dir(work_dir.path()).unwrap().count(), 0);
+}
+
+#[rstest]
+#[case::flush("flush", 5, true)]
+#[case::release("release", 2, true)]
+#[case::fsync("fsync", 1, false)]
+fn sync_on(#[case] event: &str, #[case] expected: usize, #[case] kept: bool) {
+    let mounted_fs = utils::FuseZstdProcess::with_logs(false, &["--sync-on", event]);
+    let mp = mounted_fs.mount_point();
+    let dd = mounted_fs.data_dir();
+    let compressions = || mounted_fs.logs().matches("Before compression").count();
+
+    let mut file = fs::File::create(mp.join("file.txt")).unwrap();
+    let before = compressions();
+    for i in 0..3 {
+        file.write_all(format!("{}", i).as_bytes()).unwrap();
+        // flush is sent when any descriptor is closed
+        drop(file.try_clone().unwrap());
+    }
+    file.sync_all().unwrap();
+    file.write_all(b"LAST").unwrap();
+    drop(file);
+    thread::sleep(Duration::from_millis(200));
+
+    assert_eq!(compressions() - before, expected);
+    assert_eq!(
+        utils::get_compressed_content(dd.join("file.txt.zst")),
+        if kept { "012LAST" } else { "012" }
+    );
+}
+
+#[rstest]
+fn stats_size_classes() {
+    let mounted_fs = utils::FuseZstdProcess::with_args(false, &["--flush-interval", "1"]);
+    let mp = mounted_fs.mount_point();
+    let dd = mounted_fs.data_dir();
+
+    let text = |size: usize| -> Vec<u8> {
+        (0..size)
+            .map(|e| format!("line {}\n", e % 100).into_bytes()[e % 8])
+            .collect()
+    };
+    fs::write(mp.join("tiny.txt"), text(100)).unwrap();
+    fs::write(mp.join("small.txt"), text(10_000)).unwrap();
+    fs::write(mp.join("large.txt"), text(2_000_000)).unwrap();
+    wait_for_stats(&dd, 6);
+
+    let content = fs::read_to_string(dd.join(".fuse-zstd-stats")).unwrap();
+    let counter = |name: &str| -> u64 {
+        content
+            .lines()
+            .find_map(|e| e.strip_prefix(&format!("{}=", name)))
+            .unwrap()
+            .parse()
+            .unwrap()
+    };
+    // empty files are compressed when created
+    assert_eq!(counter("size_lt_4k_files"), 4);
+    assert_eq!(counter("size_lt_4k_bytes"), 100);
+    assert_eq!(counter("size_4k_64k_files"), 1);
+    assert_eq!(counter("size_4k_64k_bytes"), 10_000);
+    assert_eq!(counter("size_64k_1m_files"), 0);
+    assert_eq!(counter("size_ge_1m_files"), 1);
+    assert_eq!(counter("size_ge_1m_bytes"), 2_000_000);
+
+    // large files compress better than tiny ones
+    let ratio = |class: &str| {
+        counter(&format!("size_{}_compressed", class)) as f64
+            / counter(&format!("size_{}_bytes", class)) as f64
+    };
+    assert!(ratio("ge_1m") < ratio("4k_64k"));
+    assert!(ratio("4k_64k") < ratio("lt_4k"));
+}
+
+#[rstest]
+#[case::lost(false)]
+#[case::strict(true)]
+fn release_error(#[case] strict: bool) {
+    let mut args = vec!["--sync-on", "release"];
+    if strict {
+        args.push("--strict-close");
+    }
+    let mounted_fs = utils::FuseZstdProcess::with_logs(false, &args);
+    let mp = mounted_fs.mount_point();
+    let dd = mounted_fs.data_dir();
+
+    fs::create_dir(mp.join("dir")).unwrap();
+    let mut file = fs::File::create(mp.join("dir/file.txt")).unwrap();
+    file.write_all(b"DATA").unwrap();
+    // compressed file can't be stored to removed directory
+    fs::remove_dir_all(dd.join("dir")).unwrap();
+    drop(file);
+    thread::sleep(Duration::from_millis(200));
+
+    let logs = mounted_fs.logs();
+    let message = format!(
+        "Failed to store '{}' on release",
+        dd.join("dir/file.txt.zst").display()
+    );
+    assert!(logs
+        .lines()
+        .any(|e| e.contains("ERROR") && e.contains(&message)));
+
+    // stored once the directory exists again and other file is released
+    fs::create_dir(dd.join("dir")).unwrap();
+    fs::write(mp.join("other.txt"), b"OTHER").unwrap();
+    thread::sleep(Duration::from_millis(200));
+    if strict {
+        assert_eq!(
+            utils::get_compressed_content(dd.join("dir/file.txt.zst")),
+            "DATA"
+        );
+    } else {
+        assert!(!dd.join("dir/file.txt.zst").exists());
+    }
+}
+
+#[rstest]
+#[case::compressed(&[], false)]
+#[case::logical(&["--report-logical-blocks"], true)]
+#[case::du_physical(&["--du-reports", "physical"], false)]
+#[case::du_logical(&["--du-reports", "logical"], true)]
+fn report_logical_blocks(#[case] args: &[&str], #[case] logical: bool) {
+    let mounted_fs = utils::FuseZstdProcess::with_args(false, args);
+    let mp = mounted_fs.mount_point();
+
+    fs::write(mp.join("file.txt"), vec![b'a'; 1024 * 1024]).unwrap();
+
+    let du_path = |path: &path::Path, apparent: bool| {
+        let mut cmd = process::Command::new("du");
+        if apparent {
+            cmd.arg("--apparent-size");
+        }
+        let output = cmd.arg(path).output().unwrap();
+        assert!(output.status.success());
+        String::from_utf8(output.stdout)
+            .unwrap()
+            .split_whitespace()
+            .next()
+            .unwrap()
+            .parse::<u64>()
+            .unwrap()
+    };
+    let du = |apparent: bool| du_path(&mp.join("file.txt"), apparent);
+    assert_eq!(du(true), 1024);
+    if logical {
+        assert_eq!(du(false), du(true));
+    } else {
+        assert!(du(false) < du(true));
+        // disk usage of the compressed file
+        assert_eq!(
+            du(false),
+            du_path(&mounted_fs.data_dir().join("file.txt.zst"), false)
+        );
+    }
+}
+
+#[rstest]
+fn strict() {
+    let mounted_fs = utils::FuseZstdProcess::with_logs(false, &["--strict"]);
+    let mp = mounted_fs.mount_point();
+    fs::write(mp.join("file.txt"), b"DATA").unwrap();
+    assert_eq!(
+        utils::get_compressed_content(mounted_fs.data_dir().join("file.txt.zst")),
+        "DATA"
+    );
+    // probe files are removed
+    assert!(!fs::read_dir(mounted_fs.data_dir()).unwrap().any(|e| e
+        .unwrap()
+        .file_name()
+        .to_string_lossy()
+        .starts_with(".fuse-zstd-probe")));
+    assert!(!mounted_fs.logs().contains("lacks required features"));
+}
+
+#[rstest]
+fn no_compress() {
+    let mounted_fs = utils::FuseZstdProcess::with_data(false, &["--no-compress"], |dd| {
+        fs::write(
+            dd.join("compressed.txt.zst"),
+            zstd::encode_all(&b"COMPRESSED"[..], 3).unwrap(),
+        )
+        .unwrap();
+    });
+    let mp = mounted_fs.mount_point();
+    let dd = mounted_fs.data_dir();
+
+    let data = b"DATA ".repeat(1000);
+    fs::write(mp.join("file.txt"), &data).unwrap();
+    assert_eq!(fs::read(dd.join("file.txt.zst")).unwrap(), data);
+    assert_eq!(fs::read(mp.join("file.txt")).unwrap(), data);
+    assert_eq!(
+        fs::metadata(mp.join("file.txt")).unwrap().len(),
+        data.len() as u64
+    );
+
+    let mut file = fs::OpenOptions::new()
+        .append(true)
+        .open(mp.join("file.txt"))
+        .unwrap();
+    file.write_all(b"MORE").unwrap();
+    drop(file);
+    assert_eq!(
+        fs::read(dd.join("file.txt.zst")).unwrap(),
+        [&data[..], b"MORE"].concat()
+    );
+
+    // compressed files are still readable and stored uncompressed once changed
+    assert_eq!(fs::read(mp.join("compressed.txt")).unwrap(), b"COMPRESSED");
+    fs::write(mp.join("compressed.txt"), b"CHANGED").unwrap();
+    assert_eq!(fs::read(dd.join("compressed.txt.zst")).unwrap(), b"CHANGED");
+}
+
+#[rstest]
+fn compression_level_store() {
+    let data = b"DATA ".repeat(1000);
+
+    let mounted_fs = utils::FuseZstdProcess::with_args(false, &["--compression-level", "store"]);
+    fs::write(mounted_fs.mount_point().join("file.txt"), &data).unwrap();
+    assert_eq!(
+        fs::read(mounted_fs.data_dir().join("file.txt.zst")).unwrap(),
+        data
+    );
+    drop(mounted_fs);
+
+    // numeric 0 is the default level of zstd
+    let mounted_fs = utils::FuseZstdProcess::with_args(false, &["-c", "0"]);
+    fs::write(mounted_fs.mount_point().join("file.txt"), &data).unwrap();
+    let stored = fs::read(mounted_fs.data_dir().join("file.txt.zst")).unwrap();
+    assert!(stored.len() < data.len());
+    assert_eq!(zstd::decode_all(&stored[..]).unwrap(), data);
+}
+
+#[rstest]
+fn skip_mime() {
+    let mounted_fs =
+        utils::FuseZstdProcess::with_args(false, &["--skip-mime", "image/*,application/zip"]);
+    let mp = mounted_fs.mount_point();
+    let dd = mounted_fs.data_dir();
+
+    // JPEG without extension
+    let mut jpeg = b"\xff\xd8\xff\xe0\x00\x10JFIF\x00".to_vec();
+    jpeg.extend(vec![0; 10000]);
+    fs::write(mp.join("photo"), &jpeg).unwrap();
+    assert_eq!(fs::read(dd.join("photo.zst")).unwrap(), jpeg);
+    assert_eq!(fs::read(mp.join("photo")).unwrap(), jpeg);
+
+    // other types are compressed
+    let text = vec![b'a'; 10000];
+    fs::write(mp.join("text"), &text).unwrap();
+    assert!(fs::metadata(dd.join("text.zst")).unwrap().len() < 1000);
+    assert_eq!(fs::read(mp.join("text")).unwrap(), text);
+
+    // incompressible data of unknown type are stored raw
+    let mut state = 0x2545f4914f6cdd1du64;
+    let noise: Vec<u8> = (0..100_000)
+        .map(|_| {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state as u8
+        })
+        .collect();
+    fs::write(mp.join("noise"), &noise).unwrap();
+    assert_eq!(fs::read(dd.join("noise.zst")).unwrap(), noise);
+    assert_eq!(fs::read(mp.join("noise")).unwrap(), noise);
+}
+
+#[rstest]
+fn fill_size_on_readdir() {
+    let mounted_fs = utils::FuseZstdProcess::with_data(false, &["--fill-size-on-readdir"], |dd| {
+        // created outside of fuse-zstd (without metadata)
+        fs::create_dir(dd.join("directory")).unwrap();
+        for name in ["imported.txt.zst", "directory/imported.txt.zst"] {
+            fs::write(
+                dd.join(name),
+                zstd::encode_all(&b"compressed data"[..], 3).unwrap(),
+            )
+            .unwrap();
+        }
+    });
+    let mp = mounted_fs.mount_point();
+    let dd = mounted_fs.data_dir();
+
+    for dir in ["", "directory"] {
+        let output = process::Command::new("ls")
+            .arg("-l")
+            .arg(mp.join(dir))
+            .output()
+            .unwrap();
+        assert!(output.status.success());
+        let listing = String::from_utf8(output.stdout).unwrap();
+        assert!(listing
+            .lines()
+            .any(|e| e.contains(" 15 ") && e.ends_with("imported.txt")));
+        assert_eq!(
+            utils::get_real_size(dd.join(dir).join("imported.txt.zst")),
+            Some(15)
+        );
+    }
+}
+
+#[rstest]
+#[case::exclusive(false)]
+#[case::shared(true)]
+fn allow_shared(#[case] shared: bool) {
+    let args: &[&str] = if shared { &["--allow-shared"] } else { &[] };
+    let mounted_fs = utils::FuseZstdProcess::with_args(false, args);
+    let mp = mounted_fs.mount_point();
+    let dd = mounted_fs.data_dir();
+    let other_mp = TempDir::new_in("/tmp/").unwrap();
+
+    let mut command = process::Command::new(cargo_bin("fuse-zstd"));
+    command
+        .arg("--data-dir")
+        .arg(&dd)
+        .arg("--mount-point")
+        .arg(other_mp.path())
+        .args(args)
+        .stderr(process::Stdio::piped());
+    if !shared {
+        let output = command.output().unwrap();
+        assert!(!output.status.success());
+        let stderr = String::from_utf8(output.stderr).unwrap();
+        assert!(stderr.contains("is already mounted by another fuse-zstd process"));
+        assert!(!utils::FuseZstdProcess::check_mounted(other_mp.path()));
+        return;
+    }
+
+    let mut other = command.spawn().unwrap();
+    for _ in 0..50 {
+        if utils::FuseZstdProcess::check_mounted(other_mp.path()) {
+            break;
+        }
+        thread::sleep(Duration::from_millis(200));
+    }
+
+    // inodes are not assigned twice
+    for i in 0..10 {
+        fs::write(mp.join(format!("first{}.txt", i)), b"FIRST").unwrap();
+        fs::write(other_mp.path().join(format!("second{}.txt", i)), b"SECOND").unwrap();
+    }
+    let mut inodes = vec![];
+    for i in 0..10 {
+        inodes.push(
+            fs::metadata(mp.join(format!("first{}.txt", i)))
+                .unwrap()
+                .ino(),
+        );
+        inodes.push(
+            fs::metadata(mp.join(format!("second{}.txt", i)))
+                .unwrap()
+                .ino(),
+        );
+    }
+    let count = inodes.len();
+    inodes.sort();
+    inodes.dedup();
+    assert_eq!(inodes.len(), count);
+    assert_eq!(
+        fs::read(other_mp.path().join("first0.txt")).unwrap(),
+        b"FIRST"
+    );
+
+    other.kill().unwrap();
+    other.wait().unwrap();
+}
+
+#[rstest]
+fn flush_interval() {
+    let mounted_fs = utils::FuseZstdProcess::with_args(false, &["--flush-interval", "1"]);
+    let mp = mounted_fs.mount_point();
+    let dd = mounted_fs.data_dir();
+
+    // file is kept opened without fsync
+    let mut file = fs::File::create(mp.join("file.txt")).unwrap();
+    file.write_all(b"DATA").unwrap();
+    thread::sleep(Duration::from_millis(2500));
+
+    process::Command::new("kill")
+        .args(["-9", &mounted_fs.pid().to_string()])
+        .status()
+        .unwrap();
+    drop(file);
+    assert_eq!(
+        utils::get_compressed_content(dd.join("file.txt.zst")),
+        "DATA"
+    );
+}
+
+#[rstest]
+fn async_unlink() {
+    let mounted_fs = utils::FuseZstdProcess::with_args(false, &["--async-unlink"]);
+    let mp = mounted_fs.mount_point();
+    let dd = mounted_fs.data_dir();
+
+    for i in 0..50 {
+        fs::write(mp.join(format!("file{}.txt", i)), b"DATA").unwrap();
+    }
+    for i in 0..50 {
+        fs::remove_file(mp.join(format!("file{}.txt", i))).unwrap();
+        assert!(!mp.join(format!("file{}.txt", i)).exists());
+        assert!(!dd.join(format!("file{}.txt.zst", i)).exists());
+    }
+    assert_eq!(fs::read_dir(&mp).unwrap().count(), 0);
+
+    // backing files are reclaimed in background
+    thread::sleep(Duration::from_millis(500));
+    let deleted = dd.join(".fuse-zstd-inode_cache").join("deleted");
+    assert_eq!(fs::read_dir(deleted).unwrap().count(), 0);
+}
+
+#[rstest]
+fn compact_cache_interval() {
+    let mounted_fs = utils::FuseZstdProcess::with_logs(false, &["--compact-cache-interval", "1"]);
+    let mp = mounted_fs.mount_point();
+    let dd = mounted_fs.data_dir();
+
+    fn dir_size(path: &path::Path) -> u64 {
+        fs::read_dir(path)
+            .unwrap()
+            .map(|e| e.unwrap())
+            .map(|e| match e.file_type().unwrap().is_dir() {
+                true => dir_size(&e.path()),
+                false => e.metadata().unwrap().len(),
+            })
+            .sum()
+    }
+
+    // removed entries remain in the database
+    for round in 0..3 {
+        for i in 0..2000 {
+            fs::write(mp.join(format!("file-{}-{}.txt", round, i)), b"DATA").unwrap();
+        }
+        for i in 0..2000 {
+            fs::remove_file(mp.join(format!("file-{}-{}.txt", round, i))).unwrap();
+        }
+    }
+    let size = dir_size(&dd.join(".fuse-zstd-inode_cache"));
+    thread::sleep(Duration::from_millis(2500));
+
+    assert!(mounted_fs.logs().contains("Inode cache compacted"));
+    let stats = fs::read_to_string(dd.join(".fuse-zstd-stats")).unwrap();
+    let cache_bytes: u64 = stats
+        .lines()
+        .find_map(|e| e.strip_prefix("cache_bytes="))
+        .unwrap()
+        .parse()
+        .unwrap();
+    assert!(cache_bytes < size);
+    assert!(dir_size(&dd.join(".fuse-zstd-inode_cache")) < size);
+}
+
+#[rstest]
+fn scrub() {
+    let data = b"DATA ".repeat(1000);
+    let mounted_fs =
+        utils::FuseZstdProcess::with_data_logs(false, &["--scrub", "--scrub-rate", "10"], |dd| {
+            fs::create_dir(dd.join("dir")).unwrap();
+            fs::write(
+                dd.join("valid.txt.zst"),
+                zstd::encode_all(&data[..], 3).unwrap(),
+            )
+            .unwrap();
+            // damaged data of a compressed file
+            let mut compressed = zstd::encode_all(&data[..], 3).unwrap();
+            compressed.truncate(compressed.len() / 2);
+            fs::write(dd.join("dir/corrupted.txt.zst"), compressed).unwrap();
+        });
+
+    let mut logs = String::new();
+    for _ in 0..50 {
+        logs = mounted_fs.logs();
+        if logs.contains("Scrub finished") {
+            break;
+        }
+        thread::sleep(Duration::from_millis(200));
+    }
+    assert!(logs.contains("Scrub found corrupted file"));
+    assert!(logs.contains("dir/corrupted.txt.zst"));
+    assert!(!logs.contains("valid.txt.zst' (err="));
+    assert!(logs.contains("Scrub finished (checked=2, corrupted=1"));
+
+    // mount works meanwhile
+    assert_eq!(
+        fs::read(mounted_fs.mount_point().join("valid.txt")).unwrap(),
+        data
+    );
+}
+
+#[rstest]
+fn max_decompressed_size() {
+    let zeros = vec![0u8; 16 * 1024 * 1024];
+    let mounted_fs =
+        utils::FuseZstdProcess::with_data(false, &["--max-decompressed-size", "1000000"], |dd| {
+            // size is stored in frame header
+            fs::write(
+                dd.join("sized.zst"),
+                zstd::bulk::compress(&zeros, 3).unwrap(),
+            )
+            .unwrap();
+            // size is not known before decompression
+            let mut encoder = zstd::stream::Encoder::new(vec![], 3).unwrap();
+            encoder.set_pledged_src_size(None).unwrap();
+            encoder.include_contentsize(false).unwrap();
+            encoder.write_all(&zeros).unwrap();
+            fs::write(dd.join("streamed.zst"), encoder.finish().unwrap()).unwrap();
+            fs::write(
+                dd.join("small.zst"),
+                zstd::encode_all(&b"SMALL"[..], 3).unwrap(),
+            )
+            .unwrap();
+        });
+    let mp = mounted_fs.mount_point();
+
+    for name in ["sized", "streamed"] {
+        let err = fs::read(mp.join(name)).unwrap_err();
+        assert_eq!(err.raw_os_error(), Some(libc::EFBIG), "{}", name);
+    }
+    assert_eq!(fs::read(mp.join("small")).unwrap(), b"SMALL");
+}
+
+#[rstest]
+fn cache_compression() {
+    let mounted_fs = utils::FuseZstdProcess::with_logs(false, &["--cache-compression"]);
+    let mp = mounted_fs.mount_point();
+
+    let nested = path::Path::new("first/second/third/fourth");
+    fs::create_dir_all(mp.join(nested)).unwrap();
+    for i in 0..20 {
+        fs::write(mp.join(nested).join(format!("{}.txt", i)), b"NESTED").unwrap();
+    }
+
+    // only the entry of renamed directory is updated
+    fs::rename(mp.join("first"), mp.join("renamed")).unwrap();
+    assert!(mounted_fs
+        .logs()
+        .contains("Paths of 0 cached entries updated after directory rename"));
+    let renamed = mp.join("renamed/second/third/fourth");
+    for i in 0..20 {
+        assert_eq!(
+            fs::read(renamed.join(format!("{}.txt", i))).unwrap(),
+            b"NESTED"
+        );
+    }
+    fs::write(renamed.join("new.txt"), b"NEW").unwrap();
+    assert_eq!(fs::read(renamed.join("new.txt")).unwrap(), b"NEW");
+    assert!(!mp.join("first").exists());
+}
+
+#[test]
+fn log_format_json() {
+    let mounted_fs = utils::FuseZstdProcess::with_logs(false, &["--log-format", "json"]);
+    let mp = mounted_fs.mount_point();
+
+    fs::write(mp.join("file.txt"), b"JSON").unwrap();
+    assert_eq!(fs::read(mp.join("file.txt")).unwrap(), b"JSON");
+
+    let logs = mounted_fs.logs();
+    let records: Vec<serde_json::Value> = logs
+        .lines()
+        .map(|e| serde_json::from_str(e).unwrap())
+        .collect();
+    assert!(!records.is_empty());
+    for record in &records {
+        for key in ["timestamp", "level", "target", "message"] {
+            assert!(record[key].is_string(), "{} missing in {}", key, record);
+        }
+        assert!(record["fields"].is_object());
+    }
+    assert!(records.iter().any(|e| e["fields"]["op"] == "open"
+        && e["fields"]["inode"].is_string()
+        && e["level"] == "debug"));
+    assert!(records.iter().any(|e| e["fields"]["path"]
+        .as_str()
+        .unwrap_or_default()
+        .contains("file.txt")));
+}
+
+#[test]
+fn mirror_inodes() {
+    let mounted_fs = utils::FuseZstdProcess::with_args(false, &["--mirror-inodes"]);
+    let mp = mounted_fs.mount_point();
+    let data_dir = mounted_fs.data_dir();
+    let ino = |path: path::PathBuf| fs::symlink_metadata(path).unwrap().ino();
+
+    fs::create_dir(mp.join("dir")).unwrap();
+    fs::write(mp.join("dir/file.txt"), b"FIRST").unwrap();
+    std::os::unix::fs::symlink("file.txt", mp.join("dir/link")).unwrap();
+    assert_eq!(ino(mp.join("dir")), ino(data_dir.join("dir")));
+    assert_eq!(
+        ino(mp.join("dir/file.txt")),
+        ino(data_dir.join("dir/file.txt.zst"))
+    );
+    assert_eq!(ino(mp.join("dir/link")), ino(data_dir.join("dir/link")));
+
+    // rewritten file keeps its inode
+    let orig = ino(data_dir.join("dir/file.txt.zst"));
+    fs::write(mp.join("dir/file.txt"), b"SECOND").unwrap();
+    assert_eq!(ino(data_dir.join("dir/file.txt.zst")), orig);
+    assert_eq!(ino(mp.join("dir/file.txt")), orig);
+    assert_eq!(fs::read(mp.join("dir/file.txt")).unwrap(), b"SECOND");
+
+    for entry in fs::read_dir(mp.join("dir")).unwrap() {
+        let entry = entry.unwrap();
+        assert_eq!(entry.ino(), ino(entry.path()));
+    }
+}
+
+#[test]
+fn network_backing() {
+    let mounted_fs = utils::FuseZstdProcess::with_logs(false, &["--network-backing"]);
+    let mp = mounted_fs.mount_point();
+    let path = mounted_fs.data_dir().join("file.txt.zst");
+    fs::write(&path, zstd::encode_all(&b"NETWORK"[..], 0).unwrap()).unwrap();
+    xattr::set(&path, "user.real_size", &7u64.to_be_bytes()).unwrap();
+
+    // attributes expire in the kernel, metadata are read from data dir only once
+    for _ in 0..3 {
+        assert_eq!(fs::metadata(mp.join("file.txt")).unwrap().len(), 7);
+        thread::sleep(Duration::from_millis(1100));
+    }
+    assert_eq!(mounted_fs.logs().matches("Metadata cached").count(), 1);
+
+    fs::write(mp.join("file.txt"), b"CHANGED DATA").unwrap();
+    assert_eq!(fs::metadata(mp.join("file.txt")).unwrap().len(), 12);
+    assert_eq!(fs::read(mp.join("file.txt")).unwrap(), b"CHANGED DATA");
+    assert_eq!(
+        utils::get_real_size(mounted_fs.data_dir().join("file.txt.zst")),
+        Some(12)
+    );
+    // stored metadata are cached directly
+    assert_eq!(mounted_fs.logs().matches("Metadata cached").count(), 1);
+}
+
+#[test]
+fn name_manifests() {
+    let mounted_fs = utils::FuseZstdProcess::with_data(false, &["--name-manifests"], |dd| {
+        for (name, data) in [
+            ("blob_abc123.zst", &b"REPORT"[..]),
+            ("other.txt.zst", b"OTHER"),
+        ] {
+            fs::write(dd.join(name), zstd::encode_all(data, 0).unwrap()).unwrap();
+            xattr::set(
+                dd.join(name),
+                "user.real_size",
+                &(data.len() as u64).to_be_bytes(),
+            )
+            .unwrap();
+        }
+        fs::write(
+            dd.join(".fuse-zstd-names"),
+            "blob_abc123.zst -> report.pdf\nwrong line\n",
+        )
+        .unwrap();
+    });
+    let mp = mounted_fs.mount_point();
+    let dd = mounted_fs.data_dir();
+
+    let mut names: Vec<_> = fs::read_dir(&mp)
+        .unwrap()
+        .map(|e| e.unwrap().file_name().to_string_lossy().to_string())
+        .collect();
+    names.sort();
+    assert_eq!(names, vec!["other.txt", "report.pdf"]);
+    assert_eq!(fs::read(mp.join("report.pdf")).unwrap(), b"REPORT");
+    assert_eq!(fs::metadata(mp.join("report.pdf")).unwrap().len(), 6);
+    assert!(!mp.join("blob_abc123").exists());
+    assert!(!mp.join(".fuse-zstd-names").exists());
+
+    // changes are stored to the backing file
+    fs::write(mp.join("report.pdf"), b"UPDATED").unwrap();
+    assert_eq!(
+        utils::get_compressed_content(dd.join("blob_abc123.zst")),
+        "UPDATED"
+    );
+
+    // renamed file is not listed in the manifest any more
+    fs::rename(mp.join("report.pdf"), mp.join("renamed.pdf")).unwrap();
+    assert!(dd.join("renamed.pdf.zst").exists());
+    assert!(!dd.join("blob_abc123.zst").exists());
+    assert_eq!(fs::read(mp.join("renamed.pdf")).unwrap(), b"UPDATED");
+}
+
+#[rstest]
+fn overlap_policy() {
+    let prepare = |dd: &path::Path| {
+        fs::write(dd.join("file.txt"), b"PLAIN").unwrap();
+        fs::write(
+            dd.join("file.txt.zst"),
+            zstd::encode_all(&b"COMPRESSED"[..], 0).unwrap(),
+        )
+        .unwrap();
+    };
+
+    // overlap is refused
+    let mounted_fs =
+        utils::FuseZstdProcess::with_data_logs(false, &["--overlap-policy", "error"], prepare);
+    let mp = mounted_fs.mount_point();
+    let listed: Result<Vec<_>, _> = fs::read_dir(&mp).unwrap().collect();
+    assert!(listed.is_err());
+    assert!(fs::read(mp.join("file.txt")).is_err());
+    assert!(mounted_fs.logs().contains("Plain file '"));
+    assert!(mounted_fs.logs().contains("file.txt' overlaps '"));
+
+    // plain file replaces the compressed one
+    let mounted_fs =
+        utils::FuseZstdProcess::with_data(false, &["--overlap-policy", "prefer-plain"], prepare);
+    let mp = mounted_fs.mount_point();
+    let dd = mounted_fs.data_dir();
+    assert_eq!(fs::read(mp.join("file.txt")).unwrap(), b"PLAIN");
+    assert!(!dd.join("file.txt").exists());
+    assert_eq!(
+        utils::get_compressed_content(dd.join("file.txt.zst")),
+        "PLAIN"
+    );
+
+    // compressed file is served by default
+    let mounted_fs = utils::FuseZstdProcess::with_data(false, &[], prepare);
+    let mp = mounted_fs.mount_point();
+    let dd = mounted_fs.data_dir();
+    assert_eq!(fs::read(mp.join("file.txt")).unwrap(), b"COMPRESSED");
+    assert!(dd.join("file.txt").exists());
+}
+
+#[rstest]
+fn profile() {
+    let mounted_fs = utils::FuseZstdProcess::with_logs(false, &["--profile"]);
+    let mp = mounted_fs.mount_point();
+
+    fs::create_dir(mp.join("dir")).unwrap();
+    for i in 0..10 {
+        fs::write(mp.join(format!("dir/file{}.txt", i)), b"DATA").unwrap();
+    }
+    assert_eq!(fs::read_dir(mp.join("dir")).unwrap().count(), 10);
+    for i in 0..10 {
+        assert_eq!(
+            fs::read(mp.join(format!("dir/file{}.txt", i))).unwrap(),
+            b"DATA"
+        );
+    }
+
+    // summary is logged on unmount
+    process::Command::new("umount").arg(&mp).status().unwrap();
+    for _ in 0..50 {
+        if mounted_fs.logs().contains("Profile (op=write") {
+            break;
+        }
+        thread::sleep(Duration::from_millis(100));
+    }
+    let logs = mounted_fs.logs();
+    for op in [
+        "open",
+        "read",
+        "write",
+        "flush",
+        "readdir",
+        "compress",
+        "decompress",
+    ] {
+        assert!(
+            logs.contains(&format!("Profile (op={}, calls=", op)),
+            "{}",
+            op
+        );
+    }
+}
+
+#[rstest]
+#[case::overwrite(false)]
+#[case::strict(true)]
+fn strict_external(#[case] strict: bool) {
+    let args: &[&str] = if strict { &["--strict-external"] } else { &[] };
+    let mounted_fs = utils::FuseZstdProcess::with_logs(false, args);
+    let mp = mounted_fs.mount_point();
+    let dd = mounted_fs.data_dir();
+
+    fs::write(mp.join("file.txt"), b"OLD").unwrap();
+    fs::write(mp.join("other.txt"), b"EXTERNAL").unwrap();
+    let mut opened = fs::OpenOptions::new()
+        .write(true)
+        .open(mp.join("file.txt"))
+        .unwrap();
+    opened.write_all(b"NEW").unwrap();
+
+    // compressed file is replaced by another process
+    fs::copy(dd.join("other.txt.zst"), dd.join("file.txt.zst")).unwrap();
+
+    let res = opened.sync_all();
+    if strict {
+        assert_eq!(res.unwrap_err().raw_os_error(), Some(libc::EBUSY));
+        assert_eq!(
+            utils::get_compressed_content(dd.join("file.txt.zst")),
+            "EXTERNAL"
+        );
+    } else {
+        res.unwrap();
+        assert_eq!(
+            utils::get_compressed_content(dd.join("file.txt.zst")),
+            "NEW"
+        );
+        assert!(mounted_fs
+            .logs()
+            .contains("file.txt.zst' was changed outside of fuse-zstd, overwriting it"));
+    }
+}
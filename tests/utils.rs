@@ -1,3 +1,5 @@
+#![allow(dead_code)]
+
 use assert_cmd::cargo::cargo_bin;
 use proc_mounts::MountIter;
 use std::{
@@ -6,7 +8,7 @@ use std::{
     process, thread,
     time::Duration,
 };
-use tempfile::TempDir;
+use tempfile::{NamedTempFile, TempDir};
 use zstd::decode_all;
 
 pub fn get_compressed_content<P>(path: P) -> String
@@ -16,23 +18,76 @@ where
     String::from_utf8(decode_all(fs::File::open(path).unwrap()).unwrap()).unwrap()
 }
 
+/// Decompressed size stored in metadata of compressed file
+pub fn get_real_size<P>(path: P) -> Option<u64>
+where
+    P: AsRef<Path>,
+{
+    xattr::get(path, "user.fuse_zstd.meta")
+        .unwrap()
+        .map(|e| u64::from_be_bytes(e[6..14].try_into().unwrap()))
+}
+
 pub struct FuseZstdProcess {
     process: process::Child,
     data_dir: TempDir,
     mount_point: TempDir,
+    log_file: Option<NamedTempFile>,
 }
 
 impl FuseZstdProcess {
     pub fn new(convert: bool) -> Self {
+        Self::with_args(convert, &[])
+    }
+
+    pub fn with_args(convert: bool, args: &[&str]) -> Self {
+        Self::spawn(convert, args, false, |_| {})
+    }
+
+    /// Stores logs of the process so they can be checked later
+    pub fn with_logs(convert: bool, args: &[&str]) -> Self {
+        Self::spawn(convert, args, true, |_| {})
+    }
+
+    /// Fills data dir before the process is started
+    pub fn with_data<F>(convert: bool, args: &[&str], prepare: F) -> Self
+    where
+        F: FnOnce(&Path),
+    {
+        Self::spawn(convert, args, false, prepare)
+    }
+
+    /// Fills data dir before the process is started and stores its logs
+    pub fn with_data_logs<F>(convert: bool, args: &[&str], prepare: F) -> Self
+    where
+        F: FnOnce(&Path),
+    {
+        Self::spawn(convert, args, true, prepare)
+    }
+
+    fn spawn<F>(convert: bool, args: &[&str], capture_logs: bool, prepare: F) -> Self
+    where
+        F: FnOnce(&Path),
+    {
         let data_dir = TempDir::new_in("/tmp/").unwrap();
+        prepare(data_dir.path());
         let mount_point = TempDir::new_in("/tmp/").unwrap();
-        let process = process::Command::new(cargo_bin("fuse-zstd"))
+        let log_file = if capture_logs {
+            Some(NamedTempFile::new_in("/tmp/").unwrap())
+        } else {
+            None
+        };
+        let mut command = process::Command::new(cargo_bin("fuse-zstd"));
+        command
             .args(["--data-dir", data_dir.path().to_str().unwrap()])
             .args(["--mount-point", mount_point.path().to_str().unwrap()])
             .args(if convert { vec!["--convert"] } else { vec![] })
-            .arg("-vvv")
-            .spawn()
-            .unwrap();
+            .args(args)
+            .arg("-vvv");
+        if let Some(log_file) = log_file.as_ref() {
+            command.stderr(log_file.reopen().unwrap());
+        }
+        let process = command.spawn().unwrap();
 
         // wait till mounted
         for _ in 0..50 {
@@ -41,6 +96,7 @@ impl FuseZstdProcess {
                     process,
                     data_dir,
                     mount_point,
+                    log_file,
                 };
             }
             thread::sleep(Duration::from_millis(200));
@@ -48,7 +104,7 @@ impl FuseZstdProcess {
         panic!("Not mounted");
     }
 
-    fn check_mounted(mount_point: &Path) -> bool {
+    pub fn check_mounted(mount_point: &Path) -> bool {
         MountIter::new()
             .unwrap()
             .filter_map(|e| e.ok())
@@ -62,6 +118,14 @@ impl FuseZstdProcess {
     pub fn mount_point(&self) -> PathBuf {
         self.mount_point.path().to_path_buf()
     }
+
+    pub fn pid(&self) -> u32 {
+        self.process.id()
+    }
+
+    pub fn logs(&self) -> String {
+        fs::read_to_string(self.log_file.as_ref().unwrap().path()).unwrap()
+    }
 }
 
 impl Drop for FuseZstdProcess {